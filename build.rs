@@ -28,4 +28,50 @@ fn main() {
     // here, we ensure the build script is only re-run when
     // `memory.x` is changed.
     println!("cargo:rerun-if-changed=memory.x");
+
+    // The generic board takes the GPIOs it hands over to PIO0 from
+    // PICO_PULSE_PINS, a list like "0-7,15,16". It has to include the
+    // trigger pin GPIO0 and at least two outputs.
+    if env::var_os("CARGO_FEATURE_GENERIC").is_some() {
+        println!("cargo:rerun-if-env-changed=PICO_PULSE_PINS");
+        let list = env::var("PICO_PULSE_PINS").unwrap_or_else(|_| "0-22".into());
+        let mut pins = Vec::new();
+        for part in list.split(',').map(str::trim) {
+            let (first, last) = part.split_once('-').unwrap_or((part, part));
+            let parse = |pin: &str| -> u8 {
+                match pin.trim().parse() {
+                    Ok(pin) if pin <= 29 => pin,
+                    _ => panic!("PICO_PULSE_PINS: bad GPIO {:?}", pin),
+                }
+            };
+            pins.extend(parse(first)..=parse(last));
+        }
+        pins.sort_unstable();
+        pins.dedup();
+        if pins.first() != Some(&0) || pins.len() < 3 {
+            panic!("PICO_PULSE_PINS needs GPIO0 for the trigger and two outputs");
+        }
+        File::create(out.join("pio_pins.rs"))
+            .unwrap()
+            .write_all(format!("&{:?}", pins).as_bytes())
+            .unwrap();
+
+        // PICO_PULSE_ARM_BUTTON optionally names a GPIO with a button to
+        // ground, held at power-on it skips AUTOARM
+        println!("cargo:rerun-if-env-changed=PICO_PULSE_ARM_BUTTON");
+        let button = match env::var("PICO_PULSE_ARM_BUTTON") {
+            Ok(pin) => match pin.trim().parse::<u8>() {
+                Ok(pin) if pin <= 29 && !pins.contains(&pin) => format!("Some({})", pin),
+                _ => panic!(
+                    "PICO_PULSE_ARM_BUTTON: bad GPIO {:?}, or one of PICO_PULSE_PINS",
+                    pin
+                ),
+            },
+            Err(_) => "None".into(),
+        };
+        File::create(out.join("arm_button.rs"))
+            .unwrap()
+            .write_all(button.as_bytes())
+            .unwrap();
+    }
 }
@@ -0,0 +1,289 @@
+// host/pico_pulse.h and host/pico_pulse.json for host tools not written in
+// Rust, rendered from the firmware's own definitions: the framing in
+// parser.rs, the binary protocol in wire.rs and the capabilities CAPS?
+// reports from pulse_generator.rs. The test fails while the checked-in
+// files differ from a fresh render, PICO_PULSE_UPDATE_PROTOCOL=1 rewrites
+// them, after a PROTOCOL_VERSION bump for one.
+
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::parser;
+use crate::pulse_generator::{
+    compile, OutputMode, ProgramConfig, NUM_CHANNELS, NUM_PULSES_MAX, TRIGGER_OUT_LATENCY_CYCLES,
+};
+use crate::time::Cycles;
+use crate::wire;
+
+// "ChunkHeader" to "chunk_header"
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
+
+fn c_bytes(bytes: &[u8]) -> String {
+    let bytes: Vec<_> = bytes.iter().map(|b| format!("0x{:02x}", b)).collect();
+    bytes.join(", ")
+}
+
+// What CAPS? reports for a channel running the program
+struct Program {
+    name: &'static str,
+    words: usize,
+    latency: u32,
+    min_width: u32,
+    min_gap: u32,
+}
+
+// One of each variant ProgramConfig::as_str() names, in its order
+fn programs() -> Vec<Program> {
+    let standard = ProgramConfig {
+        wide: false,
+        output: OutputMode::PushPull,
+        long_delay: false,
+        marker: false,
+        trigger_out: false,
+        double: false,
+        per_edge: false,
+        gap: 0,
+        split: false,
+    };
+    let variants = [
+        ProgramConfig {
+            trigger_out: true,
+            ..standard
+        },
+        ProgramConfig {
+            per_edge: true,
+            ..standard
+        },
+        ProgramConfig {
+            marker: true,
+            ..standard
+        },
+        ProgramConfig {
+            double: true,
+            ..standard
+        },
+        ProgramConfig {
+            split: true,
+            ..standard
+        },
+        ProgramConfig {
+            wide: true,
+            ..standard
+        },
+        ProgramConfig {
+            long_delay: true,
+            ..standard
+        },
+        standard,
+    ];
+    let mut programs = Vec::new();
+    for config in variants {
+        for output in [OutputMode::PushPull, OutputMode::OpenDrain] {
+            // The wide program refuses open drain
+            if config.wide && output == OutputMode::OpenDrain {
+                continue;
+            }
+            let config = ProgramConfig { output, ..config };
+            let Cycles(latency) = config.trigger_latency();
+            programs.push(Program {
+                name: config.as_str(),
+                words: compile(config).code.len(),
+                latency,
+                min_width: config.min_width(),
+                min_gap: config.min_gap(),
+            });
+        }
+    }
+    programs
+}
+
+const GENERATED: &str = "Generated by the host tests from src/wire.rs, src/parser.rs and \
+                         src/pulse_generator.rs, do not edit";
+
+fn c_header() -> String {
+    let mut h = String::new();
+    writeln!(h, "/* {} */", GENERATED).unwrap();
+    writeln!(h, "#ifndef PICO_PULSE_H").unwrap();
+    writeln!(h, "#define PICO_PULSE_H").unwrap();
+    writeln!(h).unwrap();
+    writeln!(h, "#include <stdint.h>").unwrap();
+    writeln!(h).unwrap();
+    writeln!(h, "/* Framing, see parser.rs */").unwrap();
+    let version = wire::PROTOCOL_VERSION;
+    writeln!(h, "#define PP_PROTOCOL_VERSION 0x{:02x}", version).unwrap();
+    writeln!(h, "#define PP_FRAME_START 0x{:02x}", parser::FRAME_START).unwrap();
+    writeln!(h, "#define PP_LINE_MAX {}", parser::LINE_MAX).unwrap();
+    writeln!(h, "#define PP_PAYLOAD_MAX {}", parser::PAYLOAD_MAX).unwrap();
+    writeln!(
+        h,
+        "#define PP_FRAME_TIMEOUT_US {}",
+        parser::FRAME_TIMEOUT_US
+    )
+    .unwrap();
+    for (name, magic) in [
+        ("BINARY_MAGIC", parser::BINARY_MAGIC),
+        ("ASCII_MAGIC", parser::ASCII_MAGIC),
+    ] {
+        writeln!(h, "#define PP_{}_LEN {}", name, magic.len()).unwrap();
+        writeln!(h, "#define PP_{} {{{}}}", name, c_bytes(magic)).unwrap();
+    }
+    writeln!(h).unwrap();
+    writeln!(h, "/* Capabilities, see CAPS? */").unwrap();
+    writeln!(h, "#define PP_NUM_CHANNELS {}", NUM_CHANNELS).unwrap();
+    writeln!(h, "#define PP_NUM_PULSES_MAX {}", NUM_PULSES_MAX).unwrap();
+    writeln!(
+        h,
+        "#define PP_TRIGOUT_LATENCY_CYCLES {}",
+        TRIGGER_OUT_LATENCY_CYCLES
+    )
+    .unwrap();
+    writeln!(h).unwrap();
+    writeln!(
+        h,
+        "/* Program variants: instruction words, trigger latency, shortest width and gap in cycles */"
+    )
+    .unwrap();
+    for program in programs() {
+        for (field, value) in [
+            ("WORDS", program.words as u32),
+            ("LATENCY", program.latency),
+            ("MIN_WIDTH", program.min_width),
+            ("MIN_GAP", program.min_gap),
+        ] {
+            writeln!(h, "#define PP_PROGRAM_{}_{} {}", program.name, field, value).unwrap();
+        }
+    }
+    writeln!(h).unwrap();
+    writeln!(h, "/* Command bytes */").unwrap();
+    for (name, id) in wire::FRAMES {
+        writeln!(h, "#define PP_{} 0x{:02x}", name, id).unwrap();
+    }
+    writeln!(h).unwrap();
+    writeln!(h, "/* Source bytes of a FRAME_READ */").unwrap();
+    for (i, source) in wire::ReadSource::ALL.iter().enumerate() {
+        writeln!(h, "#define PP_READ_{} {}", source.as_str(), i).unwrap();
+    }
+    writeln!(h).unwrap();
+    writeln!(h, "/* Error names after \"ERR \" in a reply line */").unwrap();
+    let errors = wire::WireError::ALL.iter().map(|err| err.as_str());
+    for error in errors.chain(parser::ParseError::ALL.iter().map(|err| err.as_str())) {
+        writeln!(h, "#define PP_ERR_{} \"{}\"", error, error).unwrap();
+    }
+    writeln!(h).unwrap();
+    writeln!(
+        h,
+        "/* Payload headers, little-endian and without padding */"
+    )
+    .unwrap();
+    for layout in &wire::LAYOUTS {
+        writeln!(h, "typedef struct {{").unwrap();
+        for field in layout.fields {
+            writeln!(h, "    uint{}_t {};", field.size * 8, field.name).unwrap();
+        }
+        writeln!(h, "}} pp_{};", snake_case(layout.name)).unwrap();
+        let name = snake_case(layout.name).to_uppercase();
+        writeln!(h, "#define PP_{}_LEN {}", name, layout.size).unwrap();
+        writeln!(h).unwrap();
+    }
+    writeln!(h, "#endif").unwrap();
+    h
+}
+
+fn json() -> String {
+    let mut j = String::new();
+    let list = |items: Vec<String>| items.join(", ");
+    writeln!(j, "{{").unwrap();
+    writeln!(j, "  \"comment\": \"{}\",", GENERATED).unwrap();
+    writeln!(j, "  \"protocol_version\": {},", wire::PROTOCOL_VERSION).unwrap();
+    writeln!(j, "  \"frame_start\": {},", parser::FRAME_START).unwrap();
+    writeln!(j, "  \"line_max\": {},", parser::LINE_MAX).unwrap();
+    writeln!(j, "  \"payload_max\": {},", parser::PAYLOAD_MAX).unwrap();
+    writeln!(j, "  \"frame_timeout_us\": {},", parser::FRAME_TIMEOUT_US).unwrap();
+    let bytes = |magic: &[u8]| list(magic.iter().map(|b| b.to_string()).collect());
+    writeln!(j, "  \"binary_magic\": [{}],", bytes(parser::BINARY_MAGIC)).unwrap();
+    writeln!(j, "  \"ascii_magic\": [{}],", bytes(parser::ASCII_MAGIC)).unwrap();
+    writeln!(j, "  \"num_channels\": {},", NUM_CHANNELS).unwrap();
+    writeln!(j, "  \"num_pulses_max\": {},", NUM_PULSES_MAX).unwrap();
+    writeln!(
+        j,
+        "  \"trigout_latency_cycles\": {},",
+        TRIGGER_OUT_LATENCY_CYCLES
+    )
+    .unwrap();
+    let programs = programs().into_iter().map(|program| {
+        format!(
+            "{{\"name\": \"{}\", \"words\": {}, \"latency\": {}, \"min_width\": {}, \"min_gap\": {}}}",
+            program.name, program.words, program.latency, program.min_width, program.min_gap
+        )
+    });
+    writeln!(j, "  \"programs\": [").unwrap();
+    let programs: Vec<String> = programs.collect();
+    for (i, program) in programs.iter().enumerate() {
+        let comma = if i + 1 < programs.len() { "," } else { "" };
+        writeln!(j, "    {}{}", program, comma).unwrap();
+    }
+    writeln!(j, "  ],").unwrap();
+    let frames = wire::FRAMES
+        .iter()
+        .map(|(name, id)| format!("\"{}\": {}", name, id));
+    writeln!(j, "  \"frames\": {{{}}},", list(frames.collect())).unwrap();
+    let sources = wire::ReadSource::ALL.iter().enumerate();
+    let sources = sources.map(|(i, source)| format!("\"{}\": {}", source.as_str(), i));
+    writeln!(j, "  \"read_sources\": {{{}}},", list(sources.collect())).unwrap();
+    let errors = wire::WireError::ALL.iter().map(|err| err.as_str());
+    let errors = errors.chain(parser::ParseError::ALL.iter().map(|err| err.as_str()));
+    let errors = errors.map(|err| format!("\"{}\"", err));
+    writeln!(j, "  \"errors\": [{}],", list(errors.collect())).unwrap();
+    writeln!(j, "  \"structs\": [").unwrap();
+    for (i, layout) in wire::LAYOUTS.iter().enumerate() {
+        let fields = layout.fields.iter().map(|field| {
+            format!(
+                "{{\"name\": \"{}\", \"offset\": {}, \"size\": {}}}",
+                field.name, field.offset, field.size
+            )
+        });
+        let comma = if i + 1 < wire::LAYOUTS.len() { "," } else { "" };
+        writeln!(
+            j,
+            "    {{\"name\": \"{}\", \"size\": {}, \"fields\": [{}]}}{}",
+            layout.name,
+            layout.size,
+            list(fields.collect()),
+            comma
+        )
+        .unwrap();
+    }
+    writeln!(j, "  ]").unwrap();
+    writeln!(j, "}}").unwrap();
+    j
+}
+
+#[test]
+fn host_files_match_the_firmware() {
+    let host = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../host");
+    let update = env::var_os("PICO_PULSE_UPDATE_PROTOCOL").is_some_and(|v| v == "1");
+    for (name, text) in [("pico_pulse.h", c_header()), ("pico_pulse.json", json())] {
+        let path = host.join(name);
+        if update {
+            fs::write(&path, text).unwrap();
+        } else {
+            assert!(
+                fs::read_to_string(&path).ok().as_deref() == Some(text.as_str()),
+                "host/{} is out of date with the firmware, rerun with \
+                 PICO_PULSE_UPDATE_PROTOCOL=1 and check it in",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,148 @@
+/* Generated by the host tests from src/wire.rs, src/parser.rs and src/pulse_generator.rs, do not edit */
+#ifndef PICO_PULSE_H
+#define PICO_PULSE_H
+
+#include <stdint.h>
+
+/* Framing, see parser.rs */
+#define PP_PROTOCOL_VERSION 0x11
+#define PP_FRAME_START 0x02
+#define PP_LINE_MAX 256
+#define PP_PAYLOAD_MAX 258
+#define PP_FRAME_TIMEOUT_US 500000
+#define PP_BINARY_MAGIC_LEN 5
+#define PP_BINARY_MAGIC {0x00, 0x50, 0x50, 0x42, 0x31}
+#define PP_ASCII_MAGIC_LEN 5
+#define PP_ASCII_MAGIC {0x2b, 0x2b, 0x2b, 0x0d, 0x0a}
+
+/* Capabilities, see CAPS? */
+#define PP_NUM_CHANNELS 2
+#define PP_NUM_PULSES_MAX 32
+#define PP_TRIGOUT_LATENCY_CYCLES 3
+
+/* Program variants: instruction words, trigger latency, shortest width and gap in cycles */
+#define PP_PROGRAM_TRIGOUT_WORDS 5
+#define PP_PROGRAM_TRIGOUT_LATENCY 3
+#define PP_PROGRAM_TRIGOUT_MIN_WIDTH 2
+#define PP_PROGRAM_TRIGOUT_MIN_GAP 3
+#define PP_PROGRAM_TRIGOUT_OD_WORDS 5
+#define PP_PROGRAM_TRIGOUT_OD_LATENCY 3
+#define PP_PROGRAM_TRIGOUT_OD_MIN_WIDTH 2
+#define PP_PROGRAM_TRIGOUT_OD_MIN_GAP 3
+#define PP_PROGRAM_PER_EDGE_WORDS 9
+#define PP_PROGRAM_PER_EDGE_LATENCY 7
+#define PP_PROGRAM_PER_EDGE_MIN_WIDTH 1
+#define PP_PROGRAM_PER_EDGE_MIN_GAP 3
+#define PP_PROGRAM_PER_EDGE_OD_WORDS 9
+#define PP_PROGRAM_PER_EDGE_OD_LATENCY 7
+#define PP_PROGRAM_PER_EDGE_OD_MIN_WIDTH 1
+#define PP_PROGRAM_PER_EDGE_OD_MIN_GAP 3
+#define PP_PROGRAM_MARKER_WORDS 12
+#define PP_PROGRAM_MARKER_LATENCY 7
+#define PP_PROGRAM_MARKER_MIN_WIDTH 2
+#define PP_PROGRAM_MARKER_MIN_GAP 3
+#define PP_PROGRAM_MARKER_OD_WORDS 12
+#define PP_PROGRAM_MARKER_OD_LATENCY 7
+#define PP_PROGRAM_MARKER_OD_MIN_WIDTH 2
+#define PP_PROGRAM_MARKER_OD_MIN_GAP 3
+#define PP_PROGRAM_DOUBLE_WORDS 13
+#define PP_PROGRAM_DOUBLE_LATENCY 7
+#define PP_PROGRAM_DOUBLE_MIN_WIDTH 2
+#define PP_PROGRAM_DOUBLE_MIN_GAP 2
+#define PP_PROGRAM_DOUBLE_OD_WORDS 13
+#define PP_PROGRAM_DOUBLE_OD_LATENCY 7
+#define PP_PROGRAM_DOUBLE_OD_MIN_WIDTH 2
+#define PP_PROGRAM_DOUBLE_OD_MIN_GAP 2
+#define PP_PROGRAM_SPLIT_WORDS 10
+#define PP_PROGRAM_SPLIT_LATENCY 8
+#define PP_PROGRAM_SPLIT_MIN_WIDTH 3
+#define PP_PROGRAM_SPLIT_MIN_GAP 4
+#define PP_PROGRAM_SPLIT_OD_WORDS 10
+#define PP_PROGRAM_SPLIT_OD_LATENCY 8
+#define PP_PROGRAM_SPLIT_OD_MIN_WIDTH 3
+#define PP_PROGRAM_SPLIT_OD_MIN_GAP 4
+#define PP_PROGRAM_WIDE_WORDS 9
+#define PP_PROGRAM_WIDE_LATENCY 7
+#define PP_PROGRAM_WIDE_MIN_WIDTH 2
+#define PP_PROGRAM_WIDE_MIN_GAP 3
+#define PP_PROGRAM_LONG_WORDS 13
+#define PP_PROGRAM_LONG_LATENCY 9
+#define PP_PROGRAM_LONG_MIN_WIDTH 1
+#define PP_PROGRAM_LONG_MIN_GAP 5
+#define PP_PROGRAM_LONG_OD_WORDS 13
+#define PP_PROGRAM_LONG_OD_LATENCY 9
+#define PP_PROGRAM_LONG_OD_MIN_WIDTH 1
+#define PP_PROGRAM_LONG_OD_MIN_GAP 5
+#define PP_PROGRAM_STANDARD_WORDS 8
+#define PP_PROGRAM_STANDARD_LATENCY 7
+#define PP_PROGRAM_STANDARD_MIN_WIDTH 1
+#define PP_PROGRAM_STANDARD_MIN_GAP 3
+#define PP_PROGRAM_STANDARD_OD_WORDS 8
+#define PP_PROGRAM_STANDARD_OD_LATENCY 7
+#define PP_PROGRAM_STANDARD_OD_MIN_WIDTH 1
+#define PP_PROGRAM_STANDARD_OD_MIN_GAP 3
+
+/* Command bytes */
+#define PP_FRAME_TABLE 0x01
+#define PP_FRAME_STREAM 0x02
+#define PP_FRAME_EXPERT_LOAD 0x03
+#define PP_FRAME_EXPERT_FEED 0x04
+#define PP_FRAME_POLL 0x05
+#define PP_FRAME_READ 0x06
+#define PP_FRAME_CHUNK 0x07
+
+/* Source bytes of a FRAME_READ */
+#define PP_READ_CAPS 0
+#define PP_READ_LOG 1
+#define PP_READ_TLOG 2
+#define PP_READ_SNAP 3
+
+/* Error names after "ERR " in a reply line */
+#define PP_ERR_PROTOCOL_VERSION "PROTOCOL_VERSION"
+#define PP_ERR_BAD_LENGTH "BAD_LENGTH"
+#define PP_ERR_UNKNOWN_FRAME "UNKNOWN_FRAME"
+#define PP_ERR_LINE_TOO_LONG "LINE_TOO_LONG"
+#define PP_ERR_FRAME_TOO_LONG "FRAME_TOO_LONG"
+#define PP_ERR_FRAME_TIMEOUT "FRAME_TIMEOUT"
+#define PP_ERR_NOT_TEXT "NOT_TEXT"
+
+/* Payload headers, little-endian and without padding */
+typedef struct {
+    uint8_t version;
+    uint8_t ch;
+} pp_channel_header;
+#define PP_CHANNEL_HEADER_LEN 2
+
+typedef struct {
+    uint32_t delay;
+    uint32_t width;
+} pp_pair;
+#define PP_PAIR_LEN 8
+
+typedef struct {
+    uint8_t version;
+    uint8_t sm;
+    uint8_t origin;
+    uint8_t wrap_target;
+    uint8_t wrap_source;
+    uint8_t side_set_bits;
+    uint8_t side_set_flags;
+    uint8_t pin_base;
+    uint8_t pin_count;
+} pp_expert_load_header;
+#define PP_EXPERT_LOAD_HEADER_LEN 9
+
+typedef struct {
+    uint8_t version;
+    uint8_t source;
+} pp_read_header;
+#define PP_READ_HEADER_LEN 2
+
+typedef struct {
+    uint8_t version;
+    uint8_t resend;
+    uint16_t chunk;
+} pp_chunk_header;
+#define PP_CHUNK_HEADER_LEN 4
+
+#endif
@@ -1,6 +1,9 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 20K
+    /* Channel configuration, lifetime counters, calibration, stored script
+       and persisted settings, see src/flash.rs */
+    CONFIG : ORIGIN = 0x10000000 + 2048K - 20K, LENGTH = 20K
     RAM   : ORIGIN = 0x20000000, LENGTH = 256K
 }
 
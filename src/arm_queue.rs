@@ -0,0 +1,161 @@
+// Arms typed at the port, queued so the command is answered at once and
+// the main loop carries them out between its USB polls, oldest first. A
+// disarm drops the queued arms that include one of the channels it stops,
+// they are still taken from the queue in turn and reported as cancelled.
+
+use arrayvec::ArrayVec;
+
+// An arm taken with PulseGenerator::request_arm() and carried out by
+// service_arm() from the main loop, so a command doesn't wait on stopping
+// and loading state machines
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ArmRequest {
+    Channel(usize),
+    All,
+    // Bit per member channel
+    Group(u32),
+}
+
+// Arms queued at once, push() refuses more
+pub const ARM_QUEUE_LEN: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ArmQueueFull;
+
+struct Queued {
+    request: ArmRequest,
+    // The channel whose disarm dropped it
+    cancelled: Option<usize>,
+}
+
+#[derive(Default)]
+pub struct ArmQueue {
+    // Oldest first
+    queued: ArrayVec<Queued, ARM_QUEUE_LEN>,
+}
+
+impl ArmQueue {
+    pub fn push(&mut self, request: ArmRequest) -> Result<(), ArmQueueFull> {
+        let queued = Queued {
+            request,
+            cancelled: None,
+        };
+        self.queued.try_push(queued).map_err(|_| ArmQueueFull)
+    }
+
+    // The oldest arm, with the channel whose disarm cancelled it if one did
+    pub fn pop(&mut self) -> Option<(ArmRequest, Option<usize>)> {
+        if self.queued.is_empty() {
+            return None;
+        }
+        let Queued { request, cancelled } = self.queued.remove(0);
+        Some((request, cancelled))
+    }
+
+    // Cancels the arms of any of `channels`, a bit per channel, with
+    // `members` giving the channels a request would arm. An arm keeps the
+    // first channel that cancelled it.
+    pub fn cancel(&mut self, channels: u32, members: impl Fn(ArmRequest) -> u32) {
+        for queued in &mut self.queued {
+            let hit = members(queued.request) & channels;
+            if hit != 0 && queued.cancelled.is_none() {
+                queued.cancelled = Some(hit.trailing_zeros() as usize);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: u32 = 0b11;
+
+    // Without sources or mirrors, ch0 and ch1
+    fn members(request: ArmRequest) -> u32 {
+        match request {
+            ArmRequest::Channel(ch) => 1 << ch,
+            ArmRequest::All => ALL,
+            ArmRequest::Group(members) => members,
+        }
+    }
+
+    fn drain(queue: &mut ArmQueue) -> Vec<(ArmRequest, Option<usize>)> {
+        core::iter::from_fn(|| queue.pop()).collect()
+    }
+
+    #[test]
+    fn arms_come_out_in_the_order_they_went_in() {
+        let mut queue = ArmQueue::default();
+        assert_eq!(queue.pop(), None);
+        let requests = [
+            ArmRequest::Channel(1),
+            ArmRequest::Group(0b11),
+            ArmRequest::Channel(0),
+            ArmRequest::All,
+        ];
+        for request in requests {
+            queue.push(request).unwrap();
+        }
+        let expected: Vec<_> = requests.iter().map(|&request| (request, None)).collect();
+        assert_eq!(drain(&mut queue), expected);
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn a_full_queue_refuses_the_next_arm() {
+        let mut queue = ArmQueue::default();
+        for _ in 0..ARM_QUEUE_LEN {
+            queue.push(ArmRequest::Channel(0)).unwrap();
+        }
+        assert_eq!(queue.push(ArmRequest::Channel(1)), Err(ArmQueueFull));
+        // Room again once the oldest is carried out
+        assert_eq!(queue.pop(), Some((ArmRequest::Channel(0), None)));
+        assert_eq!(queue.push(ArmRequest::Channel(1)), Ok(()));
+        assert_eq!(drain(&mut queue).len(), ARM_QUEUE_LEN);
+    }
+
+    #[test]
+    fn a_disarm_cancels_only_the_arms_including_its_channel() {
+        let mut queue = ArmQueue::default();
+        for request in [
+            ArmRequest::Channel(0),
+            ArmRequest::Channel(1),
+            ArmRequest::Group(0b11),
+            ArmRequest::All,
+        ] {
+            queue.push(request).unwrap();
+        }
+        queue.cancel(1 << 1, members);
+        assert_eq!(
+            drain(&mut queue),
+            [
+                (ArmRequest::Channel(0), None),
+                (ArmRequest::Channel(1), Some(1)),
+                (ArmRequest::Group(0b11), Some(1)),
+                (ArmRequest::All, Some(1)),
+            ]
+        );
+        // Arms queued after the disarm aren't affected by it
+        queue.cancel(1 << 0, members);
+        queue.push(ArmRequest::Channel(0)).unwrap();
+        assert_eq!(drain(&mut queue), [(ArmRequest::Channel(0), None)]);
+    }
+
+    #[test]
+    fn a_trip_cancels_everything_queued() {
+        let mut queue = ArmQueue::default();
+        queue.push(ArmRequest::Channel(1)).unwrap();
+        queue.push(ArmRequest::Channel(0)).unwrap();
+        // A disarm first keeps its channel as the reason
+        queue.cancel(1 << 1, members);
+        queue.cancel(ALL, members);
+        assert_eq!(
+            drain(&mut queue),
+            [
+                (ArmRequest::Channel(1), Some(1)),
+                (ArmRequest::Channel(0), Some(0)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,224 @@
+// Board specific setup, selected by exactly one cargo feature. The pulse
+// generator only ever sees the BoardPins handed over from here.
+
+use crate::command::LedMode;
+use crate::pulse_generator::BoardPins;
+#[cfg(any(feature = "pico", feature = "tiny2040"))]
+use embedded_hal::digital::OutputPin;
+
+#[cfg(not(any(
+    feature = "pico",
+    feature = "pico-w-less-led",
+    feature = "tiny2040",
+    feature = "generic"
+)))]
+compile_error!("enable one board feature: pico, pico-w-less-led, tiny2040 or generic");
+
+#[cfg(any(
+    all(
+        feature = "pico",
+        any(feature = "pico-w-less-led", feature = "tiny2040", feature = "generic")
+    ),
+    all(
+        feature = "pico-w-less-led",
+        any(feature = "tiny2040", feature = "generic")
+    ),
+    all(feature = "tiny2040", feature = "generic"),
+))]
+compile_error!("only one board feature can be enabled at a time");
+
+#[cfg(any(feature = "pico", feature = "pico-w-less-led"))]
+pub use rp_pico::{entry, hal, Pins};
+
+#[cfg(feature = "tiny2040")]
+pub use pimoroni_tiny2040::{entry, hal, Pins};
+
+#[cfg(feature = "generic")]
+pub use rp2040_hal::{self as hal, entry, gpio::Pins};
+
+// The BSPs bring their own second stage bootloader, for other boards this
+// assumes a W25Q080 compatible flash like the Pico's
+#[cfg(feature = "generic")]
+#[link_section = ".boot2"]
+#[used]
+pub static BOOT2: [u8; 256] = rp2040_boot2::BOOT_LOADER_W25Q080;
+
+use hal::{fugit::HertzU32, pac, sio::SioGpioBank0};
+
+// Every supported board runs from a 12MHz crystal
+pub const XTAL_FREQ: HertzU32 = HertzU32::MHz(12);
+
+#[cfg(any(feature = "pico", feature = "pico-w-less-led"))]
+pub const PINS: BoardPins = BoardPins {
+    // GPIO23 and up are wired to the power supply, the LED or the wireless
+    // chip of the Pico W
+    pio: &[
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22,
+    ],
+    default_outputs: [15, 16],
+};
+#[cfg(feature = "pico")]
+pub const USB_PRODUCT: &str = "Pico-Pulse";
+#[cfg(feature = "pico-w-less-led")]
+pub const USB_PRODUCT: &str = "Pico-Pulse W";
+
+#[cfg(feature = "tiny2040")]
+pub const PINS: BoardPins = BoardPins {
+    // The castellated pins, GPIO26 to 29 double as the ADC inputs
+    pio: &[0, 1, 2, 3, 4, 5, 6, 7, 26, 27, 28, 29],
+    default_outputs: [6, 7],
+};
+#[cfg(feature = "tiny2040")]
+pub const USB_PRODUCT: &str = "Pico-Pulse Tiny2040";
+
+// Listed at build time in PICO_PULSE_PINS, see build.rs. The channels start
+// on the first two pins after the trigger.
+#[cfg(feature = "generic")]
+pub const PINS: BoardPins = {
+    const PIO: &[u8] = include!(concat!(env!("OUT_DIR"), "/pio_pins.rs"));
+    BoardPins {
+        pio: PIO,
+        default_outputs: [PIO[1], PIO[2]],
+    }
+};
+#[cfg(feature = "generic")]
+pub const USB_PRODUCT: &str = "Pico-Pulse";
+
+// Button to ground that skips AUTOARM when held at power-on. The Pico's
+// BOOTSEL button is on the flash chip select, not a GPIO.
+#[cfg(any(feature = "pico", feature = "pico-w-less-led"))]
+pub const ARM_BUTTON: Option<u8> = None;
+// The BOOT button doubles as a user button
+#[cfg(feature = "tiny2040")]
+pub const ARM_BUTTON: Option<u8> = Some(23);
+// From PICO_PULSE_ARM_BUTTON, see build.rs
+#[cfg(feature = "generic")]
+pub const ARM_BUTTON: Option<u8> = include!(concat!(env!("OUT_DIR"), "/arm_button.rs"));
+
+// ADC input with VSYS behind a 1:3 divider, for PROTECT VSYS. The Pico W
+// shares GPIO29 with the wireless chip.
+#[cfg(feature = "pico")]
+pub const VSYS_ADC: Option<u8> = Some(3);
+#[cfg(not(feature = "pico"))]
+pub const VSYS_ADC: Option<u8> = None;
+
+// How long the LED stays on after a table went out in activity mode
+const ACTIVITY_FLASH_US: u64 = 100_000;
+// Half period of the blink while a protection trip is latched
+const FAULT_BLINK_US: u64 = 125_000;
+
+// Status LED, a no-op on boards without one
+pub struct Led {
+    mode: LedMode,
+    // Last state passed to activity(), a flash starts on its rising edge
+    emitted: bool,
+    off_at: u64,
+    #[cfg(feature = "pico")]
+    pin:
+        hal::gpio::Pin<hal::gpio::bank0::Gpio25, hal::gpio::FunctionSioOutput, hal::gpio::PullDown>,
+    // Green part of the RGB LED, active low
+    #[cfg(feature = "tiny2040")]
+    pin:
+        hal::gpio::Pin<hal::gpio::bank0::Gpio19, hal::gpio::FunctionSioOutput, hal::gpio::PullDown>,
+}
+
+impl Led {
+    pub fn set(&mut self, on: bool) {
+        #[cfg(feature = "pico")]
+        let _ = self.pin.set_state(on.into());
+        #[cfg(feature = "tiny2040")]
+        let _ = self.pin.set_state((!on).into());
+        #[cfg(not(any(feature = "pico", feature = "tiny2040")))]
+        let _ = on;
+    }
+
+    pub fn mode(&self) -> LedMode {
+        self.mode
+    }
+
+    // The LED is GPIO driven and can't follow the side-set, so activity is
+    // shown as a flash per table rather than the pulses themselves
+    pub fn set_mode(&mut self, mode: LedMode) {
+        self.mode = mode;
+        // A table that already went out doesn't flash
+        self.emitted = true;
+        self.set(mode == LedMode::Status);
+    }
+
+    // Polled from the main loop with whether the watched channel's table
+    // has gone out
+    pub fn activity(&mut self, emitted: bool, now: u64) {
+        if emitted && !self.emitted {
+            self.off_at = now + ACTIVITY_FLASH_US;
+            self.set(true);
+        } else if now >= self.off_at {
+            self.set(false);
+        }
+        self.emitted = emitted;
+    }
+
+    // Polled from the main loop instead of activity() while a protection
+    // trip is latched, set_mode() puts the mode back
+    pub fn fault(&mut self, now: u64) {
+        self.set((now / FAULT_BLINK_US) % 2 == 0);
+    }
+}
+
+// Whether ARM_BUTTON is held down. Switches the button's pad to a pull-up,
+// which stays on afterwards.
+pub fn arm_button_held() -> bool {
+    let Some(pin) = ARM_BUTTON else {
+        return false;
+    };
+    // Safety: only the button's pad and its input bit are touched
+    let pads = unsafe { &*pac::PADS_BANK0::ptr() };
+    let sio = unsafe { &*pac::SIO::ptr() };
+    pads.gpio(pin as usize)
+        .modify(|_, w| w.ie().set_bit().pde().clear_bit().pue().set_bit());
+    // Lets the pull-up charge the line, about 4us
+    cortex_m::asm::delay(1_000);
+    sio.gpio_in().read().bits() & (1 << pin) == 0
+}
+
+// Takes over the GPIO bank, hands the PIO pins to PIO0 and returns the LED
+pub fn init(
+    io: pac::IO_BANK0,
+    pads: pac::PADS_BANK0,
+    gpio: SioGpioBank0,
+    resets: &mut pac::RESETS,
+) -> Led {
+    #[allow(unused_variables)]
+    let pins = Pins::new(io, pads, gpio, resets);
+
+    // The pin list differs per board, so the function select is written
+    // directly rather than through the typed pins. PIO0 comes out of reset
+    // with every pin direction an input, so the pins stay high impedance on
+    // their pull-downs until PulseGenerator::new drives the outputs low.
+    let io = unsafe { &*pac::IO_BANK0::ptr() };
+    let pads = unsafe { &*pac::PADS_BANK0::ptr() };
+    for &pin in PINS.pio {
+        pads.gpio(pin as usize).modify(|_, w| {
+            w.ie()
+                .set_bit()
+                .od()
+                .clear_bit()
+                .pue()
+                .clear_bit()
+                .pde()
+                .set_bit()
+        });
+        io.gpio(pin as usize)
+            .gpio_ctrl()
+            .write(|w| w.funcsel().pio0());
+    }
+
+    Led {
+        mode: LedMode::Status,
+        emitted: false,
+        off_at: 0,
+        #[cfg(feature = "pico")]
+        pin: pins.led.into_push_pull_output(),
+        #[cfg(feature = "tiny2040")]
+        pin: pins.led_green.into_push_pull_output(),
+    }
+}
@@ -0,0 +1,1211 @@
+// Command decoding. ASCII lines are split on whitespace, keywords are case
+// insensitive. Binary frames carry a command byte and a versioned
+// little-endian payload, see wire.
+
+use crate::debugpin;
+use crate::features::Feature;
+use crate::interlock::Release;
+use crate::protect::Action;
+use crate::pulse_generator::{self, Edge, Level, Mirror, RetriggerPolicy, TestPattern};
+use crate::safestate::{self, Rest};
+use crate::script;
+use crate::time::{Rounding, PS_PER_MS, PS_PER_NS, PS_PER_S, PS_PER_US};
+use crate::usblog;
+#[cfg(feature = "binary-proto")]
+use crate::wire::{
+    self, ChannelHeader, ChunkHeader, ExpertLoadHeader, Pair, ReadHeader, ReadSource, WireError,
+    FRAME_CHUNK, FRAME_EXPERT_FEED, FRAME_EXPERT_LOAD, FRAME_POLL, FRAME_READ, FRAME_STREAM,
+    FRAME_TABLE, PAIR_LEN,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Value {
+    // Bare integer, taken as system clock cycles
+    Cycles(u64),
+    // Number with an ns/us/ms/s suffix
+    Picos(u64),
+}
+
+// Pulse table as received, decoded pair by pair when applied
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Table<'a> {
+    // "delay,width;delay,width;..."
+    Ascii(&'a str),
+    // Packed little-endian u32 (delay, width) cycle pairs
+    #[cfg(feature = "binary-proto")]
+    Binary(&'a [u8]),
+}
+
+// Raw PIO program as uploaded in expert mode
+#[cfg(feature = "binary-proto")]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RawProgram<'a> {
+    pub origin: Option<u8>,
+    pub wrap_target: u8,
+    pub wrap_source: u8,
+    // Side-set value bits, without the enable bit of an optional side-set
+    pub side_set_bits: u8,
+    pub side_set_optional: bool,
+    pub side_set_pindirs: bool,
+    pub pin_base: u8,
+    pub pin_count: u8,
+    // Little-endian u16 instruction words
+    pub code: &'a [u8],
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Target<'a> {
+    Channel(usize),
+    All,
+    // Named with GROUP
+    Group(&'a str),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LedMode {
+    // On while the firmware runs
+    Status,
+    // Flashes each time the channel's table has gone out
+    Activity(usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command<'a> {
+    Delay(usize, Value),
+    Width(usize, Value),
+    // Pulse with the levels of the pin pair in wide mode
+    Pulse(usize, Value, Value, u8),
+    Wide(usize, bool),
+    Tristate(usize, bool),
+    Compensate(usize, bool),
+    // A pulse per qualifying trigger edge instead of the table per edge
+    PerEdge(usize, bool),
+    // Output level between pulses and at rest, None for the output mode's own
+    GapLevel(usize, Option<Level>),
+    IdleLevel(usize, Option<Level>),
+    Retrigger(usize, RetriggerPolicy),
+    // Input level of a GPIO
+    PinQuery(u8),
+    // Count a GPIO's transitions for the given ms
+    Watch(u8, u32),
+    // Single pulse of the given width, emitted right away
+    Ping(usize, Value),
+    // Built-in pattern run over and over, None stops it. No channel stops
+    // every channel's.
+    TestPattern(Option<usize>, Option<TestPattern>),
+    // Pre and post margins of the marker output, None turns it off
+    Marker(usize, Option<(Value, Value)>),
+    // Trigger on every nth edge
+    Divider(usize, u32),
+    // Pulse width copying every trigger edge, None returns to the table
+    TriggerOut(usize, Option<Value>),
+    // Tick pin, period and the channels it triggers, None stops the ticks
+    Internal(Option<(u8, Value, Target<'a>)>),
+    InternalQuery,
+    // Trigger edge timestamps on a spare SM
+    Tlog(bool),
+    TlogQuery,
+    // Count trigger pulses shorter than the width, None stops counting
+    Glitch(Option<Value>),
+    GlitchReset,
+    GlitchQuery,
+    // Port mode and framing counters
+    ModeQuery,
+    // Random value picked at power-on
+    SessionQuery,
+    // Whether the stored config was used at power-on
+    ConfigQuery,
+    // Everything arming the enabled channels would refuse, changing nothing
+    Check,
+    // Counts of the errors reported unasked, see throttle
+    ErrorQuery,
+    // Time the power-on stages took
+    BootQuery,
+    // Static buffer sizes and stack depth, see mem
+    MemQuery,
+    // Lifetime counters, see lifetime
+    LifetimeQuery,
+    LifetimeSave,
+    // None asks for the token that confirms the reset
+    LifetimeReset(Option<u32>),
+    // *RST and a final save of the lifetime counters, ahead of a power-off
+    Shutdown,
+    // Measured rise skew between the channels
+    Skew,
+    // Pulses, high time and duration of the channel's runs since its arm
+    RunStat(usize),
+    // Compact status of every channel
+    Poll,
+    // Capability and per-channel program report
+    Capabilities,
+    // Instruction memory, state machines and DMA channels in use
+    Resources,
+    // Lines in the delay generator dialect until COMPAT DG OFF, see compat
+    Compat(bool),
+    // The whole configuration as SNAP lines that restore it
+    SnapQuery,
+    // Hex digits of a snapshot, collected until SnapEnd restores it
+    Snap(&'a str),
+    SnapEnd,
+    // Store the live configuration for power-on, or erase the stored one
+    SnapSave,
+    SnapClear,
+    // Arm and force-trigger a channel the given number of times
+    Stress(usize, u32),
+    // The same with a bulk DMA copy competing with the channel's refills
+    StressDma(usize, u32),
+    // Builds a two channel experiment from RESET over the commands and
+    // checks its outputs, see handlers::selftest
+    SelfTest,
+    // true for open drain, false for push-pull
+    OpenDrain(usize, bool),
+    Table(usize, Table<'a>),
+    // Table taking over once the running one went out
+    Next(usize, Table<'a>),
+    StreamStart(usize),
+    StreamEnd(usize),
+    // wire::Pair entries, whole ones, see parse_frame()
+    #[cfg(feature = "binary-proto")]
+    StreamBlock(usize, &'a [u8]),
+    Arm(Target<'a>),
+    Disarm(Target<'a>),
+    // Stored per channel, false leaves the channel out of every arm
+    Enable(usize, bool),
+    // Name and a bit per member channel, no members deletes the group
+    Group(&'a str, u32),
+    Groups,
+    // Output calibration, true for a negative offset
+    CalSet(usize, bool, Value),
+    CalClear,
+    CalQuery,
+    // Arming with the trigger already high: refused if true, reported if not
+    StuckTrigger(bool),
+    StuckTriggerQuery,
+    // Group, then its reference channel and the most lead, or None for no
+    // reference
+    T0(&'a str, Option<(usize, Option<Value>)>),
+    T0Query(&'a str),
+    // First delay relative to the group's T0, true before it
+    T0Offset(usize, Option<(bool, Value)>),
+    // Hold-off ahead of the channel's first delay, None for none
+    PreDelay(usize, Option<Value>),
+    PreDelayQuery(usize),
+    // Wait after a table went out before the firmware re-arms, None for none
+    RearmGuard(usize, Option<Value>),
+    RearmGuardQuery(usize),
+    Pin(usize, u8),
+    // Trigger input GPIO of the channel
+    TriggerPin(usize, u8),
+    // Trigger taken from another channel's output pin
+    TriggerFrom(usize, usize),
+    TriggerPinQuery(usize),
+    // Edge of a GPIO the channels waiting on it trigger on
+    TriggerEdge(u8, Edge),
+    TriggerEdgeQuery(u8),
+    // The channel copying its source's table on its own pin, None makes it
+    // independent again
+    Mirror(usize, Option<Mirror>),
+    MirrorQuery,
+    // Start editing a pending configuration
+    Stage,
+    // Validate the pending configuration and swap it in
+    Apply,
+    Discard,
+    // Return to the power-on state
+    Reset,
+    Debug(usize),
+    Program(usize),
+    // Predicted output edges of the channel's table
+    Timeline(usize),
+    // Requested against achieved durations of the channel's table
+    Achieved(usize),
+    // The DMA words the channel's next arm feeds to its SM
+    Words(usize),
+    Round(Rounding),
+    // Merge gaps below the program's minimum on arming instead of
+    // stretching them
+    Coalesce(bool),
+    // Recent command and arm latencies
+    Perf,
+    LedMode(LedMode),
+    // VID, PID and product string stored for the next enumeration
+    UsbId(u16, u16, &'a str),
+    UsbIdQuery,
+    // Drop off the bus and enumerate again, see usb
+    UsbReenum,
+    // Stored flag to arm the configured channels at power-on
+    AutoArm(bool),
+    AutoArmQuery,
+    // Stored flag for the READY line on each USB configuration
+    Banner(bool),
+    // Stored protection thresholds, whole degrees C and mV, None turns the
+    // check off
+    ProtectTemp(Option<u8>),
+    ProtectVsys(Option<u16>),
+    ProtectAction(Action),
+    // Unlatch a trip so channels can be armed again
+    ProtectClear,
+    ProtectQuery,
+    // Stored interlock input, None removes it
+    Interlock(Option<u8>),
+    InterlockRelease(Release),
+    // Release a latched trip once the input is high again
+    InterlockClear,
+    InterlockQuery,
+    // Take the following lines as CSV pairs for the channel's table, see csv
+    CsvLoad(usize),
+    // Stored debug pin and the events it pulses on, no pin turns it off
+    DebugPin(debugpin::Settings),
+    DebugPinQuery,
+    // Stored rest level of a pin, None leaves it to the channels. The flag
+    // allows HIGH on an active-high channel's pin.
+    SafeState(u8, Option<Rest>, bool),
+    SafeStateQuery,
+    // Lowest level of log line mirrored to the port, None stops it
+    LogUsb(Option<usblog::Level>),
+    LogQuery,
+    // Start recording the lines up to SCRIPT END as the stored script
+    ScriptBegin(script::Flags),
+    ScriptEnd,
+    // Stop recording, the stored script is left as it was
+    ScriptAbort,
+    ScriptClear,
+    ScriptQuery,
+    // Run the stored script
+    Run,
+    // Hold off a script's next line
+    Sleep(Value),
+    // Hold off a script's next line until the channel's run is over, or
+    // the timeout
+    WaitDone(usize, Value),
+    Expert(bool),
+    #[cfg(feature = "binary-proto")]
+    ExpertLoad(usize, RawProgram<'a>),
+    // Little-endian u32 words for the expert SM's FIFO
+    #[cfg(feature = "binary-proto")]
+    ExpertFeed(usize, &'a [u8]),
+    // Starts a chunked readback, see readback
+    #[cfg(feature = "binary-proto")]
+    ReadStart(ReadSource),
+    // The host got the chunk, the next one follows
+    #[cfg(feature = "binary-proto")]
+    ChunkAck(usize),
+    #[cfg(feature = "binary-proto")]
+    ChunkResend(usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CommandError {
+    Unknown,
+    MissingArgument,
+    TooManyArguments,
+    BadNumber,
+    MissingUnit,
+    BadUnit,
+    // Bare cycle count above u64::MAX
+    CyclesOutOfRange,
+    BadPair,
+    #[cfg(feature = "binary-proto")]
+    UnknownFrame,
+    #[cfg(feature = "binary-proto")]
+    BadLength,
+    BadName,
+    // Binary frame of another wire::PROTOCOL_VERSION
+    #[cfg(feature = "binary-proto")]
+    ProtocolVersion,
+    // Left out of this build
+    NotPresent(Feature),
+}
+
+impl CommandError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandError::Unknown => "UNKNOWN_COMMAND",
+            CommandError::MissingArgument => "MISSING_ARGUMENT",
+            CommandError::TooManyArguments => "TOO_MANY_ARGUMENTS",
+            CommandError::BadNumber => "BAD_NUMBER",
+            CommandError::MissingUnit => "MISSING_UNIT",
+            CommandError::BadUnit => "BAD_UNIT",
+            CommandError::CyclesOutOfRange => "OUT_OF_RANGE max 18446744073709551615 cyc",
+            CommandError::BadPair => "BAD_PAIR",
+            #[cfg(feature = "binary-proto")]
+            CommandError::UnknownFrame => WireError::UnknownFrame.as_str(),
+            #[cfg(feature = "binary-proto")]
+            CommandError::BadLength => WireError::Length.as_str(),
+            CommandError::BadName => "BAD_NAME",
+            #[cfg(feature = "binary-proto")]
+            CommandError::ProtocolVersion => WireError::Version.as_str(),
+            CommandError::NotPresent(Feature::Scpi) => "NOT_PRESENT scpi",
+            CommandError::NotPresent(Feature::BinaryProto) => "NOT_PRESENT binary-proto",
+            CommandError::NotPresent(Feature::Capture) => "NOT_PRESENT capture",
+            CommandError::NotPresent(Feature::FlashConfig) => "NOT_PRESENT flash-config",
+            CommandError::NotPresent(Feature::Selftest) => "NOT_PRESENT selftest",
+            CommandError::NotPresent(Feature::UsbLog) => "NOT_PRESENT usb-log",
+            CommandError::NotPresent(Feature::CompatDg) => "NOT_PRESENT compat-dg",
+        }
+    }
+}
+
+pub fn parse(line: &[u8]) -> Result<Command<'_>, CommandError> {
+    let line = core::str::from_utf8(line).map_err(|_| CommandError::Unknown)?;
+    let mut args = line.split_ascii_whitespace();
+    let keyword = args.next().ok_or(CommandError::Unknown)?;
+
+    let command = if keyword.eq_ignore_ascii_case("DELAY") {
+        Command::Delay(parse_channel(args.next())?, parse_value(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("WIDTH") {
+        Command::Width(parse_channel(args.next())?, parse_value(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("PULSE") {
+        let ch = parse_channel(args.next())?;
+        let delay = parse_value(args.next())?;
+        let width = parse_value(args.next())?;
+        Command::Pulse(ch, delay, width, parse_levels(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("WIDE") {
+        let ch = parse_channel(args.next())?;
+        Command::Wide(ch, parse_on_off(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("OUTPUT") {
+        let ch = parse_channel(args.next())?;
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("PUSHPULL") => Command::OpenDrain(ch, false),
+            Some(a) if a.eq_ignore_ascii_case("OPENDRAIN") => Command::OpenDrain(ch, true),
+            Some(_) => return Err(CommandError::Unknown),
+            None => return Err(CommandError::MissingArgument),
+        }
+    } else if keyword.eq_ignore_ascii_case("PIN?") {
+        let pin = args.next().ok_or(CommandError::MissingArgument)?;
+        Command::PinQuery(pin.parse().map_err(|_| CommandError::BadNumber)?)
+    } else if keyword.eq_ignore_ascii_case("WATCH") {
+        let pin = args.next().ok_or(CommandError::MissingArgument)?;
+        let pin = pin.parse().map_err(|_| CommandError::BadNumber)?;
+        let ms = args.next().ok_or(CommandError::MissingArgument)?;
+        Command::Watch(pin, ms.parse().map_err(|_| CommandError::BadNumber)?)
+    } else if keyword.eq_ignore_ascii_case("PING") {
+        Command::Ping(parse_channel(args.next())?, parse_value(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("TESTPAT") {
+        // "TESTPAT <ch> FAST|STAIRCASE|REF|OFF" or "TESTPAT OFF"
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("OFF") => Command::TestPattern(None, None),
+            ch => {
+                let ch = parse_channel(ch)?;
+                let pattern = match args.next() {
+                    Some(a) if a.eq_ignore_ascii_case("OFF") => None,
+                    Some(a) => Some(
+                        pulse_generator::TEST_PATTERNS
+                            .into_iter()
+                            .find(|pattern| a.eq_ignore_ascii_case(pattern.as_str()))
+                            .ok_or(CommandError::Unknown)?,
+                    ),
+                    None => return Err(CommandError::MissingArgument),
+                };
+                Command::TestPattern(Some(ch), pattern)
+            }
+        }
+    } else if keyword.eq_ignore_ascii_case("RETRIGGER") {
+        let ch = parse_channel(args.next())?;
+        let policy = match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("IGNORE") => RetriggerPolicy::Ignore,
+            Some(a) if a.eq_ignore_ascii_case("LATCH") => RetriggerPolicy::Latch,
+            Some(a) if a.eq_ignore_ascii_case("ABORT") => RetriggerPolicy::Abort,
+            Some(_) => return Err(CommandError::Unknown),
+            None => return Err(CommandError::MissingArgument),
+        };
+        Command::Retrigger(ch, policy)
+    } else if keyword.eq_ignore_ascii_case("MARKER") {
+        let ch = parse_channel(args.next())?;
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("OFF") => Command::Marker(ch, None),
+            pre => {
+                let pre = parse_value(pre)?;
+                Command::Marker(ch, Some((pre, parse_value(args.next())?)))
+            }
+        }
+    } else if keyword.eq_ignore_ascii_case("TRIGOUT") {
+        let ch = parse_channel(args.next())?;
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("OFF") => Command::TriggerOut(ch, None),
+            width => Command::TriggerOut(ch, Some(parse_value(width)?)),
+        }
+    } else if keyword.eq_ignore_ascii_case("INTERNAL") {
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("OFF") => Command::Internal(None),
+            pin => {
+                let pin = pin.ok_or(CommandError::MissingArgument)?;
+                let pin = pin.parse().map_err(|_| CommandError::BadNumber)?;
+                let period = parse_value(args.next())?;
+                Command::Internal(Some((pin, period, parse_target(args.next())?)))
+            }
+        }
+    } else if keyword.eq_ignore_ascii_case("INTERNAL?") {
+        Command::InternalQuery
+    } else if keyword.eq_ignore_ascii_case("TLOG") {
+        Command::Tlog(parse_on_off(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("TLOG?") {
+        Command::TlogQuery
+    } else if keyword.eq_ignore_ascii_case("GLITCH") {
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("OFF") => Command::Glitch(None),
+            Some(a) if a.eq_ignore_ascii_case("RESET") => Command::GlitchReset,
+            width => Command::Glitch(Some(parse_value(width)?)),
+        }
+    } else if keyword.eq_ignore_ascii_case("GLITCH?") {
+        Command::GlitchQuery
+    } else if keyword.eq_ignore_ascii_case("MODE?") {
+        Command::ModeQuery
+    } else if keyword.eq_ignore_ascii_case("SESSION?") {
+        Command::SessionQuery
+    } else if keyword.eq_ignore_ascii_case("BOOT?") {
+        Command::BootQuery
+    } else if keyword.eq_ignore_ascii_case("MEM?") {
+        Command::MemQuery
+    } else if keyword.eq_ignore_ascii_case("LIFETIME?") {
+        Command::LifetimeQuery
+    } else if keyword.eq_ignore_ascii_case("LIFETIME") {
+        // "LIFETIME SAVE", "LIFETIME RESET" then "LIFETIME RESET <token>"
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("SAVE") => Command::LifetimeSave,
+            Some(a) if a.eq_ignore_ascii_case("RESET") => match args.next() {
+                Some(token) => Command::LifetimeReset(Some(parse_hex32(Some(token))?)),
+                None => Command::LifetimeReset(None),
+            },
+            Some(_) => return Err(CommandError::Unknown),
+            None => return Err(CommandError::MissingArgument),
+        }
+    } else if keyword.eq_ignore_ascii_case("SHUTDOWN") {
+        Command::Shutdown
+    } else if keyword.eq_ignore_ascii_case("ERR?") {
+        Command::ErrorQuery
+    } else if keyword.eq_ignore_ascii_case("CHECK") {
+        Command::Check
+    } else if keyword.eq_ignore_ascii_case("CONFIG?") {
+        Command::ConfigQuery
+    } else if keyword.eq_ignore_ascii_case("RES?") {
+        Command::Resources
+    } else if keyword.eq_ignore_ascii_case("COMPAT") {
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("DG") => Command::Compat(parse_on_off(args.next())?),
+            Some(_) => return Err(CommandError::Unknown),
+            None => return Err(CommandError::MissingArgument),
+        }
+    } else if keyword.eq_ignore_ascii_case("SNAP?") {
+        Command::SnapQuery
+    } else if keyword.eq_ignore_ascii_case("SNAP") {
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("END") => Command::SnapEnd,
+            Some(a) if a.eq_ignore_ascii_case("SAVE") => Command::SnapSave,
+            Some(a) if a.eq_ignore_ascii_case("CLEAR") => Command::SnapClear,
+            hex => Command::Snap(parse_hex_bytes(hex)?),
+        }
+    } else if keyword.eq_ignore_ascii_case("SKEW?") {
+        Command::Skew
+    } else if keyword.eq_ignore_ascii_case("RUNSTAT?") {
+        Command::RunStat(parse_channel(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("POLL?") {
+        Command::Poll
+    } else if keyword.eq_ignore_ascii_case("COMPENSATE") {
+        let ch = parse_channel(args.next())?;
+        Command::Compensate(ch, parse_on_off(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("PEREDGE") {
+        let ch = parse_channel(args.next())?;
+        Command::PerEdge(ch, parse_on_off(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("GAP") {
+        let ch = parse_channel(args.next())?;
+        Command::GapLevel(ch, parse_level(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("IDLE") {
+        let ch = parse_channel(args.next())?;
+        Command::IdleLevel(ch, parse_level(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("DIVIDER") {
+        let ch = parse_channel(args.next())?;
+        let n = args.next().ok_or(CommandError::MissingArgument)?;
+        match n.parse() {
+            Ok(n) if n > 0 => Command::Divider(ch, n),
+            _ => return Err(CommandError::BadNumber),
+        }
+    } else if keyword.eq_ignore_ascii_case("STRESS") {
+        let ch = parse_channel(args.next())?;
+        let runs = args.next().ok_or(CommandError::MissingArgument)?;
+        let runs = runs.parse().map_err(|_| CommandError::BadNumber)?;
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("DMA") => Command::StressDma(ch, runs),
+            Some(_) => return Err(CommandError::Unknown),
+            None => Command::Stress(ch, runs),
+        }
+    } else if keyword.eq_ignore_ascii_case("SELFTEST") {
+        Command::SelfTest
+    } else if keyword.eq_ignore_ascii_case("CAP?") {
+        Command::Capabilities
+    } else if keyword.eq_ignore_ascii_case("TRISTATE") {
+        let ch = parse_channel(args.next())?;
+        Command::Tristate(ch, parse_on_off(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("TABLE") {
+        let ch = parse_channel(args.next())?;
+        let pairs = args.next().ok_or(CommandError::MissingArgument)?;
+        Command::Table(ch, Table::Ascii(pairs))
+    } else if keyword.eq_ignore_ascii_case("NEXT") {
+        let ch = parse_channel(args.next())?;
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("TABLE") => {}
+            Some(_) => return Err(CommandError::Unknown),
+            None => return Err(CommandError::MissingArgument),
+        }
+        let pairs = args.next().ok_or(CommandError::MissingArgument)?;
+        Command::Next(ch, Table::Ascii(pairs))
+    } else if keyword.eq_ignore_ascii_case("STREAM") {
+        let ch = parse_channel(args.next())?;
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("START") => Command::StreamStart(ch),
+            Some(a) if a.eq_ignore_ascii_case("END") => Command::StreamEnd(ch),
+            Some(_) => return Err(CommandError::Unknown),
+            None => return Err(CommandError::MissingArgument),
+        }
+    } else if keyword.eq_ignore_ascii_case("ARM") {
+        Command::Arm(parse_target(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("DISARM") {
+        Command::Disarm(parse_target(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("ENABLE") {
+        Command::Enable(parse_channel(args.next())?, true)
+    } else if keyword.eq_ignore_ascii_case("DISABLE") {
+        Command::Enable(parse_channel(args.next())?, false)
+    } else if keyword.eq_ignore_ascii_case("GROUP") {
+        let name = parse_name(args.next())?;
+        let mut members = 0u32;
+        for arg in args.by_ref() {
+            let ch = parse_channel(Some(arg))?;
+            members |= 1u32.checked_shl(ch as u32).ok_or(CommandError::BadNumber)?;
+        }
+        Command::Group(name, members)
+    } else if keyword.eq_ignore_ascii_case("GROUP?") {
+        Command::Groups
+    } else if keyword.eq_ignore_ascii_case("CAL") {
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("SET") => {
+                let ch = parse_channel(args.next())?;
+                let offset = args.next().ok_or(CommandError::MissingArgument)?;
+                match offset.strip_prefix('-') {
+                    Some(offset) => Command::CalSet(ch, true, parse_value(Some(offset))?),
+                    None => Command::CalSet(ch, false, parse_value(Some(offset))?),
+                }
+            }
+            Some(a) if a.eq_ignore_ascii_case("CLEAR") => Command::CalClear,
+            Some(_) => return Err(CommandError::Unknown),
+            None => return Err(CommandError::MissingArgument),
+        }
+    } else if keyword.eq_ignore_ascii_case("CAL?") {
+        Command::CalQuery
+    } else if keyword.eq_ignore_ascii_case("STUCKTRIG") {
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("WARN") => Command::StuckTrigger(false),
+            Some(a) if a.eq_ignore_ascii_case("REFUSE") => Command::StuckTrigger(true),
+            Some(_) => return Err(CommandError::Unknown),
+            None => return Err(CommandError::MissingArgument),
+        }
+    } else if keyword.eq_ignore_ascii_case("STUCKTRIG?") {
+        Command::StuckTriggerQuery
+    } else if keyword.eq_ignore_ascii_case("T0") {
+        let name = parse_name(args.next())?;
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("OFF") => Command::T0(name, None),
+            ch => {
+                let ch = parse_channel(ch)?;
+                let lead_max = args.next().map(|a| parse_value(Some(a))).transpose()?;
+                Command::T0(name, Some((ch, lead_max)))
+            }
+        }
+    } else if keyword.eq_ignore_ascii_case("T0?") {
+        Command::T0Query(parse_name(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("OFFSET") {
+        let ch = parse_channel(args.next())?;
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("OFF") => Command::T0Offset(ch, None),
+            Some(a) => match a.strip_prefix('-') {
+                Some(a) => Command::T0Offset(ch, Some((true, parse_value(Some(a))?))),
+                None => Command::T0Offset(ch, Some((false, parse_value(Some(a))?))),
+            },
+            None => return Err(CommandError::MissingArgument),
+        }
+    } else if keyword.eq_ignore_ascii_case("PREDELAY") {
+        let ch = parse_channel(args.next())?;
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("OFF") => Command::PreDelay(ch, None),
+            a => Command::PreDelay(ch, Some(parse_value(a)?)),
+        }
+    } else if keyword.eq_ignore_ascii_case("PREDELAY?") {
+        Command::PreDelayQuery(parse_channel(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("GUARD") {
+        let ch = parse_channel(args.next())?;
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("OFF") => Command::RearmGuard(ch, None),
+            a => Command::RearmGuard(ch, Some(parse_value(a)?)),
+        }
+    } else if keyword.eq_ignore_ascii_case("GUARD?") {
+        Command::RearmGuardQuery(parse_channel(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("PIN") {
+        let ch = parse_channel(args.next())?;
+        let pin = args.next().ok_or(CommandError::MissingArgument)?;
+        Command::Pin(ch, pin.parse().map_err(|_| CommandError::BadNumber)?)
+    } else if keyword.eq_ignore_ascii_case("TRIGPIN") {
+        // "TRIGPIN <ch> <pin>" or "TRIGPIN <ch> FROM <source>"
+        let ch = parse_channel(args.next())?;
+        let pin = args.next().ok_or(CommandError::MissingArgument)?;
+        if pin.eq_ignore_ascii_case("FROM") {
+            Command::TriggerFrom(ch, parse_channel(args.next())?)
+        } else {
+            Command::TriggerPin(ch, pin.parse().map_err(|_| CommandError::BadNumber)?)
+        }
+    } else if keyword.eq_ignore_ascii_case("TRIGPIN?") {
+        Command::TriggerPinQuery(parse_channel(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("TRIGEDGE") {
+        let pin = args.next().ok_or(CommandError::MissingArgument)?;
+        let pin = pin.parse().map_err(|_| CommandError::BadNumber)?;
+        let edge = match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("RISING") => Edge::Rising,
+            Some(a) if a.eq_ignore_ascii_case("FALLING") => Edge::Falling,
+            Some(_) => return Err(CommandError::Unknown),
+            None => return Err(CommandError::MissingArgument),
+        };
+        Command::TriggerEdge(pin, edge)
+    } else if keyword.eq_ignore_ascii_case("TRIGEDGE?") {
+        let pin = args.next().ok_or(CommandError::MissingArgument)?;
+        Command::TriggerEdgeQuery(pin.parse().map_err(|_| CommandError::BadNumber)?)
+    } else if keyword.eq_ignore_ascii_case("MIRROR") {
+        // "MIRROR <source> -> <ch> [INVERT]" or "MIRROR <ch> OFF"
+        let first = parse_channel(args.next())?;
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("OFF") => Command::Mirror(first, None),
+            Some("->") => {
+                let ch = parse_channel(args.next())?;
+                let invert = match args.next() {
+                    Some(a) if a.eq_ignore_ascii_case("INVERT") => true,
+                    Some(_) => return Err(CommandError::Unknown),
+                    None => false,
+                };
+                Command::Mirror(
+                    ch,
+                    Some(Mirror {
+                        source: first,
+                        invert,
+                    }),
+                )
+            }
+            Some(_) => return Err(CommandError::Unknown),
+            None => return Err(CommandError::MissingArgument),
+        }
+    } else if keyword.eq_ignore_ascii_case("MIRROR?") {
+        Command::MirrorQuery
+    } else if keyword.eq_ignore_ascii_case("STAGE") {
+        Command::Stage
+    } else if keyword.eq_ignore_ascii_case("APPLY") {
+        Command::Apply
+    } else if keyword.eq_ignore_ascii_case("DISCARD") {
+        Command::Discard
+    } else if keyword.eq_ignore_ascii_case("*RST") && !Feature::Scpi.present() {
+        return Err(CommandError::NotPresent(Feature::Scpi));
+    } else if keyword.eq_ignore_ascii_case("*RST") || keyword.eq_ignore_ascii_case("RESET") {
+        Command::Reset
+    } else if keyword.eq_ignore_ascii_case("DBG?") {
+        Command::Debug(parse_channel(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("PROG?") {
+        Command::Program(parse_channel(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("DUR?") {
+        Command::Timeline(parse_channel(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("ACHIEVED?") {
+        Command::Achieved(parse_channel(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("WORDS?") {
+        Command::Words(parse_channel(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("LEDMODE") {
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("STATUS") => Command::LedMode(LedMode::Status),
+            Some(a) if a.eq_ignore_ascii_case("ACTIVITY") => {
+                Command::LedMode(LedMode::Activity(parse_channel(args.next())?))
+            }
+            Some(_) => return Err(CommandError::Unknown),
+            None => return Err(CommandError::MissingArgument),
+        }
+    } else if keyword.eq_ignore_ascii_case("USBID") {
+        let vid = parse_hex16(args.next())?;
+        let pid = parse_hex16(args.next())?;
+        let product = args.next().ok_or(CommandError::MissingArgument)?;
+        // A wrong identity can leave the host without a driver for the
+        // port, so the change has to be confirmed on the same line
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("CONFIRM") => Command::UsbId(vid, pid, product),
+            Some(_) => return Err(CommandError::Unknown),
+            None => return Err(CommandError::MissingArgument),
+        }
+    } else if keyword.eq_ignore_ascii_case("USB") {
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("REENUM") => Command::UsbReenum,
+            Some(_) => return Err(CommandError::Unknown),
+            None => return Err(CommandError::MissingArgument),
+        }
+    } else if keyword.eq_ignore_ascii_case("USBID?") {
+        Command::UsbIdQuery
+    } else if keyword.eq_ignore_ascii_case("AUTOARM") {
+        Command::AutoArm(parse_on_off(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("AUTOARM?") {
+        Command::AutoArmQuery
+    } else if keyword.eq_ignore_ascii_case("BANNER") {
+        Command::Banner(parse_on_off(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("PROTECT") {
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("TEMP") => match args.next() {
+                Some(a) if a.eq_ignore_ascii_case("OFF") => Command::ProtectTemp(None),
+                max => {
+                    let max = max.ok_or(CommandError::MissingArgument)?;
+                    Command::ProtectTemp(Some(max.parse().map_err(|_| CommandError::BadNumber)?))
+                }
+            },
+            Some(a) if a.eq_ignore_ascii_case("VSYS") => match args.next() {
+                Some(a) if a.eq_ignore_ascii_case("OFF") => Command::ProtectVsys(None),
+                min => Command::ProtectVsys(Some(parse_millis(min)?)),
+            },
+            Some(a) if a.eq_ignore_ascii_case("ACTION") => match args.next() {
+                Some(a) if a.eq_ignore_ascii_case("WARN") => Command::ProtectAction(Action::Warn),
+                Some(a) if a.eq_ignore_ascii_case("DISARM") => {
+                    Command::ProtectAction(Action::Disarm)
+                }
+                Some(_) => return Err(CommandError::Unknown),
+                None => return Err(CommandError::MissingArgument),
+            },
+            Some(a) if a.eq_ignore_ascii_case("CLEAR") => Command::ProtectClear,
+            Some(_) => return Err(CommandError::Unknown),
+            None => return Err(CommandError::MissingArgument),
+        }
+    } else if keyword.eq_ignore_ascii_case("PROTECT?") {
+        Command::ProtectQuery
+    } else if keyword.eq_ignore_ascii_case("INTERLOCK") {
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("OFF") => Command::Interlock(None),
+            Some(a) if a.eq_ignore_ascii_case("LATCH") => Command::InterlockRelease(Release::Latch),
+            Some(a) if a.eq_ignore_ascii_case("MOMENTARY") => {
+                Command::InterlockRelease(Release::Momentary)
+            }
+            Some(a) if a.eq_ignore_ascii_case("CLEAR") => Command::InterlockClear,
+            Some(pin) => {
+                Command::Interlock(Some(pin.parse().map_err(|_| CommandError::BadNumber)?))
+            }
+            None => return Err(CommandError::MissingArgument),
+        }
+    } else if keyword.eq_ignore_ascii_case("INTERLOCK?") {
+        Command::InterlockQuery
+    } else if keyword.eq_ignore_ascii_case("CSVLOAD") {
+        Command::CsvLoad(parse_channel(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("DEBUGPIN") {
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("OFF") => {
+                Command::DebugPin(debugpin::Settings::default())
+            }
+            Some(pin) => {
+                let pin = pin.parse().map_err(|_| CommandError::BadNumber)?;
+                let mut events = 0;
+                for name in args.by_ref() {
+                    events |= debugpin::EVENT_NAMES
+                        .iter()
+                        .find(|(_, known)| name.eq_ignore_ascii_case(known))
+                        .ok_or(CommandError::Unknown)?
+                        .0;
+                }
+                if events == 0 {
+                    return Err(CommandError::MissingArgument);
+                }
+                Command::DebugPin(debugpin::Settings {
+                    pin: Some(pin),
+                    events,
+                })
+            }
+            None => return Err(CommandError::MissingArgument),
+        }
+    } else if keyword.eq_ignore_ascii_case("DEBUGPIN?") {
+        Command::DebugPinQuery
+    } else if keyword.eq_ignore_ascii_case("SAFESTATE") {
+        // "SAFESTATE <pin> LOW|HIGH|TRISTATE|OFF [FORCE]"
+        let pin = args.next().ok_or(CommandError::MissingArgument)?;
+        let pin = pin.parse().map_err(|_| CommandError::BadNumber)?;
+        let rest = match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("OFF") => None,
+            Some(a) => Some(
+                safestate::RESTS
+                    .into_iter()
+                    .find(|rest| a.eq_ignore_ascii_case(rest.as_str()))
+                    .ok_or(CommandError::Unknown)?,
+            ),
+            None => return Err(CommandError::MissingArgument),
+        };
+        let force = match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("FORCE") => true,
+            Some(_) => return Err(CommandError::Unknown),
+            None => false,
+        };
+        Command::SafeState(pin, rest, force)
+    } else if keyword.eq_ignore_ascii_case("SAFESTATE?") {
+        Command::SafeStateQuery
+    } else if keyword.eq_ignore_ascii_case("LOG") {
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("USB") => {}
+            Some(_) => return Err(CommandError::Unknown),
+            None => return Err(CommandError::MissingArgument),
+        }
+        if !parse_on_off(args.next())? {
+            Command::LogUsb(None)
+        } else {
+            let level = match args.next() {
+                None => usblog::Level::Info,
+                Some(name) => *usblog::LEVELS
+                    .iter()
+                    .find(|level| name.eq_ignore_ascii_case(level.as_str()))
+                    .ok_or(CommandError::Unknown)?,
+            };
+            Command::LogUsb(Some(level))
+        }
+    } else if keyword.eq_ignore_ascii_case("LOG?") {
+        Command::LogQuery
+    } else if keyword.eq_ignore_ascii_case("SCRIPT") {
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("BEGIN") => {
+                let mut flags = script::Flags::default();
+                for flag in args.by_ref() {
+                    if flag.eq_ignore_ascii_case("BOOT") {
+                        flags.boot = true;
+                    } else if flag.eq_ignore_ascii_case("UNSAFE") {
+                        flags.unrestricted = true;
+                    } else {
+                        return Err(CommandError::Unknown);
+                    }
+                }
+                Command::ScriptBegin(flags)
+            }
+            Some(a) if a.eq_ignore_ascii_case("END") => Command::ScriptEnd,
+            Some(a) if a.eq_ignore_ascii_case("ABORT") => Command::ScriptAbort,
+            Some(a) if a.eq_ignore_ascii_case("CLEAR") => Command::ScriptClear,
+            Some(_) => return Err(CommandError::Unknown),
+            None => return Err(CommandError::MissingArgument),
+        }
+    } else if keyword.eq_ignore_ascii_case("SCRIPT?") {
+        Command::ScriptQuery
+    } else if keyword.eq_ignore_ascii_case("RUN") {
+        Command::Run
+    } else if keyword.eq_ignore_ascii_case("SLEEP") {
+        Command::Sleep(parse_value(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("WAITDONE") {
+        let ch = parse_channel(args.next())?;
+        Command::WaitDone(ch, parse_value(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("PERF?") {
+        Command::Perf
+    } else if keyword.eq_ignore_ascii_case("ROUND") {
+        match args.next() {
+            Some(a) if a.eq_ignore_ascii_case("NEAREST") => Command::Round(Rounding::Nearest),
+            Some(a) if a.eq_ignore_ascii_case("DOWN") => Command::Round(Rounding::Down),
+            Some(a) if a.eq_ignore_ascii_case("UP") => Command::Round(Rounding::Up),
+            Some(_) => return Err(CommandError::Unknown),
+            None => return Err(CommandError::MissingArgument),
+        }
+    } else if keyword.eq_ignore_ascii_case("EXPERT") {
+        Command::Expert(parse_on_off(args.next())?)
+    } else if keyword.eq_ignore_ascii_case("COALESCE") {
+        Command::Coalesce(parse_on_off(args.next())?)
+    } else {
+        return Err(CommandError::Unknown);
+    };
+
+    if args.next().is_some() {
+        return Err(CommandError::TooManyArguments);
+    }
+    Ok(command)
+}
+
+// Payload layouts are in wire
+#[cfg(feature = "binary-proto")]
+pub fn parse_frame(cmd: u8, payload: &[u8]) -> Result<Command<'_>, CommandError> {
+    let word_len = match cmd {
+        FRAME_TABLE | FRAME_STREAM => PAIR_LEN,
+        FRAME_EXPERT_FEED => 4,
+        FRAME_EXPERT_LOAD => return parse_raw_program(payload),
+        FRAME_POLL => {
+            wire::decode_bare(payload)?;
+            return Ok(Command::Poll);
+        }
+        FRAME_READ => {
+            let header = ReadHeader::decode(payload)?;
+            let source = ReadSource::from_u8(header.source).ok_or(CommandError::BadNumber)?;
+            return Ok(Command::ReadStart(source));
+        }
+        FRAME_CHUNK => {
+            let header = ChunkHeader::decode(payload)?;
+            let chunk = header.chunk as usize;
+            return match header.resend {
+                0 => Ok(Command::ChunkAck(chunk)),
+                1 => Ok(Command::ChunkResend(chunk)),
+                _ => Err(CommandError::BadNumber),
+            };
+        }
+        _ => return Err(WireError::UnknownFrame.into()),
+    };
+    let (header, data) = ChannelHeader::decode(payload)?;
+    let ch = header.ch as usize;
+    if data.is_empty() || data.len() % word_len != 0 {
+        return Err(CommandError::BadLength);
+    }
+    match cmd {
+        FRAME_TABLE => Ok(Command::Table(ch, Table::Binary(data))),
+        FRAME_STREAM => Ok(Command::StreamBlock(ch, data)),
+        _ => Ok(Command::ExpertFeed(ch, data)),
+    }
+}
+
+#[cfg(feature = "binary-proto")]
+fn parse_raw_program(payload: &[u8]) -> Result<Command<'_>, CommandError> {
+    let (header, code) = ExpertLoadHeader::decode(payload)?;
+    if code.is_empty() || code.len() % 2 != 0 || code.len() > 2 * 32 {
+        return Err(CommandError::BadLength);
+    }
+    Ok(Command::ExpertLoad(
+        header.sm as usize,
+        RawProgram {
+            origin: (header.origin != 0xff).then_some(header.origin),
+            wrap_target: header.wrap_target,
+            wrap_source: header.wrap_source,
+            side_set_bits: header.side_set_bits,
+            side_set_optional: header.side_set_flags & 0x01 != 0,
+            side_set_pindirs: header.side_set_flags & 0x02 != 0,
+            pin_base: header.pin_base,
+            pin_count: header.pin_count,
+            code,
+        },
+    ))
+}
+
+#[cfg(feature = "binary-proto")]
+impl From<WireError> for CommandError {
+    fn from(err: WireError) -> Self {
+        match err {
+            WireError::Version => CommandError::ProtocolVersion,
+            WireError::Length => CommandError::BadLength,
+            WireError::UnknownFrame => CommandError::UnknownFrame,
+        }
+    }
+}
+
+#[cfg(feature = "binary-proto")]
+impl RawProgram<'_> {
+    pub fn to_program(&self) -> pio::Program<32> {
+        let mut code = pio::ArrayVec::new();
+        for word in self.code.chunks_exact(2) {
+            code.push(u16::from_le_bytes([word[0], word[1]]));
+        }
+        pio::Program {
+            code,
+            origin: self.origin,
+            wrap: pio::Wrap {
+                source: self.wrap_source,
+                target: self.wrap_target,
+            },
+            side_set: pio::SideSet::new(
+                self.side_set_optional,
+                self.side_set_bits,
+                self.side_set_pindirs,
+            ),
+        }
+    }
+}
+
+impl<'a> Table<'a> {
+    pub fn pairs(&self) -> Pairs<'a> {
+        Pairs { table: *self }
+    }
+}
+
+pub struct Pairs<'a> {
+    table: Table<'a>,
+}
+
+impl Iterator for Pairs<'_> {
+    type Item = Result<(Value, Value), CommandError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.table {
+            Table::Ascii(s) => {
+                // Empty segments, e.g. from a trailing ';', are skipped
+                let pair = loop {
+                    if s.is_empty() {
+                        return None;
+                    }
+                    let (pair, rest) = s.split_once(';').unwrap_or((s, ""));
+                    *s = rest;
+                    if !pair.is_empty() {
+                        break pair;
+                    }
+                };
+                Some(match pair.split_once(',') {
+                    Some((delay, width)) => parse_value(Some(delay))
+                        .and_then(|delay| Ok((delay, parse_value(Some(width))?))),
+                    None => Err(CommandError::BadPair),
+                })
+            }
+            #[cfg(feature = "binary-proto")]
+            Table::Binary(b) => {
+                if b.len() < PAIR_LEN {
+                    return None;
+                }
+                let (pair, rest) = b.split_at(PAIR_LEN);
+                *b = rest;
+                let pair = Pair::decode(pair);
+                Some(Ok((
+                    Value::Cycles(pair.delay as u64),
+                    Value::Cycles(pair.width as u64),
+                )))
+            }
+        }
+    }
+}
+
+fn parse_channel(arg: Option<&str>) -> Result<usize, CommandError> {
+    let arg = arg.ok_or(CommandError::MissingArgument)?;
+    arg.parse().map_err(|_| CommandError::BadNumber)
+}
+
+fn parse_on_off(arg: Option<&str>) -> Result<bool, CommandError> {
+    match arg {
+        Some(a) if a.eq_ignore_ascii_case("ON") => Ok(true),
+        Some(a) if a.eq_ignore_ascii_case("OFF") => Ok(false),
+        Some(_) => Err(CommandError::Unknown),
+        None => Err(CommandError::MissingArgument),
+    }
+}
+
+// "LOW", "HIGH" or "DEFAULT" for the output mode's own level
+fn parse_level(arg: Option<&str>) -> Result<Option<Level>, CommandError> {
+    match arg {
+        Some(a) if a.eq_ignore_ascii_case("LOW") => Ok(Some(Level::Low)),
+        Some(a) if a.eq_ignore_ascii_case("HIGH") => Ok(Some(Level::High)),
+        Some(a) if a.eq_ignore_ascii_case("DEFAULT") => Ok(None),
+        Some(_) => Err(CommandError::Unknown),
+        None => Err(CommandError::MissingArgument),
+    }
+}
+
+// Decimal with up to three places in thousandths, e.g. "4.5" for 4500
+fn parse_millis(arg: Option<&str>) -> Result<u16, CommandError> {
+    let arg = arg.ok_or(CommandError::MissingArgument)?;
+    let (int, frac) = arg.split_once('.').unwrap_or((arg, ""));
+    if int.is_empty() || frac.len() > 3 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(CommandError::BadNumber);
+    }
+    let int: u16 = int.parse().map_err(|_| CommandError::BadNumber)?;
+    let frac = frac
+        .bytes()
+        .chain(core::iter::repeat(b'0'))
+        .take(3)
+        .fold(0, |n, b| n * 10 + (b - b'0') as u16);
+    int.checked_mul(1_000)
+        .and_then(|n| n.checked_add(frac))
+        .ok_or(CommandError::BadNumber)
+}
+
+// Two binary digits, the right one for the base pin, e.g. "10"
+fn parse_levels(arg: Option<&str>) -> Result<u8, CommandError> {
+    let arg = arg.ok_or(CommandError::MissingArgument)?;
+    if arg.len() != 2 {
+        return Err(CommandError::BadNumber);
+    }
+    u8::from_str_radix(arg, 2).map_err(|_| CommandError::BadNumber)
+}
+
+// Exactly four hex digits as USB IDs are written
+fn parse_hex16(arg: Option<&str>) -> Result<u16, CommandError> {
+    let arg = arg.ok_or(CommandError::MissingArgument)?;
+    if arg.len() != 4 {
+        return Err(CommandError::BadNumber);
+    }
+    u16::from_str_radix(arg, 16).map_err(|_| CommandError::BadNumber)
+}
+
+fn parse_hex32(arg: Option<&str>) -> Result<u32, CommandError> {
+    let arg = arg.ok_or(CommandError::MissingArgument)?;
+    if arg.len() != 8 {
+        return Err(CommandError::BadNumber);
+    }
+    u32::from_str_radix(arg, 16).map_err(|_| CommandError::BadNumber)
+}
+
+// An even number of hex digits
+fn parse_hex_bytes(arg: Option<&str>) -> Result<&str, CommandError> {
+    let arg = arg.ok_or(CommandError::MissingArgument)?;
+    if arg.len() % 2 != 0 || !arg.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(CommandError::BadNumber);
+    }
+    Ok(arg)
+}
+
+fn parse_target(arg: Option<&str>) -> Result<Target<'_>, CommandError> {
+    match arg {
+        Some(a) if a.eq_ignore_ascii_case("ALL") => Ok(Target::All),
+        Some(a) if a.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+            Ok(Target::Group(parse_name(arg)?))
+        }
+        _ => Ok(Target::Channel(parse_channel(arg)?)),
+    }
+}
+
+// Group names start with a letter and can't be taken for ALL or a channel
+fn parse_name(arg: Option<&str>) -> Result<&str, CommandError> {
+    let arg = arg.ok_or(CommandError::MissingArgument)?;
+    let valid = arg.starts_with(|c: char| c.is_ascii_alphabetic())
+        && arg.chars().all(|c| c.is_ascii_alphanumeric())
+        && !arg.eq_ignore_ascii_case("ALL");
+    if !valid {
+        return Err(CommandError::BadName);
+    }
+    Ok(arg)
+}
+
+fn parse_value(arg: Option<&str>) -> Result<Value, CommandError> {
+    let arg = arg.ok_or(CommandError::MissingArgument)?;
+    let split = arg
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(arg.len());
+    let (number, unit) = arg.split_at(split);
+    parse_number(number, unit)
+}
+
+// `number` with a ns/us/ms/s `unit`, or a cycle count without one
+pub fn parse_number(number: &str, unit: &str) -> Result<Value, CommandError> {
+    let unit_ps = match unit {
+        "" => {
+            if number.contains('.') {
+                return Err(CommandError::MissingUnit);
+            }
+            // Only digits are left here, so a failed parse is an overflow
+            return match number.parse() {
+                Ok(cycles) => Ok(Value::Cycles(cycles)),
+                Err(_) if !number.is_empty() => Err(CommandError::CyclesOutOfRange),
+                Err(_) => Err(CommandError::BadNumber),
+            };
+        }
+        u if u.eq_ignore_ascii_case("ns") => PS_PER_NS,
+        u if u.eq_ignore_ascii_case("us") => PS_PER_US,
+        u if u.eq_ignore_ascii_case("ms") => PS_PER_MS,
+        u if u.eq_ignore_ascii_case("s") => PS_PER_S,
+        _ => return Err(CommandError::BadUnit),
+    };
+
+    let (int, frac) = number.split_once('.').unwrap_or((number, ""));
+    if int.is_empty() && frac.is_empty() {
+        return Err(CommandError::BadNumber);
+    }
+    if !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) {
+        return Err(CommandError::BadNumber);
+    }
+
+    // Digits below a picosecond are dropped. Oversized values saturate so the
+    // clock conversion reports them against the real limit.
+    let mut ps: u64 = 0;
+    for b in int.bytes() {
+        ps = ps.saturating_mul(10).saturating_add((b - b'0') as u64);
+    }
+    ps = ps.saturating_mul(unit_ps);
+    let mut scale = unit_ps;
+    for b in frac.bytes() {
+        scale /= 10;
+        ps = ps.saturating_add((b - b'0') as u64 * scale);
+    }
+    Ok(Value::Picos(ps))
+}
@@ -0,0 +1,247 @@
+// COMPAT DG: the command dialect of the usual 4-channel digital delay
+// generators, for host software written against one. Each channel is a
+// single pulse set by DLAY and WIDT and kept armed, as those instruments
+// are always live. Channels are letters, A and B map onto channels 0 and
+// 1, or the instruments' numbers 2 to 5 for A to D. Times are seconds in
+// any decimal or exponent form and come back as "+S.dddddddddddd".
+//
+// Settings don't answer, queries answer with the bare value. A command that
+// fails answers nothing either, its error code goes on a queue read with
+// LERR? as those instruments do. Several commands can share a line, split
+// by ';'.
+
+use arrayvec::ArrayVec;
+
+use crate::interlock;
+use crate::pulse_generator::{PulseGenerator, NUM_CHANNELS};
+use crate::text::Text;
+use crate::time::{Achieved, PS_PER_S};
+use crate::Response;
+
+// Error codes as the instruments number them
+const ILLEGAL_VALUE: u8 = 10;
+const ILLEGAL_DELAY: u8 = 12;
+const NOT_ALLOWED: u8 = 15;
+const UNDEFINED_COMMAND: u8 = 111;
+const ILLEGAL_QUERY: u8 = 112;
+const EXTRA_PARAMETERS: u8 = 115;
+const MISSING_PARAMETERS: u8 = 116;
+const INVALID_FLOAT: u8 = 118;
+// Oldest first, further errors are dropped once it is full
+const ERROR_QUEUE: usize = 20;
+
+// Name, then the least and most parameters
+const COMMANDS: [(&str, usize, usize); 9] = [
+    ("*IDN?", 0, 0),
+    ("*CLS", 0, 0),
+    ("LERR?", 0, 0),
+    ("DLAY", 2, 3),
+    ("DLAY?", 1, 1),
+    ("WIDT", 2, 2),
+    ("WIDT?", 1, 1),
+    ("POLR", 2, 2),
+    ("POLR?", 1, 1),
+];
+const ARGS_MAX: usize = 3;
+
+const IDN: &str = concat!("u74w,pico-pulse,0,", env!("CARGO_PKG_VERSION"));
+
+pub struct Dg {
+    // Requested times in ps, a channel with zero width is off
+    delay: [u64; NUM_CHANNELS],
+    width: [u64; NUM_CHANNELS],
+    errors: ArrayVec<u8, ERROR_QUEUE>,
+}
+
+impl Dg {
+    // Channels start off, with zero delay and width
+    pub fn new() -> Self {
+        Self {
+            delay: [0; NUM_CHANNELS],
+            width: [0; NUM_CHANNELS],
+            errors: ArrayVec::new(),
+        }
+    }
+
+    // The line's answer, None if it only had settings or failed
+    pub fn execute(&mut self, line: &[u8], pulse_gen: &mut PulseGenerator) -> Option<Response> {
+        let mut response = Response::new();
+        let Ok(line) = core::str::from_utf8(line) else {
+            self.error(UNDEFINED_COMMAND);
+            return None;
+        };
+        for command in line.split(';').map(str::trim).filter(|c| !c.is_empty()) {
+            let mut answer = Response::new();
+            match self.command(command, pulse_gen, &mut answer) {
+                Ok(()) if !answer.is_empty() => {
+                    // Answers to one line are joined by ';'
+                    if !response.is_empty() {
+                        response.put(";");
+                    }
+                    response.put(&answer);
+                }
+                Ok(()) => {}
+                Err(code) => self.error(code),
+            }
+        }
+        (!response.is_empty()).then_some(response)
+    }
+
+    fn error(&mut self, code: u8) {
+        let _ = self.errors.try_push(code);
+    }
+
+    fn command(
+        &mut self,
+        command: &str,
+        pulse_gen: &mut PulseGenerator,
+        answer: &mut Response,
+    ) -> Result<(), u8> {
+        let (keyword, args) = match command.find(|c: char| c.is_ascii_whitespace()) {
+            Some(at) => (&command[..at], command[at..].trim()),
+            None => (command, ""),
+        };
+        let Some(&(name, min, max)) = COMMANDS
+            .iter()
+            .find(|(name, ..)| keyword.eq_ignore_ascii_case(name))
+        else {
+            if keyword.ends_with('?') {
+                return Err(ILLEGAL_QUERY);
+            }
+            return Err(UNDEFINED_COMMAND);
+        };
+        let mut list: ArrayVec<&str, ARGS_MAX> = ArrayVec::new();
+        for arg in args.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+            list.try_push(arg).map_err(|_| EXTRA_PARAMETERS)?;
+        }
+        if list.len() < min {
+            return Err(MISSING_PARAMETERS);
+        }
+        if list.len() > max {
+            return Err(EXTRA_PARAMETERS);
+        }
+        match (name, list.as_slice()) {
+            ("*IDN?", _) => {
+                answer.put(IDN);
+            }
+            ("*CLS", _) => self.errors.clear(),
+            ("LERR?", _) => {
+                answer.dec(self.errors.pop_at(0).unwrap_or(0));
+            }
+            // "DLAY c,d,t" names the reference first, only T0 (0) exists here
+            ("DLAY", [ch, time] | [ch, "0", time]) => {
+                let ch = channel(ch)?;
+                self.delay[ch] = seconds(time).ok_or(INVALID_FLOAT)?;
+                self.update(ch, pulse_gen)?;
+            }
+            ("DLAY", _) => return Err(ILLEGAL_DELAY),
+            ("DLAY?", [ch]) => {
+                answer.put("0,");
+                write_seconds(answer, self.delay[channel(ch)?]);
+            }
+            ("WIDT", [ch, time]) => {
+                let ch = channel(ch)?;
+                self.width[ch] = seconds(time).ok_or(INVALID_FLOAT)?;
+                self.update(ch, pulse_gen)?;
+            }
+            ("WIDT?", [ch]) => {
+                write_seconds(answer, self.width[channel(ch)?]);
+            }
+            // Outputs are active high only
+            ("POLR", [ch, polarity]) => {
+                channel(ch)?;
+                match *polarity {
+                    "1" => {}
+                    "0" => return Err(NOT_ALLOWED),
+                    _ => return Err(ILLEGAL_VALUE),
+                }
+            }
+            ("POLR?", [ch]) => {
+                channel(ch)?;
+                answer.put("1");
+            }
+            _ => return Err(UNDEFINED_COMMAND),
+        }
+        Ok(())
+    }
+
+    // Makes the channel's table the single pulse and arms it, or disarms it
+    // with zero width
+    fn update(&self, ch: usize, pulse_gen: &mut PulseGenerator) -> Result<(), u8> {
+        if self.width[ch] == 0 {
+            pulse_gen.disarm(ch);
+            return Ok(());
+        }
+        if interlock::blocks() {
+            return Err(NOT_ALLOWED);
+        }
+        let sys_hz = pulse_gen.sys_hz();
+        let rounding = pulse_gen.rounding();
+        let delay = Achieved::from_ps(self.delay[ch], sys_hz, rounding);
+        let width = Achieved::from_ps(self.width[ch], sys_hz, rounding)
+            .ok()
+            .filter(|width| width.cycles <= u32::MAX as u64)
+            .ok_or(ILLEGAL_VALUE)?;
+        let delay = delay.map_err(|_| ILLEGAL_DELAY)?;
+        pulse_gen
+            .set_table(ch, &[(delay, width)])
+            .map_err(|_| ILLEGAL_VALUE)?;
+        pulse_gen.arm(ch).map_err(|_| NOT_ALLOWED)
+    }
+}
+
+// "A" to "D" or "2" to "5", the ones beyond this build's channels are out of
+// range
+fn channel(arg: &str) -> Result<usize, u8> {
+    let ch = match arg.as_bytes() {
+        [c @ b'A'..=b'D'] | [c @ b'a'..=b'd'] => (c.to_ascii_uppercase() - b'A') as usize,
+        [c @ b'2'..=b'5'] => (c - b'2') as usize,
+        _ => return Err(ILLEGAL_VALUE),
+    };
+    if ch >= NUM_CHANNELS {
+        return Err(ILLEGAL_VALUE);
+    }
+    Ok(ch)
+}
+
+// Seconds as "[+]digits[.digits][e[+|-]digits]" to ps, below 1ps is
+// rounded off. Negative times don't exist here.
+fn seconds(arg: &str) -> Option<u64> {
+    let arg = arg.strip_prefix('+').unwrap_or(arg);
+    let (mantissa, exponent) = match arg.find(['e', 'E']) {
+        Some(at) => {
+            let exponent = &arg[at + 1..];
+            let exponent = exponent.strip_prefix('+').unwrap_or(exponent);
+            (&arg[..at], exponent.parse::<i32>().ok()?)
+        }
+        None => (arg, 0),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if int.is_empty() && frac.is_empty() {
+        return None;
+    }
+    let mut digits: u128 = 0;
+    for c in int.bytes().chain(frac.bytes()) {
+        if !c.is_ascii_digit() {
+            return None;
+        }
+        digits = digits.checked_mul(10)?.checked_add((c - b'0') as u128)?;
+    }
+    // ps = digits * 10^(12 + exponent - decimals)
+    let scale = 12 + exponent - frac.len() as i32;
+    let ps = if scale >= 0 {
+        digits.checked_mul(10u128.checked_pow(scale as u32)?)?
+    } else {
+        let divisor = 10u128.checked_pow(scale.unsigned_abs())?;
+        (digits + divisor / 2) / divisor
+    };
+    ps.try_into().ok()
+}
+
+// "+S.dddddddddddd", seconds to the ps
+fn write_seconds(w: &mut impl Text, ps: u64) {
+    w.put("+")
+        .dec(ps / PS_PER_S)
+        .put(".")
+        .dec0(ps % PS_PER_S, 12);
+}
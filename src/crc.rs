@@ -0,0 +1,35 @@
+// CRC-32 (IEEE) of the flash records, snapshots and readback chunks
+
+// Bitwise since it only ever covers a few pages
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+// Carries the register across pieces of one checksum, without the final
+// inversion
+pub fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        let pieces = crc32_update(crc32_update(!0, b"1234"), b"56789");
+        assert_eq!(!pieces, 0xcbf4_3926);
+    }
+}
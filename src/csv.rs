@@ -0,0 +1,121 @@
+// Pulse tables pasted at the terminal as CSV: CSVLOAD <ch> takes the lines
+// after it as "delay,width" until a lone "." and then sets the channel's
+// table from them as TABLE would. Values without a unit are us, as the
+// spreadsheets' columns are, a unit may follow the number with or without a
+// space. Lines starting with '#' are comments and a first line starting
+// with a letter is the column header. The parser already dropped CRs and
+// blank lines, so line numbers count the lines left. After an error the
+// upload still runs to its "." so the summary can name the first bad line,
+// and nothing is loaded.
+
+use arrayvec::ArrayVec;
+
+use crate::command::{self, CommandError, Value};
+use crate::pulse_generator::NUM_PULSES_MAX;
+
+// A progress line every this many lines
+pub const PROGRESS_LINES: u32 = 16;
+// An upload without a line for this long is dropped
+pub const CSV_TIMEOUT_US: u64 = 10_000_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CsvError {
+    Parse(CommandError),
+    // More than NUM_PULSES_MAX pairs
+    SequenceFull,
+}
+
+impl CsvError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CsvError::Parse(err) => err.as_str(),
+            CsvError::SequenceFull => "SEQUENCE_FULL",
+        }
+    }
+}
+
+// What a line did to the upload
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Step {
+    Taken,
+    // Lines so far, every PROGRESS_LINES
+    Progress(u32),
+    // The lone "."
+    End,
+}
+
+pub struct Upload {
+    pub ch: usize,
+    pub pairs: ArrayVec<(Value, Value), NUM_PULSES_MAX>,
+    // Comments and the header included
+    pub lines: u32,
+    // First bad line and why
+    pub error: Option<(u32, CsvError)>,
+    // Timer ticks (us) of the CSVLOAD or the last line
+    last_line_at: u64,
+}
+
+impl Upload {
+    pub fn new(ch: usize, now: u64) -> Self {
+        Self {
+            ch,
+            pairs: ArrayVec::new(),
+            lines: 0,
+            error: None,
+            last_line_at: now,
+        }
+    }
+
+    pub fn line(&mut self, line: &[u8], now: u64) -> Step {
+        self.last_line_at = now;
+        let line = core::str::from_utf8(line).map(str::trim);
+        if line == Ok(".") {
+            return Step::End;
+        }
+        self.lines += 1;
+        if self.error.is_none() {
+            let pair = line
+                .map_err(|_| CommandError::BadPair)
+                .and_then(|line| parse_line(line, self.lines == 1))
+                .map_err(CsvError::Parse);
+            match pair {
+                Ok(None) => {}
+                Ok(Some(pair)) => {
+                    if self.pairs.try_push(pair).is_err() {
+                        self.error = Some((self.lines, CsvError::SequenceFull));
+                    }
+                }
+                Err(err) => self.error = Some((self.lines, err)),
+            }
+        }
+        if self.lines % PROGRESS_LINES == 0 {
+            return Step::Progress(self.lines);
+        }
+        Step::Taken
+    }
+
+    pub fn timed_out(&self, now: u64) -> bool {
+        now.saturating_sub(self.last_line_at) >= CSV_TIMEOUT_US
+    }
+}
+
+// A pair, or None for a comment or the header
+fn parse_line(line: &str, first: bool) -> Result<Option<(Value, Value)>, CommandError> {
+    if line.starts_with('#') || first && line.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return Ok(None);
+    }
+    let (delay, width) = line.split_once(',').ok_or(CommandError::BadPair)?;
+    Ok(Some((parse_field(delay)?, parse_field(width)?)))
+}
+
+fn parse_field(field: &str) -> Result<Value, CommandError> {
+    let field = field.trim();
+    let split = field
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(field.len());
+    let (number, unit) = field.split_at(split);
+    match unit.trim_start() {
+        "" => command::parse_number(number, "us"),
+        unit => command::parse_number(number, unit),
+    }
+}
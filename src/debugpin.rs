@@ -0,0 +1,136 @@
+// Debug pin: a GPIO driven from SIO that pulses high for about 1us on
+// selected firmware events, so a logic analyzer on it lines them up with
+// the outputs and the trigger. DMA is a channel's DMA transfer finishing,
+// pulsed from the DMA_IRQ_1 handler. END is a table run or stream the main
+// loop finds complete and CMD a command line or frame received, both from
+// the main loop, so up to a loop pass after the fact: the PIO programs
+// raise no interrupt at the end of a sequence. Off by default.
+
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+use crate::board::hal::pac::{self, interrupt};
+use crate::probe::GPIO_COUNT;
+use crate::pulse_generator::{self, CHANNEL_DMA, NUM_CHANNELS, TRIGGER_PIN};
+
+const NO_PIN: u8 = 0xff;
+// IO_BANK0 function select of SIO, and of nothing
+const FUNCSEL_SIO: u8 = 5;
+const FUNCSEL_NULL: u8 = 31;
+
+// Event bits
+pub const DMA: u8 = 1 << 0;
+pub const END: u8 = 1 << 1;
+pub const CMD: u8 = 1 << 2;
+pub const EVENTS: u8 = DMA | END | CMD;
+// Names as DEBUGPIN takes them, in bit order
+pub const EVENT_NAMES: [(u8, &str); 3] = [(DMA, "DMA"), (END, "END"), (CMD, "CMD")];
+
+static PIN: AtomicU8 = AtomicU8::new(NO_PIN);
+static MASK: AtomicU8 = AtomicU8::new(0);
+// System clock cycles the pin stays high
+static PULSE_CYCLES: AtomicU32 = AtomicU32::new(125);
+
+// Stored in the flash config
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Settings {
+    // None when off
+    pub pin: Option<u8>,
+    pub events: u8,
+}
+
+// Applies the stored settings and enables the DMA interrupt, which does
+// nothing until DMA is selected
+pub fn init(settings: Settings, sys_hz: u32) {
+    PULSE_CYCLES.store(sys_hz / 1_000_000, Ordering::Relaxed);
+    configure(settings);
+    // Safety: the handler below only touches what this module owns
+    unsafe { cortex_m::peripheral::NVIC::unmask(pac::Interrupt::DMA_IRQ_1) };
+}
+
+// Hands the old pin back to nothing, driven low first, and takes the new
+// one low
+pub fn configure(settings: Settings) {
+    // Safety: only the debug pin's function and its SIO output bits are
+    // touched
+    let io = unsafe { &*pac::IO_BANK0::ptr() };
+    let sio = unsafe { &*pac::SIO::ptr() };
+    MASK.store(0, Ordering::Release);
+    // Only stored from here, thumbv6m has no atomic swap
+    let old = PIN.load(Ordering::Relaxed);
+    PIN.store(NO_PIN, Ordering::Release);
+    if old != NO_PIN {
+        sio.gpio_oe_clr().write(|w| unsafe { w.bits(1 << old) });
+        io.gpio(old as usize)
+            .gpio_ctrl()
+            .write(|w| unsafe { w.funcsel().bits(FUNCSEL_NULL) });
+    }
+    if let Some(pin) = settings.pin {
+        sio.gpio_out_clr().write(|w| unsafe { w.bits(1 << pin) });
+        sio.gpio_oe_set().write(|w| unsafe { w.bits(1 << pin) });
+        io.gpio(pin as usize)
+            .gpio_ctrl()
+            .write(|w| unsafe { w.funcsel().bits(FUNCSEL_SIO) });
+        PIN.store(pin, Ordering::Release);
+        MASK.store(settings.events & EVENTS, Ordering::Release);
+    }
+    set_dma_interrupt(MASK.load(Ordering::Relaxed) & DMA != 0);
+}
+
+pub fn settings() -> Settings {
+    let pin = PIN.load(Ordering::Relaxed);
+    Settings {
+        pin: (pin != NO_PIN).then_some(pin),
+        events: MASK.load(Ordering::Relaxed),
+    }
+}
+
+// Whether the pin can carry the debug pin, not the trigger input nor a
+// channel's output. The interlock's pin is checked by the caller.
+pub fn valid_pin(pin: u8) -> bool {
+    pin < GPIO_COUNT && pin != TRIGGER_PIN && pulse_generator::output_pins() & 1 << pin == 0
+}
+
+// Pulses the pin if the event is selected. Bounded, the same from an
+// interrupt handler as from the main loop.
+pub fn pulse(event: u8) {
+    if MASK.load(Ordering::Acquire) & event == 0 {
+        return;
+    }
+    let pin = PIN.load(Ordering::Relaxed);
+    if pin == NO_PIN {
+        return;
+    }
+    // Safety: SIO's set and clear registers are atomic, only the debug
+    // pin's bit is written
+    let sio = unsafe { &*pac::SIO::ptr() };
+    sio.gpio_out_set().write(|w| unsafe { w.bits(1 << pin) });
+    cortex_m::asm::delay(PULSE_CYCLES.load(Ordering::Relaxed));
+    sio.gpio_out_clr().write(|w| unsafe { w.bits(1 << pin) });
+}
+
+// The channels' DMA completions on IRQ 1. The HAL goes by the channels'
+// busy bits, never by the interrupt status, so this takes nothing from it.
+fn set_dma_interrupt(enabled: bool) {
+    let bits = (0..NUM_CHANNELS).fold(0, |bits, ch| bits | 1 << CHANNEL_DMA[ch]);
+    // Safety: only the channels' bits of INTE1, with interrupts off so the
+    // handler can't race the read-modify-write
+    let dma = unsafe { &*pac::DMA::ptr() };
+    cortex_m::interrupt::free(|_| {
+        dma.inte1().modify(|r, w| unsafe {
+            w.bits(if enabled {
+                r.bits() | bits
+            } else {
+                r.bits() & !bits
+            })
+        })
+    });
+}
+
+#[interrupt]
+fn DMA_IRQ_1() {
+    // Safety: INTS1 is write-1-to-clear and IRQ 1 is this module's alone
+    let dma = unsafe { &*pac::DMA::ptr() };
+    let status = dma.ints1().read().bits();
+    dma.ints1().write(|w| unsafe { w.bits(status) });
+    pulse(DMA);
+}
@@ -0,0 +1,402 @@
+// One-line decoding of PIO instruction words, for inspecting what the
+// assembler generated. Only needs the program's side-set configuration.
+
+use crate::text::Text;
+use pio::SideSet;
+
+const JMP_CONDITIONS: [&str; 8] = [
+    "", "!x, ", "x--, ", "!y, ", "y--, ", "x!=y, ", "pin, ", "!osre, ",
+];
+const WAIT_SOURCES: [&str; 4] = ["gpio", "pin", "irq", "?"];
+const IN_SOURCES: [&str; 8] = ["pins", "x", "y", "null", "?", "?", "isr", "osr"];
+const OUT_DESTINATIONS: [&str; 8] = ["pins", "x", "y", "null", "pindirs", "pc", "isr", "exec"];
+const MOV_DESTINATIONS: [&str; 8] = ["pins", "x", "y", "?", "exec", "pc", "isr", "osr"];
+const MOV_OPS: [&str; 4] = ["", "!", "::", "?"];
+const MOV_SOURCES: [&str; 8] = ["pins", "x", "y", "null", "?", "status", "isr", "osr"];
+const SET_DESTINATIONS: [&str; 8] = ["pins", "x", "y", "?", "pindirs", "?", "?", "?"];
+
+// Writes e.g. "jmp y--, 10 side 1 [2]". Bit counts of zero in IN/OUT mean 32.
+pub fn write_instruction(w: &mut impl Text, word: u16, side_set: &SideSet) {
+    let op = (word >> 5) as usize & 0x7;
+    let low = word as usize & 0x1f;
+    let count = if low == 0 { 32 } else { low };
+    match word >> 13 {
+        0 => w.put("jmp ").put(JMP_CONDITIONS[op]).dec(low),
+        1 => w
+            .put("wait ")
+            .dec(word >> 7 & 1)
+            .put(" ")
+            .put(WAIT_SOURCES[op & 0x3])
+            .put(" ")
+            .dec(low),
+        2 => w.put("in ").put(IN_SOURCES[op]).put(", ").dec(count),
+        3 => w.put("out ").put(OUT_DESTINATIONS[op]).put(", ").dec(count),
+        4 => {
+            let (name, flag) = if word & 0x80 != 0 {
+                ("pull", "ifempty")
+            } else {
+                ("push", "iffull")
+            };
+            w.put(name);
+            if word & 0x40 != 0 {
+                w.put(" ").put(flag);
+            }
+            w.put(if word & 0x20 != 0 {
+                " block"
+            } else {
+                " noblock"
+            })
+        }
+        5 => w
+            .put("mov ")
+            .put(MOV_DESTINATIONS[op])
+            .put(", ")
+            .put(MOV_OPS[word as usize >> 3 & 0x3])
+            .put(MOV_SOURCES[word as usize & 0x7]),
+        6 => {
+            let mode = match word >> 5 & 0x3 {
+                0 => "",
+                1 => "wait ",
+                _ => "clear ",
+            };
+            w.put("irq ").put(mode).dec(low)
+        }
+        _ => w.put("set ").put(SET_DESTINATIONS[op]).put(", ").dec(low),
+    };
+
+    // Side-set takes the top bits of the delay field. SideSet::bits()
+    // includes the enable bit of an optional side-set.
+    let field = (word >> 8) & 0x1f;
+    let total = side_set.bits();
+    let value_bits = total - side_set.optional() as u8;
+    let delay = field & ((1 << (5 - total)) - 1);
+    let enabled = !side_set.optional() || field & 0x10 != 0;
+    if value_bits > 0 && enabled {
+        let side = (field >> (5 - total)) & ((1 << value_bits) - 1);
+        w.put(" side ").dec(side);
+    }
+    if delay > 0 {
+        w.put(" [").dec(delay).put("]");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pulse_generator::{compile, OutputMode, ProgramConfig};
+    use arrayvec::ArrayString;
+    use pio::{
+        Instruction, InstructionOperands, JmpCondition, MovDestination, MovOperation, MovSource,
+        OutDestination, SetDestination, WaitSource,
+    };
+
+    fn disasm(word: u16, side_set: &SideSet) -> String {
+        let mut s = ArrayString::<48>::new();
+        write_instruction(&mut s, word, side_set);
+        s.to_string()
+    }
+
+    fn line(
+        operands: InstructionOperands,
+        delay: u8,
+        side: Option<u8>,
+        side_set: SideSet,
+    ) -> String {
+        let instruction = Instruction {
+            operands,
+            delay,
+            side_set: side,
+        };
+        disasm(instruction.encode(side_set), &side_set)
+    }
+
+    // Reads a line back into the instruction, for the mnemonics and
+    // operands compile() uses
+    fn parse(text: &str) -> Instruction {
+        let (text, delay) = match text.strip_suffix(']') {
+            Some(text) => {
+                let (text, delay) = text.rsplit_once(" [").unwrap();
+                (text, delay.parse().unwrap())
+            }
+            None => (text, 0),
+        };
+        let (text, side_set) = match text.rsplit_once(" side ") {
+            Some((text, side)) => (text, Some(side.parse().unwrap())),
+            None => (text, None),
+        };
+        let (mnemonic, rest) = text.split_once(' ').unwrap();
+        let number = |s: &str| s.parse::<u8>().unwrap();
+        let operands = match mnemonic {
+            "jmp" => {
+                let (condition, address) = match rest.split_once(", ") {
+                    None => (JmpCondition::Always, rest),
+                    Some(("!x", address)) => (JmpCondition::XIsZero, address),
+                    Some(("x--", address)) => (JmpCondition::XDecNonZero, address),
+                    Some(("!y", address)) => (JmpCondition::YIsZero, address),
+                    Some(("y--", address)) => (JmpCondition::YDecNonZero, address),
+                    Some(("x!=y", address)) => (JmpCondition::XNotEqualY, address),
+                    Some(("pin", address)) => (JmpCondition::PinHigh, address),
+                    Some(("!osre", address)) => {
+                        (JmpCondition::OutputShiftRegisterNotEmpty, address)
+                    }
+                    Some((condition, _)) => panic!("jmp condition {}", condition),
+                };
+                InstructionOperands::JMP {
+                    condition,
+                    address: number(address),
+                }
+            }
+            "wait" => {
+                let fields: Vec<_> = rest.split(' ').collect();
+                let source = match fields[1] {
+                    "gpio" => WaitSource::GPIO,
+                    "pin" => WaitSource::PIN,
+                    "irq" => WaitSource::IRQ,
+                    source => panic!("wait source {}", source),
+                };
+                let index = number(fields[2]);
+                InstructionOperands::WAIT {
+                    polarity: number(fields[0]),
+                    source,
+                    index: index & 0xf,
+                    relative: index & 0x10 != 0,
+                }
+            }
+            "out" => {
+                let (destination, bit_count) = rest.split_once(", ").unwrap();
+                let destination = match destination {
+                    "pins" => OutDestination::PINS,
+                    "x" => OutDestination::X,
+                    "y" => OutDestination::Y,
+                    "null" => OutDestination::NULL,
+                    "pindirs" => OutDestination::PINDIRS,
+                    "pc" => OutDestination::PC,
+                    "isr" => OutDestination::ISR,
+                    "exec" => OutDestination::EXEC,
+                    destination => panic!("out destination {}", destination),
+                };
+                InstructionOperands::OUT {
+                    destination,
+                    bit_count: number(bit_count),
+                }
+            }
+            "pull" | "push" => {
+                let block = rest.ends_with(" block") || rest == "block";
+                let flag = rest.starts_with("if");
+                if mnemonic == "pull" {
+                    InstructionOperands::PULL {
+                        if_empty: flag,
+                        block,
+                    }
+                } else {
+                    InstructionOperands::PUSH {
+                        if_full: flag,
+                        block,
+                    }
+                }
+            }
+            "mov" => {
+                let (destination, source) = rest.split_once(", ").unwrap();
+                let destination = match destination {
+                    "pins" => MovDestination::PINS,
+                    "x" => MovDestination::X,
+                    "y" => MovDestination::Y,
+                    "exec" => MovDestination::EXEC,
+                    "pc" => MovDestination::PC,
+                    "isr" => MovDestination::ISR,
+                    "osr" => MovDestination::OSR,
+                    destination => panic!("mov destination {}", destination),
+                };
+                let (op, source) = if let Some(source) = source.strip_prefix('!') {
+                    (MovOperation::Invert, source)
+                } else if let Some(source) = source.strip_prefix("::") {
+                    (MovOperation::BitReverse, source)
+                } else {
+                    (MovOperation::None, source)
+                };
+                let source = match source {
+                    "pins" => MovSource::PINS,
+                    "x" => MovSource::X,
+                    "y" => MovSource::Y,
+                    "null" => MovSource::NULL,
+                    "status" => MovSource::STATUS,
+                    "isr" => MovSource::ISR,
+                    "osr" => MovSource::OSR,
+                    source => panic!("mov source {}", source),
+                };
+                InstructionOperands::MOV {
+                    destination,
+                    op,
+                    source,
+                }
+            }
+            "irq" => {
+                let (clear, wait, index) = match rest.split_once(' ') {
+                    Some(("clear", index)) => (true, false, index),
+                    Some(("wait", index)) => (false, true, index),
+                    None => (false, false, rest),
+                    Some((mode, _)) => panic!("irq mode {}", mode),
+                };
+                let index = number(index);
+                InstructionOperands::IRQ {
+                    clear,
+                    wait,
+                    index: index & 0xf,
+                    relative: index & 0x10 != 0,
+                }
+            }
+            "set" => {
+                let (destination, data) = rest.split_once(", ").unwrap();
+                let destination = match destination {
+                    "pins" => SetDestination::PINS,
+                    "x" => SetDestination::X,
+                    "y" => SetDestination::Y,
+                    "pindirs" => SetDestination::PINDIRS,
+                    destination => panic!("set destination {}", destination),
+                };
+                InstructionOperands::SET {
+                    destination,
+                    data: number(data),
+                }
+            }
+            mnemonic => panic!("mnemonic {}", mnemonic),
+        };
+        Instruction {
+            operands,
+            delay,
+            side_set,
+        }
+    }
+
+    #[test]
+    fn every_compiled_word_reads_back_to_itself() {
+        let standard = ProgramConfig {
+            wide: false,
+            output: OutputMode::PushPull,
+            long_delay: false,
+            marker: false,
+            trigger_out: false,
+            double: false,
+            per_edge: false,
+            gap: 0,
+            split: false,
+        };
+        let variants = [
+            standard,
+            ProgramConfig {
+                trigger_out: true,
+                ..standard
+            },
+            ProgramConfig {
+                per_edge: true,
+                ..standard
+            },
+            ProgramConfig {
+                marker: true,
+                ..standard
+            },
+            ProgramConfig {
+                double: true,
+                ..standard
+            },
+            ProgramConfig {
+                split: true,
+                ..standard
+            },
+            ProgramConfig {
+                wide: true,
+                ..standard
+            },
+            ProgramConfig {
+                long_delay: true,
+                ..standard
+            },
+        ];
+        for config in variants {
+            for output in [OutputMode::PushPull, OutputMode::OpenDrain] {
+                // The wide program refuses open drain
+                if config.wide && output == OutputMode::OpenDrain {
+                    continue;
+                }
+                for gap in [0, 1] {
+                    let config = ProgramConfig {
+                        output,
+                        gap,
+                        ..config
+                    };
+                    let program = compile(config);
+                    for (i, &word) in program.code.iter().enumerate() {
+                        let text = disasm(word, &program.side_set);
+                        let back = parse(&text).encode(program.side_set);
+                        assert_eq!(back, word, "{:?} word {} \"{}\"", config, i, text);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn an_optional_side_set_is_printed_only_when_given() {
+        let side_set = SideSet::new(true, 1, false);
+        let jmp = InstructionOperands::JMP {
+            condition: JmpCondition::YDecNonZero,
+            address: 10,
+        };
+        assert_eq!(line(jmp, 2, Some(1), side_set), "jmp y--, 10 side 1 [2]");
+        assert_eq!(line(jmp, 2, Some(0), side_set), "jmp y--, 10 side 0 [2]");
+        assert_eq!(line(jmp, 2, None, side_set), "jmp y--, 10 [2]");
+        // Two value bits and the enable bit leave two for the delay
+        let side_set = SideSet::new(true, 2, false);
+        assert_eq!(line(jmp, 3, Some(0b10), side_set), "jmp y--, 10 side 2 [3]");
+        assert_eq!(line(jmp, 0, None, side_set), "jmp y--, 10");
+    }
+
+    #[test]
+    fn a_mandatory_side_set_is_always_printed() {
+        let side_set = SideSet::new(false, 1, false);
+        let set = InstructionOperands::SET {
+            destination: SetDestination::PINDIRS,
+            data: 1,
+        };
+        assert_eq!(line(set, 0, Some(0), side_set), "set pindirs, 1 side 0");
+        assert_eq!(
+            line(set, 15, Some(1), side_set),
+            "set pindirs, 1 side 1 [15]"
+        );
+    }
+
+    #[test]
+    fn without_side_set_the_whole_field_is_delay() {
+        let side_set = SideSet::new(false, 0, false);
+        let out = InstructionOperands::OUT {
+            destination: OutDestination::X,
+            bit_count: 32,
+        };
+        assert_eq!(line(out, 31, None, side_set), "out x, 32 [31]");
+        let pull = InstructionOperands::PULL {
+            if_empty: false,
+            block: true,
+        };
+        assert_eq!(line(pull, 0, None, side_set), "pull block");
+        let mov = InstructionOperands::MOV {
+            destination: MovDestination::Y,
+            op: MovOperation::Invert,
+            source: MovSource::X,
+        };
+        assert_eq!(line(mov, 1, None, side_set), "mov y, !x [1]");
+        let wait = InstructionOperands::WAIT {
+            polarity: 1,
+            source: WaitSource::PIN,
+            index: 0,
+            relative: false,
+        };
+        assert_eq!(line(wait, 0, None, side_set), "wait 1 pin 0");
+        let irq = InstructionOperands::IRQ {
+            clear: true,
+            wait: false,
+            index: 3,
+            relative: false,
+        };
+        assert_eq!(line(irq, 0, None, side_set), "irq clear 3");
+    }
+}
@@ -0,0 +1,37 @@
+// Random numbers without an RNG peripheral, from the ring oscillator's
+// RANDOMBIT. The ROSC keeps running after the system clock moves to the
+// crystal, so its phase against the system clock jitters. Successive reads
+// are biased and correlated, so each bit folds together samples spread over
+// time and the result goes through a mixer. Good enough for a session nonce,
+// not for keys.
+
+use crate::board::hal::pac;
+
+// RANDOMBIT reads folded into each output bit
+const SAMPLES_PER_BIT: u32 = 16;
+// System clocks between samples, a few ROSC periods at its 6MHz or so
+const SAMPLE_SPACING: u32 = 50;
+
+pub fn random_u32() -> u32 {
+    // Safety: read-only access to the ROSC's random bit
+    let rosc = unsafe { &*pac::ROSC::ptr() };
+    let mut bits = 0u32;
+    for _ in 0..32 {
+        let mut bit = 0;
+        for _ in 0..SAMPLES_PER_BIT {
+            bit ^= rosc.randombit().read().randombit().bit() as u32;
+            cortex_m::asm::delay(SAMPLE_SPACING);
+        }
+        bits = bits << 1 | bit;
+    }
+    mix(bits)
+}
+
+// Finalizer of MurmurHash3, spreads every input bit over the whole output
+fn mix(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x85eb_ca6b);
+    x ^= x >> 13;
+    x = x.wrapping_mul(0xc2b2_ae35);
+    x ^ x >> 16
+}
@@ -0,0 +1,92 @@
+// Subsystems that can be compiled out, see the cargo features. A command of
+// a missing one still parses, so the host gets ERR NOT_PRESENT <feature>
+// rather than UNKNOWN_COMMAND.
+
+use crate::command::{Command, CommandError};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Feature {
+    Scpi,
+    BinaryProto,
+    Capture,
+    FlashConfig,
+    Selftest,
+    UsbLog,
+    CompatDg,
+}
+
+pub const ALL: [Feature; 7] = [
+    Feature::Scpi,
+    Feature::BinaryProto,
+    Feature::Capture,
+    Feature::FlashConfig,
+    Feature::Selftest,
+    Feature::UsbLog,
+    Feature::CompatDg,
+];
+
+impl Feature {
+    // The cargo feature name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Feature::Scpi => "scpi",
+            Feature::BinaryProto => "binary-proto",
+            Feature::Capture => "capture",
+            Feature::FlashConfig => "flash-config",
+            Feature::Selftest => "selftest",
+            Feature::UsbLog => "usb-log",
+            Feature::CompatDg => "compat-dg",
+        }
+    }
+
+    pub fn present(&self) -> bool {
+        match self {
+            Feature::Scpi => cfg!(feature = "scpi"),
+            Feature::BinaryProto => cfg!(feature = "binary-proto"),
+            Feature::Capture => cfg!(feature = "capture"),
+            Feature::FlashConfig => cfg!(feature = "flash-config"),
+            Feature::Selftest => cfg!(feature = "selftest"),
+            Feature::UsbLog => cfg!(feature = "usb-log"),
+            Feature::CompatDg => cfg!(feature = "compat-dg"),
+        }
+    }
+}
+
+// The feature a command's handler belongs to. Queries of stored settings
+// stay, they report the defaults.
+fn needed_by(command: &Command) -> Option<Feature> {
+    match command {
+        Command::Tlog(_)
+        | Command::TlogQuery
+        | Command::Glitch(_)
+        | Command::GlitchReset
+        | Command::GlitchQuery => Some(Feature::Capture),
+        Command::Stress(..) | Command::StressDma(..) | Command::Skew | Command::SelfTest => {
+            Some(Feature::Selftest)
+        }
+        Command::UsbId(..)
+        | Command::AutoArm(_)
+        | Command::Banner(_)
+        | Command::SnapSave
+        | Command::SnapClear
+        | Command::ScriptBegin(_)
+        | Command::ScriptEnd
+        | Command::ScriptAbort
+        | Command::ScriptClear
+        | Command::ScriptQuery
+        | Command::Run
+        | Command::Sleep(_)
+        | Command::WaitDone(..) => Some(Feature::FlashConfig),
+        Command::LogUsb(_) => Some(Feature::UsbLog),
+        Command::Compat(_) => Some(Feature::CompatDg),
+        _ => None,
+    }
+}
+
+// Refuses a command whose feature isn't built in
+pub fn check(command: Command<'_>) -> Result<Command<'_>, CommandError> {
+    match needed_by(&command) {
+        Some(feature) if !feature.present() => Err(CommandError::NotPresent(feature)),
+        _ => Ok(command),
+    }
+}
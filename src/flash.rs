@@ -0,0 +1,621 @@
+// Settings kept across power cycles in the last sector of flash, the
+// command script in the one before, the outputs' calibration before that,
+// the lifetime counters before that and the channel configuration before
+// those, which memory.x keeps out of the firmware image. Each sector holds
+// a single checksummed record, anything that doesn't check out is replaced
+// by the defaults as a whole. The calibration and the counters have sectors
+// of their own so nothing that rewrites the settings can take them along.
+
+use crate::board::{self, hal};
+use crate::crc::{crc32, crc32_update};
+use crate::debugpin;
+use crate::interlock::{self, Release};
+use crate::probe;
+use crate::protect::{self, Action, Thresholds};
+use crate::pulse_generator::{self, NUM_CHANNELS};
+use crate::safestate;
+use crate::script::{self, Script, SCRIPT_MAX};
+use crate::snapshot::SNAP_MAX;
+use arrayvec::ArrayString;
+use hal::rom_data;
+
+// Flash offset of the config sector, the last 4K of the 2M every supported
+// board has at least
+const CONFIG_OFFSET: u32 = 2048 * 1024 - SECTOR_SIZE as u32;
+const SCRIPT_OFFSET: u32 = CONFIG_OFFSET - SECTOR_SIZE as u32;
+const CAL_OFFSET: u32 = SCRIPT_OFFSET - SECTOR_SIZE as u32;
+const LIFE_OFFSET: u32 = CAL_OFFSET - SECTOR_SIZE as u32;
+const SNAP_OFFSET: u32 = LIFE_OFFSET - SECTOR_SIZE as u32;
+const XIP_BASE: u32 = 0x1000_0000;
+const SECTOR_SIZE: usize = 4096;
+const PAGE_SIZE: usize = 256;
+// 64K block erase command, unused for a single sector
+const BLOCK_ERASE_CMD: u8 = 0xd8;
+
+// Version 1 records predate the flags and read as all flags off, version 2
+// ones predate the protection thresholds and read as none set, version 3
+// ones predate DISABLE and read as every channel enabled, version 4 ones
+// predate the interlock and read as none, version 5 ones predate the debug
+// pin and read as off, version 6 ones predate the rest levels and read as
+// none
+const VERSION: u16 = 7;
+const MAGIC: u32 = 0x4643_5050; // "PPCF"
+
+// Record layout within the first page
+const VID_AT: usize = 6;
+const PID_AT: usize = 8;
+const PRODUCT_LEN_AT: usize = 10;
+const PRODUCT_AT: usize = 11;
+const FLAGS_AT: usize = PRODUCT_AT + PRODUCT_MAX;
+// 0xff for no temperature check, 0 for no VSYS check
+const TEMP_MAX_AT: usize = FLAGS_AT + 1;
+const VSYS_MIN_AT: usize = FLAGS_AT + 2;
+// Bit per disabled channel
+const DISABLED_AT: usize = VSYS_MIN_AT + 2;
+// 0xff for no interlock
+const INTERLOCK_PIN_AT: usize = DISABLED_AT + 1;
+// 0xff for no debug pin, then its event bits
+const DEBUG_PIN_AT: usize = INTERLOCK_PIN_AT + 1;
+const DEBUG_EVENTS_AT: usize = DEBUG_PIN_AT + 1;
+// Rest level masks, bit per GPIO
+const SAFE_LOW_AT: usize = DEBUG_EVENTS_AT + 1;
+const SAFE_HIGH_AT: usize = SAFE_LOW_AT + 4;
+const SAFE_TRISTATE_AT: usize = SAFE_HIGH_AT + 4;
+const CRC_AT: usize = PAGE_SIZE - 4;
+
+const FLAG_AUTOARM: u8 = 1 << 0;
+const FLAG_QUIET: u8 = 1 << 1;
+const FLAG_PROTECT_DISARM: u8 = 1 << 2;
+const FLAG_INTERLOCK_MOMENTARY: u8 = 1 << 3;
+// Unused flag bits were always written 0, so older records read as off
+const FLAG_REFUSE_STUCK_TRIGGER: u8 = 1 << 4;
+
+const SCRIPT_VERSION: u16 = 1;
+const SCRIPT_MAGIC: u32 = 0x4353_5050; // "PPSC"
+
+// Script record: magic, version, flags, text length, CRC of all but itself
+const SCRIPT_FLAGS_AT: usize = 6;
+const SCRIPT_LEN_AT: usize = 8;
+const SCRIPT_CRC_AT: usize = 12;
+const SCRIPT_TEXT_AT: usize = 16;
+// Whole pages, as the ROM programs them
+const SCRIPT_RECORD_LEN: usize = (SCRIPT_TEXT_AT + SCRIPT_MAX).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+
+const SCRIPT_FLAG_BOOT: u8 = 1 << 0;
+const SCRIPT_FLAG_UNRESTRICTED: u8 = 1 << 1;
+
+const CAL_VERSION: u16 = 1;
+const CAL_MAGIC: u32 = 0x4143_5050; // "PPCA"
+
+// Calibration record: magic, version, channel count, an i32 per channel,
+// then a CRC of all before it
+const CAL_CHANNELS_AT: usize = 6;
+const CAL_OFFSETS_AT: usize = 8;
+const CAL_CRC_AT: usize = CAL_OFFSETS_AT + 4 * NUM_CHANNELS;
+
+const LIFE_VERSION: u16 = 1;
+const LIFE_MAGIC: u32 = 0x464c_5050; // "PPLF"
+
+// Lifetime record: magic, version, channel count, power-on seconds, then
+// runs and high time (us) per channel, all u64, and a CRC of the whole page
+// before it at its end. Kept across firmware updates, so the layout only
+// grows: a later version appends fields ahead of the CRC and still reads
+// these, and this one reads any later record's fields it knows.
+const LIFE_CHANNELS_AT: usize = 6;
+const LIFE_SECONDS_AT: usize = 8;
+const LIFE_CHANNEL_AT: usize = 16;
+const LIFE_CHANNEL_LEN: usize = 16;
+const LIFE_CHANNELS_MAX: usize = 8;
+const LIFE_CRC_AT: usize = PAGE_SIZE - 4;
+
+// Snapshot record: the blob's length, then the blob, which carries its own
+// magic, version and CRC, see snapshot. An erased length is no record.
+const SNAP_LEN_AT: usize = 0;
+const SNAP_BLOB_AT: usize = 2;
+const SNAP_RECORD_LEN: usize = (SNAP_BLOB_AT + SNAP_MAX).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+
+// Well inside the 126 characters of a USB string descriptor
+pub const PRODUCT_MAX: usize = 32;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct UsbIdentity {
+    pub vid: u16,
+    pub pid: u16,
+    pub product: ArrayString<PRODUCT_MAX>,
+}
+
+impl Default for UsbIdentity {
+    fn default() -> Self {
+        Self {
+            vid: 0x16c0,
+            pid: 0x27dd,
+            product: ArrayString::from(board::USB_PRODUCT).unwrap(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Config {
+    pub usb: UsbIdentity,
+    // Arm the stored channel configuration at power-on, see main's
+    // auto_arm()
+    pub autoarm: bool,
+    // No READY banner when the host opens the port
+    pub quiet: bool,
+    pub protect: Thresholds,
+    // Bit per channel left out of arming, see PulseGenerator::set_enabled()
+    pub disabled: u8,
+    pub interlock: interlock::Settings,
+    // See PulseGenerator::set_refuse_stuck_trigger()
+    pub refuse_stuck_trigger: bool,
+    pub debug_pin: debugpin::Settings,
+    pub safe_state: safestate::Settings,
+}
+
+// Cycles added to each output's first delay, see
+// PulseGenerator::set_calibration()
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Calibration {
+    pub offsets: [i32; NUM_CHANNELS],
+}
+
+// Counted over the device's life, see lifetime
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Lifetime {
+    pub seconds: u64,
+    // Table runs that went out whole
+    pub runs: [u64; NUM_CHANNELS],
+    pub high_us: [u64; NUM_CHANNELS],
+}
+
+// Why the stored config wasn't used
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConfigError {
+    // Erased sector, nothing was ever saved
+    Missing,
+    // Unknown magic or version, or a CRC mismatch
+    Corrupt,
+    BadProduct,
+    // Above protect::TEMP_MAX
+    BadTempMax,
+    // A VSYS minimum on a board without VSYS sensing
+    NoVsys,
+    // Bits of channels this build doesn't have
+    BadDisabled,
+    // Not a GPIO, or the trigger input
+    BadInterlockPin,
+    // Not a GPIO, the trigger input or the interlock's, or unknown events
+    BadDebugPin,
+    // Two levels for a pin, or a pin safestate::valid_pin() refuses or that
+    // is the interlock's or the debug pin
+    BadSafeState,
+}
+
+impl ConfigError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConfigError::Missing => "MISSING",
+            ConfigError::Corrupt => "CORRUPT",
+            ConfigError::BadProduct => "BAD_PRODUCT",
+            ConfigError::BadTempMax => "BAD_TEMP_MAX",
+            ConfigError::NoVsys => "NO_VSYS",
+            ConfigError::BadDisabled => "BAD_DISABLED",
+            ConfigError::BadInterlockPin => "BAD_INTERLOCK_PIN",
+            ConfigError::BadDebugPin => "BAD_DEBUG_PIN",
+            ConfigError::BadSafeState => "BAD_SAFE_STATE",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FlashError {
+    // Some channel is armed, see write()
+    ChannelsActive,
+}
+
+impl FlashError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FlashError::ChannelsActive => "CHANNELS_ACTIVE",
+        }
+    }
+}
+
+// Writes are only allowed with every channel disarmed. For commands that
+// change something else along with the stored record, checked first.
+pub fn check_idle() -> Result<(), FlashError> {
+    if cfg!(feature = "flash-config") && (0..NUM_CHANNELS).any(pulse_generator::is_armed) {
+        return Err(FlashError::ChannelsActive);
+    }
+    Ok(())
+}
+
+// Printable ASCII, the descriptor is sent as UTF-16 and hosts show it as is
+pub fn valid_product(product: &str) -> bool {
+    (1..=PRODUCT_MAX).contains(&product.len()) && product.bytes().all(|b| (0x20..0x7f).contains(&b))
+}
+
+// The stored config, or the defaults if there is none or it is rejected
+pub fn load() -> Config {
+    load_checked().unwrap_or_default()
+}
+
+// The stored config, or why it can't be used. A record with a good CRC is
+// still checked field by field, as the commands setting them would, so a
+// hand-edited or foreign one can't get past what the device accepts.
+// Without flash-config nothing is read or written, the device always starts
+// from the defaults and settings last until power-off.
+pub fn load_checked() -> Result<Config, ConfigError> {
+    if !cfg!(feature = "flash-config") {
+        return Err(ConfigError::Missing);
+    }
+    // Safety: the config sector is mapped read-only through XIP
+    let page = unsafe { &*((XIP_BASE + CONFIG_OFFSET) as *const [u8; PAGE_SIZE]) };
+    decode(page)
+}
+
+fn decode(page: &[u8; PAGE_SIZE]) -> Result<Config, ConfigError> {
+    let u16_at = |at: usize| u16::from_le_bytes([page[at], page[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes(page[at..at + 4].try_into().unwrap());
+    let version = u16_at(4);
+    if page.iter().all(|&b| b == 0xff) {
+        return Err(ConfigError::Missing);
+    }
+    if u32_at(0) != MAGIC
+        || !(1..=VERSION).contains(&version)
+        || u32_at(CRC_AT) != crc32(&page[..CRC_AT])
+    {
+        return Err(ConfigError::Corrupt);
+    }
+    let len = page[PRODUCT_LEN_AT] as usize;
+    let product = page
+        .get(PRODUCT_AT..PRODUCT_AT + len)
+        .and_then(|product| core::str::from_utf8(product).ok())
+        .filter(|product| valid_product(product))
+        .ok_or(ConfigError::BadProduct)?;
+    let flags = if version >= 2 { page[FLAGS_AT] } else { 0 };
+    let protect = if version >= 3 {
+        Thresholds {
+            temp_max: Some(page[TEMP_MAX_AT]).filter(|&max| max != 0xff),
+            vsys_min: Some(u16_at(VSYS_MIN_AT)).filter(|&min| min != 0),
+            action: if flags & FLAG_PROTECT_DISARM != 0 {
+                Action::Disarm
+            } else {
+                Action::Warn
+            },
+        }
+    } else {
+        Thresholds::default()
+    };
+    if protect.temp_max.is_some_and(|max| max > protect::TEMP_MAX) {
+        return Err(ConfigError::BadTempMax);
+    }
+    if protect.vsys_min.is_some() && board::VSYS_ADC.is_none() {
+        return Err(ConfigError::NoVsys);
+    }
+    let disabled = if version >= 4 { page[DISABLED_AT] } else { 0 };
+    if disabled as u32 >> NUM_CHANNELS != 0 {
+        return Err(ConfigError::BadDisabled);
+    }
+    let interlock = interlock::Settings {
+        pin: Some(page[INTERLOCK_PIN_AT]).filter(|&pin| version >= 5 && pin != 0xff),
+        release: if flags & FLAG_INTERLOCK_MOMENTARY != 0 {
+            Release::Momentary
+        } else {
+            Release::Latch
+        },
+    };
+    // The channels' pins aren't set up yet, they start on the defaults
+    if interlock
+        .pin
+        .is_some_and(|pin| pin >= probe::GPIO_COUNT || pin == pulse_generator::TRIGGER_PIN)
+    {
+        return Err(ConfigError::BadInterlockPin);
+    }
+    let debug_pin = debugpin::Settings {
+        pin: Some(page[DEBUG_PIN_AT]).filter(|&pin| version >= 6 && pin != 0xff),
+        events: if version >= 6 {
+            page[DEBUG_EVENTS_AT]
+        } else {
+            0
+        },
+    };
+    if debug_pin.pin.is_some_and(|pin| {
+        pin >= probe::GPIO_COUNT
+            || pin == pulse_generator::TRIGGER_PIN
+            || interlock.pin == Some(pin)
+    }) || debug_pin.events & !debugpin::EVENTS != 0
+    {
+        return Err(ConfigError::BadDebugPin);
+    }
+    let safe_state = if version >= 7 {
+        safestate::Settings {
+            low: u32_at(SAFE_LOW_AT),
+            high: u32_at(SAFE_HIGH_AT),
+            tristate: u32_at(SAFE_TRISTATE_AT),
+        }
+    } else {
+        safestate::Settings::default()
+    };
+    let mut taken = interlock.pin.into_iter().chain(debug_pin.pin);
+    if !safe_state.valid() || taken.any(|pin| safe_state.pins() & 1 << pin != 0) {
+        return Err(ConfigError::BadSafeState);
+    }
+    Ok(Config {
+        usb: UsbIdentity {
+            vid: u16_at(VID_AT),
+            pid: u16_at(PID_AT),
+            product: ArrayString::from(product).map_err(|_| ConfigError::BadProduct)?,
+        },
+        autoarm: flags & FLAG_AUTOARM != 0,
+        quiet: flags & FLAG_QUIET != 0,
+        protect,
+        disabled,
+        interlock,
+        refuse_stuck_trigger: flags & FLAG_REFUSE_STUCK_TRIGGER != 0,
+        debug_pin,
+        safe_state,
+    })
+}
+
+#[cfg(feature = "flash-config")]
+fn encode(config: &Config) -> [u8; PAGE_SIZE] {
+    let mut page = [0xff; PAGE_SIZE];
+    page[..4].copy_from_slice(&MAGIC.to_le_bytes());
+    page[4..6].copy_from_slice(&VERSION.to_le_bytes());
+    page[VID_AT..VID_AT + 2].copy_from_slice(&config.usb.vid.to_le_bytes());
+    page[PID_AT..PID_AT + 2].copy_from_slice(&config.usb.pid.to_le_bytes());
+    let product = config.usb.product.as_bytes();
+    page[PRODUCT_LEN_AT] = product.len() as u8;
+    page[PRODUCT_AT..PRODUCT_AT + product.len()].copy_from_slice(product);
+    let disarm = config.protect.action == Action::Disarm;
+    let momentary = config.interlock.release == Release::Momentary;
+    page[FLAGS_AT] = (config.autoarm as u8 * FLAG_AUTOARM)
+        | (config.quiet as u8 * FLAG_QUIET)
+        | (disarm as u8 * FLAG_PROTECT_DISARM)
+        | (momentary as u8 * FLAG_INTERLOCK_MOMENTARY)
+        | (config.refuse_stuck_trigger as u8 * FLAG_REFUSE_STUCK_TRIGGER);
+    page[TEMP_MAX_AT] = config.protect.temp_max.unwrap_or(0xff);
+    let vsys_min = config.protect.vsys_min.unwrap_or(0);
+    page[VSYS_MIN_AT..VSYS_MIN_AT + 2].copy_from_slice(&vsys_min.to_le_bytes());
+    page[DISABLED_AT] = config.disabled;
+    page[INTERLOCK_PIN_AT] = config.interlock.pin.unwrap_or(0xff);
+    page[DEBUG_PIN_AT] = config.debug_pin.pin.unwrap_or(0xff);
+    page[DEBUG_EVENTS_AT] = config.debug_pin.events;
+    page[SAFE_LOW_AT..SAFE_LOW_AT + 4].copy_from_slice(&config.safe_state.low.to_le_bytes());
+    page[SAFE_HIGH_AT..SAFE_HIGH_AT + 4].copy_from_slice(&config.safe_state.high.to_le_bytes());
+    page[SAFE_TRISTATE_AT..SAFE_TRISTATE_AT + 4]
+        .copy_from_slice(&config.safe_state.tristate.to_le_bytes());
+    let crc = crc32(&page[..CRC_AT]);
+    page[CRC_AT..].copy_from_slice(&crc.to_le_bytes());
+    page
+}
+
+// Erases the config sector and writes the record
+#[cfg(feature = "flash-config")]
+pub fn save(config: &Config) -> Result<(), FlashError> {
+    write(CONFIG_OFFSET, &encode(config))
+}
+
+// The stored script, None if there is none or it is corrupt
+pub fn load_script() -> Option<Script> {
+    if !cfg!(feature = "flash-config") {
+        return None;
+    }
+    // Safety: the script sector is mapped read-only through XIP
+    let record = unsafe { &*((XIP_BASE + SCRIPT_OFFSET) as *const [u8; SCRIPT_RECORD_LEN]) };
+    let u16_at = |at: usize| u16::from_le_bytes([record[at], record[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes(record[at..at + 4].try_into().unwrap());
+    let len = u16_at(SCRIPT_LEN_AT) as usize;
+    if u32_at(0) != SCRIPT_MAGIC || u16_at(4) != SCRIPT_VERSION || len > SCRIPT_MAX {
+        return None;
+    }
+    let text = &record[SCRIPT_TEXT_AT..SCRIPT_TEXT_AT + len];
+    let crc = crc32_update(crc32_update(!0, &record[..SCRIPT_CRC_AT]), text);
+    if u32_at(SCRIPT_CRC_AT) != !crc {
+        return None;
+    }
+    let flags = record[SCRIPT_FLAGS_AT];
+    let mut script = Script::new(script::Flags {
+        boot: flags & SCRIPT_FLAG_BOOT != 0,
+        unrestricted: flags & SCRIPT_FLAG_UNRESTRICTED != 0,
+    });
+    script.text.try_extend_from_slice(text).ok()?;
+    Some(script)
+}
+
+// Erases the script sector and writes the script, or leaves it erased with
+// None
+pub fn save_script(script: Option<&Script>) -> Result<(), FlashError> {
+    if !cfg!(feature = "flash-config") {
+        return Ok(());
+    }
+    let mut record = [0xff; SCRIPT_RECORD_LEN];
+    if let Some(script) = script {
+        let text = &script.text;
+        record[..4].copy_from_slice(&SCRIPT_MAGIC.to_le_bytes());
+        record[4..6].copy_from_slice(&SCRIPT_VERSION.to_le_bytes());
+        record[SCRIPT_FLAGS_AT] = (script.flags.boot as u8 * SCRIPT_FLAG_BOOT)
+            | (script.flags.unrestricted as u8 * SCRIPT_FLAG_UNRESTRICTED);
+        record[SCRIPT_LEN_AT..SCRIPT_LEN_AT + 2]
+            .copy_from_slice(&(text.len() as u16).to_le_bytes());
+        record[SCRIPT_TEXT_AT..SCRIPT_TEXT_AT + text.len()].copy_from_slice(text);
+        let crc = !crc32_update(crc32_update(!0, &record[..SCRIPT_CRC_AT]), text);
+        record[SCRIPT_CRC_AT..SCRIPT_CRC_AT + 4].copy_from_slice(&crc.to_le_bytes());
+    }
+    write(SCRIPT_OFFSET, &record)
+}
+
+// The stored calibration, all zero if there is none, it is corrupt or it
+// was written by a build with another channel count
+pub fn load_calibration() -> Calibration {
+    let mut calibration = Calibration::default();
+    if !cfg!(feature = "flash-config") {
+        return calibration;
+    }
+    // Safety: the calibration sector is mapped read-only through XIP
+    let page = unsafe { &*((XIP_BASE + CAL_OFFSET) as *const [u8; PAGE_SIZE]) };
+    let u16_at = |at: usize| u16::from_le_bytes([page[at], page[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes(page[at..at + 4].try_into().unwrap());
+    if u32_at(0) != CAL_MAGIC
+        || u16_at(4) != CAL_VERSION
+        || page[CAL_CHANNELS_AT] as usize != NUM_CHANNELS
+        || u32_at(CAL_CRC_AT) != crc32(&page[..CAL_CRC_AT])
+    {
+        return calibration;
+    }
+    for (ch, offset) in calibration.offsets.iter_mut().enumerate() {
+        *offset = u32_at(CAL_OFFSETS_AT + 4 * ch) as i32;
+    }
+    calibration
+}
+
+// Erases the calibration sector and writes the calibration, or leaves it
+// erased with None
+pub fn save_calibration(calibration: Option<&Calibration>) -> Result<(), FlashError> {
+    if !cfg!(feature = "flash-config") {
+        return Ok(());
+    }
+    let mut page = [0xff; PAGE_SIZE];
+    if let Some(calibration) = calibration {
+        page[..4].copy_from_slice(&CAL_MAGIC.to_le_bytes());
+        page[4..6].copy_from_slice(&CAL_VERSION.to_le_bytes());
+        page[CAL_CHANNELS_AT] = NUM_CHANNELS as u8;
+        for (ch, offset) in calibration.offsets.iter().enumerate() {
+            let at = CAL_OFFSETS_AT + 4 * ch;
+            page[at..at + 4].copy_from_slice(&offset.to_le_bytes());
+        }
+        let crc = crc32(&page[..CAL_CRC_AT]);
+        page[CAL_CRC_AT..CAL_CRC_AT + 4].copy_from_slice(&crc.to_le_bytes());
+    }
+    write(CAL_OFFSET, &page)
+}
+
+// The stored lifetime counters, all zero if there are none or they are
+// corrupt. Channels the record has and this build hasn't are dropped,
+// ones it lacks start at zero.
+pub fn load_lifetime() -> Lifetime {
+    let mut lifetime = Lifetime::default();
+    if !cfg!(feature = "flash-config") {
+        return lifetime;
+    }
+    // Safety: the lifetime sector is mapped read-only through XIP
+    let page = unsafe { &*((XIP_BASE + LIFE_OFFSET) as *const [u8; PAGE_SIZE]) };
+    let u16_at = |at: usize| u16::from_le_bytes([page[at], page[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes(page[at..at + 4].try_into().unwrap());
+    let u64_at = |at: usize| u64::from_le_bytes(page[at..at + 8].try_into().unwrap());
+    let channels = page[LIFE_CHANNELS_AT] as usize;
+    if u32_at(0) != LIFE_MAGIC
+        || u16_at(4) < LIFE_VERSION
+        || channels > LIFE_CHANNELS_MAX
+        || u32_at(LIFE_CRC_AT) != crc32(&page[..LIFE_CRC_AT])
+    {
+        return lifetime;
+    }
+    lifetime.seconds = u64_at(LIFE_SECONDS_AT);
+    for ch in 0..channels.min(NUM_CHANNELS) {
+        let at = LIFE_CHANNEL_AT + LIFE_CHANNEL_LEN * ch;
+        lifetime.runs[ch] = u64_at(at);
+        lifetime.high_us[ch] = u64_at(at + 8);
+    }
+    lifetime
+}
+
+// Erases the lifetime sector and writes the counters
+pub fn save_lifetime(lifetime: &Lifetime) -> Result<(), FlashError> {
+    if !cfg!(feature = "flash-config") {
+        return Ok(());
+    }
+    let mut page = [0xff; PAGE_SIZE];
+    page[..4].copy_from_slice(&LIFE_MAGIC.to_le_bytes());
+    page[4..6].copy_from_slice(&LIFE_VERSION.to_le_bytes());
+    page[LIFE_CHANNELS_AT] = NUM_CHANNELS as u8;
+    page[LIFE_SECONDS_AT..LIFE_SECONDS_AT + 8].copy_from_slice(&lifetime.seconds.to_le_bytes());
+    for ch in 0..NUM_CHANNELS {
+        let at = LIFE_CHANNEL_AT + LIFE_CHANNEL_LEN * ch;
+        page[at..at + 8].copy_from_slice(&lifetime.runs[ch].to_le_bytes());
+        page[at + 8..at + 16].copy_from_slice(&lifetime.high_us[ch].to_le_bytes());
+    }
+    let crc = crc32(&page[..LIFE_CRC_AT]);
+    page[LIFE_CRC_AT..].copy_from_slice(&crc.to_le_bytes());
+    write(LIFE_OFFSET, &page)
+}
+
+// The stored channel configuration, None if there is none. The blob is
+// left for PulseGenerator::restore() to check.
+pub fn load_snapshot() -> Option<&'static [u8]> {
+    if !cfg!(feature = "flash-config") {
+        return None;
+    }
+    // Safety: the snapshot sector is mapped read-only through XIP
+    let record = unsafe { &*((XIP_BASE + SNAP_OFFSET) as *const [u8; SNAP_RECORD_LEN]) };
+    let len = u16::from_le_bytes([record[SNAP_LEN_AT], record[SNAP_LEN_AT + 1]]);
+    if len == 0xffff {
+        return None;
+    }
+    Some(&record[SNAP_BLOB_AT..SNAP_BLOB_AT + (len as usize).min(SNAP_MAX)])
+}
+
+// Erases the snapshot sector and writes a snapshot blob, or leaves it erased
+// with None
+#[cfg(feature = "flash-config")]
+pub fn save_snapshot(blob: Option<&[u8]>) -> Result<(), FlashError> {
+    let mut record = [0xff; SNAP_RECORD_LEN];
+    if let Some(blob) = blob {
+        record[SNAP_LEN_AT..SNAP_LEN_AT + 2].copy_from_slice(&(blob.len() as u16).to_le_bytes());
+        record[SNAP_BLOB_AT..SNAP_BLOB_AT + blob.len()].copy_from_slice(blob);
+    }
+    write(SNAP_OFFSET, &record)
+}
+
+// Erases the sector at `offset` and programs `data`, a whole number of
+// pages. Runs with interrupts off and nothing running from flash for
+// typically 50 ms, at most about 430 ms with the W25Q16JV's worst case
+// figures (400 ms per sector erase, 3 ms per page, 10 pages for a
+// snapshot).
+// PIO and DMA carry on from RAM, but the main loop doesn't: no stream
+// refills, retriggers or re-arms meanwhile. So it is refused while a
+// channel is armed.
+fn write(offset: u32, data: &[u8]) -> Result<(), FlashError> {
+    check_idle()?;
+    // The ROM routines leave XIP in a slow generic mode, the board's second
+    // stage bootloader is rerun from a RAM copy to restore it
+    let mut boot2 = [0u32; 64];
+    // Safety: the first 256 bytes of flash hold boot2
+    unsafe { core::ptr::copy_nonoverlapping(XIP_BASE as *const u32, boot2.as_mut_ptr(), 64) };
+    let rom = RomFlash {
+        connect_internal_flash: unsafe {
+            core::mem::transmute(rom_data::connect_internal_flash::ptr())
+        },
+        flash_exit_xip: unsafe { core::mem::transmute(rom_data::flash_exit_xip::ptr()) },
+        flash_range_erase: unsafe { core::mem::transmute(rom_data::flash_range_erase::ptr()) },
+        flash_range_program: unsafe { core::mem::transmute(rom_data::flash_range_program::ptr()) },
+        flash_flush_cache: unsafe { core::mem::transmute(rom_data::flash_flush_cache::ptr()) },
+        // Thumb bit set
+        boot2: unsafe { core::mem::transmute((boot2.as_ptr() as usize + 1) as *const ()) },
+    };
+    cortex_m::interrupt::free(|_| unsafe { write_sector(&rom, offset, data) });
+    Ok(())
+}
+
+// ROM entry points looked up beforehand, the lookup code lives in flash
+struct RomFlash {
+    connect_internal_flash: extern "C" fn(),
+    flash_exit_xip: extern "C" fn(),
+    flash_range_erase: extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: extern "C" fn(),
+    boot2: extern "C" fn(),
+}
+
+// Runs from RAM, flash can't be read while it is erased or programmed
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn write_sector(rom: &RomFlash, offset: u32, data: &[u8]) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+    (rom.flash_range_erase)(offset, SECTOR_SIZE, 1 << 16, BLOCK_ERASE_CMD);
+    (rom.flash_range_program)(offset, data.as_ptr(), data.len());
+    (rom.flash_flush_cache)();
+    (rom.boot2)();
+}
@@ -0,0 +1,119 @@
+// Trigger glitch detector: a spare SM times every high pulse on the trigger
+// input and pushes the ones shorter than a threshold, and its DMA channel
+// copies them into a ring as tlog's does. A glitch is an edge the channels'
+// wait takes as a trigger that is gone again within the threshold, the count
+// and the shortest width tell how short a pulse a trigger needs rejected.
+// Runs on expert SM2 and DMA channel 2 while no expert program or SKEW?
+// uses them, whether or not a channel is armed.
+
+use pio::{Assembler, JmpCondition, MovDestination, MovOperation, MovSource, WaitSource};
+
+use crate::tlog;
+
+// Widths kept for update(), the oldest is overwritten first
+pub const GLITCH_LEN: usize = 16;
+// A pulse is timed in 2 cycle steps, rounded up
+pub const CYCLES_PER_COUNT: u32 = 2;
+// The shortest pulse the program times, one seen by a single sample
+const MIN_WIDTH: u32 = 2;
+// Below it no pulse could count
+pub const MIN_THRESHOLD: u32 = MIN_WIDTH + 1;
+
+// Aligned to its size for the DMA write ring
+#[repr(C, align(64))]
+struct Ring([u32; GLITCH_LEN]);
+
+static mut RING: Ring = Ring([0; GLITCH_LEN]);
+pub const RING_RAM: usize = core::mem::size_of::<Ring>();
+
+// Each pulse starts X at Y, put there from the TX FIFO when the SM starts,
+// and counts it down every 2 cycles while the pin stays high. Low again
+// first is a glitch and X is pushed, X running out is a trigger and the
+// program waits for it to end.
+pub fn program() -> pio::Program<32> {
+    let mut asm: Assembler<32> = Assembler::new();
+    let mut wrap_target = asm.label();
+    let mut wrap_source = asm.label();
+    let mut high = asm.label();
+    let mut still_high = asm.label();
+
+    asm.pull(false, true);
+    asm.mov(MovDestination::Y, MovOperation::None, MovSource::OSR);
+    asm.bind(&mut wrap_target);
+    asm.wait(0, WaitSource::PIN, 0, false);
+    asm.wait(1, WaitSource::PIN, 0, false);
+    asm.mov(MovDestination::X, MovOperation::None, MovSource::Y);
+    asm.bind(&mut high);
+    asm.jmp(JmpCondition::PinHigh, &mut still_high);
+    // A full FIFO drops the width, which only happens if the DMA is
+    // stopped
+    asm.mov(MovDestination::ISR, MovOperation::None, MovSource::X);
+    asm.push(false, false);
+    asm.jmp(JmpCondition::Always, &mut wrap_target);
+    asm.bind(&mut still_high);
+    asm.jmp(JmpCondition::XDecNonZero, &mut high);
+    asm.bind(&mut wrap_source);
+
+    // A decrement through zero falls through, the wrap takes it back to
+    // waiting for the pin to go low
+    asm.assemble_with_wrap(wrap_source, wrap_target)
+}
+
+// Starts the DMA channel copying the SM's RX FIFO into the ring, with the
+// ring cleared
+pub fn start_dma(dma_ch: u8, sm: u8) {
+    // Safety: the ring is only written by this DMA channel, which is
+    // stopped, and read through Glitches::update()
+    let ring = unsafe { core::ptr::addr_of_mut!(RING) };
+    unsafe { (*ring).0 = [0; GLITCH_LEN] };
+    tlog::start_ring_dma(dma_ch, sm, ring as *mut u32, GLITCH_LEN);
+}
+
+// What the detector has seen since it started or was reset
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Glitches {
+    // High pulses shorter than this many cycles count
+    pub threshold: u32,
+    pub count: u32,
+    // Cycles, None before the first glitch
+    pub shortest: Option<u32>,
+}
+
+impl Glitches {
+    // `threshold` is at least MIN_THRESHOLD
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            count: 0,
+            shortest: None,
+        }
+    }
+
+    // The Y the program starts with. A glitch is timed at 2 * Y + 2 cycles
+    // at the most, which stays under the threshold.
+    pub fn loops(&self) -> u32 {
+        (self.threshold - 1) / CYCLES_PER_COUNT - 1
+    }
+
+    // Takes in the widths pushed since the last call. More than GLITCH_LEN
+    // in between are all counted, but only the last GLITCH_LEN widths are
+    // looked at for the shortest.
+    pub fn update(&mut self, dma_ch: u8) {
+        let total = tlog::captured(dma_ch);
+        let first = total.saturating_sub(GLITCH_LEN as u32).max(self.count);
+        let loops = self.loops();
+        // Safety: volatile reads of words the DMA writes whole
+        let ring = unsafe { core::ptr::addr_of!(RING.0) } as *const u32;
+        for i in first..total {
+            let left = unsafe { ring.add(i as usize % GLITCH_LEN).read_volatile() };
+            let width = (loops - left.min(loops)) * CYCLES_PER_COUNT + MIN_WIDTH;
+            self.shortest = Some(self.shortest.map_or(width, |shortest| shortest.min(width)));
+        }
+        self.count = total;
+    }
+
+    // After the DMA restarted with start_dma()
+    pub fn reset(&mut self) {
+        *self = Self::new(self.threshold);
+    }
+}
@@ -0,0 +1,15 @@
+// Handlers of the commands a cargo feature adds, one module per feature so
+// leaving the feature out leaves out its handlers. execute() passes each
+// module its feature's commands, the reply helpers they share stay in
+// main.rs. SCPI only adds the *RST spelling of RESET, see command::parse().
+
+#[cfg(feature = "binary-proto")]
+pub mod binary;
+#[cfg(feature = "capture")]
+pub mod capture;
+#[cfg(feature = "flash-config")]
+pub mod flash_config;
+#[cfg(feature = "selftest")]
+pub mod selftest;
+#[cfg(feature = "usb-log")]
+pub mod usb_log;
@@ -0,0 +1,81 @@
+// The binary protocol's readback frames
+
+use arrayvec::ArrayVec;
+
+use crate::command::Command;
+use crate::features;
+use crate::perf::Perf;
+use crate::protect::Protect;
+use crate::pulse_generator::PulseGenerator;
+use crate::readback::Readback;
+use crate::text::Text;
+use crate::wire::ReadSource;
+use crate::{handle_command, AutoArm, Response};
+
+pub fn execute(command: Command, response: &mut Response) {
+    match command {
+        // From a script, at the port main takes the frames itself
+        Command::ReadStart(_) | Command::ChunkAck(_) | Command::ChunkResend(_) => {
+            response.put("ERR NOT_IN_SCRIPT");
+        }
+        _ => unreachable!(),
+    }
+}
+
+// FRAME_READ: the header line, then chunk 0 or "READ END". FRAME_CHUNK:
+// the chunk that follows the acked one, or the one asked for again. The
+// sources are rendered as their ASCII queries reply, SNAP as the blob.
+pub fn readback_command(
+    command: Command,
+    readback: &mut Option<Readback>,
+    pulse_gen: &mut PulseGenerator,
+    perf: &Perf,
+    autoarm: &AutoArm,
+    protect: &mut Protect,
+) -> ArrayVec<Response, 2> {
+    let mut lines = ArrayVec::new();
+    let mut line = Response::new();
+    let chunk = match command {
+        Command::ReadStart(source) => {
+            let current = match source {
+                ReadSource::Snap => Readback::new(source, &pulse_gen.snapshot()),
+                _ => {
+                    let query = match source {
+                        ReadSource::Capabilities => Command::Capabilities,
+                        ReadSource::Log => Command::LogQuery,
+                        _ => Command::TlogQuery,
+                    };
+                    let query = features::check(query);
+                    let reply = handle_command(query, pulse_gen, perf, autoarm, protect);
+                    Readback::new(source, reply.as_bytes())
+                }
+            };
+            current.write_header(&mut line);
+            lines.push(core::mem::take(&mut line));
+            *readback = Some(current);
+            0
+        }
+        Command::ChunkAck(n) | Command::ChunkResend(n) => {
+            let Some(current) = readback.as_ref() else {
+                line.put("ERR NO_READBACK");
+                lines.push(line);
+                return lines;
+            };
+            match current.answer(n, matches!(command, Command::ChunkAck(_))) {
+                Some(chunk) => chunk,
+                None => {
+                    line.put("ERR BAD_CHUNK max ")
+                        .dec(current.chunks().saturating_sub(1));
+                    lines.push(line);
+                    return lines;
+                }
+            }
+        }
+        _ => unreachable!(),
+    };
+    if let Some(current) = readback {
+        current.write_chunk(chunk, &mut line);
+    }
+    lines.push(line);
+    lines
+}
@@ -0,0 +1,80 @@
+// TLOG and GLITCH, the timestamp log and glitch counter on SM3
+
+use crate::command::Command;
+use crate::glitch;
+use crate::pulse_generator::PulseGenerator;
+use crate::text::Text;
+use crate::time::{self, Nanos, Rounding};
+use crate::{to_achieved_u32, write_expert_result, write_ok_achieved, write_time_error, Response};
+
+pub fn execute(command: Command, pulse_gen: &mut PulseGenerator, response: &mut Response) {
+    let sys_hz = pulse_gen.sys_hz();
+    let rounding = pulse_gen.rounding();
+    match command {
+        Command::Tlog(enabled) => {
+            write_expert_result(response, pulse_gen.set_tlog(enabled));
+        }
+        // "OK <edges since arm>" then the last edges in ns from the arm,
+        // oldest first
+        Command::TlogQuery => match pulse_gen.tlog() {
+            Some((total, cycles)) => {
+                response.put("OK ").dec(total);
+                for cycles in cycles {
+                    let Nanos(ns) = Nanos::from_cycles(cycles, sys_hz, Rounding::Down);
+                    response.put(" ").dec(ns);
+                }
+            }
+            None => {
+                response.put("OK OFF");
+            }
+        },
+        Command::Glitch(width) => {
+            let width = match width.map(|width| to_achieved_u32(width, sys_hz, rounding)) {
+                Some(Ok(width)) if width.cycles < glitch::MIN_THRESHOLD as u64 => {
+                    let min = glitch::MIN_THRESHOLD as u64;
+                    response.put("ERR BELOW_RESOLUTION min ");
+                    time::write_ps(response, time::cycles_to_ps(min, sys_hz));
+                    return;
+                }
+                Some(Ok(width)) => Some(width),
+                Some(Err(err)) => {
+                    response.put("ERR ");
+                    write_time_error(response, err, sys_hz);
+                    return;
+                }
+                None => None,
+            };
+            let threshold = width.map(|width| width.cycles as u32);
+            match (pulse_gen.set_glitch(threshold), width) {
+                (Ok(()), Some(width)) => write_ok_achieved(response, width, sys_hz),
+                (result, _) => write_expert_result(response, result),
+            }
+        }
+        Command::GlitchReset => {
+            pulse_gen.reset_glitches();
+            response.put("OK");
+        }
+        // "OK <count> shortest <time>|NONE under <time>", the widths to
+        // within 2 cycles
+        Command::GlitchQuery => match pulse_gen.glitches() {
+            Some(glitches) => {
+                response.put("OK ").dec(glitches.count).put(" shortest ");
+                match glitches.shortest {
+                    Some(cycles) => {
+                        time::write_ps(response, time::cycles_to_ps(cycles as u64, sys_hz))
+                    }
+                    None => {
+                        response.put("NONE");
+                    }
+                }
+                response.put(" under ");
+                let threshold = glitches.threshold as u64;
+                time::write_ps(response, time::cycles_to_ps(threshold, sys_hz));
+            }
+            None => {
+                response.put("OK OFF");
+            }
+        },
+        _ => unreachable!(),
+    }
+}
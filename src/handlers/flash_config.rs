@@ -0,0 +1,57 @@
+// Settings written to flash: the USB identity, AUTOARM, the saved channel
+// configuration and the banner. Their queries stay in execute(), without
+// the feature they report the defaults.
+
+use arrayvec::ArrayString;
+
+use crate::command::Command;
+use crate::flash;
+use crate::pulse_generator::PulseGenerator;
+use crate::text::Text;
+use crate::{write_flash_result, Response};
+
+pub fn execute(command: Command, pulse_gen: &mut PulseGenerator, response: &mut Response) {
+    match command {
+        Command::UsbId(vid, pid, product) => {
+            if !flash::valid_product(product) {
+                response
+                    .put("ERR BAD_PRODUCT 1 to ")
+                    .dec(flash::PRODUCT_MAX)
+                    .put(" printable chars");
+                return;
+            }
+            let mut config = flash::load();
+            config.usb = flash::UsbIdentity {
+                vid,
+                pid,
+                product: ArrayString::from(product).unwrap(),
+            };
+            if write_flash_result(response, flash::save(&config)) {
+                response.put(" applies after power cycle");
+            }
+        }
+        Command::AutoArm(enabled) => {
+            let mut config = flash::load();
+            config.autoarm = enabled;
+            if write_flash_result(response, flash::save(&config)) {
+                response.put(" applies after power cycle");
+            }
+        }
+        // Restored at power-on, and armed there with AUTOARM ON
+        Command::SnapSave => {
+            write_flash_result(
+                response,
+                flash::save_snapshot(Some(pulse_gen.snapshot().as_slice())),
+            );
+        }
+        Command::SnapClear => {
+            write_flash_result(response, flash::save_snapshot(None));
+        }
+        Command::Banner(enabled) => {
+            let mut config = flash::load();
+            config.quiet = !enabled;
+            write_flash_result(response, flash::save(&config));
+        }
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,202 @@
+// STRESS, SKEW? and SELFTEST, checks of the driver on the device itself
+
+use arrayvec::ArrayString;
+
+use crate::command::{self, Command};
+use crate::features;
+use crate::perf::Perf;
+use crate::protect::Protect;
+use crate::pulse_generator::{self, PulseGenerator, SkewError, NUM_CHANNELS};
+use crate::text::Text;
+use crate::{
+    check_channel, debugpin, handle_command, interlock, write_pulse_error, AutoArm, Response,
+};
+
+pub fn execute(
+    command: Command,
+    pulse_gen: &mut PulseGenerator,
+    perf: &Perf,
+    autoarm: &AutoArm,
+    protect: &mut Protect,
+    response: &mut Response,
+) {
+    match command {
+        Command::Stress(ch, runs) => {
+            if check_channel(ch, response) {
+                let failures = pulse_gen.stress(ch, runs);
+                response
+                    .put("OK STRESS ")
+                    .dec(runs)
+                    .put(" runs ")
+                    .dec(failures)
+                    .put(" early stalls");
+            }
+        }
+        Command::StressDma(ch, runs) => {
+            if check_channel(ch, response) {
+                let failures = pulse_gen.stress_dma(ch, runs);
+                response
+                    .put("OK STRESS DMA ")
+                    .dec(runs)
+                    .put(" runs ")
+                    .dec(failures)
+                    .put(" underruns");
+            }
+        }
+        // "OK <cycles> cyc", ch1's rise less ch0's
+        Command::Skew => match pulse_gen.measure_skew() {
+            Ok(skew) => {
+                response.put("OK ");
+                if skew < 0 {
+                    response.put("-");
+                }
+                response.dec(skew.unsigned_abs()).put(" cyc");
+            }
+            Err(SkewError::Busy) => {
+                response.put("ERR BUSY");
+            }
+            Err(SkewError::Disabled { ch }) => {
+                response.put("ERR DISABLED ch").dec(ch);
+            }
+            Err(SkewError::InUse) => {
+                response.put("ERR IN_USE sm2, see RES?");
+            }
+            Err(SkewError::NoSpace) => {
+                response.put("ERR NO_SPACE, see RES?");
+            }
+            Err(SkewError::NoEdge) => {
+                response
+                    .put("ERR NO_EDGE max ")
+                    .dec(pulse_generator::SKEW_MAX as u32);
+            }
+            Err(SkewError::Arm(err)) => write_pulse_error(response, &err),
+        },
+        Command::SelfTest => self_test(pulse_gen, perf, autoarm, protect, response),
+        _ => unreachable!(),
+    }
+}
+
+// Cycles ch1's table starts after ch0's in SELFTEST, inside the sampler's
+// window
+const SELFTEST_OFFSET: usize = 4;
+
+// SELFTEST: builds a two channel experiment from RESET through execute()
+// alone, as the host would over serial. Both outputs and a shared trigger
+// input are picked at run time from the board's PIO pins, off the default
+// outputs, so every pin setting goes through an SM rebuild. The group is
+// armed, then ch0 alone is re-armed on the per-edge program and back, so its
+// program is swapped twice next to ch1's armed SM and the copy they shared.
+// Triggered, the SM2 sampler has to see ch0 rise on its pin and ch1 on its
+// own SELFTEST_OFFSET cycles later, and both runs have to be counted whole.
+// Leaves the device as after RESET.
+// "OK SELFTEST out <pin> <pin> trigger <pin>", or the first failing step.
+fn self_test(
+    pulse_gen: &mut PulseGenerator,
+    perf: &Perf,
+    autoarm: &AutoArm,
+    protect: &mut Protect,
+    response: &mut Response,
+) {
+    if (0..NUM_CHANNELS).any(|ch| pulse_gen.debug(ch).running) || pulse_gen.internal().is_some() {
+        response.put("ERR BUSY");
+        return;
+    }
+    let mut step = |line: &str, pulse_gen: &mut PulseGenerator, response: &mut Response| {
+        let command = command::parse(line.as_bytes()).and_then(features::check);
+        let reply = handle_command(command, pulse_gen, perf, autoarm, protect);
+        if reply.starts_with("OK") {
+            return true;
+        }
+        response
+            .put("ERR SELFTEST ")
+            .put(line)
+            .put(": ")
+            .put(&reply);
+        false
+    };
+    if !step("RESET", pulse_gen, response) {
+        return;
+    }
+    let taken = |pin: u8| {
+        pin == pulse_generator::TRIGGER_PIN
+            || (0..NUM_CHANNELS).any(|ch| pulse_gen.pin(ch) == pin)
+            || interlock::settings().pin == Some(pin)
+            || debugpin::settings().pin == Some(pin)
+    };
+    let mut free = pulse_gen
+        .pio_pins()
+        .iter()
+        .copied()
+        .filter(|&pin| !taken(pin));
+    let (Some(out0), Some(out1), Some(trigger)) = (free.next(), free.next(), free.next()) else {
+        response.put("ERR SELFTEST NO_PINS");
+        return;
+    };
+    let mut lines: [ArrayString<64>; 13] = Default::default();
+    lines[0].put("PIN 0 ").dec(out0);
+    lines[1].put("PIN 1 ").dec(out1);
+    lines[2].put("TRIGPIN 0 ").dec(trigger);
+    lines[3].put("TRIGPIN 1 ").dec(trigger);
+    lines[4].put("DIVIDER 0 1");
+    lines[5].put("TABLE 0 200,50;100,50");
+    lines[6]
+        .put("TABLE 1 ")
+        .dec(200 + SELFTEST_OFFSET)
+        .put(",50;100,50");
+    lines[7].put("GROUP SELFTEST 0 1");
+    lines[8].put("ARM SELFTEST");
+    lines[9].put("PEREDGE 0 ON");
+    lines[10].put("ARM 0");
+    lines[11].put("PEREDGE 0 OFF");
+    lines[12].put("ARM 0");
+    if !lines
+        .iter()
+        .all(|line| step(line.as_str(), pulse_gen, response))
+    {
+        let _ = step("RESET", pulse_gen, &mut Response::new());
+        return;
+    }
+    let result = pulse_gen.sample_run(0, out0);
+    let counted = (0..NUM_CHANNELS).all(|ch| {
+        let stats = pulse_gen.run_stats(ch);
+        stats.runs == 1 && stats.pulses == 2
+    });
+    let _ = step("RESET", pulse_gen, &mut Response::new());
+    let samples = match result {
+        Ok(samples) => samples,
+        Err(SkewError::InUse) => {
+            response.put("ERR IN_USE sm2, see RES?");
+            return;
+        }
+        Err(SkewError::NoSpace) => {
+            response.put("ERR NO_SPACE, see RES?");
+            return;
+        }
+        Err(_) => {
+            response.put("ERR SELFTEST NO_EDGE");
+            return;
+        }
+    };
+    let rise0 = samples
+        .first()
+        .is_some_and(|sample| sample & 1 << out0 != 0);
+    let rise1 = samples.iter().position(|sample| sample & 1 << out1 != 0);
+    if !rise0 || rise1 != Some(SELFTEST_OFFSET - 1) {
+        response.put("ERR SELFTEST SAMPLES");
+        for sample in &samples {
+            response.put(" ").hex0(*sample, 8);
+        }
+        return;
+    }
+    if !counted {
+        response.put("ERR SELFTEST RUNSTAT");
+        return;
+    }
+    response
+        .put("OK SELFTEST out ")
+        .dec(out0)
+        .put(" ")
+        .dec(out1)
+        .put(" trigger ")
+        .dec(trigger);
+}
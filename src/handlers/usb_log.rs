@@ -0,0 +1,17 @@
+// LOG USB, the log records sent to the host. LOG? stays in execute(), it
+// reports OFF without the feature.
+
+use crate::command::Command;
+use crate::text::Text;
+use crate::usblog;
+use crate::Response;
+
+pub fn execute(command: Command, response: &mut Response) {
+    match command {
+        Command::LogUsb(level) => {
+            usblog::set_level(level);
+            response.put("OK");
+        }
+        _ => unreachable!(),
+    }
+}
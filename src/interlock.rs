@@ -0,0 +1,286 @@
+// Safety interlock: an input that has to be held high for the channels to
+// output anything. The GPIO interrupt catches it going low, stops the
+// channels' SMs and holds every channel output pin low with the GPIO output
+// override, within a couple of us wherever the sequences are. The override
+// is what keeps the pins low, an SM restarted by a racing main loop write
+// can't drive them. Main then disarms the channels properly and arming is
+// refused until the interlock is released: by the input going high again
+// with MOMENTARY, by INTERLOCK CLEAR with the input high with LATCH. The
+// override only comes off once no channel is armed and PIO no longer drives
+// any of its pins high.
+//
+// Flash writes hold the interrupt off, they are only allowed with every
+// channel disarmed, see flash::check_idle().
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+
+use crate::board::hal::pac::{self, interrupt};
+use crate::probe::{self, GPIO_COUNT};
+use crate::pulse_generator::{self, ArmGuard, GuardError, NUM_CHANNELS, TRIGGER_PIN};
+
+const NO_PIN: u8 = 0xff;
+
+// Input pin, NO_PIN without an interlock
+static PIN: AtomicU8 = AtomicU8::new(NO_PIN);
+static MOMENTARY: AtomicBool = AtomicBool::new(false);
+// Set by the interrupt, cleared by a release
+static TRIPPED: AtomicBool = AtomicBool::new(false);
+// Output pins the override holds low while tripped
+static FORCED: AtomicU32 = AtomicU32::new(0);
+// A trip poll() hasn't reported yet
+static UNREPORTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Release {
+    // Stays tripped until INTERLOCK CLEAR
+    #[default]
+    Latch,
+    // Released as soon as the input is high again
+    Momentary,
+}
+
+impl Release {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Release::Latch => "LATCH",
+            Release::Momentary => "MOMENTARY",
+        }
+    }
+}
+
+// Stored in the flash config
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Settings {
+    // None without an interlock
+    pub pin: Option<u8>,
+    pub release: Release,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum State {
+    Off,
+    // Input high, channels may be armed
+    Closed,
+    // Input low
+    Open,
+    // Input high again, waiting for INTERLOCK CLEAR
+    Latched,
+}
+
+impl State {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            State::Off => "OFF",
+            State::Closed => "CLOSED",
+            State::Open => "OPEN",
+            State::Latched => "LATCHED",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InterlockError {
+    // Released or reconfigured while tripped
+    Tripped(State),
+    // Armed again before the release, main disarms everything on a trip
+    Armed(usize),
+    // PIO drives the pin high under the override
+    Driven(u8),
+}
+
+// Reported by poll()
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Event {
+    Opened,
+    // Released by itself, MOMENTARY only
+    Closed,
+}
+
+// Enables the bank's interrupt with the stored settings. A low input trips
+// right away, so nothing arms at power-on without the interlock closed.
+pub fn init(settings: Settings) {
+    let _ = configure(settings);
+    // Safety: the handler below only touches what this module owns
+    unsafe { cortex_m::peripheral::NVIC::unmask(pac::Interrupt::IO_IRQ_BANK0) };
+}
+
+// Refused while tripped, a trip can't be undone by moving or removing the
+// interlock
+pub fn configure(settings: Settings) -> Result<(), InterlockError> {
+    if TRIPPED.load(Ordering::Acquire) {
+        return Err(InterlockError::Tripped(state()));
+    }
+    // Only stored from the main loop, thumbv6m has no atomic swap
+    let old = PIN.load(Ordering::Relaxed);
+    PIN.store(settings.pin.unwrap_or(NO_PIN), Ordering::Release);
+    if old != NO_PIN {
+        set_interrupt(old, false);
+    }
+    MOMENTARY.store(settings.release == Release::Momentary, Ordering::Relaxed);
+    if let Some(pin) = settings.pin {
+        // Pulled down, so a disconnected interlock reads open
+        // Safety: only the interlock's pad is touched
+        let pads = unsafe { &*pac::PADS_BANK0::ptr() };
+        pads.gpio(pin as usize)
+            .modify(|_, w| w.ie().set_bit().pue().clear_bit().pde().set_bit());
+        set_interrupt(pin, true);
+    }
+    Ok(())
+}
+
+pub fn settings() -> Settings {
+    let pin = PIN.load(Ordering::Relaxed);
+    Settings {
+        pin: (pin != NO_PIN).then_some(pin),
+        release: if MOMENTARY.load(Ordering::Relaxed) {
+            Release::Momentary
+        } else {
+            Release::Latch
+        },
+    }
+}
+
+// Whether the pin can carry the interlock, not the trigger input nor a
+// channel's output
+pub fn valid_pin(pin: u8) -> bool {
+    pin < GPIO_COUNT && pin != TRIGGER_PIN && pulse_generator::output_pins() & 1 << pin == 0
+}
+
+pub fn state() -> State {
+    let pin = PIN.load(Ordering::Relaxed);
+    if pin == NO_PIN {
+        return State::Off;
+    }
+    let closed = probe::read(pin);
+    match (TRIPPED.load(Ordering::Acquire), closed) {
+        (_, false) => State::Open,
+        (true, true) => State::Latched,
+        (false, true) => State::Closed,
+    }
+}
+
+// Arming is refused unless this is false
+pub fn blocks() -> bool {
+    matches!(state(), State::Open | State::Latched)
+}
+
+// Refuses every arm while blocks(), re-arms from service() included, which
+// the host commands' own check doesn't see
+pub struct Guard;
+
+impl ArmGuard for Guard {
+    fn check(&self, _channel: usize) -> Result<(), GuardError> {
+        if blocks() {
+            return Err(GuardError {
+                reason: "INTERLOCK",
+            });
+        }
+        Ok(())
+    }
+}
+
+// Polled from the main loop, reports each trip once and releases a
+// MOMENTARY interlock once the input is high again
+pub fn poll() -> Option<Event> {
+    let opened = cortex_m::interrupt::free(|_| {
+        let opened = UNREPORTED.load(Ordering::Acquire);
+        UNREPORTED.store(false, Ordering::Release);
+        opened
+    });
+    if opened {
+        return Some(Event::Opened);
+    }
+    if TRIPPED.load(Ordering::Acquire) && MOMENTARY.load(Ordering::Relaxed) && release().is_ok() {
+        return Some(Event::Closed);
+    }
+    None
+}
+
+// INTERLOCK CLEAR, a no-op unless tripped
+pub fn clear() -> Result<(), InterlockError> {
+    if !TRIPPED.load(Ordering::Acquire) {
+        return Ok(());
+    }
+    release()
+}
+
+// Takes the override off again after checking nothing goes high under it
+fn release() -> Result<(), InterlockError> {
+    if state() == State::Open {
+        return Err(InterlockError::Tripped(State::Open));
+    }
+    if let Some(ch) = (0..NUM_CHANNELS).find(|&ch| pulse_generator::is_armed(ch)) {
+        return Err(InterlockError::Armed(ch));
+    }
+    // Safety: only the forced pins' override and status are touched
+    let io = unsafe { &*pac::IO_BANK0::ptr() };
+    let forced = FORCED.load(Ordering::Acquire);
+    let pins = (0..GPIO_COUNT).filter(|pin| forced & 1 << pin != 0);
+    for pin in pins.clone() {
+        // What PIO drives before the override
+        let status = io.gpio(pin as usize).gpio_status().read();
+        if status.oefromperi().bit_is_set() && status.outfromperi().bit_is_set() {
+            return Err(InterlockError::Driven(pin));
+        }
+    }
+    for pin in pins {
+        io.gpio(pin as usize)
+            .gpio_ctrl()
+            .modify(|_, w| w.outover().normal().oeover().normal());
+    }
+    FORCED.store(0, Ordering::Release);
+    TRIPPED.store(false, Ordering::Release);
+    // The input is level sensed, going low meanwhile trips again right here
+    let pin = PIN.load(Ordering::Relaxed);
+    if pin != NO_PIN {
+        set_interrupt(pin, true);
+    }
+    Ok(())
+}
+
+// The level low interrupt of the pin on processor 0
+fn set_interrupt(pin: u8, enabled: bool) {
+    let mask = 1 << (4 * (pin as u32 % 8));
+    // Safety: only the pin's level low enable bit changes, with interrupts
+    // off so the handler's own change can't be lost
+    let io = unsafe { &*pac::IO_BANK0::ptr() };
+    let inte = io.proc0_inte(pin as usize / 8);
+    cortex_m::interrupt::free(|_| {
+        inte.modify(|r, w| unsafe {
+            w.bits(if enabled {
+                r.bits() | mask
+            } else {
+                r.bits() & !mask
+            })
+        })
+    });
+}
+
+#[interrupt]
+fn IO_IRQ_BANK0() {
+    let pin = PIN.load(Ordering::Relaxed);
+    if pin == NO_PIN {
+        return;
+    }
+    // Level sensed, it would fire again until the input is high. Release
+    // enables it again.
+    set_interrupt(pin, false);
+    // Safety: the channels' SM enables and the GPIO output overrides of
+    // their pins, which nothing else writes
+    let pio = unsafe { &*pac::PIO0::ptr() };
+    let io = unsafe { &*pac::IO_BANK0::ptr() };
+    let channels = (1 << NUM_CHANNELS) - 1;
+    pio.ctrl()
+        .modify(|r, w| unsafe { w.sm_enable().bits(r.sm_enable().bits() & !channels) });
+    let outputs = pulse_generator::output_pins();
+    for pin in (0..GPIO_COUNT).filter(|pin| outputs & 1 << pin != 0) {
+        io.gpio(pin as usize)
+            .gpio_ctrl()
+            .modify(|_, w| w.outover().low().oeover().enable());
+    }
+    // Nothing preempts the handler and release() only clears this with the
+    // interrupt off
+    FORCED.store(FORCED.load(Ordering::Acquire) | outputs, Ordering::Release);
+    TRIPPED.store(true, Ordering::Release);
+    UNREPORTED.store(true, Ordering::Release);
+}
@@ -0,0 +1,90 @@
+// Lifetime counters for maintenance: table runs and high time per channel
+// and power-on time, over the device's whole life. The stored counts are
+// read at boot and this boot's are added on top, the runs and high time
+// from the generator's RunTotals and the power-on time from the timer. They
+// are written back at most once every SAVE_INTERVAL_US, on LIFETIME SAVE
+// and on SHUTDOWN. Flash writes are refused while a channel is armed, so a
+// due save waits for the channels to be idle, and whatever was counted
+// since the last save is lost with the power.
+
+use crate::entropy;
+use crate::flash::{self, FlashError};
+use crate::pulse_generator::{PulseGenerator, RunTotals, NUM_CHANNELS};
+
+pub const SAVE_INTERVAL_US: u64 = 3_600 * 1_000_000;
+
+pub struct Lifetime {
+    // Loaded at boot, zeroed by a reset
+    stored: flash::Lifetime,
+    // This boot's totals and timer ticks (us) the stored counts go with
+    totals: [RunTotals; NUM_CHANNELS],
+    since: u64,
+    // Timer ticks (us) of the last save or attempt at one
+    saved_at: u64,
+    // Handed out by LIFETIME RESET, needed to confirm it
+    token: Option<u32>,
+}
+
+impl Lifetime {
+    pub fn load(now: u64) -> Self {
+        Self {
+            stored: flash::load_lifetime(),
+            totals: [RunTotals::default(); NUM_CHANNELS],
+            since: now,
+            saved_at: now,
+            token: None,
+        }
+    }
+
+    pub fn current(&self, pulse_gen: &PulseGenerator, now: u64) -> flash::Lifetime {
+        let mut current = self.stored;
+        current.seconds += now.saturating_sub(self.since) / 1_000_000;
+        for ch in 0..NUM_CHANNELS {
+            let totals = pulse_gen.run_totals(ch);
+            let high_cycles = totals.high_cycles - self.totals[ch].high_cycles;
+            current.runs[ch] += totals.runs - self.totals[ch].runs;
+            current.high_us[ch] +=
+                (high_cycles as u128 * 1_000_000 / pulse_gen.sys_hz() as u128) as u64;
+        }
+        current
+    }
+
+    pub fn save(&mut self, pulse_gen: &PulseGenerator, now: u64) -> Result<(), FlashError> {
+        self.saved_at = now;
+        flash::save_lifetime(&self.current(pulse_gen, now))
+    }
+
+    // Saves once SAVE_INTERVAL_US passed since the last save, and again an
+    // interval later if the channels were busy. Polled from the main loop.
+    pub fn poll(&mut self, pulse_gen: &PulseGenerator, now: u64) {
+        if now.saturating_sub(self.saved_at) >= SAVE_INTERVAL_US {
+            let _ = self.save(pulse_gen, now);
+        }
+    }
+
+    // A fresh token for reset(), each one good for a single try
+    pub fn token(&mut self) -> u32 {
+        let token = entropy::random_u32();
+        self.token = Some(token);
+        token
+    }
+
+    // Zeroes the counters and saves them, Ok(false) unless `token` is the
+    // one token() handed out last. Refused with a channel armed before the
+    // token is used up.
+    pub fn reset(
+        &mut self,
+        pulse_gen: &PulseGenerator,
+        token: u32,
+        now: u64,
+    ) -> Result<bool, FlashError> {
+        flash::check_idle()?;
+        if self.token.take() != Some(token) {
+            return Ok(false);
+        }
+        self.stored = flash::Lifetime::default();
+        self.totals = core::array::from_fn(|ch| pulse_gen.run_totals(ch));
+        self.since = now;
+        self.save(pulse_gen, now).map(|()| true)
+    }
+}
@@ -2,38 +2,103 @@
 #![no_std]
 #![no_main]
 
-use bsp::entry;
+use arrayvec::ArrayString;
+#[cfg(feature = "binary-proto")]
+use arrayvec::ArrayVec;
+use board::{entry, hal};
 use cortex_m::singleton;
-use defmt::*;
 use defmt_rtt as _;
-use embedded_hal::digital::OutputPin;
 use panic_probe as _;
 
-use rp_pico as bsp;
-
-use bsp::hal::{
+use hal::{
     clocks::{Clock, ClockSource, ClocksManager},
     fugit::HertzU32,
-    gpio::FunctionPio0,
     pac,
     pll::{common_configs::PLL_USB_48MHZ, setup_pll_blocking, PLLConfig},
     sio::Sio,
     usb::UsbBus,
     xosc::setup_xosc_blocking,
+    Timer,
 };
 
 use usb_device::{
     bus::UsbBusAllocator,
-    device::{StringDescriptors, UsbDeviceBuilder, UsbVidPid},
+    class::UsbClass,
+    device::{StringDescriptors, UsbDeviceBuilder, UsbDeviceState, UsbVidPid},
     LangID, UsbError,
 };
 use usbd_serial::{SerialPort, USB_CLASS_CDC};
 
+mod arm_queue;
+mod board;
+mod command;
+#[cfg(feature = "compat-dg")]
+mod compat;
+mod crc;
+mod csv;
+mod debugpin;
+mod disasm;
+mod entropy;
+mod features;
+mod flash;
+#[cfg(feature = "capture")]
+mod glitch;
+mod handlers;
+mod interlock;
+mod lifetime;
+mod mem;
+mod parser;
+mod perf;
+mod probe;
+mod protect;
 mod pulse_generator;
-use pulse_generator::PulseGenerator;
+#[cfg(feature = "binary-proto")]
+mod readback;
+mod safestate;
+mod script;
+mod snapshot;
+mod text;
+mod throttle;
+mod tick;
+mod time;
+mod timeline;
+#[cfg(feature = "capture")]
+mod tlog;
+mod usb;
+mod usblog;
+#[cfg(feature = "binary-proto")]
+mod wire;
+use arm_queue::{ArmQueueFull, ArmRequest};
+use command::{Command, CommandError, LedMode, Target, Value};
+use features::Feature;
+use flash::FlashError;
+use interlock::InterlockError;
+use lifetime::Lifetime;
+use parser::{Event, Mode, ParseError, Parser};
+use perf::{Perf, ARM_PHASES};
+use protect::{Action, Protect, Thresholds, Trip};
+#[cfg(any(feature = "binary-proto", feature = "capture"))]
+use pulse_generator::ExpertError;
+use pulse_generator::{
+    ChannelEvent, DelayError, GroupError, InstructionMemoryFull, Internal, InternalError, Invalid,
+    Level, Marker, MirrorError, NextError, OutputMode, Pairs, Problem, Problems, PulseError,
+    PulseGenerator, RestoreError, SequenceFull, T0Solution, Trigger, TriggerArmed, Violation,
+    INSTRUCTION_MEMORY, NUM_CHANNELS, NUM_PULSES_MAX, TRIGGER_OUT_LATENCY_CYCLES,
+};
+#[cfg(feature = "binary-proto")]
+use pulse_generator::{EXPERT_FEED_LEN, STREAM_BLOCK_PAIRS};
+#[cfg(feature = "binary-proto")]
+use readback::Readback;
+use safestate::Rest;
+use script::{Runner, Script, Step};
+use snapshot::{Blob, SNAP_CHUNK};
+use text::Text;
+use throttle::{Kind, Throttle, KINDS};
+use time::{Achieved, Cycles, Rounding, TimeError};
+use usblog::info;
+#[cfg(feature = "binary-proto")]
+use wire::{Pair, PAIR_LEN};
 
-// External high-speed crystal on the pico board is 12Mhz
-const XTAL_FREQ: HertzU32 = HertzU32::MHz(12);
 const PLL_SYS_250MHZ: PLLConfig = PLLConfig {
     vco_freq: HertzU32::MHz(1500),
     refdiv: 1,
@@ -43,12 +108,14 @@ const PLL_SYS_250MHZ: PLLConfig = PLLConfig {
 
 #[entry]
 fn main() -> ! {
+    // Before anything else is on the stack, see MEM?
+    mem::paint();
     let mut pac = pac::Peripherals::take().unwrap();
     let sio = Sio::new(pac.SIO);
 
     let mut clocks = ClocksManager::new(pac.CLOCKS);
 
-    let xosc = setup_xosc_blocking(pac.XOSC, XTAL_FREQ).unwrap();
+    let xosc = setup_xosc_blocking(pac.XOSC, board::XTAL_FREQ).unwrap();
 
     let pll_sys = setup_pll_blocking(
         pac.PLL_SYS,
@@ -76,6 +143,7 @@ fn main() -> ! {
         "System Clock: {} MHz",
         clocks.system_clock.get_freq().to_MHz()
     );
+    let sys_hz = clocks.system_clock.freq().to_Hz();
 
     clocks
         .usb_clock
@@ -87,12 +155,102 @@ fn main() -> ! {
         .configure_clock(&clocks.system_clock, clocks.system_clock.freq())
         .unwrap();
 
-    let pins = bsp::Pins::new(
+    // The ADC needs its 48MHz for the protection readings
+    clocks
+        .adc_clock
+        .configure_clock(&pll_usb, pll_usb.get_freq())
+        .unwrap();
+
+    // Hands the board's PIO pins to PIO0, pulled low and not yet driven
+    let mut led = board::init(
         pac.IO_BANK0,
         pac.PADS_BANK0,
         sio.gpio_bank0,
         &mut pac.RESETS,
     );
+    led.set(true);
+
+    let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+
+    // Everything up to AUTOARM is on the boot time budget, see BOOT?, and
+    // whatever the arm doesn't need comes after it. A rejected config is
+    // replaced by the defaults as a whole.
+    let config = flash::load_checked();
+    if let Err(err) = config {
+        info!("stored config not used: {}", err.as_str());
+    }
+    let config_error = config.as_ref().err().copied();
+    let config = config.unwrap_or_default();
+    // The pins rest as stored before the channels' SMs are built
+    safestate::init(config.safe_state);
+    let config_at = timer.get_counter().ticks();
+
+    // Channels are set up before the USB device exists. Their SMs are built
+    // stopped with the default outputs driven low, and only AUTOARM starts
+    // them: without it no pin changes level until the host arms a channel.
+    let mut pulse_gen =
+        PulseGenerator::new(pac.PIO0, pac.DMA, &mut pac.RESETS, sys_hz, board::PINS)
+            .unwrap_or_else(|err| {
+                defmt::panic!(
+                    "PIO program space: {} words requested, {} free",
+                    err.words,
+                    err.free
+                )
+            });
+    // Nothing armed yet, every channel's SM holds its pins at the idle level
+    let mismatch = pulse_gen.idle_mismatch();
+    for ch in (0..NUM_CHANNELS).filter(|ch| mismatch & 1 << ch != 0) {
+        usblog::warn!("ch{} not at its idle level", ch);
+    }
+    for ch in 0..NUM_CHANNELS {
+        if config.disabled & 1 << ch != 0 {
+            let _ = pulse_gen.set_enabled(ch, false);
+        }
+    }
+    pulse_gen.set_refuse_stuck_trigger(config.refuse_stuck_trigger);
+    pulse_gen.set_arm_guard(&interlock::Guard);
+    let calibration = flash::load_calibration();
+    for ch in 0..NUM_CHANNELS {
+        pulse_gen.set_calibration(ch, calibration.offsets[ch]);
+    }
+    let pio_at = timer.get_counter().ticks();
+    // Before anything arms, an open interlock trips right away
+    interlock::init(config.interlock);
+    debugpin::init(config.debug_pin, sys_hz);
+    let autoarm = if config.autoarm {
+        auto_arm(&mut pulse_gen)
+    } else {
+        if let Err(error) = restore_saved(&mut pulse_gen) {
+            info!("stored channels not used: {}", error.as_str());
+        }
+        AutoArm::Off
+    };
+    let autoarm_at = timer.get_counter().ticks();
+    info!("autoarm stage done at {} us", autoarm_at);
+
+    let mut protect = Protect::new(config.protect);
+    let mut lifetime = Lifetime::load(timer.get_counter().ticks());
+    // Tells the host about a restart, see wire
+    let session = entropy::random_u32();
+    info!("session {:08x}", session);
+    // A boot script runs from the main loop, skipped like AUTOARM while
+    // ARM_BUTTON is held
+    let mut script = flash::load_script();
+    let mut recording: Option<Script> = None;
+    // Lines go to the CSV upload while set, see csv
+    let mut upload: Option<csv::Upload> = None;
+    // SNAP lines received so far, restored by SNAP END
+    let mut snap = Blob::new();
+    // The reply the host is reading back in chunks, see readback
+    #[cfg(feature = "binary-proto")]
+    let mut readback: Option<Readback> = None;
+    // Lines go to the delay generator dialect while set
+    #[cfg(feature = "compat-dg")]
+    let mut compat: Option<compat::Dg> = None;
+    let mut runner = script
+        .as_ref()
+        .filter(|script| script.flags.boot && !board::arm_button_held())
+        .map(|_| Runner::new());
 
     let usb_bus = UsbBus::new(
         pac.USBCTRL_REGS,
@@ -104,59 +262,3300 @@ fn main() -> ! {
     let usb_bus: &'static UsbBusAllocator<UsbBus> =
         singleton!(: UsbBusAllocator<UsbBus> = UsbBusAllocator::new(usb_bus)).unwrap();
     let mut serial = SerialPort::new(usb_bus);
-    let descriptor = StringDescriptors::new(LangID::EN_US).product("Pico-Pulse");
-    let mut usb_dev = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x16c0, 0x27dd))
+    // Falls back to the defaults if the stored identity is missing or corrupt
+    let usb_id: &'static flash::UsbIdentity =
+        singleton!(: flash::UsbIdentity = config.usb).unwrap();
+    let descriptor = StringDescriptors::new(LangID::EN_US).product(&usb_id.product);
+    let mut usb_dev = UsbDeviceBuilder::new(usb_bus, UsbVidPid(usb_id.vid, usb_id.pid))
         .strings(&[descriptor])
         .unwrap()
         .device_class(USB_CLASS_CDC)
         .build();
+    let boot = BootTimes {
+        config: config_at,
+        pio: pio_at,
+        autoarm: autoarm_at,
+        usb: timer.get_counter().ticks(),
+    };
 
-    // init pins for PIO
-    pins.gpio0.into_function::<FunctionPio0>();
-    pins.gpio1.into_function::<FunctionPio0>();
-    pins.gpio2.into_function::<FunctionPio0>();
-    pins.gpio3.into_function::<FunctionPio0>();
-    pins.gpio4.into_function::<FunctionPio0>();
-    pins.gpio5.into_function::<FunctionPio0>();
-    pins.gpio6.into_function::<FunctionPio0>();
-    pins.gpio7.into_function::<FunctionPio0>();
-    pins.gpio8.into_function::<FunctionPio0>();
-    pins.gpio9.into_function::<FunctionPio0>();
-    pins.gpio10.into_function::<FunctionPio0>();
-    pins.gpio11.into_function::<FunctionPio0>();
-    pins.gpio12.into_function::<FunctionPio0>();
-    pins.gpio13.into_function::<FunctionPio0>();
-    pins.gpio14.into_function::<FunctionPio0>();
-    pins.gpio15.into_function::<FunctionPio0>();
-    pins.gpio16.into_function::<FunctionPio0>();
-    pins.gpio17.into_function::<FunctionPio0>();
-    pins.gpio18.into_function::<FunctionPio0>();
-    pins.gpio19.into_function::<FunctionPio0>();
-    pins.gpio20.into_function::<FunctionPio0>();
-    pins.gpio21.into_function::<FunctionPio0>();
-    pins.gpio22.into_function::<FunctionPio0>();
-
-    let mut led_pin = pins.led.into_push_pull_output();
-    led_pin.set_high().unwrap();
-
-    let mut pulse_gen = PulseGenerator::new(pac.PIO0, pac.DMA, &mut pac.RESETS);
-    pulse_gen.set_delay(10);
-    pulse_gen.set_width(10);
-    pulse_gen.arm();
+    let mut parser = Parser::new();
+    let mut perf = Perf::new();
+    perf::init();
+    let mut throttle = Throttle::new();
+    // Set by the host's configuration, including after a USB reset
+    let mut configured = false;
+    // The host opened the port since, commands are taken from here on.
+    // Without the banner that doesn't wait for DTR.
+    let mut ready = false;
+    let mut quiet = false;
+    let mut reenum = usb::Reenum::new();
+    mem::mark_boot();
 
     loop {
+        // The outputs are already held low, this parks the channels for good
+        match interlock::poll() {
+            Some(interlock::Event::Opened) => {
+                disarm_everything(&mut pulse_gen);
+                write_line(&mut serial, b"ERR INTERLOCK OPEN");
+            }
+            Some(interlock::Event::Closed) => {
+                safestate::apply();
+                led.set_mode(led.mode());
+                write_line(&mut serial, b"INTERLOCK CLOSED");
+            }
+            None => {}
+        }
+
+        if let Some(err) = parser.poll(timer.get_counter().ticks()) {
+            if parser.mode() == Mode::Binary {
+                write_session(&mut serial, session);
+            }
+            write_error(&mut serial, err);
+        }
+
+        let now = timer.get_counter().ticks();
+        match pulse_gen.service(now) {
+            Some((ch, ChannelEvent::StreamDone(pulses))) => {
+                let mut response = Response::new();
+                response.put("STREAM ").dec(ch).put(" DONE ").dec(pulses);
+                write_line(&mut serial, response.as_bytes());
+            }
+            // "ERR NEXT <ch> <error>"
+            Some((ch, ChannelEvent::NextFailed(_))) if !throttle.allow(Kind::Next, ch, now) => {}
+            Some((ch, ChannelEvent::NextFailed(err))) => {
+                let mut response = Response::new();
+                response.put("ERR NEXT ").dec(ch).put(" ");
+                match err {
+                    NextError::Invalid(violation) => write_violation(&mut response, violation),
+                    NextError::Arm(err) => {
+                        let mut error = Response::new();
+                        write_pulse_error(&mut error, &err);
+                        response.put(error.trim_start_matches("ERR "));
+                    }
+                }
+                write_line(&mut serial, response.as_bytes());
+            }
+            // "ERR GUARD <ch> <reason>"
+            Some((ch, ChannelEvent::Guarded(_))) if !throttle.allow(Kind::Guard, ch, now) => {}
+            Some((ch, ChannelEvent::Guarded(err))) => {
+                let mut response = Response::new();
+                response.put("ERR GUARD ").dec(ch).put(" ").put(err.reason);
+                write_line(&mut serial, response.as_bytes());
+            }
+            Some((ch, ChannelEvent::Underrun(_))) if !throttle.allow(Kind::Underrun, ch, now) => {}
+            Some((ch, ChannelEvent::Underrun(underrun))) => {
+                let mut response = Response::new();
+                response
+                    .put("ERR UNDERRUN ")
+                    .dec(ch)
+                    .put(" ")
+                    .dec(underrun.pulses)
+                    .put(" at ")
+                    .dec(underrun.at)
+                    .put("us");
+                write_line(&mut serial, response.as_bytes());
+            }
+            None => {}
+        }
+        // One arm queued from the port per pass, see queue_arm()
+        service_arms(
+            &mut serial,
+            &mut pulse_gen,
+            &mut throttle,
+            &mut perf,
+            &protect,
+            &timer,
+            false,
+        );
+        // "ERR UNDERRUN <ch> SUPPRESSED <lines>"
+        if let Some((kind, ch, held)) = throttle.poll(now) {
+            let mut response = Response::new();
+            response
+                .put("ERR ")
+                .put(kind.as_str())
+                .put(" ")
+                .dec(ch)
+                .put(" SUPPRESSED ")
+                .dec(held);
+            write_line(&mut serial, response.as_bytes());
+        }
+        // "# INFO arm 0", one queued log line per pass. Binary frames can't
+        // carry them, there they are taken and dropped.
+        #[cfg(feature = "usb-log")]
+        if let Some(record) = usblog::pop() {
+            if parser.mode() == Mode::Ascii {
+                let mut response = Response::new();
+                usblog::render(&mut response, &record);
+                write_line(&mut serial, response.as_bytes());
+            }
+        }
+
+        let now = timer.get_counter().ticks();
+        if let Some(trip) = protect.poll(now) {
+            if protect.thresholds.action == Action::Disarm {
+                disarm_everything(&mut pulse_gen);
+                safestate::apply();
+            }
+            let mut response = Response::new();
+            write_trip(&mut response, trip);
+            write_line(&mut serial, response.as_bytes());
+        }
+
+        if let (Some(run), Some(stored)) = (runner.as_mut(), script.as_ref()) {
+            let mut response = Response::new();
+            let finished = match run.poll(stored, now) {
+                Step::Wait => false,
+                Step::Done(lines) => {
+                    response.put("SCRIPT DONE ").dec(lines);
+                    true
+                }
+                Step::Line(number, line) => {
+                    service_arms(
+                        &mut serial,
+                        &mut pulse_gen,
+                        &mut throttle,
+                        &mut perf,
+                        &protect,
+                        &timer,
+                        true,
+                    );
+                    let command = command::parse(line).and_then(features::check);
+                    let reply = match command {
+                        Ok(Command::Sleep(value)) => {
+                            run.sleep(now + to_us(value, sys_hz));
+                            Response::new()
+                        }
+                        Ok(Command::WaitDone(ch, timeout)) => {
+                            wait_done(&mut pulse_gen, ch, to_us(timeout, sys_hz))
+                        }
+                        Ok(command) if !stored.flags.unrestricted && writes_flash(&command) => {
+                            let mut reply = Response::new();
+                            reply.put("ERR UNSAFE");
+                            reply
+                        }
+                        _ => handle_command(command, &mut pulse_gen, &perf, &autoarm, &mut protect),
+                    };
+                    if let Some(mode) = led_mode(&command) {
+                        led.set_mode(mode);
+                    }
+                    if matches!(command, Ok(Command::ProtectClear | Command::InterlockClear)) {
+                        led.set_mode(led.mode());
+                    }
+                    // "ERR SCRIPT <line> <error>", the rest of the script is
+                    // dropped
+                    let failed = reply.starts_with("ERR ");
+                    if failed {
+                        response
+                            .put("ERR SCRIPT ")
+                            .dec(number)
+                            .put(" ")
+                            .put(&reply[4..]);
+                    }
+                    failed
+                }
+            };
+            if !response.is_empty() {
+                write_line(&mut serial, response.as_bytes());
+            }
+            if finished {
+                runner = None;
+            }
+        }
+
+        if upload.as_ref().is_some_and(|upload| upload.timed_out(now)) {
+            upload = None;
+            write_line(&mut serial, b"ERR CSV TIMEOUT nothing loaded");
+        }
+
+        if protect.tripped().is_some() || interlock::blocks() {
+            led.fault(now);
+        } else if let LedMode::Activity(ch) = led.mode() {
+            led.activity(pulse_gen.debug(ch).emitted(), now);
+        }
+
+        // The host sees a new device and resets the bus, which takes the
+        // state below back to Default and brings the banner again
+        if reenum.poll(now) {
+            info!("usb attached again");
+        }
+        lifetime.poll(&pulse_gen, now);
+
+        // Suspend and resume keep the configuration, so they don't count
+        match usb_dev.state() {
+            UsbDeviceState::Configured if !configured => {
+                configured = true;
+                ready = false;
+                perf.usb.new_session();
+                quiet = flash::load().quiet;
+                // Each session starts in ASCII mode
+                if parser.set_mode(Mode::Ascii) {
+                    info!("port mode ASCII");
+                }
+            }
+            UsbDeviceState::Default | UsbDeviceState::Addressed => configured = false,
+            _ => {}
+        }
+        if configured && !ready && (quiet || serial.dtr()) {
+            ready = true;
+            if !quiet {
+                write_banner(&mut serial, session);
+            }
+        }
+
         if !usb_dev.poll(&mut [&mut serial]) {
             continue;
         }
 
         let mut buf = [0u8; 64];
         match serial.read(&mut buf[..]) {
-            Ok(_count) => {
-                serial.write(&buf).unwrap();
-                pulse_gen.check();
+            // Sent before the banner, the host can't know it was seen
+            Ok(_) if !ready => {}
+            Ok(count) => {
+                let now = timer.get_counter().ticks();
+                // Acted on once the whole read is parsed
+                let mut reenum_asked = false;
+                for &byte in &buf[..count] {
+                    let event = parser.feed(byte, now);
+                    if matches!(event, Some(Event::Line(_) | Event::Frame { .. })) {
+                        debugpin::pulse(debugpin::CMD);
+                    }
+                    match event {
+                        Some(Event::Line(line)) if upload.is_some() => {
+                            service_arms(
+                                &mut serial,
+                                &mut pulse_gen,
+                                &mut throttle,
+                                &mut perf,
+                                &protect,
+                                &timer,
+                                true,
+                            );
+                            let current = upload.as_mut().unwrap();
+                            let mut response = Response::new();
+                            match current.line(line, now) {
+                                csv::Step::Taken => {}
+                                csv::Step::Progress(lines) => {
+                                    response.put("OK CSV ").dec(lines).put(" lines");
+                                }
+                                csv::Step::End => {
+                                    response = csv_end(upload.take().unwrap(), &mut pulse_gen);
+                                }
+                            }
+                            if !response.is_empty() {
+                                write_line(&mut serial, response.as_bytes());
+                            }
+                        }
+                        Some(Event::Line(line)) if recording.is_some() => {
+                            let response = record_line(line, &mut recording, &mut script);
+                            write_line(&mut serial, response.as_bytes());
+                        }
+                        Some(Event::Line(line)) => {
+                            let command = command::parse(line).and_then(features::check);
+                            // Anything but another ARM waits for the queued
+                            // ones, so it finds the channels as it would
+                            // have after a synchronous ARM
+                            if !matches!(command, Ok(Command::Arm(_))) {
+                                service_arms(
+                                    &mut serial,
+                                    &mut pulse_gen,
+                                    &mut throttle,
+                                    &mut perf,
+                                    &protect,
+                                    &timer,
+                                    true,
+                                );
+                            }
+                            if let Ok(Command::Compat(enabled)) = command {
+                                #[cfg(feature = "compat-dg")]
+                                {
+                                    compat = enabled.then(compat::Dg::new);
+                                }
+                                #[cfg(not(feature = "compat-dg"))]
+                                let _ = enabled;
+                                write_line(&mut serial, b"OK");
+                                continue;
+                            }
+                            // Only COMPAT DG OFF is taken as a command meanwhile
+                            #[cfg(feature = "compat-dg")]
+                            if let Some(dg) = &mut compat {
+                                if let Some(response) = dg.execute(line, &mut pulse_gen) {
+                                    write_line(&mut serial, response.as_bytes());
+                                }
+                                continue;
+                            }
+                            if let Ok(Command::Arm(target)) = command {
+                                let response = queue_arm(target, &mut pulse_gen, &protect);
+                                perf.command.record(timer.get_counter().ticks() - now);
+                                write_line(&mut serial, response.as_bytes());
+                                continue;
+                            }
+                            if command == Ok(Command::ModeQuery) {
+                                let response = mode_query(&parser);
+                                write_line(&mut serial, response.as_bytes());
+                                continue;
+                            }
+                            if command == Ok(Command::BootQuery) {
+                                let response = boot_query(&boot);
+                                write_line(&mut serial, response.as_bytes());
+                                continue;
+                            }
+                            if command == Ok(Command::UsbReenum) {
+                                reenum_asked = true;
+                                continue;
+                            }
+                            if let Ok(
+                                command @ (Command::LifetimeQuery
+                                | Command::LifetimeSave
+                                | Command::LifetimeReset(_)
+                                | Command::Shutdown),
+                            ) = command
+                            {
+                                let now = timer.get_counter().ticks();
+                                let response =
+                                    lifetime_command(command, &mut lifetime, &mut pulse_gen, now);
+                                write_line(&mut serial, response.as_bytes());
+                                continue;
+                            }
+                            // The summary, then an ERR line per problem
+                            if command == Ok(Command::Check) {
+                                let blocked = arm_blocked(&protect);
+                                let mut problems = Problems::new();
+                                let longest = pulse_gen.check(&mut problems);
+                                let mut response = Response::new();
+                                let sys_hz = pulse_gen.sys_hz();
+                                let blocks = blocked.is_some();
+                                write_check(&mut response, blocks, longest, &problems, sys_hz);
+                                write_line(&mut serial, response.as_bytes());
+                                if let Some(blocked) = blocked {
+                                    write_line(&mut serial, blocked.as_bytes());
+                                }
+                                for problem in &problems {
+                                    let mut line = Response::new();
+                                    line.put("ERR ");
+                                    write_problem(&mut line, problem);
+                                    write_line(&mut serial, line.as_bytes());
+                                }
+                                continue;
+                            }
+                            if command == Ok(Command::ConfigQuery) {
+                                let response = config_query(config_error);
+                                write_line(&mut serial, response.as_bytes());
+                                continue;
+                            }
+                            // A line per channel, quick enough to poll
+                            // from a dashboard
+                            if command == Ok(Command::Poll) {
+                                for ch in 0..NUM_CHANNELS {
+                                    let mut line = Response::new();
+                                    write_poll(&mut line, &pulse_gen, ch);
+                                    write_line(&mut serial, line.as_bytes());
+                                }
+                                continue;
+                            }
+                            if command == Ok(Command::ErrorQuery) {
+                                let response = error_query(&throttle, &perf);
+                                write_line(&mut serial, response.as_bytes());
+                                continue;
+                            }
+                            if command == Ok(Command::SessionQuery) {
+                                let mut response = Response::new();
+                                response.put("OK ").hex0(session, 8);
+                                write_line(&mut serial, response.as_bytes());
+                                continue;
+                            }
+                            if let Ok(
+                                command
+                                @ (Command::SnapQuery | Command::Snap(_) | Command::SnapEnd),
+                            ) = command
+                            {
+                                let response = snap_command(command, &mut snap, &mut pulse_gen);
+                                write_line(&mut serial, response.as_bytes());
+                                if command == Command::SnapQuery {
+                                    write_snapshot(&mut serial, &pulse_gen.snapshot());
+                                }
+                                continue;
+                            }
+                            if let Ok(Command::CsvLoad(ch)) = command {
+                                let mut response = Response::new();
+                                if check_channel(ch, &mut response)
+                                    && check_not_mirror(&pulse_gen, ch, &mut response)
+                                {
+                                    upload = Some(csv::Upload::new(ch, now));
+                                    response.put("OK end with .");
+                                }
+                                write_line(&mut serial, response.as_bytes());
+                                continue;
+                            }
+                            if let Ok(
+                                command @ (Command::ScriptBegin(_)
+                                | Command::ScriptEnd
+                                | Command::ScriptAbort
+                                | Command::ScriptClear
+                                | Command::ScriptQuery
+                                | Command::Run),
+                            ) = command
+                            {
+                                let response = script_command(
+                                    command,
+                                    &mut script,
+                                    &mut recording,
+                                    &mut runner,
+                                );
+                                write_line(&mut serial, response.as_bytes());
+                                if let (Command::ScriptQuery, Some(script)) = (command, &script) {
+                                    for line in script.lines() {
+                                        write_line(&mut serial, line);
+                                    }
+                                }
+                                continue;
+                            }
+                            let reset = command == Ok(Command::Reset);
+                            let listing = match command {
+                                Ok(Command::Program(ch)) if ch < NUM_CHANNELS => Some(ch),
+                                _ => None,
+                            };
+                            let timeline = match command {
+                                Ok(Command::Timeline(ch)) if ch < NUM_CHANNELS => Some(ch),
+                                _ => None,
+                            };
+                            let achieved = match command {
+                                Ok(Command::Achieved(ch)) if ch < NUM_CHANNELS => Some(ch),
+                                _ => None,
+                            };
+                            let words = match command {
+                                Ok(Command::Words(ch)) if ch < NUM_CHANNELS => Some(ch),
+                                _ => None,
+                            };
+                            let led_mode = led_mode(&command);
+                            let arming = matches!(&command, Ok(command) if arms(command));
+                            if arming {
+                                throttle.reset();
+                            }
+                            let clear = matches!(
+                                command,
+                                Ok(Command::ProtectClear | Command::InterlockClear)
+                            );
+                            let response = handle_command(
+                                command,
+                                &mut pulse_gen,
+                                &perf,
+                                &autoarm,
+                                &mut protect,
+                            );
+                            perf.command.record(timer.get_counter().ticks() - now);
+                            write_line(&mut serial, response.as_bytes());
+                            if arming {
+                                write_coalesced(&mut serial, &mut pulse_gen);
+                            }
+                            if let Some(ch) = listing {
+                                write_program(&mut serial, &pulse_gen.program(ch));
+                            }
+                            if let Some(ch) = timeline {
+                                write_timeline(&mut serial, &pulse_gen, ch);
+                            }
+                            if let Some(ch) = achieved {
+                                write_achieved(&mut serial, &pulse_gen, ch);
+                            }
+                            if let Some(ch) = words {
+                                write_words(&mut serial, &pulse_gen, ch);
+                            }
+                            if let Some(mode) = led_mode {
+                                led.set_mode(mode);
+                            }
+                            if clear {
+                                led.set_mode(led.mode());
+                            }
+                            if reset {
+                                parser.reset();
+                            }
+                        }
+                        Some(Event::Frame { .. }) if recording.is_some() => {
+                            write_session(&mut serial, session);
+                            write_line(&mut serial, b"ERR RECORDING");
+                        }
+                        #[cfg(feature = "binary-proto")]
+                        Some(Event::Frame { cmd, payload }) => {
+                            service_arms(
+                                &mut serial,
+                                &mut pulse_gen,
+                                &mut throttle,
+                                &mut perf,
+                                &protect,
+                                &timer,
+                                true,
+                            );
+                            let command = command::parse_frame(cmd, payload);
+                            if let Ok(
+                                command @ (Command::ReadStart(_)
+                                | Command::ChunkAck(_)
+                                | Command::ChunkResend(_)),
+                            ) = command
+                            {
+                                let lines = handlers::binary::readback_command(
+                                    command,
+                                    &mut readback,
+                                    &mut pulse_gen,
+                                    &perf,
+                                    &autoarm,
+                                    &mut protect,
+                                );
+                                perf.command.record(timer.get_counter().ticks() - now);
+                                for line in lines {
+                                    write_session(&mut serial, session);
+                                    write_line(&mut serial, line.as_bytes());
+                                }
+                                continue;
+                            }
+                            let response = handle_command(
+                                command,
+                                &mut pulse_gen,
+                                &perf,
+                                &autoarm,
+                                &mut protect,
+                            );
+                            perf.command.record(timer.get_counter().ticks() - now);
+                            write_session(&mut serial, session);
+                            write_line(&mut serial, response.as_bytes());
+                        }
+                        // Never seen, the port is kept out of binary mode
+                        #[cfg(not(feature = "binary-proto"))]
+                        Some(Event::Frame { .. }) => {}
+                        Some(Event::Mode(Mode::Binary)) if !Feature::BinaryProto.present() => {
+                            parser.set_mode(Mode::Ascii);
+                            write_bytes(&mut serial, b"ERR ");
+                            let err = CommandError::NotPresent(Feature::BinaryProto);
+                            write_line(&mut serial, err.as_str().as_bytes());
+                        }
+                        Some(Event::Mode(mode)) => {
+                            info!("port mode {}", mode.as_str());
+                            write_bytes(&mut serial, b"OK ");
+                            write_line(&mut serial, mode.as_str().as_bytes());
+                        }
+                        Some(Event::Error(err)) => {
+                            if parser.mode() == Mode::Binary {
+                                write_session(&mut serial, session);
+                            }
+                            write_error(&mut serial, err);
+                        }
+                        None => {}
+                    }
+                }
+                // Refused with the next line or frame already arriving, the
+                // host would lose it halfway
+                if reenum_asked && !parser.idle() {
+                    write_line(&mut serial, b"ERR BUSY");
+                } else if reenum_asked {
+                    write_line(&mut serial, b"OK");
+                    let until = timer.get_counter().ticks() + usb::FLUSH_US;
+                    while serial.flush().is_err() && timer.get_counter().ticks() < until {
+                        usb_dev.poll(&mut [&mut serial]);
+                    }
+                    info!("usb detached");
+                    reenum.detach(timer.get_counter().ticks());
+                    serial.reset();
+                    parser.reset();
+                }
             }
             Err(UsbError::WouldBlock) => {} // No data received
-            Err(_err) => {}                 // An error occurred
+            // What the class buffered may be part of a packet, so its
+            // buffers and the line or frame being parsed start over. The
+            // next poll reads afresh however many of these come in a row.
+            Err(
+                err
+                @ (UsbError::ParseError | UsbError::BufferOverflow | UsbError::EndpointOverflow),
+            ) => {
+                perf.usb.record(err);
+                serial.reset();
+                parser.reset();
+            }
+            // Not configured, or a class or driver bug, nothing to clear
+            Err(
+                err @ (UsbError::EndpointMemoryOverflow
+                | UsbError::InvalidEndpoint
+                | UsbError::Unsupported
+                | UsbError::InvalidState),
+            ) => perf.usb.record(err),
         };
     }
 }
+
+// Power-on to armed on a cold boot, BOOT? flags a boot over it
+const BOOT_BUDGET_US: u64 = 100_000;
+
+// Timer ticks (us) at the end of each power-on stage. The timer starts once
+// the clocks run, the crystal and PLL start-up before it isn't counted.
+struct BootTimes {
+    // Stored config loaded and checked
+    config: u64,
+    // Channel programs installed
+    pio: u64,
+    // AUTOARM armed, failed or was off
+    autoarm: u64,
+    // USB device built, enumeration follows in the main loop
+    usb: u64,
+}
+
+// LIFETIME?: "OK HOURS <h> ch<n> RUNS <runs> HIGH <us>us ...". LIFETIME
+// RESET: "OK TOKEN <hex>" to send back with it, a wrong one has to be asked
+// for again.
+fn lifetime_command(
+    command: Command,
+    lifetime: &mut Lifetime,
+    pulse_gen: &mut PulseGenerator,
+    now: u64,
+) -> Response {
+    let mut response = Response::new();
+    match command {
+        Command::LifetimeQuery => {
+            let current = lifetime.current(pulse_gen, now);
+            response.put("OK HOURS ").dec(current.seconds / 3600);
+            for ch in 0..NUM_CHANNELS {
+                response
+                    .put(" ch")
+                    .dec(ch)
+                    .put(" RUNS ")
+                    .dec(current.runs[ch])
+                    .put(" HIGH ")
+                    .dec(current.high_us[ch])
+                    .put("us");
+            }
+        }
+        Command::LifetimeSave => {
+            write_flash_result(&mut response, lifetime.save(pulse_gen, now));
+        }
+        Command::LifetimeReset(None) => {
+            response.put("OK TOKEN ").hex0(lifetime.token(), 8);
+        }
+        Command::LifetimeReset(Some(token)) => match lifetime.reset(pulse_gen, token, now) {
+            Ok(true) => {
+                response.put("OK");
+            }
+            Ok(false) => {
+                response.put("ERR BAD_TOKEN");
+            }
+            Err(err) => {
+                response.put("ERR ").put(err.as_str());
+            }
+        },
+        // Nothing is left armed, so the save can't be refused
+        Command::Shutdown => {
+            pulse_gen.reset_all();
+            power_on_defaults(pulse_gen);
+            safestate::apply();
+            write_flash_result(&mut response, lifetime.save(pulse_gen, now));
+        }
+        _ => {}
+    }
+    response
+}
+
+// "OK autoarm <us> us budget <us> [OVER_BUDGET]; config <us> pio <us>
+// usb <us>", each stage as the time it ended
+fn boot_query(boot: &BootTimes) -> Response {
+    let mut response = Response::new();
+    response
+        .put("OK autoarm ")
+        .dec(boot.autoarm)
+        .put(" us budget ")
+        .dec(BOOT_BUDGET_US)
+        .put(" us");
+    if boot.autoarm > BOOT_BUDGET_US {
+        response.put(" OVER_BUDGET");
+    }
+    response
+        .put("; config ")
+        .dec(boot.config)
+        .put(" pio ")
+        .dec(boot.pio)
+        .put(" usb ")
+        .dec(boot.usb);
+    response
+}
+
+// "OK STORED", "OK DEFAULTS" without a stored config, or
+// "ERR REJECTED <reason>" when the stored one failed its checks at power-on
+// and the defaults were used instead
+fn config_query(error: Option<flash::ConfigError>) -> Response {
+    let mut response = Response::new();
+    match error {
+        None => response.put("OK STORED"),
+        Some(flash::ConfigError::Missing) => response.put("OK DEFAULTS"),
+        Some(err) => response.put("ERR REJECTED ").put(err.as_str()),
+    };
+    // So a capture saved with it tells what the debug pin shows
+    response.put(" DEBUGPIN ");
+    write_debug_pin(&mut response);
+    response
+}
+
+// "OK UNDERRUN <ch0> <ch1> NEXT <ch0> <ch1>", the occurrences since
+// power-on whether their lines were sent or not, then the USB read errors
+// as PERF? has them
+fn error_query(throttle: &Throttle, perf: &Perf) -> Response {
+    let mut response = Response::new();
+    response.put("OK");
+    for kind in KINDS {
+        response.put(" ").put(kind.as_str());
+        for ch in 0..NUM_CHANNELS {
+            response.put(" ").dec(throttle.total(kind, ch));
+        }
+    }
+    for (name, count) in perf.usb.counts() {
+        response.put(" USB ").put(name).put(" ").dec(count);
+    }
+    response
+}
+
+// "OK <mode> switches <n> discarded <bytes> rejected <lines>"
+fn mode_query(parser: &Parser) -> Response {
+    let counters = parser.counters();
+    let mut response = Response::new();
+    response
+        .put("OK ")
+        .put(parser.mode().as_str())
+        .put(" switches ")
+        .dec(counters.switches)
+        .put(" discarded ")
+        .dec(counters.discarded)
+        .put(" rejected ")
+        .dec(counters.rejected);
+    response
+}
+
+// The LED mode a command switches to
+fn led_mode(command: &Result<Command, CommandError>) -> Option<LedMode> {
+    match *command {
+        Ok(Command::LedMode(LedMode::Activity(ch))) if ch >= NUM_CHANNELS => None,
+        Ok(Command::LedMode(mode)) => Some(mode),
+        Ok(Command::Reset) => Some(LedMode::Status),
+        _ => None,
+    }
+}
+
+// SNAP? is "OK <bytes> bytes" followed by write_snapshot()'s lines, which
+// the host sends back as they are to restore
+fn snap_command(command: Command, snap: &mut Blob, pulse_gen: &mut PulseGenerator) -> Response {
+    let mut response = Response::new();
+    match command {
+        Command::SnapQuery => {
+            response
+                .put("OK ")
+                .dec(pulse_gen.snapshot().len())
+                .put(" bytes");
+        }
+        Command::Snap(hex) => {
+            let bytes = hex
+                .as_bytes()
+                .chunks(2)
+                .map(|pair| u8::from_str_radix(core::str::from_utf8(pair).unwrap(), 16).unwrap());
+            for byte in bytes {
+                if snap.try_push(byte).is_err() {
+                    snap.clear();
+                    response.put("ERR SNAP_FULL");
+                    return response;
+                }
+            }
+            response.put("OK ").dec(snap.len()).put(" bytes");
+        }
+        Command::SnapEnd => {
+            match pulse_gen.restore(snap) {
+                Ok(()) => {
+                    response.put("OK RESTORED");
+                }
+                Err(err) => write_restore_error(&mut response, &err),
+            }
+            snap.clear();
+        }
+        _ => {}
+    }
+    response
+}
+
+// The snapshot as SNAP lines of SNAP_CHUNK bytes, then SNAP END
+fn write_snapshot(serial: &mut SerialPort<UsbBus>, blob: &[u8]) {
+    for chunk in blob.chunks(SNAP_CHUNK) {
+        let mut line = Response::new();
+        line.put("SNAP ");
+        for &byte in chunk {
+            line.hex0(byte, 2);
+        }
+        write_line(serial, line.as_bytes());
+    }
+    write_line(serial, b"SNAP END");
+}
+
+// SCRIPT and RUN typed at the port
+fn script_command(
+    command: Command,
+    script: &mut Option<Script>,
+    recording: &mut Option<Script>,
+    runner: &mut Option<Runner>,
+) -> Response {
+    let mut response = Response::new();
+    let running = runner.is_some();
+    match command {
+        Command::ScriptBegin(_) | Command::ScriptClear | Command::Run if running => {
+            response.put("ERR SCRIPT_RUNNING");
+        }
+        Command::ScriptBegin(flags) => {
+            *recording = Some(Script::new(flags));
+            response.put("OK end with SCRIPT END");
+        }
+        Command::ScriptClear => match flash::save_script(None) {
+            Ok(()) => {
+                *script = None;
+                response.put("OK");
+            }
+            Err(err) => {
+                response.put("ERR ").put(err.as_str());
+            }
+        },
+        Command::Run => match script {
+            Some(script) => {
+                *runner = Some(Runner::new());
+                response.put("OK ").dec(script.line_count()).put(" lines");
+            }
+            None => {
+                response.put("ERR NO_SCRIPT");
+            }
+        },
+        // "OK <lines> lines <bytes> bytes [BOOT] [UNSAFE] [RUNNING]", then
+        // the lines
+        Command::ScriptQuery => {
+            let (lines, bytes) = script
+                .as_ref()
+                .map_or((0, 0), |script| (script.line_count(), script.text.len()));
+            response
+                .put("OK ")
+                .dec(lines)
+                .put(" lines ")
+                .dec(bytes)
+                .put(" bytes");
+            if let Some(script) = script {
+                if script.flags.boot {
+                    response.put(" BOOT");
+                }
+                if script.flags.unrestricted {
+                    response.put(" UNSAFE");
+                }
+            }
+            if running {
+                response.put(" RUNNING");
+            }
+        }
+        // Only seen while recording
+        _ => {
+            response.put("ERR NOT_RECORDING");
+        }
+    }
+    response
+}
+
+// A line between SCRIPT BEGIN and SCRIPT END is checked and kept, not run.
+// SCRIPT END stores the script, SCRIPT ABORT drops it.
+fn record_line(
+    line: &[u8],
+    recording: &mut Option<Script>,
+    script: &mut Option<Script>,
+) -> Response {
+    let mut response = Response::new();
+    let Some(new) = recording.as_mut() else {
+        return response;
+    };
+    match command::parse(line) {
+        // Refused while armed, the recording goes on so SCRIPT END can be
+        // sent again after a DISARM
+        Ok(Command::ScriptEnd) => {
+            if let Err(err) = flash::save_script(Some(new)) {
+                response.put("ERR ").put(err.as_str());
+                return response;
+            }
+            response
+                .put("OK ")
+                .dec(new.line_count())
+                .put(" lines stored");
+            *script = recording.take();
+        }
+        Ok(Command::ScriptAbort) => {
+            *recording = None;
+            response.put("OK");
+        }
+        Ok(
+            Command::ScriptBegin(_) | Command::ScriptClear | Command::ScriptQuery | Command::Run,
+        ) => {
+            response.put("ERR NOT_IN_SCRIPT");
+        }
+        Ok(_) if !new.push_line(line) => {
+            response
+                .put("ERR SCRIPT_FULL max ")
+                .dec(script::SCRIPT_MAX)
+                .put(" bytes");
+        }
+        Ok(_) => {
+            response.put("OK ").dec(new.line_count());
+        }
+        Err(err) => {
+            response.put("ERR ").put(err.as_str());
+        }
+    }
+    response
+}
+
+// The TABLE answer for the upload's pairs, or "ERR CSV LINE <line>
+// <error>, <lines> lines, nothing loaded" for its first bad line
+fn csv_end(upload: csv::Upload, pulse_gen: &mut PulseGenerator) -> Response {
+    let mut response = Response::new();
+    if let Some((line, err)) = upload.error {
+        response
+            .put("ERR CSV LINE ")
+            .dec(line)
+            .put(" ")
+            .put(err.as_str())
+            .put(", ")
+            .dec(upload.lines)
+            .put(" lines, nothing loaded");
+        return response;
+    }
+    let pairs = upload.pairs.into_iter().map(Ok);
+    load_table(pulse_gen, upload.ch, pairs, &mut response);
+    response
+}
+
+// Commands changing the stored settings, only run by scripts stored with
+// UNSAFE
+fn writes_flash(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::UsbId(..)
+            | Command::AutoArm(_)
+            | Command::Banner(_)
+            | Command::SnapSave
+            | Command::SnapClear
+            | Command::Enable(..)
+            | Command::StuckTrigger(_)
+            | Command::CalSet(..)
+            | Command::CalClear
+            | Command::ProtectTemp(_)
+            | Command::ProtectVsys(_)
+            | Command::ProtectAction(_)
+            | Command::Interlock(_)
+            | Command::InterlockRelease(_)
+            | Command::DebugPin(_)
+            | Command::SafeState(..)
+    )
+}
+
+// Timer ticks (us) of a SLEEP
+fn to_us(value: Value, sys_hz: u32) -> u64 {
+    match value {
+        Value::Cycles(cycles) => (cycles as u128 * 1_000_000 / sys_hz as u128) as u64,
+        Value::Picos(ps) => ps / 1_000_000,
+    }
+}
+
+// A script's WAITDONE, USB goes unserved for as long as it waits
+fn wait_done(pulse_gen: &mut PulseGenerator, ch: usize, timeout_us: u64) -> Response {
+    let mut response = Response::new();
+    if !check_channel(ch, &mut response) {
+        return response;
+    }
+    let timeout_us = timeout_us.min(script::WAITDONE_MAX_US as u64) as u32;
+    if pulse_gen.wait_done(ch, timeout_us).is_err() {
+        response.put("ERR TIMEOUT");
+    }
+    response
+}
+
+// Configuration *RST returns to and the device boots with, unless SNAP SAVE
+// stored one, every channel disarmed
+fn power_on_defaults(pulse_gen: &mut PulseGenerator) {
+    let sys_hz = pulse_gen.sys_hz();
+    let _ = pulse_gen.set_delay(0, Achieved::from_cycles(10, sys_hz));
+    let _ = pulse_gen.set_width(0, Achieved::from_cycles(10, sys_hz));
+}
+
+// The configuration SNAP SAVE stored, in place of the defaults if it
+// restores. Otherwise the defaults stay and the error SNAP END would have
+// given is returned.
+fn restore_saved(pulse_gen: &mut PulseGenerator) -> Result<(), Response> {
+    power_on_defaults(pulse_gen);
+    let Some(blob) = flash::load_snapshot() else {
+        return Ok(());
+    };
+    pulse_gen.restore(blob).map_err(|err| {
+        let mut error = Response::new();
+        write_restore_error(&mut error, &err);
+        error
+    })
+}
+
+// Outcome of the power-on auto-arm, kept for AUTOARM?
+enum AutoArm {
+    Off,
+    // ARM_BUTTON was held at power-on
+    Skipped,
+    Armed,
+    // The error SNAP END, ARM or APPLY would have given, every channel was
+    // left disarmed
+    Failed(Response),
+}
+
+// Restores the stored configuration and arms every configured channel at
+// once, or none of them if nothing is stored or the configuration doesn't
+// restore, validate or fit
+fn auto_arm(pulse_gen: &mut PulseGenerator) -> AutoArm {
+    let restored = restore_saved(pulse_gen);
+    if board::arm_button_held() {
+        info!("autoarm skipped, arm button held");
+        return AutoArm::Skipped;
+    }
+    let mut problems = Problems::new();
+    pulse_gen.check(&mut problems);
+    let mut error = Response::new();
+    if let Err(failed) = restored {
+        error = failed;
+    } else if flash::load_snapshot().is_none() {
+        error.put("ERR AUTOARM NO_SNAPSHOT");
+    } else if interlock::blocks() {
+        error.put("ERR INTERLOCK ").put(interlock::state().as_str());
+    } else if !problems.is_empty() {
+        error.put("ERR AUTOARM");
+        for (index, problem) in problems.iter().enumerate() {
+            error.put(if index == 0 { " " } else { "; " });
+            write_problem(&mut error, problem);
+        }
+    } else if let Err(err) = pulse_gen.arm_group(pulse_gen.configured()) {
+        write_pulse_error(&mut error, &err);
+    }
+    if error.is_empty() {
+        info!("autoarm armed");
+        return AutoArm::Armed;
+    }
+    for ch in 0..NUM_CHANNELS {
+        pulse_gen.disarm(ch);
+    }
+    info!("autoarm failed: {}", error.as_str());
+    AutoArm::Failed(error)
+}
+
+// Room for an APPLY failure listing every violation
+type Response = ArrayString<256>;
+
+fn handle_command(
+    command: Result<Command, CommandError>,
+    pulse_gen: &mut PulseGenerator,
+    perf: &Perf,
+    autoarm: &AutoArm,
+    protect: &mut Protect,
+) -> Response {
+    let mut response = Response::new();
+    match command {
+        Ok(command) => execute(command, pulse_gen, perf, autoarm, protect, &mut response),
+        Err(err) => {
+            response.put("ERR ").put(err.as_str());
+        }
+    }
+    response
+}
+
+fn execute(
+    command: Command,
+    pulse_gen: &mut PulseGenerator,
+    perf: &Perf,
+    autoarm: &AutoArm,
+    protect: &mut Protect,
+    response: &mut Response,
+) {
+    let sys_hz = pulse_gen.sys_hz();
+    let rounding = pulse_gen.rounding();
+    if let Some(blocked) = arm_blocked(protect).filter(|_| arms(&command)) {
+        response.put(&blocked);
+        return;
+    }
+    if let Some(ch) = edited_channel(&command).filter(|&ch| ch < NUM_CHANNELS) {
+        if !check_not_mirror(pulse_gen, ch, response) {
+            return;
+        }
+    }
+    match command {
+        // The report lines only follow at the port
+        Command::Check => {
+            let blocked = arm_blocked(protect);
+            let mut problems = Problems::new();
+            let longest = pulse_gen.check(&mut problems);
+            write_check(response, blocked.is_some(), longest, &problems, sys_hz);
+        }
+        Command::Delay(ch, value) | Command::Width(ch, value) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            // Delays may exceed u32 cycles, widths may not
+            let achieved = match command {
+                Command::Delay(..) => to_achieved(value, sys_hz, rounding),
+                _ => to_achieved_u32(value, sys_hz, rounding),
+            };
+            let achieved = match achieved {
+                Ok(achieved) => achieved,
+                Err(err) => {
+                    response.put("ERR ");
+                    write_time_error(response, err, sys_hz);
+                    return;
+                }
+            };
+            let result = match command {
+                Command::Delay(..) => pulse_gen.set_delay(ch, achieved),
+                _ => pulse_gen.set_width(ch, achieved).map_err(DelayError::Full),
+            };
+            match result {
+                Ok(()) => write_ok_achieved(response, achieved, sys_hz),
+                Err(DelayError::Full(SequenceFull)) => write_sequence_full(response),
+                Err(DelayError::Invalid(violation)) => {
+                    response.put("ERR ");
+                    write_violation(response, violation);
+                }
+            }
+        }
+        Command::Pulse(ch, delay, width, levels) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            let achieved = to_achieved_u32(delay, sys_hz, rounding)
+                .and_then(|delay| Ok((delay, to_achieved_u32(width, sys_hz, rounding)?)));
+            match achieved {
+                Ok((delay, width)) => {
+                    if pulse_gen
+                        .add_pulse_levels(ch, delay, width, levels)
+                        .is_err()
+                    {
+                        write_sequence_full(response);
+                        return;
+                    }
+                    response.put("OK levels ").bin0(levels, 2).put(" delay ");
+                    time::write_ps(response, time::cycles_to_ps(delay.cycles, sys_hz));
+                    response.put(" width ");
+                    time::write_ps(response, time::cycles_to_ps(width.cycles, sys_hz));
+                }
+                Err(err) => {
+                    response.put("ERR ");
+                    write_time_error(response, err, sys_hz);
+                }
+            }
+        }
+        Command::Wide(ch, _) | Command::OpenDrain(ch, _) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            let result = match command {
+                Command::Wide(_, wide) => pulse_gen.set_wide(ch, wide),
+                Command::OpenDrain(_, true) => pulse_gen.set_output_mode(ch, OutputMode::OpenDrain),
+                _ => pulse_gen.set_output_mode(ch, OutputMode::PushPull),
+            };
+            match result {
+                Ok(()) => {
+                    response.put("OK");
+                }
+                Err(violation) => {
+                    response.put("ERR ");
+                    write_violation(response, violation);
+                }
+            }
+        }
+        Command::PinQuery(pin) | Command::Watch(pin, _) if pin >= probe::GPIO_COUNT => {
+            response.put("ERR BAD_PIN max ").dec(probe::GPIO_COUNT - 1);
+        }
+        Command::PinQuery(pin) => {
+            response.put("OK ").dec(probe::read(pin) as u8);
+        }
+        Command::Watch(_, ms) if ms > probe::WATCH_MS_MAX => {
+            response
+                .put("ERR BAD_DURATION max ")
+                .dec(probe::WATCH_MS_MAX)
+                .put("ms");
+        }
+        // Streams need the main loop to keep them fed
+        Command::Watch(..) if pulse_gen.streaming() => {
+            response.put("ERR STREAMING");
+        }
+        Command::Watch(pin, ms) => {
+            let seen = probe::watch(pin, ms);
+            response
+                .put("OK rising ")
+                .dec(seen.rising)
+                .put(" falling ")
+                .dec(seen.falling)
+                .put(" level ")
+                .dec(seen.level as u8);
+        }
+        Command::Ping(ch, width) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            let width = match to_achieved_u32(width, sys_hz, rounding) {
+                Ok(width) => width,
+                Err(err) => {
+                    response.put("ERR ");
+                    write_time_error(response, err, sys_hz);
+                    return;
+                }
+            };
+            match pulse_gen.ping(ch, width) {
+                Ok(()) => write_ok_achieved(response, width, sys_hz),
+                Err(err) => write_pulse_error(response, &err),
+            }
+        }
+        Command::TestPattern(None, _) => {
+            for ch in 0..NUM_CHANNELS {
+                let _ = pulse_gen.set_test_pattern(ch, None);
+            }
+            response.put("OK");
+        }
+        Command::TestPattern(Some(ch), pattern) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            match pulse_gen.set_test_pattern(ch, pattern) {
+                Ok(()) => {
+                    response.put("OK");
+                }
+                Err(err) => write_pulse_error(response, &err),
+            }
+        }
+        Command::Retrigger(ch, policy) => {
+            if check_channel(ch, response) {
+                pulse_gen.set_retrigger_policy(ch, policy);
+                response.put("OK");
+            }
+        }
+        Command::Marker(ch, margins) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            let marker = match margins {
+                Some((pre, post)) => {
+                    let margins = to_achieved_u32(pre, sys_hz, rounding)
+                        .and_then(|pre| Ok((pre, to_achieved_u32(post, sys_hz, rounding)?)));
+                    match margins {
+                        Ok((pre, post)) => Some(Marker {
+                            pre: pre.cycles as u32,
+                            post: post.cycles as u32,
+                        }),
+                        Err(err) => {
+                            response.put("ERR ");
+                            write_time_error(response, err, sys_hz);
+                            return;
+                        }
+                    }
+                }
+                None => None,
+            };
+            match pulse_gen.set_marker(ch, marker) {
+                Ok(()) => {
+                    response.put("OK");
+                }
+                Err(violation) => {
+                    response.put("ERR ");
+                    write_violation(response, violation);
+                }
+            }
+        }
+        Command::TriggerOut(ch, width) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            let width = match width.map(|width| to_achieved_u32(width, sys_hz, rounding)) {
+                Some(Ok(width)) => Some(width),
+                Some(Err(err)) => {
+                    response.put("ERR ");
+                    write_time_error(response, err, sys_hz);
+                    return;
+                }
+                None => None,
+            };
+            match (pulse_gen.set_trigger_out(ch, width), width) {
+                (Ok(()), Some(width)) => write_ok_achieved(response, width, sys_hz),
+                (Ok(()), None) => {
+                    response.put("OK");
+                }
+                (Err(violation), _) => {
+                    response.put("ERR ");
+                    write_violation(response, violation);
+                }
+            }
+        }
+        Command::Compensate(ch, compensate) => {
+            if check_channel(ch, response) {
+                pulse_gen.set_latency_compensation(ch, compensate);
+                response.put("OK");
+            }
+        }
+        Command::PerEdge(ch, per_edge) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            match pulse_gen.set_per_edge(ch, per_edge) {
+                Ok(()) => {
+                    response.put("OK");
+                }
+                Err(violation) => {
+                    response.put("ERR ");
+                    write_violation(response, violation);
+                }
+            }
+        }
+        Command::GapLevel(ch, level) | Command::IdleLevel(ch, level) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            let result = if matches!(command, Command::GapLevel(..)) {
+                pulse_gen.set_gap_level(ch, level)
+            } else {
+                pulse_gen.set_idle_level(ch, level)
+            };
+            match result {
+                Ok(()) => {
+                    response.put("OK");
+                }
+                Err(violation) => {
+                    response.put("ERR ");
+                    write_violation(response, violation);
+                }
+            }
+        }
+        Command::Divider(ch, n) => {
+            if check_channel(ch, response) {
+                // LIVE when the armed SM took the new count right away
+                response.put("OK");
+                if pulse_gen.set_trigger_divider(ch, n) {
+                    response.put(" LIVE");
+                }
+            }
+        }
+        #[cfg(feature = "selftest")]
+        command @ (Command::Stress(..)
+        | Command::StressDma(..)
+        | Command::Skew
+        | Command::SelfTest) => {
+            handlers::selftest::execute(command, pulse_gen, perf, autoarm, protect, response)
+        }
+        Command::Internal(None) => {
+            let _ = pulse_gen.set_internal(None);
+            response.put("OK");
+        }
+        Command::Internal(Some((pin, period, target))) => {
+            let members = match target {
+                Target::All => (1 << NUM_CHANNELS) - 1,
+                Target::Group(name) => match pulse_gen.group(name) {
+                    Some(members) => members,
+                    None => {
+                        write_group_error(response, &GroupError::UnknownGroup);
+                        return;
+                    }
+                },
+                Target::Channel(ch) => {
+                    if !check_channel(ch, response) {
+                        return;
+                    }
+                    1 << ch
+                }
+            };
+            let period = match to_achieved(period, sys_hz, rounding) {
+                Ok(period) => period,
+                Err(err) => {
+                    response.put("ERR ");
+                    write_time_error(response, err, sys_hz);
+                    return;
+                }
+            };
+            let internal = Internal {
+                pin,
+                members,
+                period: period.cycles,
+            };
+            match pulse_gen.set_internal(Some(internal)) {
+                Ok(cycles) => write_ok_achieved(response, Achieved { cycles, ..period }, sys_hz),
+                Err(InternalError::BadPin) => {
+                    response.put("ERR BAD_PIN");
+                }
+                Err(InternalError::BadPeriod) => {
+                    response.put("ERR OUT_OF_RANGE min ");
+                    time::write_ps(response, time::cycles_to_ps(tick::PERIOD_MIN, sys_hz));
+                    response.put(" max ");
+                    time::write_ps(response, time::cycles_to_ps(tick::PERIOD_MAX, sys_hz));
+                }
+                Err(InternalError::NoChannels) => {
+                    response.put("ERR NO_CHANNELS");
+                }
+                Err(InternalError::Arm(err)) => write_pulse_error(response, &err),
+            }
+        }
+        Command::InternalQuery => match pulse_gen.internal() {
+            Some(internal) => {
+                response.put("OK GPIO").dec(internal.pin).put(" ");
+                time::write_ps(response, time::cycles_to_ps(internal.period, sys_hz));
+                response.put(" (").dec(internal.period).put(" cyc) ch");
+                for ch in (0..NUM_CHANNELS).filter(|ch| internal.members & 1 << ch != 0) {
+                    response.put(" ").dec(ch);
+                }
+            }
+            None => {
+                response.put("OK OFF");
+            }
+        },
+        #[cfg(feature = "capture")]
+        command @ (Command::Tlog(_)
+        | Command::TlogQuery
+        | Command::Glitch(_)
+        | Command::GlitchReset
+        | Command::GlitchQuery) => handlers::capture::execute(command, pulse_gen, response),
+        // "OK <runs> runs <pulses> pulses high <time> last <time>|NONE
+        // [TLOG]", the last run's duration from its trigger, TLOG when the
+        // trigger time is the timestamp log's
+        Command::RunStat(ch) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            let stats = pulse_gen.run_stats(ch);
+            response
+                .put("OK ")
+                .dec(stats.runs)
+                .put(" runs ")
+                .dec(stats.pulses)
+                .put(" pulses high ");
+            time::write_ps(response, time::cycles_to_ps(stats.high_cycles, sys_hz));
+            response.put(" last ");
+            match stats.last_us {
+                Some(us) => time::write_ps(response, us * time::PS_PER_US),
+                None => {
+                    response.put("NONE");
+                }
+            }
+            if stats.logged {
+                response.put(" TLOG");
+            }
+        }
+        // Typed at the port main answers a line per channel, a frame or a
+        // script gets them on one line: "OK <channel>; <channel>"
+        Command::Poll => {
+            response.put("OK ");
+            for ch in 0..NUM_CHANNELS {
+                if ch > 0 {
+                    response.put("; ");
+                }
+                write_poll(response, pulse_gen, ch);
+            }
+        }
+        // "OK PIO0 free <words> sm <role>...; PIO1 free 32 sm FREE...;
+        // DMA <role>..." with the roles by SM and channel number
+        Command::Resources => {
+            let res = pulse_gen.resources();
+            response.put("OK PIO0 free ").dec(res.pio0_free).put(" sm");
+            for role in res.sm {
+                response.put(" ").put(role.as_str());
+            }
+            response
+                .put("; PIO1 free ")
+                .dec(INSTRUCTION_MEMORY)
+                .put(" sm FREE FREE FREE FREE; DMA");
+            for role in res.dma {
+                response.put(" ").put(role.as_str());
+            }
+        }
+        Command::Capabilities => {
+            response
+                .put("OK channels ")
+                .dec(NUM_CHANNELS)
+                .put(" pulses ")
+                .dec(NUM_PULSES_MAX)
+                .put(" clock ")
+                .dec(sys_hz)
+                .put("Hz");
+            #[cfg(feature = "binary-proto")]
+            response.put(" protocol 0x").hex0(wire::PROTOCOL_VERSION, 2);
+            for ch in 0..NUM_CHANNELS {
+                let config = pulse_gen.program_config(ch);
+                let Cycles(latency) = config.trigger_latency();
+                response
+                    .put("; ch")
+                    .dec(ch)
+                    .put(" ")
+                    .dec(pulse_gen.program(ch).code.len())
+                    .put(" words latency ")
+                    .dec(latency)
+                    .put(" cyc (");
+                time::write_ps(response, time::cycles_to_ps(latency as u64, sys_hz));
+                // What TESTPAT FAST runs
+                let (Cycles(width), Cycles(gap)) = pulse_gen.fastest(ch);
+                response
+                    .put(") min width ")
+                    .dec(width)
+                    .put(" gap ")
+                    .dec(gap)
+                    .put(" cyc idle ")
+                    .put(pulse_gen.rest_level(ch).map_or("Z", |level| level.as_str()));
+            }
+            let latency = TRIGGER_OUT_LATENCY_CYCLES;
+            response
+                .put("; TRIGOUT latency ")
+                .dec(latency)
+                .put(" cyc (");
+            time::write_ps(response, time::cycles_to_ps(latency as u64, sys_hz));
+            response.put("); PROTECT ");
+            write_thresholds(response, &protect.thresholds);
+            response.put("; INTERLOCK ");
+            write_interlock(response);
+        }
+        Command::Tristate(ch, tristate) => {
+            if check_channel(ch, response) {
+                pulse_gen.set_idle_tristate(ch, tristate);
+                response.put("OK");
+            }
+        }
+        Command::Table(ch, table) => load_table(pulse_gen, ch, table.pairs(), response),
+        // "OK <pulses> pulses queued", "REPLACED" if it took the place of
+        // another queued table
+        Command::Next(ch, table) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            let Some(pairs) = decode_table(table.pairs(), sys_hz, rounding, response) else {
+                return;
+            };
+            let pulses = pairs.len();
+            let replaced = pulse_gen.set_next(ch, pairs);
+            response.put("OK ").dec(pulses).put(" pulses queued");
+            if replaced {
+                response.put(" REPLACED");
+            }
+        }
+        Command::StreamStart(ch) | Command::StreamEnd(ch) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            let result = match command {
+                Command::StreamStart(_) => {
+                    if pulse_gen.disabled() & 1 << ch != 0 {
+                        write_pulse_error(response, &PulseError::Disabled { ch });
+                        return;
+                    }
+                    if let Err(err) = pulse_gen.stream_start(ch) {
+                        write_memory_full(response, &err);
+                        return;
+                    }
+                    Ok(())
+                }
+                _ => pulse_gen.stream_end(ch),
+            };
+            match result {
+                Ok(()) => response.put("OK"),
+                Err(err) => response.put("ERR ").put(err.as_str()),
+            };
+        }
+        #[cfg(feature = "binary-proto")]
+        Command::StreamBlock(ch, data) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            // The whole block or none of it, a short one would leave a gap in
+            // the stream
+            let mut pairs: ArrayVec<(Cycles, Cycles), STREAM_BLOCK_PAIRS> = ArrayVec::new();
+            for pair in data.chunks_exact(PAIR_LEN).map(Pair::decode) {
+                if pairs
+                    .try_push((Cycles(pair.delay), Cycles(pair.width)))
+                    .is_err()
+                {
+                    response
+                        .put("ERR BLOCK_TOO_LARGE max ")
+                        .dec(STREAM_BLOCK_PAIRS);
+                    return;
+                }
+            }
+            // Acknowledge with the free space so the host can throttle
+            match pulse_gen.stream_block(ch, &pairs) {
+                Ok(free) => response.put("OK STREAM ").dec(free),
+                Err(err) => response.put("ERR ").put(err.as_str()),
+            };
+        }
+        Command::Arm(Target::Channel(ch)) | Command::Disarm(Target::Channel(ch)) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            let result = match command {
+                Command::Arm(_) => pulse_gen.arm(ch),
+                _ => {
+                    pulse_gen.disarm(ch);
+                    Ok(())
+                }
+            };
+            match result {
+                Ok(()) => {
+                    response.put("OK");
+                    if matches!(command, Command::Arm(_)) {
+                        write_trigger_level(response, pulse_gen, 1 << ch);
+                    }
+                }
+                Err(err) => write_pulse_error(response, &err),
+            }
+        }
+        Command::Arm(Target::All) => match pulse_gen.arm_all() {
+            Ok(()) => {
+                response.put("OK");
+                write_trigger_level(response, pulse_gen, (1 << NUM_CHANNELS) - 1);
+            }
+            Err(err) => write_pulse_error(response, &err),
+        },
+        // Applied now and stored in the calibration sector, which nothing
+        // else writes
+        Command::CalSet(ch, negative, value) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            let cycles = match to_achieved(value, sys_hz, rounding) {
+                Ok(achieved) if achieved.cycles <= i32::MAX as u64 => achieved.cycles as i32,
+                Ok(_) => {
+                    response.put("ERR OUT_OF_RANGE");
+                    return;
+                }
+                Err(err) => {
+                    response.put("ERR ");
+                    write_time_error(response, err, sys_hz);
+                    return;
+                }
+            };
+            if let Err(err) = flash::check_idle() {
+                response.put("ERR ").put(err.as_str());
+                return;
+            }
+            pulse_gen.set_calibration(ch, if negative { -cycles } else { cycles });
+            let mut calibration = flash::load_calibration();
+            calibration.offsets[ch] = pulse_gen.calibration(ch);
+            write_flash_result(response, flash::save_calibration(Some(&calibration)));
+        }
+        Command::CalClear => {
+            if let Err(err) = flash::check_idle() {
+                response.put("ERR ").put(err.as_str());
+                return;
+            }
+            for ch in 0..NUM_CHANNELS {
+                pulse_gen.set_calibration(ch, 0);
+            }
+            write_flash_result(response, flash::save_calibration(None));
+        }
+        // "OK ch0 <cycles> <time>; ch1 ...", negative times with a '-'
+        Command::CalQuery => {
+            response.put("OK");
+            for ch in 0..NUM_CHANNELS {
+                let cycles = pulse_gen.calibration(ch);
+                response
+                    .put(if ch == 0 { " ch" } else { "; ch" })
+                    .dec(ch)
+                    .put(" ")
+                    .put(if cycles < 0 { "-" } else { "" })
+                    .dec(cycles.unsigned_abs())
+                    .put(" ")
+                    .put(if cycles < 0 { "-" } else { "" });
+                time::write_ps(
+                    response,
+                    time::cycles_to_ps(cycles.unsigned_abs() as u64, sys_hz),
+                );
+            }
+        }
+        // Applied now and stored
+        Command::StuckTrigger(refuse) => {
+            if let Err(err) = flash::check_idle() {
+                response.put("ERR ").put(err.as_str());
+                return;
+            }
+            pulse_gen.set_refuse_stuck_trigger(refuse);
+            let mut config = flash::load();
+            config.refuse_stuck_trigger = refuse;
+            write_flash_result(response, flash::save(&config));
+        }
+        Command::StuckTriggerQuery => {
+            response.put(if pulse_gen.refuse_stuck_trigger() {
+                "OK REFUSE"
+            } else {
+                "OK WARN"
+            });
+        }
+        // Applied now and stored, so the channel stays out at power-on
+        Command::Enable(ch, enabled) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            if let Err(err) = flash::check_idle() {
+                response.put("ERR ").put(err.as_str());
+                return;
+            }
+            match pulse_gen.set_enabled(ch, enabled) {
+                Ok(()) => {
+                    let mut config = flash::load();
+                    config.disabled = pulse_gen.disabled() as u8;
+                    write_flash_result(response, flash::save(&config));
+                }
+                Err(violation) => {
+                    response.put("ERR ");
+                    write_violation(response, violation);
+                }
+            }
+        }
+        Command::Disarm(Target::All) => {
+            for ch in 0..NUM_CHANNELS {
+                pulse_gen.disarm(ch);
+            }
+            response.put("OK");
+        }
+        Command::Arm(Target::Group(name)) | Command::Disarm(Target::Group(name)) => {
+            let Some(members) = pulse_gen.group(name) else {
+                write_group_error(response, &GroupError::UnknownGroup);
+                return;
+            };
+            let result = match command {
+                Command::Arm(_) => pulse_gen.arm_group(members),
+                _ => {
+                    pulse_gen.disarm_group(members);
+                    Ok(())
+                }
+            };
+            match result {
+                Ok(()) => {
+                    response.put("OK");
+                    if matches!(command, Command::Arm(_)) {
+                        write_trigger_level(response, pulse_gen, members);
+                    }
+                }
+                Err(err) => write_pulse_error(response, &err),
+            }
+        }
+        Command::Group(name, members) => match pulse_gen.set_group(name, members) {
+            Ok(()) => {
+                response.put("OK");
+            }
+            Err(err) => write_group_error(response, &err),
+        },
+        Command::Groups => {
+            response.put("OK");
+            for (i, group) in pulse_gen.groups().iter().enumerate() {
+                response
+                    .put(if i == 0 { " " } else { "; " })
+                    .put(&group.name);
+                for ch in (0..NUM_CHANNELS).filter(|ch| group.members & 1 << ch != 0) {
+                    response.put(" ").dec(ch);
+                }
+            }
+        }
+        Command::T0(name, reference) => {
+            let (reference, lead_max) = match reference {
+                Some((ch, Some(lead_max))) => match to_achieved(lead_max, sys_hz, rounding) {
+                    Ok(lead_max) => (Some(ch), lead_max.cycles),
+                    Err(err) => {
+                        response.put("ERR ");
+                        write_time_error(response, err, sys_hz);
+                        return;
+                    }
+                },
+                Some((ch, None)) => (Some(ch), 0),
+                None => (None, 0),
+            };
+            if reference.is_some_and(|ch| !check_channel(ch, response)) {
+                return;
+            }
+            match pulse_gen.set_t0(name, reference, lead_max) {
+                Ok(()) => {
+                    response.put("OK");
+                }
+                Err(err) => write_group_error(response, &err),
+            }
+        }
+        Command::T0Query(name) => {
+            if pulse_gen.group(name).is_none() {
+                write_group_error(response, &GroupError::UnknownGroup);
+                return;
+            }
+            match pulse_gen.t0(name) {
+                None => {
+                    response.put("OK OFF");
+                }
+                Some(Ok(solution)) => write_t0(response, &solution, sys_hz),
+                Some(Err(err)) => write_pulse_error(response, &err),
+            }
+        }
+        Command::T0Offset(ch, offset) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            let offset = match offset
+                .map(|(before, value)| (before, to_achieved(value, sys_hz, rounding)))
+            {
+                None => None,
+                Some((before, Ok(achieved))) => {
+                    let cycles = achieved.cycles as i64;
+                    Some(if before { -cycles } else { cycles })
+                }
+                Some((_, Err(err))) => {
+                    response.put("ERR ");
+                    write_time_error(response, err, sys_hz);
+                    return;
+                }
+            };
+            pulse_gen.set_t0_offset(ch, offset);
+            response.put("OK");
+        }
+        Command::PreDelay(ch, pre_delay) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            match to_achieved_or_off(pre_delay, sys_hz, rounding) {
+                Ok(pre_delay) => {
+                    pulse_gen.set_pre_delay(ch, pre_delay);
+                    response.put("OK");
+                }
+                Err(err) => {
+                    response.put("ERR ");
+                    write_time_error(response, err, sys_hz);
+                }
+            }
+        }
+        Command::PreDelayQuery(ch) => {
+            if check_channel(ch, response) {
+                write_ok_achieved_or_off(response, pulse_gen.pre_delay(ch), sys_hz);
+            }
+        }
+        Command::RearmGuard(ch, guard) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            match to_achieved_or_off(guard, sys_hz, rounding) {
+                Ok(guard) => {
+                    pulse_gen.set_rearm_guard(ch, guard);
+                    response.put("OK");
+                }
+                Err(err) => {
+                    response.put("ERR ");
+                    write_time_error(response, err, sys_hz);
+                }
+            }
+        }
+        Command::RearmGuardQuery(ch) => {
+            if check_channel(ch, response) {
+                write_ok_achieved_or_off(response, pulse_gen.rearm_guard(ch), sys_hz);
+            }
+        }
+        Command::Pin(ch, pin) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            if interlock::settings().pin == Some(pin) {
+                response.put("ERR INTERLOCK_PIN");
+                return;
+            }
+            if debugpin::settings().pin == Some(pin) {
+                response.put("ERR DEBUG_PIN");
+                return;
+            }
+            match pulse_gen.set_pin(ch, pin) {
+                Ok(()) => {
+                    response.put("OK");
+                }
+                Err(violation) => {
+                    response.put("ERR ");
+                    write_violation(response, violation);
+                }
+            }
+        }
+        Command::TriggerPin(ch, pin) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            if pin >= probe::GPIO_COUNT {
+                response.put("ERR BAD_PIN max ").dec(probe::GPIO_COUNT - 1);
+                return;
+            }
+            if interlock::settings().pin == Some(pin) {
+                response.put("ERR INTERLOCK_PIN");
+                return;
+            }
+            if debugpin::settings().pin == Some(pin) {
+                response.put("ERR DEBUG_PIN");
+                return;
+            }
+            if safestate::settings().pins() & 1 << pin != 0 {
+                response.put("ERR SAFESTATE_PIN");
+                return;
+            }
+            match pulse_gen.set_trigger_pin(ch, pin) {
+                Ok(()) => {
+                    response.put("OK");
+                }
+                Err(violation) => {
+                    response.put("ERR ");
+                    write_violation(response, violation);
+                }
+            }
+        }
+        Command::TriggerFrom(ch, source) => {
+            if !check_channel(ch, response) || !check_channel(source, response) {
+                return;
+            }
+            match pulse_gen.set_trigger(ch, Trigger::InternalFromChannel(source)) {
+                // The consumer sees the pin, not the source's SM
+                Ok(()) => {
+                    response
+                        .put("OK WARN ELECTRICAL_EDGE pin ")
+                        .dec(pulse_gen.trigger_pin(ch));
+                }
+                Err(violation) => {
+                    response.put("ERR ");
+                    write_violation(response, violation);
+                }
+            }
+        }
+        Command::TriggerPinQuery(ch) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            response.put("OK ");
+            if let Trigger::InternalFromChannel(source) = pulse_gen.trigger(ch) {
+                response.put("FROM ").dec(source).put(" pin ");
+            }
+            response.dec(pulse_gen.trigger_pin(ch));
+        }
+        Command::TriggerEdge(pin, _) | Command::TriggerEdgeQuery(pin)
+            if pin >= probe::GPIO_COUNT =>
+        {
+            response.put("ERR BAD_PIN max ").dec(probe::GPIO_COUNT - 1);
+        }
+        Command::TriggerEdge(pin, edge) => match pulse_gen.set_trigger_edge(pin, edge) {
+            Ok(()) => {
+                response.put("OK");
+            }
+            Err(TriggerArmed { ch }) => {
+                response.put("ERR ARMED ch").dec(ch);
+            }
+        },
+        Command::TriggerEdgeQuery(pin) => {
+            response
+                .put("OK ")
+                .put(pulse_gen.trigger_edge(pin).as_str());
+        }
+        Command::Mirror(ch, mirror) => {
+            if !check_channel(ch, response)
+                || mirror.is_some_and(|mirror| !check_channel(mirror.source, response))
+            {
+                return;
+            }
+            match pulse_gen.set_mirror(ch, mirror) {
+                Ok(()) => {
+                    response.put("OK");
+                }
+                Err(MirrorError::Chain) => {
+                    response.put("ERR MIRROR_CHAIN");
+                }
+                Err(MirrorError::Armed { ch }) => {
+                    response.put("ERR ARMED ch").dec(ch);
+                }
+                Err(MirrorError::Invalid(violation)) => {
+                    response.put("ERR ");
+                    write_violation(response, violation);
+                }
+            }
+        }
+        // "OK ch0 -> ch1 INVERT; ..." or "OK NONE"
+        Command::MirrorQuery => {
+            let mut mirrors = (0..NUM_CHANNELS)
+                .filter_map(|ch| Some((ch, pulse_gen.mirror(ch)?)))
+                .peekable();
+            if mirrors.peek().is_none() {
+                response.put("OK NONE");
+            }
+            for (i, (ch, mirror)) in mirrors.enumerate() {
+                response
+                    .put(if i == 0 { "OK ch" } else { "; ch" })
+                    .dec(mirror.source)
+                    .put(" -> ch")
+                    .dec(ch);
+                if mirror.invert {
+                    response.put(" INVERT");
+                }
+            }
+        }
+        Command::Stage => {
+            pulse_gen.stage();
+            response.put("OK STAGING");
+        }
+        Command::Apply => {
+            if !pulse_gen.is_staging() {
+                response.put("ERR NOT_STAGING");
+                return;
+            }
+            match pulse_gen.apply() {
+                Ok(()) => {
+                    response.put("OK APPLIED");
+                }
+                Err(invalid) => {
+                    response.put("ERR APPLY ");
+                    write_invalid(response, &invalid);
+                }
+            }
+        }
+        Command::Discard => {
+            pulse_gen.discard();
+            response.put("OK");
+        }
+        Command::Reset => {
+            pulse_gen.reset_all();
+            power_on_defaults(pulse_gen);
+            safestate::apply();
+            response.put("OK");
+        }
+        Command::Expert(enabled) => {
+            pulse_gen.set_expert(enabled);
+            response.put("OK");
+        }
+        #[cfg(feature = "binary-proto")]
+        Command::ExpertLoad(sm, raw) => {
+            let pins = raw.pin_base..raw.pin_base.saturating_add(raw.pin_count);
+            let result = pulse_gen.expert_load(sm, &raw.to_program(), pins);
+            write_expert_result(response, result);
+        }
+        #[cfg(feature = "binary-proto")]
+        Command::ExpertFeed(sm, data) => {
+            let mut words: ArrayVec<u32, EXPERT_FEED_LEN> = ArrayVec::new();
+            for word in data.chunks_exact(4) {
+                if words
+                    .try_push(u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+                    .is_err()
+                {
+                    response.put("ERR FEED_TOO_LARGE max ").dec(EXPERT_FEED_LEN);
+                    return;
+                }
+            }
+            write_expert_result(response, pulse_gen.expert_feed(sm, &words));
+        }
+        Command::Program(ch) => {
+            // The listing itself follows as one line per instruction
+            if check_channel(ch, response) {
+                response
+                    .put("OK ")
+                    .dec(pulse_gen.program(ch).code.len())
+                    .put(" words");
+            }
+        }
+        Command::Timeline(ch) => {
+            // One line per pulse follows
+            if check_channel(ch, response) {
+                let pulses = pulse_gen.timeline(ch).count();
+                response.put("OK ").dec(pulses).put(" pulses");
+                // Already in the edges below, named so the table's own
+                // timing can be told from it
+                let pre_delay = pulse_gen.pre_delay(ch).cycles;
+                if pre_delay != 0 {
+                    response.put(", pre-delay ");
+                    time::write_ps(response, time::cycles_to_ps(pre_delay, sys_hz));
+                }
+                if let Some((_, fall)) = pulse_gen.timeline(ch).last() {
+                    response.put(", last fall ");
+                    time::write_ps(response, fall);
+                    // Shortest trigger to trigger time of runs the firmware
+                    // re-arms, less the main loop getting to it
+                    let guard = pulse_gen.rearm_guard(ch).cycles;
+                    if guard != 0 {
+                        response.put(", guard ");
+                        time::write_ps(response, time::cycles_to_ps(guard, sys_hz));
+                    }
+                    response.put(", min period ");
+                    time::write_ps(response, fall + time::cycles_to_ps(guard, sys_hz));
+                }
+            }
+        }
+        Command::Words(ch) => {
+            // One line per word follows
+            if check_channel(ch, response) {
+                match pulse_gen.build_words(ch) {
+                    Ok(words) => {
+                        response.put("OK ").dec(words.len()).put(" words");
+                    }
+                    Err(err) => write_pulse_error(response, &err),
+                }
+            }
+        }
+        Command::Achieved(ch) => {
+            // One line per pulse follows
+            if check_channel(ch, response) {
+                let pulses = pulse_gen.achieved(ch).count();
+                response
+                    .put("OK ")
+                    .dec(pulses)
+                    .put(" pulses, rounding ")
+                    .put(rounding.as_str());
+            }
+        }
+        // Applied by the main loop, which owns the LED
+        Command::LedMode(LedMode::Activity(ch)) => {
+            if check_channel(ch, response) {
+                response.put("OK");
+            }
+        }
+        Command::LedMode(LedMode::Status) => {
+            response.put("OK");
+        }
+        #[cfg(feature = "flash-config")]
+        command @ (Command::UsbId(..)
+        | Command::AutoArm(_)
+        | Command::SnapSave
+        | Command::SnapClear
+        | Command::Banner(_)) => handlers::flash_config::execute(command, pulse_gen, response),
+        Command::UsbIdQuery => {
+            let usb = flash::load().usb;
+            response
+                .put("OK ")
+                .hex0(usb.vid, 4)
+                .put(" ")
+                .hex0(usb.pid, 4)
+                .put(" ")
+                .put(&usb.product);
+        }
+        // The stored flag, then what happened at this power-on. A failure
+        // is reported as the error it latched.
+        Command::AutoArmQuery => match autoarm {
+            AutoArm::Failed(error) => {
+                response.put(error);
+            }
+            _ => {
+                response
+                    .put(if flash::load().autoarm {
+                        "OK ON "
+                    } else {
+                        "OK OFF "
+                    })
+                    .put(match autoarm {
+                        AutoArm::Off => "OFF",
+                        AutoArm::Skipped => "SKIPPED",
+                        _ => "ARMED",
+                    });
+            }
+        },
+        Command::ProtectTemp(Some(max)) if max > protect::TEMP_MAX => {
+            response
+                .put("ERR OUT_OF_RANGE max ")
+                .dec(protect::TEMP_MAX)
+                .put("C");
+        }
+        Command::ProtectVsys(Some(_)) if board::VSYS_ADC.is_none() => {
+            response.put("ERR NO_VSYS");
+        }
+        // Stored too with flash-config
+        Command::ProtectTemp(_) | Command::ProtectVsys(_) | Command::ProtectAction(_) => {
+            if let Err(err) = flash::check_idle() {
+                response.put("ERR ").put(err.as_str());
+                return;
+            }
+            let thresholds = &mut protect.thresholds;
+            match command {
+                Command::ProtectTemp(max) => thresholds.temp_max = max,
+                // Nothing reads below 0V
+                Command::ProtectVsys(min) => thresholds.vsys_min = min.filter(|&min| min != 0),
+                Command::ProtectAction(action) => thresholds.action = action,
+                _ => {}
+            }
+            let mut config = flash::load();
+            config.protect = protect.thresholds;
+            write_flash_result(response, flash::save(&config));
+        }
+        Command::ProtectClear => {
+            protect.clear();
+            response.put("OK");
+        }
+        Command::ProtectQuery => {
+            let readings = protect::read();
+            response.put("OK ");
+            write_thresholds(response, &protect.thresholds);
+            response.put(" now ");
+            write_milli(response, readings.temp, "C");
+            if let Some(vsys) = readings.vsys {
+                response.put(" ");
+                write_milli(response, vsys as i32, "V");
+            }
+            if let Some(trip) = protect.tripped() {
+                response.put(" TRIPPED ").put(trip.as_str()).put(" ");
+                write_trip_reading(response, trip);
+            }
+            response.put(" INTERLOCK ").put(interlock::state().as_str());
+        }
+        Command::Interlock(Some(pin))
+            if !interlock::valid_pin(pin)
+                || debugpin::settings().pin == Some(pin)
+                || safestate::settings().pins() & 1 << pin != 0
+                || pulse_gen.trigger_pins() & 1 << pin != 0 =>
+        {
+            response.put("ERR BAD_PIN");
+        }
+        // Stored too with flash-config
+        Command::Interlock(_) | Command::InterlockRelease(_) => {
+            if let Err(err) = flash::check_idle() {
+                response.put("ERR ").put(err.as_str());
+                return;
+            }
+            let mut settings = interlock::settings();
+            match command {
+                Command::Interlock(pin) => settings.pin = pin,
+                Command::InterlockRelease(release) => settings.release = release,
+                _ => {}
+            }
+            if let Err(err) = interlock::configure(settings) {
+                write_interlock_error(response, err);
+                return;
+            }
+            let mut config = flash::load();
+            config.interlock = settings;
+            write_flash_result(response, flash::save(&config));
+        }
+        Command::InterlockClear => match interlock::clear() {
+            Ok(()) => {
+                safestate::apply();
+                response.put("OK");
+            }
+            Err(err) => write_interlock_error(response, err),
+        },
+        Command::InterlockQuery => {
+            response.put("OK ");
+            write_interlock(response);
+        }
+        Command::DebugPin(settings)
+            if settings.pin.is_some_and(|pin| {
+                !debugpin::valid_pin(pin)
+                    || interlock::settings().pin == Some(pin)
+                    || safestate::settings().pins() & 1 << pin != 0
+                    || pulse_gen.trigger_pins() & 1 << pin != 0
+            }) =>
+        {
+            response.put("ERR BAD_PIN");
+        }
+        // Stored too with flash-config
+        Command::DebugPin(settings) => {
+            if let Err(err) = flash::check_idle() {
+                response.put("ERR ").put(err.as_str());
+                return;
+            }
+            debugpin::configure(settings);
+            let mut config = flash::load();
+            config.debug_pin = settings;
+            write_flash_result(response, flash::save(&config));
+        }
+        Command::DebugPinQuery => {
+            response.put("OK ");
+            write_debug_pin(response);
+        }
+        Command::MemQuery => {
+            response.put("OK ");
+            write_mem(response);
+        }
+        Command::SafeState(pin, _, _)
+            if !safestate::valid_pin(pin)
+                || interlock::settings().pin == Some(pin)
+                || debugpin::settings().pin == Some(pin)
+                || pulse_gen.trigger_pins() & 1 << pin != 0 =>
+        {
+            response.put("ERR BAD_PIN");
+        }
+        // Stored too with flash-config
+        Command::SafeState(pin, rest, force) => {
+            // Held high between runs, an active-high output would read as
+            // one pulse that never ends
+            let active_high = (0..NUM_CHANNELS)
+                .find(|&ch| pulse_gen.pin_mask(ch) & 1 << pin != 0 && pulse_gen.active_high(ch));
+            if let (Some(Rest::High), false, Some(ch)) = (rest, force, active_high) {
+                response.put("ERR ACTIVE_HIGH ch").dec(ch);
+                return;
+            }
+            if let Err(err) = flash::check_idle() {
+                response.put("ERR ").put(err.as_str());
+                return;
+            }
+            let mut settings = safestate::settings();
+            settings.set(pin, rest);
+            safestate::configure(settings);
+            let mut config = flash::load();
+            config.safe_state = settings;
+            write_flash_result(response, flash::save(&config));
+        }
+        Command::SafeStateQuery => {
+            response.put("OK ");
+            write_safe_state(response);
+        }
+        #[cfg(feature = "usb-log")]
+        command @ Command::LogUsb(_) => handlers::usb_log::execute(command, response),
+        // "OK USB <level> DROPPED <records>" or "OK OFF"
+        Command::LogQuery => match usblog::level() {
+            Some(level) => {
+                response
+                    .put("OK USB ")
+                    .put(level.as_str())
+                    .put(" DROPPED ")
+                    .dec(usblog::dropped());
+            }
+            None => {
+                response.put("OK OFF");
+            }
+        },
+        // Typed at the port these are handled by main, from a script they are
+        // refused
+        Command::ScriptBegin(_)
+        | Command::ScriptEnd
+        | Command::ScriptAbort
+        | Command::ScriptClear
+        | Command::ScriptQuery
+        | Command::Run
+        | Command::ModeQuery
+        | Command::SessionQuery
+        | Command::ConfigQuery
+        | Command::ErrorQuery
+        | Command::BootQuery
+        | Command::UsbReenum
+        | Command::LifetimeQuery
+        | Command::LifetimeSave
+        | Command::LifetimeReset(_)
+        | Command::Shutdown
+        | Command::SnapQuery
+        | Command::Snap(_)
+        | Command::SnapEnd
+        | Command::CsvLoad(_)
+        | Command::Compat(_) => {
+            response.put("ERR NOT_IN_SCRIPT");
+        }
+        // Frames, handled by main
+        #[cfg(feature = "binary-proto")]
+        command @ (Command::ReadStart(_) | Command::ChunkAck(_) | Command::ChunkResend(_)) => {
+            handlers::binary::execute(command, response)
+        }
+        // Run by the script runner
+        Command::Sleep(_) | Command::WaitDone(..) => {
+            response.put("ERR SCRIPT_ONLY");
+        }
+        Command::Perf => {
+            response
+                .put("OK CMD max ")
+                .dec(perf.command.max())
+                .put("us mean ")
+                .dec(perf.command.mean())
+                .put("us n ")
+                .dec(perf.command.count())
+                .put(" ARM max ")
+                .dec(perf.arm.max())
+                .put("us mean ")
+                .dec(perf.arm.mean())
+                .put("us n ")
+                .dec(perf.arm.count());
+            // Cycles of each arm phase, with the perf feature
+            for phase in ARM_PHASES {
+                if let Some(cycles) = pulse_gen.arm_perf().phase(phase) {
+                    response
+                        .put(" ")
+                        .put(phase.as_str())
+                        .put(" max ")
+                        .dec(cycles.max())
+                        .put("cyc mean ")
+                        .dec(cycles.mean())
+                        .put("cyc");
+                }
+            }
+            // Only the kinds seen since power-on
+            for (name, count) in perf.usb.counts() {
+                response.put(" USB ").put(name).put(" ").dec(count);
+            }
+        }
+        Command::Round(rounding) => {
+            pulse_gen.set_rounding(rounding);
+            response.put("OK");
+        }
+        Command::Coalesce(enabled) => {
+            pulse_gen.set_coalesce(enabled);
+            response.put("OK");
+        }
+        Command::Debug(ch) => {
+            if !check_channel(ch, response) {
+                return;
+            }
+            let info = pulse_gen.debug(ch);
+            let state = if pulse_gen.disabled() & 1 << ch != 0 {
+                "DISABLED"
+            } else if info.running {
+                "RUNNING"
+            } else {
+                "STOPPED"
+            };
+            response
+                .put("OK ")
+                .put(state)
+                .put(" ")
+                .put(info.phase.as_str())
+                .put(" pc ")
+                .dec(info.pc)
+                .put(" fifo ")
+                .dec(info.tx_level)
+                .put(" stall ")
+                .dec(info.tx_stalled as u8)
+                .put(" dma ");
+            match info.dma_remaining {
+                Some(words) => response.dec(words),
+                None => response.put("-"),
+            };
+            response.put(" emitted ").dec(info.emitted() as u8);
+            if let Some(progress) = info.progress {
+                response
+                    .put(" pulse ")
+                    .dec(progress.pulse)
+                    .put("/")
+                    .dec(progress.pulses);
+                if pulse_gen.per_edge(ch) {
+                    response.put(" remaining ").dec(progress.remaining);
+                }
+            }
+            response
+                .put(" ready ")
+                .dec(info.ready as u8)
+                .put(" retrigger ")
+                .put(pulse_gen.retrigger_policy(ch).as_str())
+                .put(" trigger ")
+                .put(if pulse_gen.trigger_high(ch) {
+                    "HIGH"
+                } else {
+                    "LOW"
+                })
+                .put(" gap ")
+                .put(level_str(pulse_gen.gap_level(ch)))
+                .put(" idle ")
+                .put(level_str(pulse_gen.idle_level(ch)))
+                .put(" interlock ")
+                .put(interlock::state().as_str());
+            if let Some(underrun) = info.underrun {
+                response
+                    .put(" underrun ")
+                    .dec(underrun.pulses)
+                    .put(" at ")
+                    .dec(underrun.at)
+                    .put("us");
+            }
+            match pulse_gen.next_state(ch) {
+                (true, _) => {
+                    response.put(" next QUEUED");
+                }
+                (false, true) => {
+                    response.put(" next FAILED");
+                }
+                _ => {}
+            }
+        }
+        // Left out of this build, refused by features::check
+        #[allow(unreachable_patterns)]
+        _ => {}
+    }
+}
+
+// Why a snapshot wasn't restored, as SNAP END reports it
+fn write_restore_error(response: &mut Response, err: &RestoreError) {
+    match err {
+        RestoreError::Snap(err) => {
+            response.put("ERR SNAP ").put(err.as_str());
+        }
+        RestoreError::Armed { ch } => {
+            response.put("ERR ARMED ch").dec(*ch);
+        }
+        RestoreError::Invalid(invalid) => {
+            response.put("ERR SNAP ");
+            write_invalid(response, invalid);
+        }
+    }
+}
+
+// "OK" or the error, returns whether it was stored
+fn write_flash_result(response: &mut Response, result: Result<(), FlashError>) -> bool {
+    match result {
+        Ok(()) => response.put("OK"),
+        Err(err) => response.put("ERR ").put(err.as_str()),
+    };
+    result.is_ok()
+}
+
+#[cfg(any(feature = "binary-proto", feature = "capture"))]
+fn write_expert_result(response: &mut Response, result: Result<(), ExpertError>) {
+    match result {
+        Ok(()) => response.put("OK"),
+        Err(err @ (ExpertError::NoSpace | ExpertError::InUse)) => {
+            response.put("ERR ").put(err.as_str()).put(", see RES?")
+        }
+        Err(err) => response.put("ERR ").put(err.as_str()),
+    };
+}
+
+// DEFAULT for the output mode's own level
+fn level_str(level: Option<Level>) -> &'static str {
+    level.map_or("DEFAULT", |level| level.as_str())
+}
+
+// The first violation, then how many more there are
+fn write_invalid(response: &mut Response, invalid: &Invalid) {
+    write_violation(response, invalid.first);
+    if invalid.count > 1 {
+        response.put("; ").dec(invalid.count - 1).put(" more");
+    }
+}
+
+fn write_violation(response: &mut Response, violation: Violation) {
+    match violation {
+        Violation::Unpaired { ch, delays, widths } => response
+            .put("ch")
+            .dec(ch)
+            .put(" UNPAIRED ")
+            .dec(delays)
+            .put(" delays ")
+            .dec(widths)
+            .put(" widths"),
+        Violation::PinUnavailable { ch, pin } => {
+            response.put("ch").dec(ch).put(" PIN_UNAVAILABLE ").dec(pin)
+        }
+        Violation::PinConflict { ch, other, pin } => response
+            .put("ch")
+            .dec(ch)
+            .put(" PIN_CONFLICT ")
+            .dec(pin)
+            .put(" with ch")
+            .dec(other),
+        Violation::WideOpenDrain { ch } => response.put("ch").dec(ch).put(" WIDE_OPEN_DRAIN"),
+        Violation::WideLongDelay { ch } => response.put("ch").dec(ch).put(" WIDE_LONG_DELAY"),
+        Violation::MarkerUnsupported { ch } => {
+            response.put("ch").dec(ch).put(" MARKER_UNSUPPORTED")
+        }
+        Violation::MarkerOverlap { ch, pulse } => response
+            .put("ch")
+            .dec(ch)
+            .put(" MARKER_OVERLAP pulse ")
+            .dec(pulse),
+        Violation::PerEdgeUnsupported { ch } => {
+            response.put("ch").dec(ch).put(" PER_EDGE_UNSUPPORTED")
+        }
+        Violation::LevelsUnsupported { ch } => {
+            response.put("ch").dec(ch).put(" LEVELS_UNSUPPORTED")
+        }
+        Violation::LoopbackSource { ch, source } => response
+            .put("ch")
+            .dec(ch)
+            .put(" LOOPBACK_SOURCE ch")
+            .dec(source),
+        Violation::ProgramSpace { words } => response
+            .put("PROGRAM_SPACE ")
+            .dec(words)
+            .put(" of ")
+            .dec(INSTRUCTION_MEMORY)
+            .put(" words"),
+    };
+}
+
+// "OK <pulses> pulses, total <time> (<cycles> cyc)"
+fn load_table(
+    pulse_gen: &mut PulseGenerator,
+    ch: usize,
+    pairs: impl Iterator<Item = Result<(Value, Value), CommandError>>,
+    response: &mut Response,
+) {
+    if !check_channel(ch, response) {
+        return;
+    }
+    let sys_hz = pulse_gen.sys_hz();
+    // Everything is validated before the channel's table is touched
+    let Some(pairs) = decode_table(pairs, sys_hz, pulse_gen.rounding(), response) else {
+        return;
+    };
+    if let Err(violation) = pulse_gen.set_table(ch, &pairs) {
+        response.put("ERR ");
+        write_violation(response, violation);
+        return;
+    }
+    let total: u64 = pairs.iter().map(|(d, w)| d.cycles + w.cycles).sum();
+    response.put("OK ").dec(pairs.len()).put(" pulses, total ");
+    time::write_ps(response, time::cycles_to_ps(total, sys_hz));
+    response.put(" (").dec(total).put(" cyc)");
+}
+
+// The pairs of a TABLE or NEXT, None with the first bad pair reported
+fn decode_table(
+    pairs: impl Iterator<Item = Result<(Value, Value), CommandError>>,
+    sys_hz: u32,
+    rounding: Rounding,
+    response: &mut Response,
+) -> Option<Pairs> {
+    let mut decoded = Pairs::new();
+    for (index, pair) in pairs.enumerate() {
+        let pair = match pair {
+            Ok((delay, width)) => to_achieved(delay, sys_hz, rounding)
+                .and_then(|delay| Ok((delay, to_achieved_u32(width, sys_hz, rounding)?))),
+            Err(err) => {
+                response
+                    .put("ERR PAIR ")
+                    .dec(index)
+                    .put(" ")
+                    .put(err.as_str());
+                return None;
+            }
+        };
+        match pair {
+            Ok(pair) => {
+                if decoded.try_push(pair).is_err() {
+                    response
+                        .put("ERR PAIR ")
+                        .dec(index)
+                        .put(" SEQUENCE_FULL ")
+                        .dec(NUM_PULSES_MAX);
+                    return None;
+                }
+            }
+            Err(err) => {
+                response.put("ERR PAIR ").dec(index).put(" ");
+                write_time_error(response, err, sys_hz);
+                return None;
+            }
+        }
+    }
+    Some(decoded)
+}
+
+fn write_pulse_error(response: &mut Response, err: &PulseError) {
+    match err {
+        PulseError::EmptySequence { ch } => {
+            response.put("ERR EMPTY_SEQUENCE ch").dec(*ch);
+        }
+        PulseError::Armed { ch } => {
+            response.put("ERR ARMED ch").dec(*ch);
+        }
+        PulseError::Disabled { ch } => {
+            response.put("ERR DISABLED ch").dec(*ch);
+        }
+        PulseError::TriggerHigh { ch } => {
+            response.put("ERR TRIGGER_HIGH ch").dec(*ch);
+        }
+        PulseError::T0Infeasible { ch, short_ps } => {
+            response
+                .put("ERR T0_INFEASIBLE ch")
+                .dec(*ch)
+                .put(" lead short by ");
+            time::write_ps(response, *short_ps);
+        }
+        PulseError::InstructionMemoryFull(err) => write_memory_full(response, err),
+        PulseError::Guard { ch, err } => {
+            response
+                .put("ERR GUARD ch")
+                .dec(*ch)
+                .put(" ")
+                .put(err.reason);
+        }
+        PulseError::Cancelled { ch } => {
+            response.put("ERR CANCELLED ch").dec(*ch);
+        }
+    }
+}
+
+// " TRIGGER HIGH" when the input of an enabled member is already high, so
+// its first trigger only comes after the input was low, " TRIGGER LOW"
+// otherwise
+fn write_trigger_level(response: &mut Response, pulse_gen: &PulseGenerator, members: u32) {
+    let high = (0..NUM_CHANNELS)
+        .filter(|ch| (members & !pulse_gen.disabled()) & 1 << ch != 0)
+        .any(|ch| pulse_gen.trigger_high(ch));
+    response.put(if high {
+        " TRIGGER HIGH"
+    } else {
+        " TRIGGER LOW"
+    });
+}
+
+fn write_group_error(response: &mut Response, err: &GroupError) {
+    match err {
+        GroupError::NameTooLong => response.put("ERR BAD_NAME"),
+        GroupError::UnknownGroup => response.put("ERR UNKNOWN_GROUP"),
+        GroupError::BadChannel { .. } => response.put("ERR BAD_CHANNEL max ").dec(NUM_CHANNELS - 1),
+        GroupError::ChannelArmed { ch } => response.put("ERR ARMED ch").dec(*ch),
+        GroupError::ChannelInGroup { ch, group } => {
+            response.put("ERR IN_GROUP ch").dec(*ch).put(" ").put(group)
+        }
+        GroupError::NotMember { ch } => response.put("ERR NOT_MEMBER ch").dec(*ch),
+    };
+}
+
+// "OK REF ch0 T0 <time> LEAD <time>; ch0 <time> ch1 <time>", the first
+// delays the group's next arm uses
+fn write_t0(response: &mut Response, solution: &T0Solution, sys_hz: u32) {
+    response
+        .put("OK REF ch")
+        .dec(solution.reference)
+        .put(" T0 ");
+    time::write_ps(response, time::cycles_to_ps(solution.t0, sys_hz));
+    response.put(" LEAD ");
+    time::write_ps(response, time::cycles_to_ps(solution.lead, sys_hz));
+    response.put(";");
+    for (ch, delay) in solution.delays.iter().enumerate() {
+        if let Some(delay) = delay {
+            response.put(" ch").dec(ch).put(" ");
+            time::write_ps(response, time::cycles_to_ps(*delay, sys_hz));
+        }
+    }
+}
+
+// Lists the resident variants so the host can tell what to free
+fn write_memory_full(response: &mut Response, err: &InstructionMemoryFull) {
+    response
+        .put("ERR PROGRAM_SPACE ")
+        .dec(err.words)
+        .put(" words, ")
+        .dec(err.free)
+        .put(" free, resident");
+    for config in &err.installed {
+        response.put(" ").put(config.as_str());
+    }
+    response.put(", see RES?");
+}
+
+fn check_channel(ch: usize, response: &mut Response) -> bool {
+    if ch >= NUM_CHANNELS {
+        response.put("ERR BAD_CHANNEL max ").dec(NUM_CHANNELS - 1);
+        return false;
+    }
+    true
+}
+
+// A mirror takes its table and settings from its source, "ERR MIRROR OF
+// ch0"
+fn check_not_mirror(pulse_gen: &PulseGenerator, ch: usize, response: &mut Response) -> bool {
+    if let Some(mirror) = pulse_gen.mirror(ch) {
+        response.put("ERR MIRROR OF ch").dec(mirror.source);
+        return false;
+    }
+    true
+}
+
+// The requested duration follows when rounding changed it
+fn write_ok_achieved(response: &mut Response, achieved: Achieved, sys_hz: u32) {
+    let ps = time::cycles_to_ps(achieved.cycles, sys_hz);
+    response.put("OK ");
+    time::write_ps(response, ps);
+    response.put(" (").dec(achieved.cycles).put(" cyc)");
+    if ps != achieved.requested_ps {
+        response.put(" requested ");
+        time::write_ps(response, achieved.requested_ps);
+    }
+}
+
+// "ERR SEQUENCE_FULL <capacity>", as CAP? reports the capacity
+fn write_sequence_full(response: &mut Response) {
+    response.put("ERR SEQUENCE_FULL ").dec(NUM_PULSES_MAX);
+}
+
+// "OK OFF" for a duration of none
+fn write_ok_achieved_or_off(response: &mut Response, achieved: Achieved, sys_hz: u32) {
+    if achieved.cycles == 0 {
+        response.put("OK OFF");
+    } else {
+        write_ok_achieved(response, achieved, sys_hz);
+    }
+}
+
+// None, for OFF, is no cycles at all
+fn to_achieved_or_off(
+    value: Option<Value>,
+    sys_hz: u32,
+    rounding: Rounding,
+) -> Result<Achieved, TimeError> {
+    match value {
+        Some(value) => to_achieved(value, sys_hz, rounding),
+        None => Ok(Achieved::from_cycles(0, sys_hz)),
+    }
+}
+
+// Widths are counted in u32 cycles
+fn to_achieved_u32(value: Value, sys_hz: u32, rounding: Rounding) -> Result<Achieved, TimeError> {
+    let achieved = to_achieved(value, sys_hz, rounding)?;
+    if achieved.cycles > u32::MAX as u64 {
+        return Err(TimeError::OutOfRange);
+    }
+    Ok(achieved)
+}
+
+fn to_achieved(value: Value, sys_hz: u32, rounding: Rounding) -> Result<Achieved, TimeError> {
+    match value {
+        Value::Cycles(cycles) => Ok(Achieved::from_cycles(cycles, sys_hz)),
+        Value::Picos(ps) => Achieved::from_ps(ps, sys_hz, rounding),
+    }
+}
+
+// Names the violated limit so the user knows what is achievable
+fn write_time_error(response: &mut Response, err: TimeError, sys_hz: u32) {
+    let (name, limit) = match err {
+        TimeError::BelowResolution => ("BELOW_RESOLUTION min ", 1),
+        TimeError::OutOfRange => ("OUT_OF_RANGE max ", u32::MAX as u64),
+    };
+    response.put(name);
+    time::write_ps(response, time::cycles_to_ps(limit, sys_hz));
+}
+
+// Responses are dropped rather than blocking when the host isn't reading
+fn write_bytes(serial: &mut SerialPort<UsbBus>, mut data: &[u8]) {
+    while !data.is_empty() {
+        match serial.write(data) {
+            Ok(0) | Err(_) => break,
+            Ok(count) => data = &data[count..],
+        }
+    }
+}
+
+fn write_line(serial: &mut SerialPort<UsbBus>, line: &[u8]) {
+    write_bytes(serial, line);
+    write_bytes(serial, b"\r\n");
+}
+
+// "<addr> <word> <decoded>" per instruction, addresses relative to the
+// program start
+fn write_program(serial: &mut SerialPort<UsbBus>, program: &pio::Program<32>) {
+    for (addr, &word) in program.code.iter().enumerate() {
+        let mut line = Response::new();
+        line.dec0(addr, 2).put(" ").hex0(word, 4).put(" ");
+        disasm::write_instruction(&mut line, word, &program.side_set);
+        write_line(serial, line.as_bytes());
+    }
+}
+
+// "<pulse> <rise> <fall>" per pulse, from the trigger edge at the pin
+fn write_timeline(serial: &mut SerialPort<UsbBus>, pulse_gen: &PulseGenerator, ch: usize) {
+    for (i, (rise, fall)) in pulse_gen.timeline(ch).enumerate() {
+        let mut line = Response::new();
+        line.dec(i).put(" ");
+        time::write_ps(&mut line, rise);
+        line.put(" ");
+        time::write_ps(&mut line, fall);
+        write_line(serial, line.as_bytes());
+    }
+}
+
+// "<pulse> DELAY <requested> <achieved> (<n> cyc) WIDTH ..." per pulse
+fn write_achieved(serial: &mut SerialPort<UsbBus>, pulse_gen: &PulseGenerator, ch: usize) {
+    let sys_hz = pulse_gen.sys_hz();
+    for (i, (delay, width)) in pulse_gen.achieved(ch).enumerate() {
+        let mut line = Response::new();
+        line.dec(i);
+        for (name, achieved) in [(" DELAY ", delay), (" WIDTH ", width)] {
+            line.put(name);
+            time::write_ps(&mut line, achieved.requested_ps);
+            line.put(" ");
+            time::write_ps(&mut line, time::cycles_to_ps(achieved.cycles, sys_hz));
+            line.put(" (").dec(achieved.cycles).put(" cyc)");
+        }
+        write_line(serial, line.as_bytes());
+    }
+}
+
+// "COALESCED ch<n> <index>..." with the pulses the arm merged into the one
+// before them, then the resulting table as ACHIEVED? lists it
+fn write_coalesced(serial: &mut SerialPort<UsbBus>, pulse_gen: &mut PulseGenerator) {
+    for ch in 0..NUM_CHANNELS {
+        let merged = pulse_gen.take_coalesced(ch);
+        if merged.is_empty() {
+            continue;
+        }
+        let mut line = Response::new();
+        line.put("COALESCED ch").dec(ch);
+        for i in merged {
+            line.put(" ").dec(i);
+        }
+        write_line(serial, line.as_bytes());
+        write_achieved(serial, pulse_gen, ch);
+    }
+}
+
+// "<index> <word>" per word, in hex. Nothing follows an error.
+fn write_words(serial: &mut SerialPort<UsbBus>, pulse_gen: &PulseGenerator, ch: usize) {
+    let Ok(words) = pulse_gen.build_words(ch) else {
+        return;
+    };
+    for (i, &word) in words.iter().enumerate() {
+        let mut line = Response::new();
+        line.dec0(i, 3).put(" ").hex0(word, 8);
+        write_line(serial, line.as_bytes());
+    }
+}
+
+// The channel whose table or settings the command changes, for the ones a
+// mirror refuses. Its output pin, calibration and enable are its own.
+fn edited_channel(command: &Command) -> Option<usize> {
+    match *command {
+        Command::Delay(ch, _)
+        | Command::Width(ch, _)
+        | Command::Pulse(ch, ..)
+        | Command::Wide(ch, _)
+        | Command::Tristate(ch, _)
+        | Command::Compensate(ch, _)
+        | Command::PerEdge(ch, _)
+        | Command::GapLevel(ch, _)
+        | Command::IdleLevel(ch, _)
+        | Command::Retrigger(ch, _)
+        | Command::Marker(ch, _)
+        | Command::Divider(ch, _)
+        | Command::TriggerOut(ch, _)
+        | Command::OpenDrain(ch, _)
+        | Command::Table(ch, _)
+        | Command::Next(ch, _)
+        | Command::StreamStart(ch)
+        | Command::T0Offset(ch, _)
+        | Command::PreDelay(ch, _)
+        | Command::RearmGuard(ch, _)
+        | Command::TriggerPin(ch, _)
+        | Command::TriggerFrom(ch, _) => Some(ch),
+        _ => None,
+    }
+}
+
+// ARM from the port: "OK QUEUED" with the arm queued, the outcome follows
+// from service_arms(). What's known without arming is refused right away, as
+// the synchronous ARM of scripts and frames would.
+fn queue_arm(target: Target, pulse_gen: &mut PulseGenerator, protect: &Protect) -> Response {
+    if let Some(blocked) = arm_blocked(protect) {
+        return blocked;
+    }
+    let mut response = Response::new();
+    let request = match target {
+        Target::Channel(ch) => {
+            if !check_channel(ch, &mut response) {
+                return response;
+            }
+            ArmRequest::Channel(ch)
+        }
+        Target::All => ArmRequest::All,
+        Target::Group(name) => match pulse_gen.group(name) {
+            Some(members) => ArmRequest::Group(members),
+            None => {
+                write_group_error(&mut response, &GroupError::UnknownGroup);
+                return response;
+            }
+        },
+    };
+    match pulse_gen.request_arm(request) {
+        Ok(()) => response.put("OK QUEUED"),
+        Err(ArmQueueFull) => response.put("ERR BUSY"),
+    };
+    response
+}
+
+// Carries out the oldest arm queued with queue_arm() and reports it, or all
+// of them for a command that has to find them done. Arms queued before a
+// protection trip or the interlock opening are cancelled instead.
+fn service_arms(
+    serial: &mut SerialPort<UsbBus>,
+    pulse_gen: &mut PulseGenerator,
+    throttle: &mut Throttle,
+    perf: &mut Perf,
+    protect: &Protect,
+    timer: &Timer,
+    all: bool,
+) {
+    if arm_blocked(protect).is_some() {
+        pulse_gen.cancel_arms();
+    }
+    loop {
+        let started = timer.get_counter().ticks();
+        let Some((request, result)) = pulse_gen.service_arm() else {
+            return;
+        };
+        perf.arm.record(timer.get_counter().ticks() - started);
+        throttle.reset();
+        let mut response = Response::new();
+        write_arm_done(&mut response, pulse_gen, request, result);
+        write_line(serial, response.as_bytes());
+        write_coalesced(serial, pulse_gen);
+        if !all {
+            return;
+        }
+    }
+}
+
+// "ARMED <target> TRIGGER LOW|HIGH" or "ERR ARM <target> <error>", the
+// target ALL or the channels asked for
+fn write_arm_done(
+    response: &mut Response,
+    pulse_gen: &PulseGenerator,
+    request: ArmRequest,
+    result: Result<(), PulseError>,
+) {
+    let members = match request {
+        ArmRequest::Channel(ch) => 1 << ch,
+        ArmRequest::All => (1 << NUM_CHANNELS) - 1,
+        ArmRequest::Group(members) => members,
+    };
+    response.put(if result.is_ok() { "ARMED" } else { "ERR ARM" });
+    if request == ArmRequest::All {
+        response.put(" ALL");
+    } else {
+        for ch in (0..NUM_CHANNELS).filter(|ch| members & 1 << ch != 0) {
+            response.put(" ").dec(ch);
+        }
+    }
+    match result {
+        Ok(()) => write_trigger_level(response, pulse_gen, members),
+        Err(err) => {
+            let mut error = Response::new();
+            write_pulse_error(&mut error, &err);
+            response.put(" ").put(error.trim_start_matches("ERR "));
+        }
+    }
+}
+
+// Commands refused while a protection trip is latched, the ones starting
+// an SM
+fn arms(command: &Command) -> bool {
+    match command {
+        Command::Arm(_)
+        | Command::Ping(..)
+        | Command::TestPattern(_, Some(_))
+        | Command::Stress(..)
+        | Command::StressDma(..)
+        | Command::StreamStart(_)
+        | Command::Internal(Some(_))
+        | Command::Skew
+        | Command::SelfTest => true,
+        #[cfg(feature = "binary-proto")]
+        Command::ExpertLoad(..) => true,
+        _ => false,
+    }
+}
+
+// Why nothing may arm at all: a protection trip, or the interlock
+fn arm_blocked(protect: &Protect) -> Option<Response> {
+    let mut response = Response::new();
+    if let Some(trip) = protect.tripped() {
+        write_trip(&mut response, trip);
+    } else if interlock::blocks() {
+        response
+            .put("ERR INTERLOCK ")
+            .put(interlock::state().as_str());
+    }
+    (!response.is_empty()).then_some(response)
+}
+
+// "CHECK OK <longest table> (<cycles> cyc)" or "ERR CHECK <n> problems"
+fn write_check(
+    response: &mut Response,
+    blocked: bool,
+    longest: u64,
+    problems: &Problems,
+    sys_hz: u32,
+) {
+    let count = blocked as usize + problems.len();
+    if count == 0 {
+        response.put("CHECK OK ");
+        time::write_ps(response, time::cycles_to_ps(longest, sys_hz));
+        response.put(" (").dec(longest).put(" cyc)");
+    } else {
+        response.put("ERR CHECK ").dec(count).put(" problems");
+    }
+}
+
+// As write_violation() and write_pulse_error() have it, without "ERR "
+fn write_problem(response: &mut Response, problem: &Problem) {
+    match problem {
+        Problem::Violation(violation) => write_violation(response, *violation),
+        Problem::Pulse(err) => {
+            let mut error = Response::new();
+            write_pulse_error(&mut error, err);
+            response.put(error.strip_prefix("ERR ").unwrap_or(&error));
+        }
+    }
+}
+
+// A protection trip with the DISARM action: the internal ticks stop and every
+// channel goes to its idle level
+fn disarm_everything(pulse_gen: &mut PulseGenerator) {
+    let _ = pulse_gen.set_internal(None);
+    for ch in 0..NUM_CHANNELS {
+        pulse_gen.disarm(ch);
+    }
+}
+
+// "TEMP <max>C|OFF VSYS <min>V|OFF ACTION <action>"
+fn write_thresholds(response: &mut Response, thresholds: &Thresholds) {
+    response.put("TEMP ");
+    match thresholds.temp_max {
+        Some(max) => response.dec(max).put("C"),
+        None => response.put("OFF"),
+    };
+    response.put(" VSYS ");
+    match thresholds.vsys_min {
+        Some(min) => write_milli(response, min as i32, "V"),
+        None => {
+            response.put("OFF");
+        }
+    }
+    response.put(" ACTION ").put(thresholds.action.as_str());
+}
+
+// "<ch> <state> <pulses> <trigger>|- <error>", the trigger in timer ticks
+// (us) and the error flag 0 or 1
+fn write_poll(response: &mut Response, pulse_gen: &PulseGenerator, ch: usize) {
+    let status = pulse_gen.poll_status(ch);
+    let state = if status.disabled {
+        "DISABLED"
+    } else if status.running {
+        "RUNNING"
+    } else {
+        "STOPPED"
+    };
+    response
+        .dec(ch)
+        .put(" ")
+        .put(state)
+        .put(" ")
+        .dec(status.pulses)
+        .put(" ");
+    match status.triggered_at {
+        Some(at) => response.dec(at),
+        None => response.put("-"),
+    };
+    response.put(" ").dec(status.error as u8);
+}
+
+// "<state> pin <pin> <release>", or "OFF" without an interlock
+fn write_interlock(response: &mut Response) {
+    response.put(interlock::state().as_str());
+    let settings = interlock::settings();
+    if let Some(pin) = settings.pin {
+        response
+            .put(" pin ")
+            .dec(pin)
+            .put(" ")
+            .put(settings.release.as_str());
+    }
+}
+
+// "<buffer> <bytes>... STATIC <bytes> STACK <bytes> BOOT <bytes> PEAK
+// <bytes> FREE <bytes>": the static buffers each subsystem owns, then all
+// statics, the room left for the stack, its deepest by the main loop's
+// start and since, and what that leaves
+fn write_mem(response: &mut Response) {
+    let buffers = [
+        ("TABLES", pulse_generator::TABLES_RAM),
+        ("STREAM", pulse_generator::STREAM_RAM),
+        ("EXPERT", pulse_generator::EXPERT_RAM),
+        #[cfg(feature = "capture")]
+        ("TLOG", tlog::RING_RAM),
+        #[cfg(feature = "capture")]
+        ("GLITCH", glitch::RING_RAM),
+        #[cfg(feature = "usb-log")]
+        ("LOGQ", usblog::QUEUE_RAM),
+        ("USB", core::mem::size_of::<UsbBusAllocator<UsbBus>>()),
+    ];
+    for (name, bytes) in buffers {
+        response.put(name).put(" ").dec(bytes).put(" ");
+    }
+    let peak = mem::stack_peak();
+    response
+        .put("STATIC ")
+        .dec(mem::statics())
+        .put(" STACK ")
+        .dec(mem::stack_size())
+        .put(" BOOT ")
+        .dec(mem::boot_peak())
+        .put(" PEAK ")
+        .dec(peak)
+        .put(" FREE ")
+        .dec(mem::stack_size() - peak);
+}
+
+// "<pin> <level>; ..." by pin, or "NONE"
+fn write_safe_state(response: &mut Response) {
+    let settings = safestate::settings();
+    if settings.pins() == 0 {
+        response.put("NONE");
+        return;
+    }
+    let pins = (0..probe::GPIO_COUNT).filter(|&pin| settings.pins() & 1 << pin != 0);
+    for (index, pin) in pins.enumerate() {
+        if index != 0 {
+            response.put("; ");
+        }
+        response.dec(pin).put(" ");
+        if let Some(rest) = settings.get(pin) {
+            response.put(rest.as_str());
+        }
+    }
+}
+
+// "pin <pin> <event>..." in EVENT_NAMES order, or "OFF"
+fn write_debug_pin(response: &mut Response) {
+    let settings = debugpin::settings();
+    let Some(pin) = settings.pin else {
+        response.put("OFF");
+        return;
+    };
+    response.put("pin ").dec(pin);
+    for (bit, name) in debugpin::EVENT_NAMES {
+        if settings.events & bit != 0 {
+            response.put(" ").put(name);
+        }
+    }
+}
+
+// "ERR INTERLOCK OPEN", "ERR INTERLOCK ARMED ch0" or "ERR INTERLOCK DRIVEN
+// 15"
+fn write_interlock_error(response: &mut Response, err: InterlockError) {
+    response.put("ERR INTERLOCK ");
+    match err {
+        InterlockError::Tripped(state) => response.put(state.as_str()),
+        InterlockError::Armed(ch) => response.put("ARMED ch").dec(ch),
+        InterlockError::Driven(pin) => response.put("DRIVEN ").dec(pin),
+    };
+}
+
+// "ERR PROTECT TEMP 71.250C", latched until PROTECT CLEAR
+fn write_trip(response: &mut Response, trip: Trip) {
+    response.put("ERR PROTECT ").put(trip.as_str()).put(" ");
+    write_trip_reading(response, trip);
+}
+
+fn write_trip_reading(response: &mut Response, trip: Trip) {
+    match trip {
+        Trip::Temp(temp) => write_milli(response, temp, "C"),
+        Trip::Vsys(vsys) => write_milli(response, vsys as i32, "V"),
+    }
+}
+
+// Thousandths with three places, e.g. 4500 as "4.500"
+fn write_milli(response: &mut Response, value: i32, unit: &str) {
+    if value < 0 {
+        response.put("-");
+    }
+    let value = value.unsigned_abs();
+    response
+        .dec(value / 1_000)
+        .put(".")
+        .dec0(value % 1_000, 3)
+        .put(unit);
+}
+
+// "READY pico-pulse <version> <channels> session <nonce>", once the host
+// opened the port
+fn write_banner(serial: &mut SerialPort<UsbBus>, session: u32) {
+    let mut line = Response::new();
+    line.put("READY pico-pulse ")
+        .put(env!("CARGO_PKG_VERSION"))
+        .put(" ")
+        .dec(NUM_CHANNELS)
+        .put(" session ")
+        .hex0(session, 8);
+    write_line(serial, line.as_bytes());
+}
+
+// Header of a binary mode reply, see wire
+fn write_session(serial: &mut SerialPort<UsbBus>, session: u32) {
+    let mut header = ArrayString::<9>::new();
+    header.hex0(session, 8).put(" ");
+    write_bytes(serial, header.as_bytes());
+}
+
+fn write_error(serial: &mut SerialPort<UsbBus>, err: ParseError) {
+    write_bytes(serial, b"ERR ");
+    write_line(serial, err.as_str().as_bytes());
+}
@@ -0,0 +1,78 @@
+// RAM accounting for MEM?. The statics end where cortex-m-rt's heap would
+// start, which nothing here uses, and the stack grows down from the top of
+// RAM into what is left. paint() fills that gap with a pattern first thing
+// in main, so the lowest word no longer holding it is as deep as the stack
+// has ever been. The buffer sizes are size_of the statics' own types, see
+// each module's *_RAM.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+// Left alone below the stack pointer when painting, for paint()'s own frame
+const PAINT_MARGIN: usize = 64;
+const PATTERN: u32 = 0x5354_4b50; // "PKTS"
+
+// Defined by cortex-m-rt's link.x
+extern "C" {
+    static __sdata: u32;
+    static __sheap: u32;
+    static _stack_start: u32;
+}
+
+// Stack peak when the main loop started, in bytes
+static BOOT_PEAK: AtomicU32 = AtomicU32::new(0);
+
+fn statics_start() -> usize {
+    // Safety: only the symbol's address is taken
+    unsafe { core::ptr::addr_of!(__sdata) as usize }
+}
+
+fn stack_limit() -> usize {
+    // Safety: as above
+    unsafe { core::ptr::addr_of!(__sheap) as usize }
+}
+
+fn stack_top() -> usize {
+    // Safety: as above
+    unsafe { core::ptr::addr_of!(_stack_start) as usize }
+}
+
+// .data, .bss and .uninit, every static of the firmware and its crates
+pub fn statics() -> usize {
+    stack_limit() - statics_start()
+}
+
+// Room the stack has before it runs into the statics
+pub fn stack_size() -> usize {
+    stack_top() - stack_limit()
+}
+
+// Fills the unused stack with PATTERN. Called before anything that cares
+// about RAM contents is set up and with interrupts still off.
+pub fn paint() {
+    let end = cortex_m::register::msp::read() as usize - PAINT_MARGIN;
+    let mut at = stack_limit();
+    while at < end {
+        // Safety: between the statics and the live stack, nothing owns it
+        unsafe { core::ptr::write_volatile(at as *mut u32, PATTERN) };
+        at += 4;
+    }
+}
+
+// Deepest the stack has been since paint(), in bytes
+pub fn stack_peak() -> usize {
+    let mut at = stack_limit();
+    // Safety: word reads of RAM between the statics and the stack
+    while at < stack_top() && unsafe { core::ptr::read_volatile(at as *const u32) } == PATTERN {
+        at += 4;
+    }
+    stack_top() - at
+}
+
+// Called as the main loop starts
+pub fn mark_boot() {
+    BOOT_PEAK.store(stack_peak() as u32, Ordering::Relaxed);
+}
+
+pub fn boot_peak() -> usize {
+    BOOT_PEAK.load(Ordering::Relaxed) as usize
+}
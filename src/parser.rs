@@ -0,0 +1,701 @@
+// Byte-stream framing for the serial command port.
+//
+// The port is in one of two modes, switched only by magic sequences. In
+// ASCII mode commands are lines terminated by '\r' or '\n', and a line with
+// anything but printable ASCII in it is rejected whole. A frame sent in
+// ASCII mode by mistake is skipped by its length and rejects the line it
+// turned up in, so none of its payload is read as text. BINARY_MAGIC at the
+// start of a line switches to binary mode, where binary frames start with
+// FRAME_START, followed by a command byte, a little-endian u16 payload length
+// and the payload itself. Between frames, ASCII_MAGIC switches back and any
+// other byte is discarded. Neither framing is ever read as the other. The
+// parser is a plain state machine over bytes with no hardware dependencies.
+
+pub const LINE_MAX: usize = 256;
+// Large enough for a full binary pulse table: version and channel bytes + 32
+// u32 pairs
+pub const PAYLOAD_MAX: usize = 2 + 32 * 8;
+pub const FRAME_START: u8 = 0x02;
+pub const FRAME_TIMEOUT_US: u64 = 500_000;
+pub const BINARY_MAGIC: &[u8] = b"\x00PPB1";
+pub const ASCII_MAGIC: &[u8] = b"+++\r\n";
+
+const BUF_LEN: usize = if LINE_MAX > PAYLOAD_MAX {
+    LINE_MAX
+} else {
+    PAYLOAD_MAX
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParseError {
+    LineTooLong,
+    FrameTooLong,
+    FrameTimeout,
+    // Control or non-ASCII bytes in an ASCII line
+    NotText,
+}
+
+impl ParseError {
+    pub const ALL: [ParseError; 4] = [
+        ParseError::LineTooLong,
+        ParseError::FrameTooLong,
+        ParseError::FrameTimeout,
+        ParseError::NotText,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ParseError::LineTooLong => "LINE_TOO_LONG",
+            ParseError::FrameTooLong => "FRAME_TOO_LONG",
+            ParseError::FrameTimeout => "FRAME_TIMEOUT",
+            ParseError::NotText => "NOT_TEXT",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    Ascii,
+    Binary,
+}
+
+impl Mode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Ascii => "ASCII",
+            Mode::Binary => "BINARY",
+        }
+    }
+}
+
+// Since power-on
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Counters {
+    // Mode changes, by magic or set_mode()
+    pub switches: u32,
+    // Bytes dropped between frames in binary mode
+    pub discarded: u32,
+    // Lines dropped as NotText in ASCII mode
+    pub rejected: u32,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum Event<'a> {
+    Line(&'a [u8]),
+    Frame { cmd: u8, payload: &'a [u8] },
+    // The mode the magic switched to
+    Mode(Mode),
+    Error(ParseError),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    // Accumulating an ASCII line
+    Line,
+    // Line overflowed, dropping bytes up to the next terminator
+    Discard,
+    // Matching BINARY_MAGIC at the start of a line
+    Magic,
+    // Binary mode between frames, matching ASCII_MAGIC
+    Idle,
+    // Collecting command byte and payload length (3 bytes)
+    FrameHeader,
+    // Collecting frame_len payload bytes
+    FramePayload,
+    // Oversized frame, or any frame in ASCII mode, dropping frame_len payload
+    // bytes
+    FrameSkip,
+}
+
+pub struct Parser {
+    state: State,
+    mode: Mode,
+    buf: [u8; BUF_LEN],
+    len: usize,
+    // The line being collected has a byte that isn't printable ASCII
+    not_text: bool,
+    // Bytes of the current mode's magic matched so far
+    magic: usize,
+    header: [u8; 3],
+    frame_len: usize,
+    last_byte_us: u64,
+    counters: Counters,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Line,
+            mode: Mode::Ascii,
+            buf: [0; BUF_LEN],
+            len: 0,
+            not_text: false,
+            magic: 0,
+            header: [0; 3],
+            frame_len: 0,
+            last_byte_us: 0,
+            counters: Counters {
+                switches: 0,
+                discarded: 0,
+                rejected: 0,
+            },
+        }
+    }
+
+    // Drops any partial line or frame, staying in the current mode
+    pub fn reset(&mut self) {
+        self.state = match self.mode {
+            Mode::Ascii => State::Line,
+            Mode::Binary => State::Idle,
+        };
+        self.len = 0;
+        self.not_text = false;
+        self.magic = 0;
+        self.frame_len = 0;
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn counters(&self) -> Counters {
+        self.counters
+    }
+
+    // Returns whether the mode changed
+    pub fn set_mode(&mut self, mode: Mode) -> bool {
+        let changed = mode != self.mode;
+        if changed {
+            self.mode = mode;
+            self.counters.switches += 1;
+        }
+        self.reset();
+        changed
+    }
+
+    // Nothing of a next line or frame received yet
+    pub fn idle(&self) -> bool {
+        self.len == 0 && matches!(self.state, State::Line | State::Idle)
+    }
+
+    fn in_frame(&self) -> bool {
+        matches!(
+            self.state,
+            State::FrameHeader | State::FramePayload | State::FrameSkip
+        )
+    }
+
+    // Drops a half-received frame once no byte arrived for FRAME_TIMEOUT_US.
+    // Call this regularly and before feeding each new batch of bytes so the
+    // timeout gets reported.
+    pub fn poll(&mut self, now_us: u64) -> Option<ParseError> {
+        if self.in_frame() && now_us.wrapping_sub(self.last_byte_us) >= FRAME_TIMEOUT_US {
+            self.reset();
+            return Some(ParseError::FrameTimeout);
+        }
+        None
+    }
+
+    pub fn feed(&mut self, byte: u8, now_us: u64) -> Option<Event<'_>> {
+        if self.in_frame() && now_us.wrapping_sub(self.last_byte_us) >= FRAME_TIMEOUT_US {
+            self.reset();
+        }
+        self.last_byte_us = now_us;
+
+        match self.state {
+            State::Line => return self.line_byte(byte),
+            // A stray frame in an overlong line, reported as NotText then
+            State::Discard if byte == FRAME_START => return self.line_byte(byte),
+            State::Discard => {
+                if byte == b'\r' || byte == b'\n' {
+                    self.state = State::Line;
+                    return Some(Event::Error(ParseError::LineTooLong));
+                }
+            }
+            State::Magic => {
+                if byte != BINARY_MAGIC[self.magic] {
+                    // Not the magic after all, the line so far is dropped
+                    // as it has a NUL in it
+                    self.state = State::Line;
+                    self.buf[..self.magic].copy_from_slice(&BINARY_MAGIC[..self.magic]);
+                    self.len = self.magic;
+                    self.not_text = true;
+                    self.magic = 0;
+                    return self.line_byte(byte);
+                }
+                self.magic += 1;
+                if self.magic == BINARY_MAGIC.len() {
+                    self.set_mode(Mode::Binary);
+                    return Some(Event::Mode(Mode::Binary));
+                }
+            }
+            State::Idle => {
+                if byte == ASCII_MAGIC[self.magic] {
+                    self.magic += 1;
+                    if self.magic == ASCII_MAGIC.len() {
+                        self.set_mode(Mode::Ascii);
+                        return Some(Event::Mode(Mode::Ascii));
+                    }
+                    return None;
+                }
+                // A longer run of '+' still ends in the magic
+                if self.magic > 0 && ASCII_MAGIC[..self.magic].iter().all(|&b| b == byte) {
+                    self.counters.discarded += 1;
+                    return None;
+                }
+                self.counters.discarded += self.magic as u32;
+                self.magic = 0;
+                if byte == FRAME_START {
+                    self.state = State::FrameHeader;
+                } else if byte == ASCII_MAGIC[0] {
+                    self.magic = 1;
+                } else {
+                    self.counters.discarded += 1;
+                }
+            }
+            State::FrameHeader => {
+                self.header[self.len] = byte;
+                self.len += 1;
+                if self.len == self.header.len() {
+                    self.len = 0;
+                    self.frame_len = u16::from_le_bytes([self.header[1], self.header[2]]) as usize;
+                    if self.mode == Mode::Ascii {
+                        self.state = if self.frame_len == 0 {
+                            State::Line
+                        } else {
+                            State::FrameSkip
+                        };
+                        return None;
+                    }
+                    if self.frame_len > PAYLOAD_MAX {
+                        self.state = State::FrameSkip;
+                        return Some(Event::Error(ParseError::FrameTooLong));
+                    }
+                    if self.frame_len == 0 {
+                        self.reset();
+                        return Some(Event::Frame {
+                            cmd: self.header[0],
+                            payload: &[],
+                        });
+                    }
+                    self.state = State::FramePayload;
+                }
+            }
+            State::FramePayload => {
+                self.buf[self.len] = byte;
+                self.len += 1;
+                if self.len == self.frame_len {
+                    let len = self.len;
+                    self.reset();
+                    return Some(Event::Frame {
+                        cmd: self.header[0],
+                        payload: &self.buf[..len],
+                    });
+                }
+            }
+            State::FrameSkip => {
+                self.frame_len -= 1;
+                if self.frame_len == 0 {
+                    match self.mode {
+                        // Still rejecting the line the stray frame was in
+                        Mode::Ascii => self.state = State::Line,
+                        Mode::Binary => self.reset(),
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn line_byte(&mut self, byte: u8) -> Option<Event<'_>> {
+        match byte {
+            b'\r' | b'\n' => {
+                let len = self.len;
+                let not_text = self.not_text;
+                self.len = 0;
+                self.not_text = false;
+                if not_text {
+                    self.counters.rejected += 1;
+                    return Some(Event::Error(ParseError::NotText));
+                }
+                if len > 0 {
+                    return Some(Event::Line(&self.buf[..len]));
+                }
+            }
+            // A frame in the wrong mode, skipped whole
+            FRAME_START => {
+                self.len = 0;
+                self.not_text = true;
+                self.state = State::FrameHeader;
+            }
+            _ if self.len == 0 && byte == BINARY_MAGIC[0] => {
+                self.state = State::Magic;
+                self.magic = 1;
+            }
+            _ if self.len == LINE_MAX => {
+                self.len = 0;
+                self.not_text = false;
+                self.state = State::Discard;
+            }
+            _ => {
+                self.buf[self.len] = byte;
+                self.len += 1;
+                self.not_text |= byte != b'\t' && !(0x20..0x7f).contains(&byte);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    // Event without the borrow of the parser
+    #[derive(PartialEq, Eq, Debug)]
+    enum Seen {
+        Line(Vec<u8>),
+        Frame(u8, Vec<u8>),
+        Mode(Mode),
+        Error(ParseError),
+    }
+
+    fn feed(parser: &mut Parser, bytes: &[u8], now_us: u64) -> Vec<Seen> {
+        let mut seen = Vec::new();
+        for &byte in bytes {
+            seen.extend(parser.feed(byte, now_us).map(|event| match event {
+                Event::Line(line) => Seen::Line(line.to_vec()),
+                Event::Frame { cmd, payload } => Seen::Frame(cmd, payload.to_vec()),
+                Event::Mode(mode) => Seen::Mode(mode),
+                Event::Error(err) => Seen::Error(err),
+            }));
+        }
+        seen
+    }
+
+    fn frame(cmd: u8, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![FRAME_START, cmd];
+        bytes.extend((payload.len() as u16).to_le_bytes());
+        bytes.extend(payload);
+        bytes
+    }
+
+    fn binary() -> Parser {
+        let mut parser = Parser::new();
+        assert_eq!(
+            feed(&mut parser, BINARY_MAGIC, 0),
+            [Seen::Mode(Mode::Binary)]
+        );
+        parser
+    }
+
+    #[test]
+    fn line_of_line_max_bytes_fits() {
+        let mut parser = Parser::new();
+        let line = [b'A'; LINE_MAX];
+        assert_eq!(feed(&mut parser, &line, 0), []);
+        assert_eq!(feed(&mut parser, b"\n", 0), [Seen::Line(line.to_vec())]);
+    }
+
+    #[test]
+    fn overlong_line_is_dropped_whole() {
+        let mut parser = Parser::new();
+        let line = [b'A'; LINE_MAX + 1];
+        assert_eq!(feed(&mut parser, &line, 0), []);
+        assert_eq!(
+            feed(&mut parser, b"\r\n", 0),
+            [Seen::Error(ParseError::LineTooLong)]
+        );
+        assert_eq!(
+            feed(&mut parser, b"ARM 0\n", 0),
+            [Seen::Line(b"ARM 0".to_vec())]
+        );
+        // Nothing of the dropped line's tail survives either
+        let mut long = vec![b'B'; 3 * LINE_MAX];
+        long.extend(b"\nX\n");
+        assert_eq!(
+            feed(&mut parser, &long, 0),
+            [
+                Seen::Error(ParseError::LineTooLong),
+                Seen::Line(b"X".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn line_with_control_bytes_is_rejected() {
+        let mut parser = Parser::new();
+        assert_eq!(
+            feed(&mut parser, b"AR\x01M 0\nARM\t0\n", 0),
+            [
+                Seen::Error(ParseError::NotText),
+                Seen::Line(b"ARM\t0".to_vec())
+            ]
+        );
+        assert_eq!(parser.counters().rejected, 1);
+    }
+
+    #[test]
+    fn frame_split_over_many_feeds() {
+        let mut parser = binary();
+        let bytes = frame(0x05, &[1, 2, 3, 4, 5]);
+        let step = FRAME_TIMEOUT_US - 1;
+        let (last, head) = bytes.split_last().unwrap();
+        for (i, &byte) in head.iter().enumerate() {
+            assert_eq!(feed(&mut parser, &[byte], i as u64 * step), []);
+            assert_eq!(parser.poll(i as u64 * step), None);
+        }
+        assert_eq!(
+            feed(&mut parser, &[*last], head.len() as u64 * step),
+            [Seen::Frame(0x05, vec![1, 2, 3, 4, 5])]
+        );
+    }
+
+    #[test]
+    fn frames_interleaved_with_noise() {
+        let mut parser = binary();
+        let mut bytes = b"xy".to_vec();
+        bytes.extend(frame(0x01, &[9; 10]));
+        // A partial ASCII magic, then a frame start cuts it off
+        bytes.extend(b"++");
+        bytes.extend(frame(0x02, &[4]));
+        bytes.extend(b"\r\n\x00");
+        bytes.extend(frame(0x03, &[7]));
+        assert_eq!(
+            feed(&mut parser, &bytes, 0),
+            [
+                Seen::Frame(0x01, vec![9; 10]),
+                Seen::Frame(0x02, vec![4]),
+                Seen::Frame(0x03, vec![7])
+            ]
+        );
+        assert_eq!(parser.counters().discarded, 7);
+        assert_eq!(parser.mode(), Mode::Binary);
+    }
+
+    #[test]
+    fn frame_times_out() {
+        let mut parser = binary();
+        let bytes = frame(0x05, &[7, 8, 9]);
+        assert_eq!(feed(&mut parser, &bytes[..5], 100), []);
+        assert!(!parser.idle());
+        assert_eq!(parser.poll(100 + FRAME_TIMEOUT_US - 1), None);
+        assert_eq!(
+            parser.poll(100 + FRAME_TIMEOUT_US),
+            Some(ParseError::FrameTimeout)
+        );
+        assert!(parser.idle());
+        assert_eq!(parser.poll(100 + 2 * FRAME_TIMEOUT_US), None);
+        // The rest of the timed out frame is noise now
+        let late = 100 + FRAME_TIMEOUT_US;
+        assert_eq!(feed(&mut parser, &bytes[5..], late), []);
+        assert_eq!(
+            feed(&mut parser, &bytes, late),
+            [Seen::Frame(0x05, vec![7, 8, 9])]
+        );
+    }
+
+    #[test]
+    fn stale_frame_is_dropped_without_poll() {
+        let mut parser = binary();
+        let bytes = frame(0x05, &[1, 2, 3]);
+        assert_eq!(feed(&mut parser, &bytes[..4], 0), []);
+        assert_eq!(
+            feed(&mut parser, &bytes, FRAME_TIMEOUT_US),
+            [Seen::Frame(0x05, vec![1, 2, 3])]
+        );
+    }
+
+    #[test]
+    fn oversized_frame_is_skipped() {
+        let mut parser = binary();
+        let mut bytes = frame(0x01, &[0; PAYLOAD_MAX + 1]);
+        bytes.extend(frame(0x02, &[1]));
+        assert_eq!(
+            feed(&mut parser, &bytes, 0),
+            [
+                Seen::Error(ParseError::FrameTooLong),
+                Seen::Frame(0x02, vec![1])
+            ]
+        );
+    }
+
+    // Bytes the framing cares about, so random input gets into every state
+    const INTERESTING: &[u8] = &[
+        FRAME_START,
+        0x00,
+        0x01,
+        0xff,
+        b'+',
+        b'\r',
+        b'\n',
+        b'P',
+        b'B',
+        b'1',
+        b'A',
+    ];
+
+    fn noise(rng: &mut Rng, parser: &mut Parser, now: &mut u64) {
+        for _ in 0..rng.below(600) {
+            let byte = match rng.below(4) {
+                0 => rng.u8(),
+                1 => BINARY_MAGIC[rng.below(BINARY_MAGIC.len() as u64) as usize],
+                2 => ASCII_MAGIC[rng.below(ASCII_MAGIC.len() as u64) as usize],
+                _ => *rng.pick(INTERESTING),
+            };
+            *now += match rng.below(50) {
+                0 => FRAME_TIMEOUT_US,
+                1..=5 => rng.below(FRAME_TIMEOUT_US),
+                _ => rng.below(100),
+            };
+            if rng.one_in(100) {
+                let _ = parser.poll(*now);
+            }
+            if rng.one_in(500) {
+                let mode = *rng.pick(&[Mode::Ascii, Mode::Binary]);
+                parser.set_mode(mode);
+            }
+            let _ = parser.feed(byte, *now);
+        }
+    }
+
+    // Whatever came before, a quiet FRAME_TIMEOUT_US, the ASCII magic and a
+    // line terminator get a command through
+    #[test]
+    fn random_bytes_never_wedge_the_parser() {
+        let mut rng = Rng::new(103);
+        let mut now = 0;
+        for _ in 0..2_000 {
+            let mut parser = Parser::new();
+            noise(&mut rng, &mut parser, &mut now);
+            now += FRAME_TIMEOUT_US;
+            let _ = parser.poll(now);
+            feed(&mut parser, ASCII_MAGIC, now);
+            feed(&mut parser, b"\n", now);
+            assert_eq!(
+                feed(&mut parser, b"*IDN?\n", now),
+                [Seen::Line(b"*IDN?".to_vec())]
+            );
+        }
+    }
+
+    #[test]
+    fn empty_frame_stays_in_binary_mode() {
+        let mut parser = binary();
+        let mut bytes = frame(0x05, &[]);
+        bytes.extend(b"ARM 0\n");
+        assert_eq!(feed(&mut parser, &bytes, 0), [Seen::Frame(0x05, vec![])]);
+        assert_eq!(parser.mode(), Mode::Binary);
+    }
+
+    #[test]
+    fn run_of_plus_ends_in_ascii_magic() {
+        let mut parser = binary();
+        assert_eq!(
+            feed(&mut parser, b"+++++\r\n", 0),
+            [Seen::Mode(Mode::Ascii)]
+        );
+        assert_eq!(parser.counters().discarded, 2);
+    }
+
+    #[test]
+    fn frame_in_ascii_mode_is_skipped_whole() {
+        let mut parser = Parser::new();
+        let stray = frame(0x01, b"\nARM 0\n+++\r\n\x00PPB1");
+        let mut bytes = stray.clone();
+        bytes.extend(b"\n*IDN?\n");
+        assert_eq!(
+            feed(&mut parser, &bytes, 0),
+            [
+                Seen::Error(ParseError::NotText),
+                Seen::Line(b"*IDN?".to_vec())
+            ]
+        );
+        // In the middle of a line, and with text straight after it
+        let mut bytes = b"ST".to_vec();
+        bytes.extend(&stray);
+        bytes.extend(b"ARM 0\n");
+        assert_eq!(
+            feed(&mut parser, &bytes, 0),
+            [Seen::Error(ParseError::NotText)]
+        );
+        // In an overlong line
+        let mut bytes = vec![b'A'; LINE_MAX + 10];
+        bytes.extend(&stray);
+        bytes.extend(b"\n");
+        assert_eq!(
+            feed(&mut parser, &bytes, 0),
+            [Seen::Error(ParseError::NotText)]
+        );
+        assert_eq!(parser.mode(), Mode::Ascii);
+        assert_eq!(parser.counters().rejected, 3);
+    }
+
+    // Lines that arm or write flash. The host only sends them as text while
+    // the parser is in ASCII mode, so they must never come out otherwise.
+    const DANGEROUS: &[&[u8]] = &[
+        b"ARM 0",
+        b"arm all",
+        b"AUTOARM ON",
+        b"USBID 1234 5678 CONFIRM",
+        b"SCRIPT END",
+        b"LIFETIME SAVE",
+    ];
+    const HARMLESS: &[&[u8]] = &[b"*IDN?", b"STATUS?", b"MODE?"];
+
+    // Frame payloads made of everything either framing reacts to
+    fn payload(rng: &mut Rng) -> Vec<u8> {
+        let mut payload = Vec::new();
+        for _ in 0..rng.below(12) {
+            match rng.below(5) {
+                0 => payload.extend(*rng.pick(DANGEROUS)),
+                1 => payload.extend(ASCII_MAGIC),
+                2 => payload.extend(BINARY_MAGIC),
+                3 => payload.push(*rng.pick(b"\r\n")),
+                _ => payload.push(rng.u8()),
+            }
+        }
+        payload.truncate(PAYLOAD_MAX);
+        payload
+    }
+
+    // Whatever mix of lines, frames, magics and stray bytes the host sends,
+    // text only becomes a line in ASCII mode
+    #[test]
+    fn interleaved_framings_never_leak_commands() {
+        let mut rng = Rng::new(152);
+        for _ in 0..500 {
+            let mut parser = Parser::new();
+            for _ in 0..200 {
+                let mut bytes = Vec::new();
+                match rng.below(7) {
+                    0 => bytes.extend(*rng.pick(HARMLESS)),
+                    1 if parser.mode() == Mode::Binary => bytes.extend(*rng.pick(DANGEROUS)),
+                    1 => bytes.resize(LINE_MAX + 1, b'A'),
+                    2 | 3 => bytes = frame(rng.u8(), &payload(&mut rng)),
+                    4 => {
+                        bytes.push(b'\n');
+                        bytes.extend(BINARY_MAGIC);
+                    }
+                    5 => bytes.extend(ASCII_MAGIC),
+                    _ => {
+                        let magic = *rng.pick(&[BINARY_MAGIC, ASCII_MAGIC]);
+                        bytes.extend(&magic[..rng.below(magic.len() as u64) as usize]);
+                        // Anything but a frame start, which would make the
+                        // next frame's bytes look like the gap between two
+                        bytes.extend((0..rng.below(4)).map(|_| rng.u8() | 0x80));
+                    }
+                }
+                if rng.one_in(2) {
+                    bytes.push(*rng.pick(b"\r\n"));
+                }
+                for seen in feed(&mut parser, &bytes, 0) {
+                    if let Seen::Line(line) = seen {
+                        assert!(!DANGEROUS.contains(&line.as_slice()), "{:?}", line);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,252 @@
+// Command latency bookkeeping for PERF?. Samples are timer ticks (us) from
+// the USB packet carrying the end of a command to its response being ready,
+// so several commands in one packet overstate the later ones. With the perf
+// feature the phases of each arm are timed in cycles too, see ArmPerf.
+// The USB errors reading the port are counted here too, see UsbErrors.
+
+use usb_device::UsbError;
+
+use crate::usblog;
+
+// Samples the max and mean are taken over
+pub const WINDOW: usize = 32;
+
+pub struct Latency {
+    samples: [u32; WINDOW],
+    next: usize,
+    len: usize,
+}
+
+impl Latency {
+    pub const fn new() -> Self {
+        Self {
+            samples: [0; WINDOW],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    pub fn record(&mut self, us: u64) {
+        self.samples[self.next] = us.min(u32::MAX as u64) as u32;
+        self.next = (self.next + 1) % WINDOW;
+        self.len = (self.len + 1).min(WINDOW);
+    }
+
+    pub fn count(&self) -> usize {
+        self.len
+    }
+
+    pub fn max(&self) -> u32 {
+        self.samples[..self.len].iter().copied().max().unwrap_or(0)
+    }
+
+    pub fn mean(&self) -> u32 {
+        let sum: u64 = self.samples[..self.len].iter().map(|&s| s as u64).sum();
+        sum.checked_div(self.len as u64).unwrap_or(0) as u32
+    }
+}
+
+// Every command, and the arms up to the channel waiting for its trigger. An
+// ARM from the port counts when the main loop carries it out, its command
+// only queues it.
+pub struct Perf {
+    pub command: Latency,
+    pub arm: Latency,
+    pub usb: UsbErrors,
+}
+
+impl Perf {
+    pub const fn new() -> Self {
+        Self {
+            command: Latency::new(),
+            arm: Latency::new(),
+            usb: UsbErrors::new(),
+        }
+    }
+}
+
+// Every UsbError but WouldBlock, which only means no data
+pub const USB_ERRORS: [(UsbError, &str); 7] = [
+    (UsbError::ParseError, "PARSE"),
+    (UsbError::BufferOverflow, "BUFFER_OVERFLOW"),
+    (UsbError::EndpointOverflow, "ENDPOINT_OVERFLOW"),
+    (UsbError::EndpointMemoryOverflow, "ENDPOINT_MEMORY_OVERFLOW"),
+    (UsbError::InvalidEndpoint, "INVALID_ENDPOINT"),
+    (UsbError::Unsupported, "UNSUPPORTED"),
+    (UsbError::InvalidState, "INVALID_STATE"),
+];
+
+// Errors reading the serial port since power-on, by kind
+pub struct UsbErrors {
+    counts: [u32; USB_ERRORS.len()],
+    // Bit per kind logged since the host last configured the device
+    logged: u8,
+}
+
+impl UsbErrors {
+    pub const fn new() -> Self {
+        Self {
+            counts: [0; USB_ERRORS.len()],
+            logged: 0,
+        }
+    }
+
+    // Counts the error, and logs it the first time its kind shows up in
+    // the session
+    pub fn record(&mut self, err: UsbError) {
+        let Some(kind) = USB_ERRORS.iter().position(|&(known, _)| known == err) else {
+            return;
+        };
+        self.counts[kind] = self.counts[kind].saturating_add(1);
+        if self.logged & 1 << kind == 0 {
+            self.logged |= 1 << kind;
+            usblog::warn!("USB read error {}", USB_ERRORS[kind].1);
+        }
+    }
+
+    // The host configured the device again
+    pub fn new_session(&mut self) {
+        self.logged = 0;
+    }
+
+    // Name and count of the kinds seen at all
+    pub fn counts(&self) -> impl Iterator<Item = (&'static str, u32)> + '_ {
+        USB_ERRORS
+            .iter()
+            .zip(self.counts)
+            .filter(|(_, count)| *count != 0)
+            .map(|(&(_, name), count)| (name, count))
+    }
+}
+
+// Phases of arming a channel, timed in system clock cycles with the perf
+// feature
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ArmPhase {
+    // Stopping the SM, and swapping its program if the variant changed
+    Stop,
+    // Writing the table into the DMA buffer
+    Build,
+    // Starting the DMA and waiting for the first pulse to reach the FIFO
+    Dma,
+    // Driving the outputs and starting the SM
+    Start,
+}
+
+pub const ARM_PHASES: [ArmPhase; 4] = [
+    ArmPhase::Stop,
+    ArmPhase::Build,
+    ArmPhase::Dma,
+    ArmPhase::Start,
+];
+
+impl ArmPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArmPhase::Stop => "STOP",
+            ArmPhase::Build => "BUILD",
+            ArmPhase::Dma => "DMA",
+            ArmPhase::Start => "START",
+        }
+    }
+}
+
+// SysTick counts down from its 24-bit reload at the system clock, wrapping
+// every 134 ms at 125 MHz, far longer than any phase
+#[cfg(feature = "perf")]
+const SYST_MASK: u32 = 0x00ff_ffff;
+
+// Starts SysTick free-running for Stopwatch, nothing without the perf
+// feature. Nothing else uses SysTick.
+pub fn init() {
+    #[cfg(feature = "perf")]
+    {
+        // Safety: SysTick isn't used otherwise, its interrupt stays off
+        let syst = unsafe { &*cortex_m::peripheral::SYST::PTR };
+        unsafe {
+            syst.rvr.write(SYST_MASK);
+            syst.cvr.write(0);
+            // Processor clock, counter enabled
+            syst.csr.write(0b101);
+        }
+    }
+}
+
+#[cfg(feature = "perf")]
+fn systick() -> u32 {
+    // Safety: read-only access to the current value
+    unsafe { (*cortex_m::peripheral::SYST::PTR).cvr.read() }
+}
+
+// Cycles of each phase of one arm. Without the perf feature it is empty
+// and lap() does nothing, so the instrumentation compiles away.
+pub struct Stopwatch {
+    #[cfg(feature = "perf")]
+    last: u32,
+    #[cfg(feature = "perf")]
+    laps: [u32; ARM_PHASES.len()],
+}
+
+impl Stopwatch {
+    #[inline(always)]
+    pub fn start() -> Self {
+        Self {
+            #[cfg(feature = "perf")]
+            last: systick(),
+            #[cfg(feature = "perf")]
+            laps: [0; ARM_PHASES.len()],
+        }
+    }
+
+    // Ends `phase`, the next one starts now
+    #[inline(always)]
+    pub fn lap(&mut self, phase: ArmPhase) {
+        #[cfg(feature = "perf")]
+        {
+            let now = systick();
+            self.laps[phase as usize] = self.last.wrapping_sub(now) & SYST_MASK;
+            self.last = now;
+        }
+        #[cfg(not(feature = "perf"))]
+        let _ = phase;
+    }
+}
+
+// Per-phase cycles over the last WINDOW arms
+pub struct ArmPerf {
+    #[cfg(feature = "perf")]
+    phases: [Latency; ARM_PHASES.len()],
+}
+
+impl ArmPerf {
+    pub const fn new() -> Self {
+        Self {
+            #[cfg(feature = "perf")]
+            phases: [const { Latency::new() }; ARM_PHASES.len()],
+        }
+    }
+
+    // Logs the arm's phases at debug level and adds them to the statistics
+    #[inline(always)]
+    pub fn record(&mut self, ch: usize, stopwatch: &Stopwatch) {
+        #[cfg(feature = "perf")]
+        for phase in ARM_PHASES {
+            let cycles = stopwatch.laps[phase as usize];
+            usblog::debug!("arm {} {} {} cycles", ch, phase.as_str(), cycles);
+            self.phases[phase as usize].record(cycles as u64);
+        }
+        #[cfg(not(feature = "perf"))]
+        let _ = (ch, stopwatch);
+    }
+
+    // Cycles the phase took, None without the perf feature
+    #[cfg(feature = "perf")]
+    pub fn phase(&self, phase: ArmPhase) -> Option<&Latency> {
+        Some(&self.phases[phase as usize])
+    }
+
+    #[cfg(not(feature = "perf"))]
+    pub fn phase(&self, _phase: ArmPhase) -> Option<&Latency> {
+        None
+    }
+}
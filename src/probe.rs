@@ -0,0 +1,20 @@
+// Read-only looks at GPIO inputs for debugging trigger wiring. Nothing here
+// changes a pin's function, direction or output, so pins driven by armed
+// channels carry on undisturbed.
+
+pub const GPIO_COUNT: u8 = 30;
+// Longest WATCH, the main loop and USB are held off meanwhile
+pub const WATCH_MS_MAX: u32 = 1_000;
+
+// Transitions counted by watch()
+pub struct Transitions {
+    pub rising: u32,
+    pub falling: u32,
+    // Level at the end of the watch
+    pub level: bool,
+}
+
+#[cfg(target_os = "none")]
+mod hw;
+#[cfg(target_os = "none")]
+pub use hw::*;
@@ -0,0 +1,68 @@
+use super::*;
+use crate::board::hal::pac;
+
+// Synchronized input level as SIO sees it. The pad's input buffer is
+// enabled for the read if it was off and put back afterwards.
+pub fn read(pin: u8) -> bool {
+    with_input(pin, || level(pin))
+}
+
+// Counts edges for `ms` from the latches behind the GPIO interrupts, so
+// glitches shorter than a poll are still seen. Only one edge of each
+// direction is counted per poll, a burst faster than the poll loop counts
+// low. Clears the pin's edge latches, on the trigger pin that includes the
+// one the retrigger monitor reads.
+pub fn watch(pin: u8, ms: u32) -> Transitions {
+    with_input(pin, || {
+        // Safety: INTR edge bits are write-1-to-clear, only this pin's are
+        // touched. TIMERAWL is read-only.
+        let io = unsafe { &*pac::IO_BANK0::ptr() };
+        let timer = unsafe { &*pac::TIMER::ptr() };
+        let intr = io.intr(pin as usize / 8);
+        let shift = 4 * (pin as u32 % 8);
+        let (fall, rise) = (1 << (shift + 2), 1 << (shift + 3));
+        intr.write(|w| unsafe { w.bits(fall | rise) });
+
+        let mut transitions = Transitions {
+            rising: 0,
+            falling: 0,
+            level: false,
+        };
+        let start = timer.timerawl().read().bits();
+        while timer.timerawl().read().bits().wrapping_sub(start) < ms * 1_000 {
+            let bits = intr.read().bits() & (fall | rise);
+            if bits != 0 {
+                intr.write(|w| unsafe { w.bits(bits) });
+                transitions.rising += (bits & rise != 0) as u32;
+                transitions.falling += (bits & fall != 0) as u32;
+            }
+        }
+        transitions.level = level(pin);
+        transitions
+    })
+}
+
+fn level(pin: u8) -> bool {
+    // Safety: read-only access to GPIO_IN
+    let sio = unsafe { &*pac::SIO::ptr() };
+    sio.gpio_in().read().bits() & (1 << pin) != 0
+}
+
+// The input enable only gates the pad's input path, the output is left as
+// it is
+fn with_input<T>(pin: u8, f: impl FnOnce() -> T) -> T {
+    // Safety: only this pad's IE bit is changed, and restored
+    let pads = unsafe { &*pac::PADS_BANK0::ptr() };
+    let pad = pads.gpio(pin as usize);
+    let enabled = pad.read().ie().bit_is_set();
+    if !enabled {
+        pad.modify(|_, w| w.ie().set_bit());
+        // Through the input synchronizer
+        cortex_m::asm::delay(4);
+    }
+    let result = f();
+    if !enabled {
+        pad.modify(|_, w| w.ie().clear_bit());
+    }
+    result
+}
@@ -0,0 +1,157 @@
+// Over-temperature and under-voltage protection. The ADC reads the die
+// temperature sensor and, on boards that wire it, VSYS; main checks them
+// every CHECK_INTERVAL_US. A reading past its threshold latches a trip,
+// which stays until PROTECT CLEAR even if the reading recovers.
+
+use crate::board::{self, hal::pac};
+
+pub const CHECK_INTERVAL_US: u64 = 100_000;
+// RP2040 rated junction temperature range
+pub const TEMP_MAX: u8 = 125;
+
+// 12 bit conversions against the 3.3V supply
+const ADC_FULL_SCALE: u64 = 4096;
+const ADC_VREF_UV: u64 = 3_300_000;
+const TEMP_SENSOR_INPUT: u8 = 4;
+// Sensor voltage at 27C and its slope, from the datasheet
+const TEMP_SENSOR_27C_UV: i64 = 706_000;
+const TEMP_SENSOR_UV_PER_C: i64 = 1_721;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Action {
+    // Latch and report, armed channels carry on
+    #[default]
+    Warn,
+    // Also disarm every channel, driving the outputs to their idle level
+    Disarm,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Warn => "WARN",
+            Action::Disarm => "DISARM",
+        }
+    }
+}
+
+// Stored in the flash config, None turns a check off
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Thresholds {
+    // Highest die temperature, whole degrees C
+    pub temp_max: Option<u8>,
+    // Lowest VSYS, mV
+    pub vsys_min: Option<u16>,
+    pub action: Action,
+}
+
+// The reading that tripped
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Trip {
+    // Millidegrees C
+    Temp(i32),
+    // mV
+    Vsys(u32),
+}
+
+impl Trip {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Trip::Temp(_) => "TEMP",
+            Trip::Vsys(_) => "VSYS",
+        }
+    }
+}
+
+pub struct Readings {
+    // Millidegrees C
+    pub temp: i32,
+    // mV, None on boards without VSYS on an ADC input
+    pub vsys: Option<u32>,
+}
+
+pub struct Protect {
+    pub thresholds: Thresholds,
+    tripped: Option<Trip>,
+    next_check: u64,
+}
+
+impl Protect {
+    // Powers up the ADC and the temperature sensor, the ADC clock has to be
+    // running
+    pub fn new(thresholds: Thresholds) -> Self {
+        // Safety: only the ADC block, which nothing else uses, and the VSYS
+        // pad are touched
+        let resets = unsafe { &*pac::RESETS::ptr() };
+        resets.reset().modify(|_, w| w.adc().clear_bit());
+        while resets.reset_done().read().adc().bit_is_clear() {}
+        let adc = unsafe { &*pac::ADC::ptr() };
+        adc.cs().write(|w| w.en().set_bit().ts_en().set_bit());
+        while adc.cs().read().ready().bit_is_clear() {}
+        if let Some(input) = board::VSYS_ADC {
+            // Analog input: digital input and output both off
+            let pads = unsafe { &*pac::PADS_BANK0::ptr() };
+            pads.gpio(26 + input as usize)
+                .modify(|_, w| w.ie().clear_bit().od().set_bit());
+        }
+        Self {
+            thresholds,
+            tripped: None,
+            next_check: 0,
+        }
+    }
+
+    // Checks the readings once per interval, returns a trip when it is
+    // latched
+    pub fn poll(&mut self, now: u64) -> Option<Trip> {
+        if now < self.next_check || self.tripped.is_some() {
+            return None;
+        }
+        self.next_check = now + CHECK_INTERVAL_US;
+        let readings = read();
+        let hot = self
+            .thresholds
+            .temp_max
+            .filter(|&max| readings.temp > max as i32 * 1_000)
+            .map(|_| Trip::Temp(readings.temp));
+        let low = match (self.thresholds.vsys_min, readings.vsys) {
+            (Some(min), Some(vsys)) if vsys < min as u32 => Some(Trip::Vsys(vsys)),
+            _ => None,
+        };
+        self.tripped = hot.or(low);
+        self.tripped
+    }
+
+    pub fn tripped(&self) -> Option<Trip> {
+        self.tripped
+    }
+
+    // A reading still past its threshold trips again on the next check
+    pub fn clear(&mut self) {
+        self.tripped = None;
+        self.next_check = 0;
+    }
+}
+
+pub fn read() -> Readings {
+    let sensor_uv = convert(TEMP_SENSOR_INPUT) as i64 * ADC_VREF_UV as i64;
+    let sensor_uv = sensor_uv / ADC_FULL_SCALE as i64;
+    let temp = 27_000 - (sensor_uv - TEMP_SENSOR_27C_UV) * 1_000 / TEMP_SENSOR_UV_PER_C;
+    // Behind a 1:3 divider
+    let vsys = board::VSYS_ADC
+        .map(|input| (convert(input) as u64 * ADC_VREF_UV * 3 / ADC_FULL_SCALE / 1_000) as u32);
+    Readings {
+        temp: temp as i32,
+        vsys,
+    }
+}
+
+// One conversion, a couple of us at the 48MHz ADC clock
+fn convert(input: u8) -> u16 {
+    // Safety: the ADC is only used from here
+    let adc = unsafe { &*pac::ADC::ptr() };
+    adc.cs()
+        .modify(|_, w| unsafe { w.ainsel().bits(input).start_once().set_bit() });
+    while adc.cs().read().ready().bit_is_clear() {}
+    adc.result().read().result().bits()
+}
@@ -1,141 +1,3062 @@
-use defmt::info;
+use arrayvec::ArrayString;
+use core::ops::Range;
+#[cfg(feature = "binary-proto")]
+use core::ops::RangeInclusive;
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering};
 use pio::{
-    ArrayVec, Assembler, JmpCondition, MovDestination, MovOperation, MovSource, SideSet, WaitSource,
-};
-use rp2040_hal::{
-    pac::{DMA, PIO0, RESETS},
-    pio::{Buffers::OnlyTx, PIOBuilder, PIOExt, Running, Rx, StateMachine, Tx, PIO, SM0},
+    ArrayVec, Assembler, JmpCondition, MovDestination, MovOperation, MovSource, OutDestination,
+    SideSet, WaitSource,
 };
 
+use crate::probe;
+use crate::snapshot::{Blob, Reader, SnapError, Writer};
+use crate::time::{cycles_to_ps, Achieved, Cycles, Rounding};
+use crate::timeline::{self, Timeline, Timing};
+
+// The state machines, DMA channels and pins themselves. Everything above
+// them, parameters, validation and program generation, builds on the host.
+#[cfg(target_os = "none")]
+mod hw;
+#[cfg(target_os = "none")]
+pub use hw::*;
+// A PIO state machine for the tests to run the programs on
+#[cfg(test)]
+mod pio_model;
+
+pub const NUM_CHANNELS: usize = 2;
 pub const NUM_PULSES_MAX: usize = 32;
+// (delay, width) pairs, (delay, width, levels) triples in wide mode, (delay,
+// pre, width, post) with a marker or (delay high, delay low, width) triples
+// with long delays. The edge count and the words ahead of it are written to
+// the FIFO by the CPU, the room for them is kept for build_words().
+pub const DMA_BUF_LEN: usize = 2 + 4 * NUM_PULSES_MAX;
+
+// Delays the standard program can count in a single u32 loop
+const SHORT_DELAY_MAX: u64 = u32::MAX as u64 + 1;
+// Taken off delays for the long delay program's extra out and branches, the
+// standard program only needs 1 for its loop exit
+const LONG_DELAY_OVERHEAD: u64 = 3;
+// Loaded into X for each extra 2^32 cycle chunk, the chunk branch itself
+// takes the remaining 4 cycles
+const LONG_DELAY_CHUNK_RELOAD: u32 = u32::MAX - 3;
+
+// Instruction memory shared by all state machines of a PIO block
+pub const INSTRUCTION_MEMORY: usize = 32;
+
+// Streamed pulses are fed through a ring of static blocks: one is being sent
+// by the DMA while the host fills the others
+pub const STREAM_BLOCK_PAIRS: usize = 32;
+pub const STREAM_BLOCKS: usize = 4;
+
+pub const TRIGGER_PIN: u8 = 0;
+
+// DMA channel feeding each channel's SM, CH0 and CH1 as new() takes them
+pub const CHANNEL_DMA: [u8; NUM_CHANNELS] = [0, 1];
+
+// Channel state for code that can't borrow the PulseGenerator, such as
+// interrupt handlers. Only written from the main loop where the SMs are
+// started and stopped, channel n runs on SM n.
+static ARMED: [AtomicBool; NUM_CHANNELS] = [const { AtomicBool::new(false) }; NUM_CHANNELS];
+// Absolute addresses of the edge wait, first | last << 8
+static WAIT_PCS: [AtomicU16; NUM_CHANNELS] = [const { AtomicU16::new(0) }; NUM_CHANNELS];
+// Bit per GPIO the channel's program drives
+static OUTPUTS: [AtomicU32; NUM_CHANNELS] = [const { AtomicU32::new(0) }; NUM_CHANNELS];
+
+// The channel's SM is running, it may be past its trigger already
+pub fn is_armed(ch: usize) -> bool {
+    ARMED
+        .get(ch)
+        .is_some_and(|armed| armed.load(Ordering::Acquire))
+}
+
+// Bit per GPIO driven by any channel, armed or not
+pub fn output_pins() -> u32 {
+    OUTPUTS
+        .iter()
+        .fold(0, |pins, outputs| pins | outputs.load(Ordering::Relaxed))
+}
+
+// Bit per GPIO the channel drives
+pub fn channel_outputs(ch: usize) -> u32 {
+    OUTPUTS[ch].load(Ordering::Relaxed)
+}
+
+// GPIOs the board hands over to PIO0, including the trigger pin, and the
+// outputs the channels start on
+#[derive(Clone, Copy)]
+pub struct BoardPins {
+    pub pio: &'static [u8],
+    pub default_outputs: [u8; NUM_CHANNELS],
+}
+
+// Levels of the pin pair during a pulse in wide mode, bit 0 is the base pin
+pub const LEVELS_DEFAULT: u8 = 0b01;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OutputMode {
+    PushPull,
+    // Pulses pull the pin low, idle releases it to an external pull-up
+    OpenDrain,
+}
+
+// Output level between pulses or at rest, see PulseGenerator::set_gap_level()
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Level {
+    Low,
+    High,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Low => "LOW",
+            Level::High => "HIGH",
+        }
+    }
+}
+
+// Trigger edge a GPIO delivers, see PulseGenerator::set_trigger_edge()
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Edge {
+    Rising,
+    Falling,
+}
+
+impl Edge {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Edge::Rising => "RISING",
+            Edge::Falling => "FALLING",
+        }
+    }
+}
+
+// Side-set value driving the level in the output mode. In open drain 1
+// drives the pin low and 0 releases it, None is 0 in either mode.
+fn level_side(level: Option<Level>, output: OutputMode) -> u8 {
+    match level {
+        None => 0,
+        Some(Level::High) => (output == OutputMode::PushPull) as u8,
+        Some(Level::Low) => (output == OutputMode::OpenDrain) as u8,
+    }
+}
+
+// What a trigger edge arriving while the channel's table is running does
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RetriggerPolicy {
+    // Nothing, the SM only looks at the trigger before the first pulse
+    Ignore,
+    // Remembers one edge and runs the table again once it is done
+    Latch,
+    // Drives the output low and runs the table again from the start
+    Abort,
+}
+
+impl RetriggerPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RetriggerPolicy::Ignore => "IGNORE",
+            RetriggerPolicy::Latch => "LATCH",
+            RetriggerPolicy::Abort => "ABORT",
+        }
+    }
+}
+
+// Trigger edges seen while a channel's table runs
+#[derive(Clone, Copy, Default)]
+struct Retrigger {
+    // Past the trigger, edges from here on are retriggers
+    started: bool,
+    // Latched edge waiting for the table to finish
+    pending: bool,
+}
+
+// Variant of the pulse program a channel runs
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ProgramConfig {
+    pub wide: bool,
+    pub output: OutputMode,
+    // Counts delays above 2^32 cycles with a second loop register
+    pub long_delay: bool,
+    // Drives pin + 1 high around each pulse
+    pub marker: bool,
+    // Copies every trigger edge as a fixed width pulse, see
+    // compile_trigger_out()
+    pub trigger_out: bool,
+    // Two pulses with the second delay below the standard minimum, see
+    // compile_double()
+    pub double: bool,
+    // Waits for trigger edges before every pulse, see compile_per_edge()
+    pub per_edge: bool,
+    // Side-set value during delays, pulses get the other one
+    pub gap: u8,
+    // Rests at the pulse's side-set value after the table rather than the
+    // gap's, see compile_split()
+    pub split: bool,
+}
+
+// Margins of the marker output around each pulse, in cycles
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Marker {
+    // Marker high before the rising edge
+    pub pre: u32,
+    // Marker high after the falling edge
+    pub post: u32,
+}
+
+impl Marker {
+    // Shortest margins the marker program can produce, shorter ones are
+    // rounded up
+    pub const PRE_MIN: u32 = 1;
+    pub const POST_MIN: u32 = 2;
+
+    // Cycles the delay before a pulse has to leave for the marker to drop
+    // after the previous pulse and rise again, plus the program's one cycle
+    // minimum delay
+    fn delay_min(&self, first: bool) -> u64 {
+        let post = if first { 0 } else { self.post_cycles() };
+        (self.pre_cycles() + post) as u64 + 1
+    }
+
+    fn pre_cycles(&self) -> u32 {
+        self.pre.max(Self::PRE_MIN)
+    }
+
+    fn post_cycles(&self) -> u32 {
+        self.post.max(Self::POST_MIN)
+    }
+}
+
+// Trigger input synchronizer ahead of the SM's `wait`
+pub const TRIGGER_SYNC_CYCLES: u32 = 2;
+// The `wait` that sees the final edge, the edge loop's `jmp` and the two
+// `out`s of the first pulse. Every program variant compensates its extra
+// instructions in the delay, so this is the same for all of them.
+pub const TRIGGER_PATH_CYCLES: u32 = 4;
+// The two `out`s at the top of the pulse loop, low after each falling edge
+// before the next delay starts counting
+pub const PULSE_GAP_CYCLES: u32 = 2;
+// The `wait` that sees the edge, the trigger-out program rises right after
+pub const TRIGGER_OUT_PATH_CYCLES: u32 = 1;
+// The shortest trigger to output latency PIO allows
+pub const TRIGGER_OUT_LATENCY_CYCLES: u32 = TRIGGER_SYNC_CYCLES + TRIGGER_OUT_PATH_CYCLES;
+
+impl ProgramConfig {
+    fn pins(&self, base: u8) -> Range<u8> {
+        base..base.saturating_add(1 + (self.wide || self.marker) as u8)
+    }
+
+    // First instruction of the edge wait, relative to the program start.
+    // The others pull the edge count ahead of it.
+    fn edge_loop_start(&self) -> u8 {
+        if self.per_edge {
+            PC_EDGE_LOOP_START_PER_EDGE
+        } else {
+            0
+        }
+    }
+
+    // Last instruction of the edge wait, relative to the program start
+    fn edge_loop_end(&self) -> u8 {
+        if self.trigger_out {
+            PC_EDGE_LOOP_END_TRIGGER_OUT
+        } else if self.per_edge {
+            PC_EDGE_LOOP_END_PER_EDGE
+        } else if self.double {
+            PC_EDGE_LOOP_END_DOUBLE
+        } else if self.long_delay {
+            PC_EDGE_LOOP_END_LONG
+        } else {
+            PC_EDGE_LOOP_END
+        }
+    }
 
+    // Side-set value of the level the pins rest at while disarmed and
+    // waiting for the trigger, the pulse level with a split program
+    pub fn idle_side(&self) -> u8 {
+        self.gap ^ self.split as u8
+    }
+
+    // Shortest delay the program can count, shorter ones are rounded up
+    pub fn min_delay(&self) -> u32 {
+        if self.long_delay {
+            LONG_DELAY_OVERHEAD as u32
+        } else {
+            1 + self.split as u32
+        }
+    }
+
+    // Words after the last pulse: the end word of the split program
+    fn epilogue_words(&self) -> u32 {
+        self.split as u32
+    }
+
+    // Delay and width, plus the levels in wide mode, the split delay with
+    // long delays or both margins with a marker. The second pulse of a
+    // double pulse only has its delay left.
+    fn words_per_pulse(&self) -> u32 {
+        2 + (self.wide || self.long_delay) as u32 + 2 * self.marker as u32
+    }
+
+    // Delays after the first pulse, the double pulse program can leave out
+    // the loop exit cycle
+    pub fn min_next_delay(&self) -> u32 {
+        if self.double {
+            0
+        } else {
+            self.min_delay()
+        }
+    }
+
+    // Shortest low time between two pulses: the gap after the falling edge
+    // and the shortest delay after it
+    pub fn min_gap(&self) -> u32 {
+        PULSE_GAP_CYCLES + self.min_next_delay()
+    }
+
+    // The wide and marker programs spend a cycle pulling the next word with
+    // the output high, the trigger-out program one reloading the width, the
+    // double pulse program one on either width and the split program two
+    // looking for the end word
+    pub fn min_width(&self) -> u32 {
+        1 + (self.wide || self.marker || self.trigger_out || self.double) as u32
+            + 2 * self.split as u32
+    }
+
+    pub fn timing(&self) -> Timing {
+        Timing {
+            latency: TRIGGER_SYNC_CYCLES + TRIGGER_PATH_CYCLES,
+            min_delay: self.min_delay(),
+            min_next_delay: self.min_next_delay(),
+            min_width: self.min_width(),
+            gap: PULSE_GAP_CYCLES,
+            per_edge: self.per_edge,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match (self.wide, self.long_delay, self.output) {
+            _ if self.trigger_out && self.output == OutputMode::OpenDrain => "TRIGOUT_OD",
+            _ if self.trigger_out => "TRIGOUT",
+            _ if self.per_edge && self.output == OutputMode::OpenDrain => "PER_EDGE_OD",
+            _ if self.per_edge => "PER_EDGE",
+            _ if self.marker && self.output == OutputMode::OpenDrain => "MARKER_OD",
+            _ if self.marker => "MARKER",
+            _ if self.double && self.output == OutputMode::OpenDrain => "DOUBLE_OD",
+            _ if self.double => "DOUBLE",
+            _ if self.split && self.output == OutputMode::OpenDrain => "SPLIT_OD",
+            _ if self.split => "SPLIT",
+            (true, _, _) => "WIDE",
+            (_, true, OutputMode::PushPull) => "LONG",
+            (_, true, OutputMode::OpenDrain) => "LONG_OD",
+            (_, _, OutputMode::PushPull) => "STANDARD",
+            (_, _, OutputMode::OpenDrain) => "STANDARD_OD",
+        }
+    }
+
+    // Cycles from the final trigger edge at the pin to the first output edge
+    // for a zero delay pulse. A first delay d above min_delay() adds
+    // d - min_delay(). The edge count doesn't change it as long as the
+    // first pulse is already in the FIFO, which the DMA ensures.
+    pub fn trigger_latency(&self) -> Cycles {
+        if self.trigger_out {
+            return Cycles(TRIGGER_OUT_LATENCY_CYCLES);
+        }
+        Cycles(TRIGGER_SYNC_CYCLES + TRIGGER_PATH_CYCLES + self.min_delay())
+    }
+}
+
+// Delays and widths are kept in cycles as requested, the per-program
+// compensation is applied when the DMA buffer is built
+#[derive(Clone)]
 pub struct PulseParameter {
-    delay: ArrayVec<u32, NUM_PULSES_MAX>,
+    delay: ArrayVec<u64, NUM_PULSES_MAX>,
     width: ArrayVec<u32, NUM_PULSES_MAX>,
+    // Durations in ps as they were asked for, before rounding to cycles
+    delay_requested: ArrayVec<u64, NUM_PULSES_MAX>,
+    width_requested: ArrayVec<u64, NUM_PULSES_MAX>,
+    // Per-pulse levels in wide mode, pulses past the end use LEVELS_DEFAULT
+    levels: ArrayVec<u8, NUM_PULSES_MAX>,
+    // Output level during delays and at rest, None for the output mode's
+    // own: low in push-pull, released in open drain. Pulses get the other
+    // level of the gap.
+    gap: Option<Level>,
+    idle: Option<Level>,
+    pin: u8,
+    // Drives pin and pin + 1 from a 2-bit level per pulse
+    wide: bool,
+    output: OutputMode,
+    // Releases the output pins to high impedance while disarmed
+    idle_tristate: bool,
+    // Shortens the first delay by the trigger latency, every delay in per
+    // edge mode
+    compensate_latency: bool,
+    // Each qualifying trigger edge releases the next pulse alone, its delay
+    // counting from that edge
+    per_edge: bool,
+    // GPIO the channel waits on for its trigger unless it is an internal
+    // member
+    trigger_pin: u8,
+    // Channel whose output pin is waited on instead, see Trigger
+    trigger_from: Option<usize>,
+    // Output on pin + 1 bracketing each pulse
+    marker: Option<Marker>,
+    retrigger: RetriggerPolicy,
+    // Runs on the nth trigger edge after arming, 1 for the first
+    trigger_divider: u32,
+    // Width of the copy of each trigger edge, replaces the table, delays,
+    // levels, marker and divider while set
+    trigger_out: Option<u32>,
+    // First delay in cycles relative to the T0 of the channel's group,
+    // negative before it, see PulseGenerator::set_t0()
+    t0_offset: Option<i64>,
+    // Cycles added ahead of the first delay, and as asked for in ps. Kept
+    // apart from the table so a table whose first pulse starts right away
+    // can still be held off after the trigger.
+    pre_delay: u64,
+    pre_delay_requested: u64,
+    // Cycles after a table went out before the firmware arms the channel
+    // again, for an internal tick, a latched retrigger or a queued table.
+    // And as asked for in ps.
+    rearm_guard: u64,
+    rearm_guard_requested: u64,
+    // The channel copies another's configuration but for its pin, see
+    // PulseGenerator::set_mirror()
+    mirror: Option<Mirror>,
+    // First delay placed by the group's T0 and the output's calibration at
+    // the last arm, not part of the configuration
+    placed_delay: Option<u64>,
 }
 
 impl PulseParameter {
-    fn new() -> Self {
+    fn new(pin: u8) -> Self {
         Self {
             delay: ArrayVec::new(),
             width: ArrayVec::new(),
+            delay_requested: ArrayVec::new(),
+            width_requested: ArrayVec::new(),
+            levels: ArrayVec::new(),
+            gap: None,
+            idle: None,
+            pin,
+            wide: false,
+            output: OutputMode::PushPull,
+            idle_tristate: false,
+            compensate_latency: false,
+            per_edge: false,
+            trigger_pin: TRIGGER_PIN,
+            trigger_from: None,
+            marker: None,
+            retrigger: RetriggerPolicy::Ignore,
+            trigger_divider: 1,
+            trigger_out: None,
+            t0_offset: None,
+            pre_delay: 0,
+            pre_delay_requested: 0,
+            rearm_guard: 0,
+            rearm_guard_requested: 0,
+            mirror: None,
+            placed_delay: None,
+        }
+    }
+
+    // Replaces the table, dropping any levels
+    fn set_pairs(&mut self, pairs: &[(Achieved, Achieved)]) {
+        self.delay.clear();
+        self.width.clear();
+        self.delay_requested.clear();
+        self.width_requested.clear();
+        self.levels.clear();
+        for &(delay, width) in pairs {
+            self.delay.push(delay.cycles);
+            self.width.push(width.cycles as u32);
+            self.delay_requested.push(delay.requested_ps);
+            self.width_requested.push(width.requested_ps);
+        }
+    }
+
+    // One more delay, the table stays as it is when full
+    fn push_delay(&mut self, delay: Achieved) -> Result<(), SequenceFull> {
+        self.delay
+            .try_push(delay.cycles)
+            .map_err(|_| SequenceFull)?;
+        self.delay_requested.push(delay.requested_ps);
+        Ok(())
+    }
+
+    fn push_width(&mut self, width: Achieved) -> Result<(), SequenceFull> {
+        self.width
+            .try_push(width.cycles as u32)
+            .map_err(|_| SequenceFull)?;
+        self.width_requested.push(width.requested_ps);
+        Ok(())
+    }
+
+    // A whole pulse with the pin pair levels it drives in wide mode
+    fn push_pulse(
+        &mut self,
+        delay: Achieved,
+        width: Achieved,
+        levels: u8,
+    ) -> Result<(), SequenceFull> {
+        if self.delay.is_full() || self.width.is_full() {
+            return Err(SequenceFull);
+        }
+        // Pulses added without levels keep the default
+        while self.levels.len() < self.delay.len() {
+            self.levels.push(LEVELS_DEFAULT);
+        }
+        self.push_delay(delay)?;
+        self.push_width(width)?;
+        self.levels.push(levels & 0b11);
+        Ok(())
+    }
+
+    // Every field, for SNAP?. The lists keep their own lengths since a table
+    // being entered can be unpaired.
+    fn encode(&self, w: &mut Writer) {
+        w.u8(self.pin);
+        w.u8(self.wide as u8);
+        w.u8(self.output as u8);
+        w.u8(self.idle_tristate as u8);
+        w.u8(self.compensate_latency as u8);
+        w.u8(self.per_edge as u8);
+        w.u8(self.trigger_pin);
+        w.u8(self.trigger_from.map_or(0xff, |from| from as u8));
+        w.u8(self.retrigger as u8);
+        w.u32(self.trigger_divider);
+        w.u8(self.marker.is_some() as u8);
+        let marker = self.marker.unwrap_or(Marker { pre: 0, post: 0 });
+        w.u32(marker.pre);
+        w.u32(marker.post);
+        w.u8(self.trigger_out.is_some() as u8);
+        w.u32(self.trigger_out.unwrap_or(0));
+        w.u8(encode_level(self.gap));
+        w.u8(encode_level(self.idle));
+        w.u8(self.t0_offset.is_some() as u8);
+        w.u64(self.t0_offset.unwrap_or(0) as u64);
+        w.u64(self.pre_delay);
+        w.u64(self.pre_delay_requested);
+        w.u64(self.rearm_guard);
+        w.u64(self.rearm_guard_requested);
+        w.u8(self.mirror.map_or(0xff, |mirror| mirror.source as u8));
+        w.u8(self.mirror.is_some_and(|mirror| mirror.invert) as u8);
+        w.list(self.delay.iter().copied(), Writer::u64);
+        w.list(self.width.iter().copied(), Writer::u32);
+        w.list(self.delay_requested.iter().copied(), Writer::u64);
+        w.list(self.width_requested.iter().copied(), Writer::u64);
+        w.list(self.levels.iter().copied(), Writer::u8);
+    }
+
+    fn decode(r: &mut Reader) -> Result<Self, SnapError> {
+        let pin = r.u8()?;
+        let wide = r.bool()?;
+        let output = match r.u8()? {
+            0 => OutputMode::PushPull,
+            1 => OutputMode::OpenDrain,
+            _ => return Err(SnapError::Corrupt),
+        };
+        let idle_tristate = r.bool()?;
+        let compensate_latency = r.bool()?;
+        let per_edge = r.bool()?;
+        let trigger_pin = r.u8()?;
+        let trigger_from = Some(r.u8()? as usize).filter(|&from| from != 0xff);
+        let retrigger = match r.u8()? {
+            0 => RetriggerPolicy::Ignore,
+            1 => RetriggerPolicy::Latch,
+            2 => RetriggerPolicy::Abort,
+            _ => return Err(SnapError::Corrupt),
+        };
+        let trigger_divider = r.u32()?;
+        let has_marker = r.bool()?;
+        let marker = Marker {
+            pre: r.u32()?,
+            post: r.u32()?,
+        };
+        let has_trigger_out = r.bool()?;
+        let trigger_out = r.u32()?;
+        let gap = decode_level(r.u8()?)?;
+        let idle = decode_level(r.u8()?)?;
+        let has_t0_offset = r.bool()?;
+        let t0_offset = r.u64()? as i64;
+        let pre_delay = r.u64()?;
+        let pre_delay_requested = r.u64()?;
+        let rearm_guard = r.u64()?;
+        let rearm_guard_requested = r.u64()?;
+        let mirror = match r.u8()? {
+            0xff => None,
+            source => Some(source as usize),
+        };
+        let invert = r.bool()?;
+        let params = Self {
+            delay: r.list(Reader::u64)?,
+            width: r.list(Reader::u32)?,
+            delay_requested: r.list(Reader::u64)?,
+            width_requested: r.list(Reader::u64)?,
+            levels: r.list(Reader::u8)?,
+            gap,
+            idle,
+            pin,
+            wide,
+            output,
+            idle_tristate,
+            compensate_latency,
+            per_edge,
+            trigger_pin,
+            trigger_from,
+            marker: has_marker.then_some(marker),
+            retrigger,
+            trigger_divider,
+            trigger_out: has_trigger_out.then_some(trigger_out),
+            t0_offset: has_t0_offset.then_some(t0_offset),
+            pre_delay,
+            pre_delay_requested,
+            rearm_guard,
+            rearm_guard_requested,
+            mirror: mirror.map(|source| Mirror { source, invert }),
+            placed_delay: None,
+        };
+        // What the commands setting these fields would have refused
+        if trigger_divider == 0
+            || mirror.is_some_and(|source| source >= NUM_CHANNELS)
+            || trigger_from.is_some_and(|from| from >= NUM_CHANNELS)
+            || trigger_pin >= probe::GPIO_COUNT
+            || params.delay.len() != params.delay_requested.len()
+            || params.width.len() != params.width_requested.len()
+            || params.levels.iter().any(|&levels| levels > 0b11)
+            || has_marker && (marker.pre < Marker::PRE_MIN || marker.post < Marker::POST_MIN)
+        {
+            return Err(SnapError::Corrupt);
+        }
+        Ok(params)
+    }
+
+    fn program_config(&self) -> ProgramConfig {
+        if self.trigger_out.is_some() {
+            return ProgramConfig {
+                wide: false,
+                output: self.output,
+                long_delay: false,
+                marker: false,
+                trigger_out: true,
+                double: false,
+                per_edge: false,
+                gap: 0,
+                split: false,
+            };
+        }
+        let long_delay = self.delays().any(|delay| delay > SHORT_DELAY_MAX);
+        let gap = self.gap_side();
+        let split = self.idle_side() != gap;
+        let double = self.pulses() == 2
+            && self.delay[1] == 0
+            && !(self.wide
+                || long_delay
+                || self.marker.is_some()
+                || self.per_edge
+                || gap != 0
+                || split);
+        ProgramConfig {
+            wide: self.wide,
+            output: self.output,
+            long_delay,
+            marker: self.marker.is_some(),
+            trigger_out: false,
+            double,
+            per_edge: self.per_edge,
+            gap,
+            split,
+        }
+    }
+
+    fn gap_side(&self) -> u8 {
+        level_side(self.gap, self.output)
+    }
+
+    // Driven by the stopped SM too, while disarmed and waiting for the
+    // trigger
+    fn idle_side(&self) -> u8 {
+        level_side(self.idle, self.output)
+    }
+
+    // Delays as the program counts them, the ones measured from a trigger
+    // edge already include the fixed latency
+    fn effective_delays(&self) -> impl Iterator<Item = u64> + '_ {
+        self.delays().enumerate().map(|(i, delay)| {
+            if (i == 0 || self.per_edge) && self.compensate_latency {
+                delay.saturating_sub((TRIGGER_SYNC_CYCLES + TRIGGER_PATH_CYCLES) as u64)
+            } else {
+                delay
+            }
+        })
+    }
+
+    // The table's delays, the first one as the group's T0 and the output's
+    // calibration placed it and with the pre-delay ahead of it. Folded into
+    // the first delay rather than a word of its own, so latency compensation
+    // comes off the sum and no pre-delay leaves the words as they were.
+    fn delays(&self) -> impl Iterator<Item = u64> + '_ {
+        self.delay
+            .iter()
+            .enumerate()
+            .map(|(i, &delay)| match self.placed_delay {
+                _ if i != 0 => delay,
+                Some(placed) => placed + self.pre_delay,
+                None => delay + self.pre_delay,
+            })
+    }
+
+    fn pins(&self) -> Range<u8> {
+        self.program_config().pins(self.pin)
+    }
+
+    // Complete (delay, width) pairs, the ones that get emitted
+    fn pulses(&self) -> usize {
+        self.delay.len().min(self.width.len())
+    }
+
+    // See PulseGenerator::timeline()
+    fn timeline(&self, sys_hz: u32) -> Timeline<'_> {
+        timeline::predict(
+            &self.delay,
+            &self.width,
+            self.pre_delay,
+            self.program_config().timing(),
+            self.compensate_latency,
+            sys_hz,
+        )
+    }
+
+    // Nothing to emit on arming
+    fn is_empty(&self) -> bool {
+        self.trigger_out.is_none() && self.pulses() == 0
+    }
+
+    // Merges each pulse whose delay is below what the program can count
+    // into the pulse before it. The width takes in the gap and the delay, so
+    // the first rise and the last fall stay where they were asked for and
+    // the high time grows by exactly the low time swallowed. Per-edge tables
+    // have no gaps, wide pulses only merge with ones of the same levels and
+    // a width past u32 cycles is left apart.
+    fn coalesce(&mut self, sys_hz: u32) -> Merged {
+        let mut merged = Merged::new();
+        let config = self.program_config();
+        if config.per_edge || self.trigger_out.is_some() {
+            return merged;
+        }
+        let min = config.min_next_delay() as u64;
+        let gap = PULSE_GAP_CYCLES as u64;
+        let levels = |p: &Self, i: usize| p.levels.get(i).copied().unwrap_or(LEVELS_DEFAULT);
+        let (mut i, mut original) = (1, 1);
+        while i < self.pulses() {
+            let width = self.width[i - 1] as u64 + gap + self.delay[i] + self.width[i] as u64;
+            if self.delay[i] >= min
+                || (self.wide && levels(self, i) != levels(self, i - 1))
+                || width > u32::MAX as u64
+            {
+                i += 1;
+                original += 1;
+                continue;
+            }
+            self.width[i - 1] = width as u32;
+            self.width_requested[i - 1] +=
+                cycles_to_ps(gap, sys_hz) + self.delay_requested[i] + self.width_requested[i];
+            self.delay.remove(i);
+            self.width.remove(i);
+            self.delay_requested.remove(i);
+            self.width_requested.remove(i);
+            if i < self.levels.len() {
+                self.levels.remove(i);
+            }
+            merged.push(original);
+            original += 1;
+        }
+        merged
+    }
+
+    // Every word the channel's program pulls, the prologue then the table.
+    // An immediate table leaves out the trigger edge count.
+    fn write_words(&self, immediate: bool, mut push: impl FnMut(u32)) {
+        self.write_prologue(immediate, &mut push);
+        self.write_table(push);
+    }
+
+    // The words ahead of the first pulse, written to the FIFO by the CPU
+    // rather than by DMA so the edge count never is part of the table: the
+    // second width of a double pulse or the chunk reload word, then the
+    // edge count
+    fn write_prologue(&self, immediate: bool, mut push: impl FnMut(u32)) {
+        if self.trigger_out.is_some() {
+            return;
+        }
+        let config = self.program_config();
+        if config.double {
+            push(self.width[1].saturating_sub(2));
+        }
+        if config.long_delay {
+            push(LONG_DELAY_CHUNK_RELOAD);
+        }
+        if !immediate {
+            push(self.trigger_divider - 1); // trigger edges after the first
+        }
+    }
+
+    // The DMA table for the channel's program, word by word
+    fn write_table(&self, mut push: impl FnMut(u32)) {
+        if let Some(width) = self.trigger_out {
+            push(width.saturating_sub(2));
+            return;
+        }
+        let config = self.program_config();
+        let delays = self.effective_delays();
+        for (i, (delay, &width)) in delays.zip(&self.width).enumerate() {
+            if let Some(marker) = self.marker {
+                // The margins are counted out of the delay before the pulse,
+                // the outs pulling the width and post words out of the width
+                // and the post margin
+                let delay = delay.saturating_sub(marker.delay_min(i == 0)) as u32;
+                push(delay);
+                push(marker.pre_cycles() - 1);
+                push(width.saturating_sub(2));
+                push(marker.post_cycles() - 2);
+            } else if config.long_delay {
+                // Each 2^32 cycle chunk is counted by the high word, the
+                // extra out and branches are taken off the low word
+                let delay = delay.saturating_sub(LONG_DELAY_OVERHEAD);
+                push((delay >> 32) as u32);
+                push(delay as u32);
+                push(width.saturating_sub(1));
+            } else if config.double {
+                // The first width loads the gap with the output high, the
+                // gap is the whole low time from the falling edge
+                if i == 0 {
+                    push(delay.saturating_sub(1) as u32);
+                    push(width.saturating_sub(2));
+                } else {
+                    push((delay + PULSE_GAP_CYCLES as u64 - 1) as u32);
+                }
+            } else if config.split {
+                // The end word test and the delay pull keep the output at
+                // the pulse level, taken off the width except ahead of the
+                // first pulse where they stand in for the 1-bit program's
+                // outs. A delay of 0 would read as the end word. Later delays
+                // are rounded up to the same minimum as the first.
+                if i == 0 {
+                    push(delay.saturating_sub(1).max(1) as u32);
+                } else {
+                    let delay = delay.max(config.min_next_delay() as u64);
+                    push((delay + PULSE_GAP_CYCLES as u64 - 1) as u32);
+                }
+                push(width.saturating_sub(3));
+            } else if config.wide {
+                // The wide program spends one more cycle driving the levels,
+                // taken off the width
+                push(delay.saturating_sub(1) as u32);
+                push(width.saturating_sub(2));
+                push(*self.levels.get(i).unwrap_or(&LEVELS_DEFAULT) as u32);
+            } else {
+                push(delay.saturating_sub(1) as u32);
+                push(width.saturating_sub(1));
+            }
+        }
+        if config.split {
+            push(0);
         }
     }
 }
 
-pub struct PulseGenerator {
-    pio: PIO<PIO0>,
-    sm: Option<StateMachine<(PIO0, SM0), Running>>,
-    tx: Option<Tx<(PIO0, SM0)>>,
-    rx: Option<Rx<(PIO0, SM0)>>,
-    params: [PulseParameter; 2],
+// Level fields of a snapshot: 0 for the output mode's own, then LOW and HIGH
+fn encode_level(level: Option<Level>) -> u8 {
+    match level {
+        None => 0,
+        Some(Level::Low) => 1,
+        Some(Level::High) => 2,
+    }
 }
 
-impl PulseGenerator {
-    pub fn new(pio: PIO0, _dma: DMA, resets: &mut RESETS) -> Self {
-        let (mut pio, sm0, _, _, _) = pio.split(resets);
-        let mut asm = Assembler::new();
-        asm.push(true, true);
-        let program = pio.install(&asm.assemble_program()).unwrap();
-        let (sm, rx, tx) = PIOBuilder::from_installed_program(program)
-            .buffers(OnlyTx)
-            .build(sm0);
-        let sm = sm.start();
-        Self {
-            pio,
-            sm: Some(sm),
-            tx: Some(tx),
-            rx: Some(rx),
-            params: [PulseParameter::new(), PulseParameter::new()],
-        }
-    }
-
-    pub fn check(&mut self) {
-        let sm = self.sm.take().unwrap();
-        let sm = sm.stop();
-        info!("sm address: {}", sm.instruction_address());
-        let sm = sm.start();
-        self.sm = Some(sm);
-    }
-
-    pub fn arm(&mut self) {
-        info!("arm");
-        match self.sm.take() {
-            Some(sm) => {
-                let (sm, old) = sm.uninit(self.rx.take().unwrap(), self.tx.take().unwrap());
-                self.pio.uninstall(old);
-                let program = self.compile();
-                info!("Program[3]: {}", program.code[3]);
-                info!("install");
-                let program = self.pio.install(&program).unwrap();
-                info!("sm");
-                let (sm, rx, mut tx) = PIOBuilder::from_installed_program(program)
-                    .buffers(OnlyTx)
-                    .side_set_pin_base(15)
-                    .in_pin_base(0)
-                    .build(sm);
-                info!("edge");
-                tx.write(0); // number of trigger edges
-                info!("delay");
-                tx.write(self.params[0].delay[0]);
-                info!("width");
-                tx.write(self.params[0].width[0]);
-
-                let sm = sm.start();
-                self.sm = Some(sm);
-                self.tx = Some(tx);
-                self.rx = Some(rx);
-            }
-            _ => unreachable!(),
-        }
-    }
-
-    pub fn set_delay(&mut self, delay: u32) {
-        self.params[0].delay.push(delay.saturating_sub(1));
-    }
-
-    pub fn set_width(&mut self, width: u32) {
-        self.params[0].width.push(width.saturating_sub(1));
-    }
-
-    pub fn compile(&mut self) -> pio::Program<32> {
-        let sideset = SideSet::new(true, 1, true);
-        let mut asm: Assembler<32> = Assembler::new_with_side_set(sideset);
-
-        // Get number of edges before triggering
-        asm.pull(false, true);
-        asm.mov(MovDestination::Y, MovOperation::None, MovSource::OSR);
-
-        // Wait number of edges
-        let mut edge_label = asm.label();
-        asm.bind(&mut edge_label);
-        asm.wait(0, WaitSource::PIN, 0, false);
-        asm.wait(1, WaitSource::PIN, 0, false);
-        asm.jmp(JmpCondition::YDecNonZero, &mut edge_label);
-
-        // Get delay cycles
-        let mut loop_label = asm.label();
-        asm.bind(&mut loop_label);
-        asm.pull(false, true);
-        asm.mov(MovDestination::X, MovOperation::None, MovSource::OSR);
-
-        // Get width cycles
-        asm.pull(false, true);
-        asm.mov(MovDestination::Y, MovOperation::None, MovSource::OSR);
-
-        // Wait delay cycles
-        let mut delay_label = asm.label();
-        asm.bind(&mut delay_label);
-        asm.jmp(JmpCondition::XDecNonZero, &mut delay_label);
-
-        // Wait width cycles (Pulse High)
-        let mut width_label = asm.label();
-        asm.bind(&mut width_label);
-        asm.jmp_with_side_set(JmpCondition::YDecNonZero, &mut width_label, 1);
+fn decode_level(byte: u8) -> Result<Option<Level>, SnapError> {
+    match byte {
+        0 => Ok(None),
+        1 => Ok(Some(Level::Low)),
+        2 => Ok(Some(Level::High)),
+        _ => Err(SnapError::Corrupt),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Violation {
+    // Number of delays and widths differ
+    Unpaired {
+        ch: usize,
+        delays: usize,
+        widths: usize,
+    },
+    // Pin is the trigger input or not routed to PIO0
+    PinUnavailable {
+        ch: usize,
+        pin: u8,
+    },
+    // Pin is already the output of another channel
+    PinConflict {
+        ch: usize,
+        other: usize,
+        pin: u8,
+    },
+    // Wide output is push-pull only
+    WideOpenDrain {
+        ch: usize,
+    },
+    // Delays above 2^32 cycles need the 1-bit program
+    WideLongDelay {
+        ch: usize,
+    },
+    // The marker takes the pin wide mode uses and has no long delay variant
+    MarkerUnsupported {
+        ch: usize,
+    },
+    // The delay before the pulse is too short for the marker margins, the
+    // markers of adjacent pulses would merge or it would rise before the
+    // trigger
+    MarkerOverlap {
+        ch: usize,
+        pulse: usize,
+    },
+    // Per edge mode only has a 1-bit program with short delays and no marker
+    PerEdgeUnsupported {
+        ch: usize,
+    },
+    // Gap and idle levels of their own need the 1-bit table program with
+    // short delays, and an idle tristate only goes with the low idle level
+    LevelsUnsupported {
+        ch: usize,
+    },
+    // The loopback source is the channel itself, disabled, has no table or
+    // takes its own trigger from a channel
+    LoopbackSource {
+        ch: usize,
+        source: usize,
+    },
+    // Programs of all channels together don't fit in instruction memory
+    ProgramSpace {
+        words: usize,
+    },
+}
+
+// At most one violation of each kind per channel, plus program space
+pub const VIOLATIONS_MAX: usize = 10 * NUM_CHANNELS + 1;
+
+// A configuration validate() refuses: the first violation found and how
+// many there are in all, see violations() for the rest
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Invalid {
+    pub first: Violation,
+    pub count: usize,
+}
+
+// Something arming would refuse, see PulseGenerator::check()
+#[derive(Debug)]
+pub enum Problem {
+    Violation(Violation),
+    Pulse(PulseError),
+}
+
+// The violations, then at most an empty table, a high trigger and an
+// infeasible first delay per channel, plus program space left by expert
+// programs
+pub type Problems = ArrayVec<Problem, { VIOLATIONS_MAX + 3 * NUM_CHANNELS + 1 }>;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RestoreError {
+    Snap(SnapError),
+    Armed { ch: usize },
+    // Everything but unpaired tables, which can be saved mid-entry
+    Invalid(Invalid),
+}
+
+impl From<SnapError> for RestoreError {
+    fn from(error: SnapError) -> Self {
+        RestoreError::Snap(error)
+    }
+}
+
+// The live configuration as *RST would clear it: every channel's
+// parameters, the rounding, the falling edge trigger pins and the groups.
+// Arming, expert programs, the tick source and stored settings are left
+// out.
+pub struct Snapshot {
+    pub params: [PulseParameter; NUM_CHANNELS],
+    pub rounding: Rounding,
+    // A bit per GPIO triggering on its falling edge
+    pub falling: u32,
+    pub groups: ArrayVec<Group, NUM_CHANNELS>,
+}
+
+pub fn encode_snapshot(
+    params: &[PulseParameter; NUM_CHANNELS],
+    rounding: Rounding,
+    falling: u32,
+    groups: &[Group],
+) -> Blob {
+    let mut w = Writer::start();
+    for p in params {
+        p.encode(&mut w);
+    }
+    w.u8(rounding as u8);
+    w.u32(falling);
+    w.list(groups.iter(), |w, group| {
+        w.list(group.name.bytes(), Writer::u8);
+        w.u32(group.members);
+        w.u8(group.reference.map_or(0xff, |ch| ch as u8));
+        w.u64(group.lead_max);
+    });
+    w.finish()
+}
+
+// Only the layout and what the setters would refuse field by field, see
+// Snapshot::check() for the configuration as a whole
+pub fn decode_snapshot(blob: &[u8]) -> Result<Snapshot, SnapError> {
+    let mut r = Reader::open(blob)?;
+    let mut params = [TRIGGER_PIN; NUM_CHANNELS].map(PulseParameter::new);
+    for p in &mut params {
+        *p = PulseParameter::decode(&mut r)?;
+    }
+    // Mirrors of mirrors or of themselves, as set_mirror() refuses them
+    let chained = params.iter().enumerate().any(|(ch, p)| {
+        p.mirror
+            .is_some_and(|mirror| mirror.source == ch || params[mirror.source].mirror.is_some())
+    });
+    if chained {
+        return Err(SnapError::Corrupt);
+    }
+    sync_mirrors(&mut params);
+    let rounding = match r.u8()? {
+        0 => Rounding::Nearest,
+        1 => Rounding::Down,
+        2 => Rounding::Up,
+        _ => return Err(SnapError::Corrupt),
+    };
+    let falling = r.u32()?;
+    if falling >> probe::GPIO_COUNT != 0 {
+        return Err(SnapError::Corrupt);
+    }
+    let groups: ArrayVec<Group, NUM_CHANNELS> = r.list(|r| {
+        let name: ArrayVec<u8, GROUP_NAME_MAX> = r.list(Reader::u8)?;
+        let name = core::str::from_utf8(&name).map_err(|_| SnapError::Corrupt)?;
+        Ok(Group {
+            name: GroupName::from(name).map_err(|_| SnapError::Corrupt)?,
+            members: r.u32()?,
+            reference: match r.u8()? {
+                0xff => None,
+                ch => Some(ch as usize),
+            },
+            lead_max: r.u64()?,
+        })
+    })?;
+    r.finish()?;
+    // Members within the channels and in one group each
+    let mut grouped = 0;
+    for group in &groups {
+        if group.members == 0
+            || group.members >> NUM_CHANNELS != 0
+            || group.members & grouped != 0
+            || group
+                .reference
+                .is_some_and(|ch| ch >= NUM_CHANNELS || group.members & 1 << ch == 0)
+        {
+            return Err(SnapError::Corrupt);
+        }
+        grouped |= group.members;
+    }
+    Ok(Snapshot {
+        params,
+        rounding,
+        falling,
+        groups,
+    })
+}
+
+impl Snapshot {
+    // Everything validate() checks but unpaired tables, which can be saved
+    // mid-entry
+    pub fn check(&self, pio_pins: &[u8], disabled: u32) -> Result<(), Invalid> {
+        validate_allowing_unpaired(&self.params, pio_pins, disabled)
+    }
+}
+
+// Copies each mirror's source over it, keeping its own pin and mirror
+// setting and inverting the levels if asked. In wide mode the pulses' pin
+// pair levels come from the table and stay as they are.
+fn sync_mirrors(params: &mut [PulseParameter; NUM_CHANNELS]) {
+    for ch in 0..NUM_CHANNELS {
+        let Some(mirror) = params[ch].mirror else {
+            continue;
+        };
+        let mut copy = params[mirror.source].clone();
+        copy.pin = params[ch].pin;
+        copy.mirror = Some(mirror);
+        copy.placed_delay = params[ch].placed_delay;
+        if mirror.invert {
+            copy.gap = Some(inverted(copy.gap, copy.output));
+            copy.idle = Some(inverted(copy.idle, copy.output));
+        }
+        params[ch] = copy;
+    }
+}
+
+// The other level than the one driven, None being low in push-pull and
+// released to the pull-up in open drain
+fn inverted(level: Option<Level>, output: OutputMode) -> Level {
+    match level_side(level, output) ^ (output == OutputMode::OpenDrain) as u8 {
+        0 => Level::High,
+        _ => Level::Low,
+    }
+}
+
+// The channel with its source or its mirrors, a bit per channel
+fn linked(params: &[PulseParameter; NUM_CHANNELS], ch: usize) -> u32 {
+    let source = params[ch].mirror.map_or(ch, |mirror| mirror.source);
+    (0..NUM_CHANNELS)
+        .filter(|&other| {
+            other == source || params[other].mirror.is_some_and(|m| m.source == source)
+        })
+        .fold(0, |mask, other| mask | 1 << other)
+}
+
+// A bit per GPIO some channel takes its trigger from, the default trigger
+// input always among them. Loopback triggers are on outputs and left out.
+fn trigger_pins(params: &[PulseParameter]) -> u32 {
+    params
+        .iter()
+        .filter(|p| p.trigger_from.is_none())
+        .fold(1 << TRIGGER_PIN, |pins, p| pins | 1 << p.trigger_pin)
+}
+
+pub fn validate(
+    params: &[PulseParameter; NUM_CHANNELS],
+    pio_pins: &[u8],
+    disabled: u32,
+) -> Result<(), Invalid> {
+    first_violation(params, pio_pins, disabled, |_| true)
+}
+
+// As validate() but for unpaired tables, which are left mid-entry between
+// a DELAY and its WIDTH
+pub fn validate_allowing_unpaired(
+    params: &[PulseParameter; NUM_CHANNELS],
+    pio_pins: &[u8],
+    disabled: u32,
+) -> Result<(), Invalid> {
+    first_violation(params, pio_pins, disabled, |violation| {
+        !matches!(violation, Violation::Unpaired { .. })
+    })
+}
+
+fn first_violation(
+    params: &[PulseParameter; NUM_CHANNELS],
+    pio_pins: &[u8],
+    disabled: u32,
+    counted: impl Fn(&Violation) -> bool,
+) -> Result<(), Invalid> {
+    let mut invalid: Option<Invalid> = None;
+    violations(params, pio_pins, disabled, |violation| {
+        if !counted(&violation) {
+            return;
+        }
+        match &mut invalid {
+            Some(invalid) => invalid.count += 1,
+            None => {
+                invalid = Some(Invalid {
+                    first: violation,
+                    count: 1,
+                })
+            }
+        }
+    });
+    invalid.map_or(Ok(()), Err)
+}
+
+// Passes every violation of the configuration to `found`, at most
+// VIOLATIONS_MAX. Disabled channels are only checked for pin conflicts,
+// their SMs keep holding their pins at the idle level.
+pub fn violations(
+    params: &[PulseParameter; NUM_CHANNELS],
+    pio_pins: &[u8],
+    disabled: u32,
+    mut found: impl FnMut(Violation),
+) {
+    // An output on a trigger input would trigger itself
+    let triggers = trigger_pins(params);
+    for (ch, p) in params.iter().enumerate() {
+        let conflict = params[..ch].iter().enumerate().find_map(|(other, o)| {
+            let pin = p.pins().find(|pin| o.pins().contains(pin))?;
+            Some(Violation::PinConflict { ch, other, pin })
+        });
+        if let Some(violation) = conflict {
+            found(violation);
+        }
+        if disabled & 1 << ch != 0 {
+            continue;
+        }
+        if p.delay.len() != p.width.len() {
+            found(Violation::Unpaired {
+                ch,
+                delays: p.delay.len(),
+                widths: p.width.len(),
+            });
+        }
+        if let Some(pin) = p
+            .pins()
+            .find(|pin| triggers & 1 << pin != 0 || !pio_pins.contains(pin))
+        {
+            found(Violation::PinUnavailable { ch, pin });
+        }
+        let config = p.program_config();
+        if config.wide && config.output == OutputMode::OpenDrain {
+            found(Violation::WideOpenDrain { ch });
+        }
+        if config.wide && config.long_delay {
+            found(Violation::WideLongDelay { ch });
+        }
+        if let Some(marker) = p.marker {
+            if config.wide || config.long_delay {
+                found(Violation::MarkerUnsupported { ch });
+            }
+            let overlap = p
+                .effective_delays()
+                .enumerate()
+                .position(|(i, delay)| delay < marker.delay_min(i == 0));
+            if let Some(pulse) = overlap {
+                found(Violation::MarkerOverlap { ch, pulse });
+            }
+        }
+        if config.per_edge && (config.wide || config.long_delay || config.marker) {
+            found(Violation::PerEdgeUnsupported { ch });
+        }
+        let levels = p.gap_side() != 0 || p.idle_side() != 0;
+        let tristate = p.idle_tristate && p.output == OutputMode::PushPull;
+        if levels
+            && (config.wide
+                || config.long_delay
+                || config.marker
+                || config.per_edge
+                || config.trigger_out
+                || (tristate && p.idle_side() != 0))
+        {
+            found(Violation::LevelsUnsupported { ch });
+        }
+        if let Some(source) = p.trigger_from {
+            let usable = params.get(source).is_some_and(|s| {
+                source != ch
+                    && disabled & 1 << source == 0
+                    && !s.is_empty()
+                    && s.trigger_from.is_none()
+            });
+            if !usable {
+                found(Violation::LoopbackSource { ch, source });
+            }
+        }
+    }
+    let words = program_words(params, disabled);
+    if words > INSTRUCTION_MEMORY {
+        found(Violation::ProgramSpace { words });
+    }
+}
+
+// Instruction words the enabled channels' programs take, channels running
+// the same variant share one copy
+fn program_words(params: &[PulseParameter; NUM_CHANNELS], disabled: u32) -> usize {
+    let mut configs: ArrayVec<ProgramConfig, NUM_CHANNELS> = ArrayVec::new();
+    let enabled = params
+        .iter()
+        .enumerate()
+        .filter(|&(ch, _)| disabled & 1 << ch == 0);
+    for (_, p) in enabled {
+        let config = p.program_config();
+        if !configs.contains(&config) {
+            configs.push(config);
+        }
+    }
+    configs.iter().map(|&c| compile(c).code.len()).sum()
+}
+
+type StreamBlock = [u32; 2 * STREAM_BLOCK_PAIRS];
+// The statics new() hands out, with their sizes for MEM?
+type Tables = [[u32; DMA_BUF_LEN]; NUM_CHANNELS];
+type StreamBuffers = [[StreamBlock; STREAM_BLOCKS]; NUM_CHANNELS];
+type ExpertFeeds = [[u32; EXPERT_FEED_LEN]; 2];
+pub const TABLES_RAM: usize = core::mem::size_of::<Tables>();
+pub const STREAM_RAM: usize = core::mem::size_of::<StreamBuffers>();
+pub const EXPERT_RAM: usize = core::mem::size_of::<ExpertFeeds>();
+struct Stream {
+    // Pair words the DMA has finished pushing into the FIFO
+    words_fed: u32,
+    ended: bool,
+}
+
+// The FIFO ran dry while words were still to come, latched until the next
+// arm
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Underrun {
+    // Pulses emitted before the output was stopped
+    pub pulses: u32,
+    // Timer ticks (us) when it was noticed
+    pub at: u64,
+}
+
+// A channel's table runs since its last arm, reruns and internal ticks
+// included. Kept through disarm until the next arm.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct RunStats {
+    // Runs whose table went out whole
+    pub runs: u32,
+    pub pulses: u64,
+    // Cycles the output was high over those runs
+    pub high_cycles: u64,
+    // Timer ticks (us) from the trigger to the end of the last run, None if
+    // the run started and ended between two main loop passes
+    pub last_us: Option<u64>,
+    // Timer ticks (us) of the last run's trigger, same sources as last_us
+    pub triggered_at: Option<u64>,
+    // The trigger time came from the timestamp log instead of the main loop
+    pub logged: bool,
+}
+
+// Runs counted as RunStats does since power-on, never reset, for the
+// lifetime counters
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct RunTotals {
+    pub runs: u64,
+    pub high_cycles: u64,
+}
+
+// wait_done() ran out of time with the channel's table still going
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Timeout;
+
+// Cycles between wait_done()'s checks. Nothing interrupts on a table's end
+// to wake a WFI, so it spins instead.
+const WAIT_DONE_POLL_CYCLES: u32 = 1_000;
+
+// What POLL? reports of a channel, from state the main loop keeps anyway.
+// Nothing of the SM or its DMA channel is read.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PollStatus {
+    pub disabled: bool,
+    pub running: bool,
+    // Pulses of the whole runs since the arm
+    pub pulses: u64,
+    pub triggered_at: Option<u64>,
+    // An underrun or a failed NEXT is latched
+    pub error: bool,
+}
+
+// The run the main loop is following on a channel
+#[derive(Clone, Copy, Default)]
+struct RunTrack {
+    // Timer ticks (us) at the arm
+    armed_at: u64,
+    // Timer ticks (us) when the run was first seen past its trigger
+    started_at: Option<u64>,
+    // Counted, or not a table run, until the next arm
+    done: bool,
+    pulses: u32,
+    high_cycles: u64,
+    // Timer ticks (us) when the run was counted, the re-arm guard starts
+    ended_at: Option<u64>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Progress {
+    // Index of the pulse being emitted, 0 until the first one starts
+    pub pulse: u32,
+    pub pulses: u32,
+    // Pulses not started yet. Exact while waiting for a trigger edge, one
+    // low while a pulse other than the last is going out.
+    pub remaining: u32,
+}
+
+pub enum ChannelEvent {
+    // Stream ended cleanly after the given number of pulses
+    StreamDone(u32),
+    // A table's DMA fell behind, or a stream ran dry before STREAM END. The
+    // output was forced low.
+    Underrun(Underrun),
+    // The table queued with NEXT couldn't take over, the channel was
+    // disarmed instead of running its old table again
+    NextFailed(NextError),
+    // The ArmGuard refused a re-arm for a retrigger or an internal tick, the
+    // channel stays disarmed until it allows one
+    Guarded(GuardError),
+}
+
+pub enum NextError {
+    Invalid(Violation),
+    Arm(PulseError),
+}
+
+// (delay, width) pairs of a whole table
+pub type Pairs = ArrayVec<(Achieved, Achieved), NUM_PULSES_MAX>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StreamError {
+    NotStreaming,
+    #[cfg(feature = "binary-proto")]
+    Ended,
+    #[cfg(feature = "binary-proto")]
+    Full,
+    #[cfg(feature = "binary-proto")]
+    BlockTooLarge,
+}
+
+impl StreamError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StreamError::NotStreaming => "NOT_STREAMING",
+            #[cfg(feature = "binary-proto")]
+            StreamError::Ended => "STREAM_ENDED",
+            #[cfg(feature = "binary-proto")]
+            StreamError::Full => "STREAM_FULL",
+            #[cfg(feature = "binary-proto")]
+            StreamError::BlockTooLarge => "BLOCK_TOO_LARGE",
+        }
+    }
+}
 
-        // Loop (Pulse Low)
-        asm.jmp_with_side_set(JmpCondition::Always, &mut loop_label, 0);
+// A channel running another's table on its own pin, see
+// PulseGenerator::set_mirror()
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Mirror {
+    pub source: usize,
+    // Swaps the gap and idle levels, so the pulses are of the other level
+    pub invert: bool,
+}
+
+// A trigger edge changes only while no armed channel waits on the pin
+#[derive(Debug)]
+pub struct TriggerArmed {
+    pub ch: usize,
+}
+
+#[derive(Debug)]
+pub enum MirrorError {
+    // Onto itself, from another mirror or onto a channel with mirrors
+    Chain,
+    // Mirrors only change while both channels are disarmed
+    Armed { ch: usize },
+    // The copy doesn't fit the mirror's pin
+    Invalid(Violation),
+}
+
+// Pulses COALESCE merged into the one before them, numbered as they were
+// before the merge
+pub type Merged = ArrayVec<usize, NUM_PULSES_MAX>;
+
+// Where a channel takes its trigger from. A loopback waits on the source
+// channel's output pin itself, the input buffer of a PIO output reads back
+// what the pad drives. The consumer so sees the source's electrical edge,
+// slowed or held off by whatever loads the pin, then the same
+// TRIGGER_SYNC_CYCLES + TRIGGER_PATH_CYCLES as from the trigger input: a
+// rise of the source starts the consumer's first delay that many cycles
+// later, plus the pad's output to input delay of a few ns.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Trigger {
+    Pin(u8),
+    InternalFromChannel(usize),
+}
 
-        asm.assemble_program().set_origin(Some(0))
+// Built-in tables for TESTPAT, run over and over in place of the channel's
+// own table, which is left as it was
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TestPattern {
+    // NUM_PULSES_MAX pulses as short and as close as the program allows, see
+    // PulseGenerator::fastest()
+    Fast,
+    // Widths of 1, 2, 4... cycles, STAIRCASE_STEPS of them, each after a
+    // delay as long as itself
+    Staircase,
+    // REFERENCE_WIDTH_US pulses at REFERENCE_HZ
+    Reference,
+}
+
+pub const TEST_PATTERNS: [TestPattern; 3] = [
+    TestPattern::Fast,
+    TestPattern::Staircase,
+    TestPattern::Reference,
+];
+// Up to 2^23 cycles, 67ms at 125MHz
+pub const STAIRCASE_STEPS: usize = 24;
+pub const REFERENCE_HZ: u32 = 1000;
+pub const REFERENCE_WIDTH_US: u32 = 10;
+
+impl TestPattern {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TestPattern::Fast => "FAST",
+            TestPattern::Staircase => "STAIRCASE",
+            TestPattern::Reference => "REF",
+        }
+    }
+}
+
+// Channels triggered by tick::start() on `pin` instead of the trigger input,
+// each re-armed by service() once its table went out
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Internal {
+    pub pin: u8,
+    pub members: u32,
+    // Cycles between ticks, as the PWM runs them once set
+    pub period: u64,
+}
+
+#[derive(Debug)]
+pub enum InternalError {
+    // Not a GPIO, or the trigger input or a channel output
+    BadPin,
+    // Outside tick::PERIOD_MIN..=tick::PERIOD_MAX
+    BadPeriod,
+    NoChannels,
+    Arm(PulseError),
+}
+
+#[cfg(feature = "selftest")]
+#[derive(Debug)]
+pub enum SkewError {
+    // A channel is armed, or the internal ticks run
+    Busy,
+    Disabled { ch: usize },
+    // Expert SM2 has a program loaded
+    InUse,
+    NoSpace,
+    // No rise on ch1 within SKEW_MAX cycles of ch0's
+    NoEdge,
+    Arm(PulseError),
+}
+
+// What a state machine or DMA channel is in use for, see resources()
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    Channel(usize),
+    Expert,
+    Tlog,
+    Glitch,
+    // Kept for STRESS DMA's bus load
+    Bulk,
+    Free,
+}
+
+// RES? names, one per channel
+const CHANNEL_ROLES: [&str; NUM_CHANNELS] = ["CH0", "CH1"];
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Channel(ch) => CHANNEL_ROLES[*ch],
+            Role::Expert => "EXPERT",
+            Role::Tlog => "TLOG",
+            Role::Glitch => "GLITCH",
+            Role::Bulk => "BULK",
+            Role::Free => "FREE",
+        }
+    }
+}
+
+pub const DMA_CHANNELS: usize = 12;
+
+// PIO0 and DMA as PulseGenerator holds them. PIO1 and the DMA channels past
+// the bulk one are never touched.
+pub struct Resources {
+    // Instruction words left, possibly split in gaps
+    pub pio0_free: usize,
+    pub sm: [Role; 4],
+    pub dma: [Role; DMA_CHANNELS],
+}
+
+// Largest skew measure_skew() looks for either way
+#[cfg(feature = "selftest")]
+pub const SKEW_MAX: i32 = 32;
+// Consecutive cycles the sampler gets, the depth of the joined RX FIFO
+#[cfg(feature = "selftest")]
+pub const SKEW_SAMPLES: usize = 8;
+// Test pulse, ch1's delay is moved around ch0's for each window
+#[cfg(feature = "selftest")]
+const SKEW_DELAY: u64 = 100;
+#[cfg(feature = "selftest")]
+const SKEW_WIDTH: u32 = 100;
+// Both pulses are over within a few us of the forced trigger
+#[cfg(feature = "selftest")]
+const SKEW_TIMEOUT_US: u32 = 1_000;
+
+// A new program variant doesn't fit next to the ones already installed
+#[derive(Debug)]
+pub struct InstructionMemoryFull {
+    // Words the program needs
+    pub words: usize,
+    // Unused words, possibly in gaps too small for the program
+    pub free: usize,
+    pub installed: ArrayVec<ProgramConfig, CACHED_PROGRAMS>,
+}
+
+// A delay, width or pulse past NUM_PULSES_MAX of the channel's table
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SequenceFull;
+
+#[derive(Debug)]
+pub enum DelayError {
+    Full(SequenceFull),
+    Invalid(Violation),
+}
+
+// Channels armed and disarmed together under a name. Every channel is in at
+// most one group, so there are never more groups than channels.
+pub const GROUP_NAME_MAX: usize = 8;
+pub type GroupName = ArrayString<GROUP_NAME_MAX>;
+
+pub struct Group {
+    // Upper case, names are matched case insensitively
+    pub name: GroupName,
+    // Bit per member channel
+    pub members: u32,
+    // Member whose first delay is the group's T0, see PulseGenerator::set_t0()
+    pub reference: Option<usize>,
+    // Most cycles the whole group may be delayed by to fit negative offsets
+    pub lead_max: u64,
+}
+
+// First delays of a group with a reference, in cycles
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct T0Solution {
+    pub reference: usize,
+    pub t0: u64,
+    // Added to every member's first delay, 0 unless an offset reaches
+    // before what the trigger path allows
+    pub lead: u64,
+    // Members with a table
+    pub delays: [Option<u64>; NUM_CHANNELS],
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GroupError {
+    NameTooLong,
+    UnknownGroup,
+    BadChannel { ch: usize },
+    // Membership only changes while the channel is disarmed
+    ChannelArmed { ch: usize },
+    ChannelInGroup { ch: usize, group: GroupName },
+    // A T0 reference outside the group
+    NotMember { ch: usize },
+}
+
+// A veto on arming from outside the generator, door switches or a high
+// voltage supply not ready. Asked before every arm of a channel: from the
+// host, in groups, for internal ticks and re-arms from service(), and before
+// a forced trigger. Kept cheap, it runs in the main loop's service().
+pub trait ArmGuard {
+    fn check(&self, channel: usize) -> Result<(), GuardError>;
+}
+
+// Why a guard refused, reported as is after "GUARD"
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct GuardError {
+    pub reason: &'static str,
+}
+
+// The guard in place until set_arm_guard()
+pub struct AllowAll;
+
+impl ArmGuard for AllowAll {
+    fn check(&self, _channel: usize) -> Result<(), GuardError> {
+        Ok(())
+    }
+}
+
+// Reasons arming a channel fails, the channel is left disarmed
+#[derive(Debug)]
+pub enum PulseError {
+    // No (delay, width) pair to emit, the SM would wait on the FIFO forever
+    EmptySequence { ch: usize },
+    // Armed with its own table, which hasn't gone out yet
+    Armed { ch: usize },
+    // Taken out with DISABLE
+    Disabled { ch: usize },
+    // The trigger input was already high, see set_refuse_stuck_trigger()
+    TriggerHigh { ch: usize },
+    // The channel's offset from its group's T0 or its calibration needs
+    // more lead than the group allows, by `short_ps`
+    T0Infeasible { ch: usize, short_ps: u64 },
+    InstructionMemoryFull(InstructionMemoryFull),
+    // Refused by the ArmGuard
+    Guard { ch: usize, err: GuardError },
+    // A queued arm dropped by the disarm of one of its channels before
+    // service_arm() got to it
+    Cancelled { ch: usize },
+}
+
+impl From<InstructionMemoryFull> for PulseError {
+    fn from(err: InstructionMemoryFull) -> Self {
+        PulseError::InstructionMemoryFull(err)
+    }
+}
+
+// A program per channel, and the one a channel swaps to while its old one
+// is still installed, see ChannelHw::reload()
+const CACHED_PROGRAMS: usize = NUM_CHANNELS + 1;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Phase {
+    // Pulling the edge count or waiting for trigger edges
+    WaitTrigger,
+    WaitDelay,
+    PulseHigh,
+    // Pulling the next pair, stalls here once the FIFO is empty
+    Idle,
+}
+
+impl Phase {
+    fn from_pc(pc: u8, config: ProgramConfig) -> Self {
+        if config.long_delay {
+            return match pc {
+                0..=PC_EDGE_LOOP_END_LONG => Phase::WaitTrigger,
+                PC_DELAY_LONG..=PC_DELAY_END_LONG | PC_CHUNK_LONG..=u8::MAX => Phase::WaitDelay,
+                PC_WIDTH_LONG => Phase::PulseHigh,
+                _ => Phase::Idle,
+            };
+        }
+        if config.trigger_out {
+            return match pc {
+                0..=PC_EDGE_LOOP_END_TRIGGER_OUT => Phase::WaitTrigger,
+                _ => Phase::PulseHigh,
+            };
+        }
+        if config.per_edge {
+            // Stalls pulling the next delay once the table went out
+            return match pc {
+                PC_DELAY_PULL_PER_EDGE => Phase::Idle,
+                0..=PC_EDGE_LOOP_END_PER_EDGE => Phase::WaitTrigger,
+                PC_WIDTH_PER_EDGE => Phase::PulseHigh,
+                _ => Phase::WaitDelay,
+            };
+        }
+        if config.double {
+            return match pc {
+                0..=PC_EDGE_LOOP_END_DOUBLE => Phase::WaitTrigger,
+                PC_DELAY_DOUBLE | PC_GAP_DOUBLE => Phase::WaitDelay,
+                PC_WIDTH_DOUBLE..=u8::MAX => Phase::PulseHigh,
+                _ => Phase::Idle,
+            };
+        }
+        if config.split {
+            return match pc {
+                0..=PC_EDGE_LOOP_END => Phase::WaitTrigger,
+                PC_END_SPLIT => Phase::Idle,
+                PC_DELAY_PULL_SPLIT | PC_DELAY_SPLIT => Phase::WaitDelay,
+                _ => Phase::PulseHigh,
+            };
+        }
+        if config.marker {
+            // The post margin counts as idle, the pulse itself is over
+            return match pc {
+                0..=PC_EDGE_LOOP_END => Phase::WaitTrigger,
+                PC_DELAY..=PC_PRE_MARKER => Phase::WaitDelay,
+                PC_WIDTH_MARKER..=PC_WIDTH_END_MARKER => Phase::PulseHigh,
+                _ => Phase::Idle,
+            };
+        }
+        let (delay, width) = if config.wide {
+            (PC_DELAY_WIDE, PC_LEVELS_WIDE..=PC_WIDTH_WIDE)
+        } else {
+            (PC_DELAY, PC_WIDTH..=PC_WIDTH)
+        };
+        match pc {
+            0..=PC_EDGE_LOOP_END => Phase::WaitTrigger,
+            pc if pc == delay => Phase::WaitDelay,
+            pc if width.contains(&pc) => Phase::PulseHigh,
+            _ => Phase::Idle,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::WaitTrigger => "WAIT_TRIGGER",
+            Phase::WaitDelay => "WAIT_DELAY",
+            Phase::PulseHigh => "PULSE_HIGH",
+            Phase::Idle => "IDLE",
+        }
+    }
+}
+
+pub struct DebugInfo {
+    pub running: bool,
+    // Waiting for trigger edges
+    pub ready: bool,
+    pub tx_level: u8,
+    pub tx_stalled: bool,
+    // Program counter relative to the program start
+    pub pc: u8,
+    pub phase: Phase,
+    // Words left in the DMA transfer, None when no transfer is active
+    pub dma_remaining: Option<u32>,
+    // Position in a running table, None for streams and without a transfer
+    pub progress: Option<Progress>,
+    pub underrun: Option<Underrun>,
+}
+
+impl DebugInfo {
+    // The whole table went out, not just into the FIFO: the DMA is done and
+    // the SM ran the FIFO dry waiting for the next pulse
+    pub fn emitted(&self) -> bool {
+        self.running
+            && self.dma_remaining == Some(0)
+            && self.tx_level == 0
+            && self.tx_stalled
+            && self.phase == Phase::Idle
+    }
+}
+
+// Largest feed accepted in one go by an expert state machine
+pub const EXPERT_FEED_LEN: usize = 64;
+#[cfg(feature = "binary-proto")]
+pub const EXPERT_SM: RangeInclusive<usize> = 2..=3;
+
+#[cfg(any(feature = "binary-proto", feature = "capture", feature = "selftest"))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExpertError {
+    // EXPERT ON has not been sent
+    #[cfg(feature = "binary-proto")]
+    Locked,
+    #[cfg(feature = "binary-proto")]
+    BadStateMachine,
+    #[cfg(feature = "binary-proto")]
+    NotLoaded,
+    #[cfg(feature = "binary-proto")]
+    BadOrigin,
+    #[cfg(feature = "binary-proto")]
+    BadWrap,
+    #[cfg(feature = "binary-proto")]
+    BadSideSet,
+    // Pins overlap a channel output or are not routed to PIO0
+    #[cfg(feature = "binary-proto")]
+    BadPins,
+    NoSpace,
+    // Previous feed is still being pushed into the FIFO
+    #[cfg(feature = "binary-proto")]
+    Busy,
+    #[cfg(feature = "binary-proto")]
+    FeedTooLarge,
+    // SM3 runs the trigger timestamp log or SM2 the glitch detector, or
+    // the other way round
+    InUse,
+}
+
+#[cfg(any(feature = "binary-proto", feature = "capture"))]
+impl ExpertError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "binary-proto")]
+            ExpertError::Locked => "EXPERT_LOCKED",
+            #[cfg(feature = "binary-proto")]
+            ExpertError::BadStateMachine => "BAD_SM",
+            #[cfg(feature = "binary-proto")]
+            ExpertError::NotLoaded => "NOT_LOADED",
+            #[cfg(feature = "binary-proto")]
+            ExpertError::BadOrigin => "BAD_ORIGIN",
+            #[cfg(feature = "binary-proto")]
+            ExpertError::BadWrap => "BAD_WRAP",
+            #[cfg(feature = "binary-proto")]
+            ExpertError::BadSideSet => "BAD_SIDE_SET",
+            #[cfg(feature = "binary-proto")]
+            ExpertError::BadPins => "BAD_PINS",
+            ExpertError::NoSpace => "NO_SPACE",
+            #[cfg(feature = "binary-proto")]
+            ExpertError::Busy => "BUSY",
+            #[cfg(feature = "binary-proto")]
+            ExpertError::FeedTooLarge => "FEED_TOO_LARGE",
+            ExpertError::InUse => "IN_USE",
+        }
+    }
+}
+
+// Instruction addresses in compile(), used to name the SM phase
+const PC_EDGE_LOOP_END: u8 = 3;
+const PC_DELAY: u8 = 6;
+const PC_WIDTH: u8 = 7;
+const PC_DELAY_WIDE: u8 = 6;
+const PC_LEVELS_WIDE: u8 = 7;
+const PC_WIDTH_WIDE: u8 = 8;
+
+const PC_EDGE_LOOP_END_LONG: u8 = 4;
+const PC_DELAY_LONG: u8 = 7;
+const PC_DELAY_END_LONG: u8 = 9;
+const PC_WIDTH_LONG: u8 = 10;
+const PC_CHUNK_LONG: u8 = 11;
+
+const PC_PRE_MARKER: u8 = 7;
+const PC_WIDTH_MARKER: u8 = 8;
+const PC_WIDTH_END_MARKER: u8 = 9;
+
+const PC_EDGE_LOOP_END_TRIGGER_OUT: u8 = 2;
+
+const PC_EDGE_LOOP_END_DOUBLE: u8 = 4;
+const PC_DELAY_DOUBLE: u8 = 7;
+const PC_WIDTH_DOUBLE: u8 = 8;
+const PC_GAP_DOUBLE: u8 = 10;
+
+const PC_END_SPLIT: u8 = 6;
+const PC_DELAY_PULL_SPLIT: u8 = 7;
+const PC_DELAY_SPLIT: u8 = 8;
+
+const PC_DELAY_PULL_PER_EDGE: u8 = 1;
+const PC_EDGE_LOOP_START_PER_EDGE: u8 = 2;
+const PC_EDGE_LOOP_END_PER_EDGE: u8 = 5;
+const PC_WIDTH_PER_EDGE: u8 = 8;
+
+// The wide program takes a third word per pulse with the levels of the pin
+// pair and drives them with `out pins` before the width loop. In open drain
+// the side-set drives the pin direction of a pin whose latch stays low, so
+// the active edge keeps its timing while the release edge rises with the
+// external pull-up's RC time constant.
+//
+// Every delay and width loop in these programs is a single jmp taking one
+// cycle per count, so delays and widths are exact to the cycle from each
+// program's minimum up, odd counts included.
+//
+// Every variant starts with prologue(), and a new one has to as well.
+pub fn compile(config: ProgramConfig) -> pio::Program<32> {
+    if config.trigger_out {
+        return compile_trigger_out(config);
+    }
+    if config.long_delay {
+        return compile_long(config);
+    }
+    if config.marker {
+        return compile_marker(config);
+    }
+    if config.double {
+        return compile_double(config);
+    }
+    if config.per_edge {
+        return compile_per_edge(config);
+    }
+    if config.split {
+        return compile_split(config);
+    }
+    let wide = config.wide;
+    let open_drain = config.output == OutputMode::OpenDrain;
+    let sideset = SideSet::new(true, 1 + wide as u8, open_drain);
+    let mut asm: Assembler<32> = Assembler::new_with_side_set(sideset);
+
+    // Words are shifted out of the OSR as they are needed, autopull keeps it
+    // refilled from the joined TX FIFO without extra pull instructions
+
+    // Get number of edges before triggering
+    prologue(&mut asm, config, OutDestination::Y);
+
+    // Wait number of edges
+    let mut edge_label = asm.label();
+    asm.bind(&mut edge_label);
+    asm.wait(0, WaitSource::PIN, 0, false);
+    asm.wait(1, WaitSource::PIN, 0, false);
+    asm.jmp(JmpCondition::YDecNonZero, &mut edge_label);
+
+    // Get delay cycles (Pulse Low), the wrap lands here after each pulse
+    let mut wrap_target = asm.label();
+    asm.bind(&mut wrap_target);
+    asm.out_with_side_set(OutDestination::X, 32, config.gap);
+
+    // Get width cycles
+    asm.out(OutDestination::Y, 32);
+
+    // Wait delay cycles
+    let mut delay_label = asm.label();
+    asm.bind(&mut delay_label);
+    asm.jmp(JmpCondition::XDecNonZero, &mut delay_label);
+
+    // Wait width cycles (Pulse High)
+    let mut width_label = asm.label();
+    if wide {
+        asm.out(OutDestination::PINS, 32);
+        asm.bind(&mut width_label);
+        asm.jmp(JmpCondition::YDecNonZero, &mut width_label);
+    } else {
+        asm.bind(&mut width_label);
+        asm.jmp_with_side_set(JmpCondition::YDecNonZero, &mut width_label, 1 - config.gap);
+    }
+    let mut wrap_source = asm.label();
+    asm.bind(&mut wrap_source);
+
+    // Installed wherever it fits, so the program must be relocatable
+    asm.assemble_with_wrap(wrap_source, wrap_target)
+}
+
+// The first instruction of every program variant: the `out` of its first
+// word, side-setting the level the pins rest at. However the SM got to the
+// start of its program, it drives that level from its first cycle on, so
+// the pins can't change before the first pulse. force_idle() sets the same
+// level while the SM is stopped.
+fn prologue(asm: &mut Assembler<32>, config: ProgramConfig, destination: OutDestination) {
+    asm.out_with_side_set(destination, 32, config.idle_side());
+}
+
+// Like the 1-bit program, for a channel resting at the pulse level rather
+// than the gap level between pulses. The 1-bit program stalls pulling the
+// next delay at the gap level, this one pulls the delay first with the
+// output still at the pulse level and drops to the gap level only once the
+// delay is there. A zero delay word ends the table and parks the SM at the
+// pulse level, without pulling the width.
+fn compile_split(config: ProgramConfig) -> pio::Program<32> {
+    let open_drain = config.output == OutputMode::OpenDrain;
+    let sideset = SideSet::new(true, 1, open_drain);
+    let mut asm: Assembler<32> = Assembler::new_with_side_set(sideset);
+    let pulse = 1 - config.gap;
+
+    // Get number of edges before triggering
+    prologue(&mut asm, config, OutDestination::Y);
+
+    // Wait number of edges
+    let mut edge_label = asm.label();
+    asm.bind(&mut edge_label);
+    asm.wait(0, WaitSource::PIN, 0, false);
+    asm.wait(1, WaitSource::PIN, 0, false);
+    asm.jmp(JmpCondition::YDecNonZero, &mut edge_label);
+
+    // Get delay cycles, still at the pulse level
+    let mut wrap_target = asm.label();
+    asm.bind(&mut wrap_target);
+    asm.out(OutDestination::X, 32);
+    let mut more_label = asm.label();
+    asm.jmp(JmpCondition::XDecNonZero, &mut more_label);
+
+    // End of the table, stalls here at the idle level
+    asm.out_with_side_set(OutDestination::NULL, 32, pulse);
+
+    // Get width cycles (Pulse Low)
+    asm.bind(&mut more_label);
+    asm.out_with_side_set(OutDestination::Y, 32, config.gap);
+
+    // Wait delay cycles
+    let mut delay_label = asm.label();
+    asm.bind(&mut delay_label);
+    asm.jmp(JmpCondition::XDecNonZero, &mut delay_label);
+
+    // Wait width cycles (Pulse High)
+    let mut width_label = asm.label();
+    asm.bind(&mut width_label);
+    asm.jmp_with_side_set(JmpCondition::YDecNonZero, &mut width_label, pulse);
+    let mut wrap_source = asm.label();
+    asm.bind(&mut wrap_source);
+
+    asm.assemble_with_wrap(wrap_source, wrap_target)
+}
+
+// Like the 1-bit program with a second side-set pin for the marker. Each
+// pulse is a delay, the pre margin with only the marker high, the width with
+// both high and the post margin with only the marker high again.
+fn compile_marker(config: ProgramConfig) -> pio::Program<32> {
+    let open_drain = config.output == OutputMode::OpenDrain;
+    let sideset = SideSet::new(true, 2, open_drain);
+    let mut asm: Assembler<32> = Assembler::new_with_side_set(sideset);
+
+    // Get number of edges before triggering
+    prologue(&mut asm, config, OutDestination::Y);
+
+    // Wait number of edges
+    let mut edge_label = asm.label();
+    asm.bind(&mut edge_label);
+    asm.wait(0, WaitSource::PIN, 0, false);
+    asm.wait(1, WaitSource::PIN, 0, false);
+    asm.jmp(JmpCondition::YDecNonZero, &mut edge_label);
+
+    // Get delay and pre margin cycles (both low)
+    let mut wrap_target = asm.label();
+    asm.bind(&mut wrap_target);
+    asm.out_with_side_set(OutDestination::X, 32, 0b00);
+    asm.out(OutDestination::Y, 32);
+
+    // Wait delay cycles
+    let mut delay_label = asm.label();
+    asm.bind(&mut delay_label);
+    asm.jmp(JmpCondition::XDecNonZero, &mut delay_label);
+
+    // Wait pre margin cycles (Marker High)
+    let mut pre_label = asm.label();
+    asm.bind(&mut pre_label);
+    asm.jmp_with_side_set(JmpCondition::YDecNonZero, &mut pre_label, 0b10);
+
+    // Get and wait width cycles (Pulse and Marker High)
+    asm.out_with_side_set(OutDestination::Y, 32, 0b11);
+    let mut width_label = asm.label();
+    asm.bind(&mut width_label);
+    asm.jmp_with_side_set(JmpCondition::YDecNonZero, &mut width_label, 0b11);
+
+    // Get and wait post margin cycles (Marker High)
+    asm.out_with_side_set(OutDestination::Y, 32, 0b10);
+    let mut post_label = asm.label();
+    asm.bind(&mut post_label);
+    asm.jmp_with_side_set(JmpCondition::YDecNonZero, &mut post_label, 0b10);
+    let mut wrap_source = asm.label();
+    asm.bind(&mut wrap_source);
+
+    asm.assemble_with_wrap(wrap_source, wrap_target)
+}
+
+// Fans the trigger out: a pulse on every rising edge, rising the cycle after
+// the `wait` sees it. The width is pulled once and kept in the ISR, so the
+// SM never needs the FIFO again. Edges while the pulse is high are missed.
+fn compile_trigger_out(config: ProgramConfig) -> pio::Program<32> {
+    let open_drain = config.output == OutputMode::OpenDrain;
+    let sideset = SideSet::new(true, 1, open_drain);
+    let mut asm: Assembler<32> = Assembler::new_with_side_set(sideset);
+
+    // Get width cycles
+    prologue(&mut asm, config, OutDestination::ISR);
+
+    // Wait for the edge (Pulse Low)
+    let mut wrap_target = asm.label();
+    asm.bind(&mut wrap_target);
+    asm.wait_with_side_set(0, WaitSource::PIN, 0, false, 0);
+    asm.wait(1, WaitSource::PIN, 0, false);
+
+    // Wait width cycles (Pulse High)
+    asm.mov_with_side_set(MovDestination::Y, MovOperation::None, MovSource::ISR, 1);
+    let mut width_label = asm.label();
+    asm.bind(&mut width_label);
+    asm.jmp_with_side_set(JmpCondition::YDecNonZero, &mut width_label, 1);
+    let mut wrap_source = asm.label();
+    asm.bind(&mut wrap_source);
+
+    asm.assemble_with_wrap(wrap_source, wrap_target)
+}
+
+// Two pulses with the second delay below the standard program's minimum.
+// The second width is pulled ahead of the trigger wait and kept in the ISR,
+// and the gap is pulled while the first pulse is high, so nothing is pulled
+// between the pulses: the output is low for the gap loop alone, down to
+// PULSE_GAP_CYCLES with a zero second delay against 3 cycles otherwise.
+// The first pulse starts as in the 1-bit program.
+fn compile_double(config: ProgramConfig) -> pio::Program<32> {
+    let open_drain = config.output == OutputMode::OpenDrain;
+    let sideset = SideSet::new(true, 1, open_drain);
+    let mut asm: Assembler<32> = Assembler::new_with_side_set(sideset);
+
+    // Get second width cycles
+    prologue(&mut asm, config, OutDestination::ISR);
+
+    // Get number of edges before triggering
+    asm.out(OutDestination::Y, 32);
+
+    // Wait number of edges
+    let mut edge_label = asm.label();
+    asm.bind(&mut edge_label);
+    asm.wait(0, WaitSource::PIN, 0, false);
+    asm.wait(1, WaitSource::PIN, 0, false);
+    asm.jmp(JmpCondition::YDecNonZero, &mut edge_label);
+
+    // Get delay and first width cycles (Pulse Low), stalls here once both
+    // pulses went out
+    let mut wrap_target = asm.label();
+    asm.bind(&mut wrap_target);
+    asm.out_with_side_set(OutDestination::X, 32, 0);
+    asm.out(OutDestination::Y, 32);
+
+    // Wait delay cycles
+    let mut delay_label = asm.label();
+    asm.bind(&mut delay_label);
+    asm.jmp(JmpCondition::XDecNonZero, &mut delay_label);
+
+    // Get gap cycles and wait first width cycles (Pulse High)
+    asm.out_with_side_set(OutDestination::X, 32, 1);
+    let mut width_label = asm.label();
+    asm.bind(&mut width_label);
+    asm.jmp_with_side_set(JmpCondition::YDecNonZero, &mut width_label, 1);
+
+    // Wait gap cycles (Pulse Low)
+    let mut gap_label = asm.label();
+    asm.bind(&mut gap_label);
+    asm.jmp_with_side_set(JmpCondition::XDecNonZero, &mut gap_label, 0);
+
+    // Wait second width cycles (Pulse High)
+    asm.mov_with_side_set(MovDestination::Y, MovOperation::None, MovSource::ISR, 1);
+    let mut width2_label = asm.label();
+    asm.bind(&mut width2_label);
+    asm.jmp_with_side_set(JmpCondition::YDecNonZero, &mut width2_label, 1);
+    let mut wrap_source = asm.label();
+    asm.bind(&mut wrap_source);
+
+    asm.assemble_with_wrap(wrap_source, wrap_target)
+}
+
+// Like the 1-bit program with the edge wait moved into the pulse loop: each
+// pulse pulls its delay, waits for its own trigger edges, then counts the
+// delay from the last of them with the same trigger path as the first pulse
+// of the other programs. The edge count is pulled once and kept in the ISR.
+// The SM only looks for the next edge once the pulse is over, an edge
+// arriving during its delay or width is missed.
+fn compile_per_edge(config: ProgramConfig) -> pio::Program<32> {
+    let open_drain = config.output == OutputMode::OpenDrain;
+    let sideset = SideSet::new(true, 1, open_drain);
+    let mut asm: Assembler<32> = Assembler::new_with_side_set(sideset);
+
+    // Get number of edges before each pulse
+    prologue(&mut asm, config, OutDestination::ISR);
+
+    // Get delay cycles (Pulse Low), stalls here once the table went out
+    let mut wrap_target = asm.label();
+    asm.bind(&mut wrap_target);
+    asm.out_with_side_set(OutDestination::X, 32, 0);
+
+    // Wait number of edges
+    asm.mov(MovDestination::Y, MovOperation::None, MovSource::ISR);
+    let mut edge_label = asm.label();
+    asm.bind(&mut edge_label);
+    asm.wait(0, WaitSource::PIN, 0, false);
+    asm.wait(1, WaitSource::PIN, 0, false);
+    asm.jmp(JmpCondition::YDecNonZero, &mut edge_label);
+
+    // Get width cycles, the extra cycle stands in for the other programs'
+    // second out after the edge
+    asm.out_with_delay(OutDestination::Y, 32, 1);
+
+    // Wait delay cycles
+    let mut delay_label = asm.label();
+    asm.bind(&mut delay_label);
+    asm.jmp(JmpCondition::XDecNonZero, &mut delay_label);
+
+    // Wait width cycles (Pulse High)
+    let mut width_label = asm.label();
+    asm.bind(&mut width_label);
+    asm.jmp_with_side_set(JmpCondition::YDecNonZero, &mut width_label, 1);
+    let mut wrap_source = asm.label();
+    asm.bind(&mut wrap_source);
+
+    asm.assemble_with_wrap(wrap_source, wrap_target)
+}
+
+// Like the 1-bit program, with delays counted in X (low word) and Y (high
+// word). Every time X runs out while Y is non-zero, X is reloaded from the
+// ISR for another chunk of exactly 2^32 cycles.
+fn compile_long(config: ProgramConfig) -> pio::Program<32> {
+    let open_drain = config.output == OutputMode::OpenDrain;
+    let sideset = SideSet::new(true, 1, open_drain);
+    let mut asm: Assembler<32> = Assembler::new_with_side_set(sideset);
+
+    // Get chunk reload value
+    prologue(&mut asm, config, OutDestination::ISR);
+
+    // Get number of edges before triggering
+    asm.out(OutDestination::Y, 32);
+
+    // Wait number of edges
+    let mut edge_label = asm.label();
+    asm.bind(&mut edge_label);
+    asm.wait(0, WaitSource::PIN, 0, false);
+    asm.wait(1, WaitSource::PIN, 0, false);
+    asm.jmp(JmpCondition::YDecNonZero, &mut edge_label);
+
+    // Get delay chunks and remaining delay cycles (Pulse Low)
+    let mut wrap_target = asm.label();
+    asm.bind(&mut wrap_target);
+    asm.out_with_side_set(OutDestination::Y, 32, 0);
+    asm.out(OutDestination::X, 32);
+
+    // Wait delay cycles, then one more chunk while Y is non-zero
+    let mut delay_label = asm.label();
+    let mut chunk_label = asm.label();
+    asm.bind(&mut delay_label);
+    asm.jmp(JmpCondition::XDecNonZero, &mut delay_label);
+    asm.jmp(JmpCondition::YDecNonZero, &mut chunk_label);
+
+    // Get and wait width cycles (Pulse High)
+    asm.out(OutDestination::Y, 32);
+    let mut width_label = asm.label();
+    asm.bind(&mut width_label);
+    asm.jmp_with_side_set(JmpCondition::YDecNonZero, &mut width_label, 1);
+    let mut wrap_source = asm.label();
+    asm.bind(&mut wrap_source);
+
+    // Only reached by jumps, outside the wrapped loop
+    asm.bind(&mut chunk_label);
+    asm.mov(MovDestination::X, MovOperation::None, MovSource::ISR);
+    asm.jmp(JmpCondition::Always, &mut delay_label);
+
+    asm.assemble_with_wrap(wrap_source, wrap_target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crc::crc32;
+    use crate::rng::Rng;
+
+    // Past the magic and the version
+    const CH0: usize = 5;
+    // A channel with an empty table, five list lengths after 72 bytes
+    const EMPTY_CHANNEL_LEN: usize = 77;
+
+    // Every GPIO routed to PIO0, as on the Pico
+    fn pio_pins() -> Vec<u8> {
+        (0..probe::GPIO_COUNT).collect()
+    }
+
+    fn channel(pin: u8, pairs: &[(u64, u32)]) -> PulseParameter {
+        let mut p = PulseParameter::new(pin);
+        for &(delay, width) in pairs {
+            p.delay.push(delay);
+            p.width.push(width);
+            p.delay_requested.push(delay.saturating_mul(8_000));
+            p.width_requested.push(width as u64 * 8_000);
+        }
+        p
+    }
+
+    fn blob(params: &[PulseParameter; NUM_CHANNELS], groups: &[Group]) -> Blob {
+        encode_snapshot(params, Rounding::Nearest, 0, groups)
+    }
+
+    fn empty() -> [PulseParameter; NUM_CHANNELS] {
+        [channel(1, &[]), channel(2, &[])]
+    }
+
+    // A hand edit that keeps the CRC right
+    fn patched(mut blob: Blob, at: usize, byte: u8) -> Blob {
+        blob[at] = byte;
+        resealed(blob)
+    }
+
+    fn resealed(mut blob: Blob) -> Blob {
+        let body = blob.len() - 4;
+        let crc = crc32(&blob[..body]);
+        blob[body..].copy_from_slice(&crc.to_le_bytes());
+        blob
+    }
+
+    fn error(blob: &[u8]) -> Option<SnapError> {
+        decode_snapshot(blob).err()
+    }
+
+    fn group(name: &str, members: u32, reference: Option<usize>) -> Group {
+        Group {
+            name: GroupName::from(name).unwrap(),
+            members,
+            reference,
+            lead_max: 0,
+        }
+    }
+
+    // Anything decode() accepts, valid as a configuration or not
+    fn random_channel(rng: &mut Rng) -> PulseParameter {
+        let mut p = PulseParameter::new(rng.below(probe::GPIO_COUNT as u64) as u8);
+        let levels = [None, Some(Level::Low), Some(Level::High)];
+        p.wide = rng.one_in(4);
+        p.output = *rng.pick(&[OutputMode::PushPull, OutputMode::OpenDrain]);
+        p.idle_tristate = rng.one_in(2);
+        p.compensate_latency = rng.one_in(2);
+        p.per_edge = rng.one_in(4);
+        p.trigger_pin = rng.below(probe::GPIO_COUNT as u64) as u8;
+        p.trigger_from = Some(rng.below(NUM_CHANNELS as u64) as usize).filter(|_| rng.one_in(4));
+        p.retrigger = *rng.pick(&[
+            RetriggerPolicy::Ignore,
+            RetriggerPolicy::Latch,
+            RetriggerPolicy::Abort,
+        ]);
+        p.trigger_divider = 1 + rng.below(u32::MAX as u64) as u32;
+        p.marker = rng.one_in(3).then(|| Marker {
+            pre: Marker::PRE_MIN + rng.below(100) as u32,
+            post: Marker::POST_MIN + rng.below(100) as u32,
+        });
+        p.trigger_out = rng.one_in(4).then(|| rng.u64() as u32);
+        p.gap = *rng.pick(&levels);
+        p.idle = *rng.pick(&levels);
+        p.t0_offset = rng.one_in(3).then(|| rng.u64() as i64);
+        p.pre_delay = rng.u64();
+        p.pre_delay_requested = rng.u64();
+        p.rearm_guard = rng.u64();
+        p.rearm_guard_requested = rng.u64();
+        // Unpaired now and then, as while a table is being entered
+        let delays = rng.below(NUM_PULSES_MAX as u64 + 1) as usize;
+        let widths = match rng.below(4) {
+            0 => rng.below(NUM_PULSES_MAX as u64 + 1) as usize,
+            _ => delays,
+        };
+        for _ in 0..delays {
+            p.delay.push(rng.u64() >> rng.below(64));
+            p.delay_requested.push(rng.u64());
+        }
+        for _ in 0..widths {
+            p.width.push(rng.u64() as u32);
+            p.width_requested.push(rng.u64());
+        }
+        if p.wide {
+            for _ in 0..widths {
+                p.levels.push(rng.below(4) as u8);
+            }
+        }
+        p
+    }
+
+    fn random_snapshot(rng: &mut Rng) -> Blob {
+        let mut params = [random_channel(rng), random_channel(rng)];
+        if rng.one_in(3) {
+            let ch = rng.below(NUM_CHANNELS as u64) as usize;
+            params[ch].mirror = Some(Mirror {
+                source: 1 - ch,
+                invert: rng.one_in(2),
+            });
+            // As set_mirror() leaves them
+            sync_mirrors(&mut params);
+        }
+        let rounding = *rng.pick(&[Rounding::Nearest, Rounding::Down, Rounding::Up]);
+        let falling = rng.u64() as u32 >> (32 - probe::GPIO_COUNT);
+        let mut groups = ArrayVec::<Group, NUM_CHANNELS>::new();
+        match rng.below(3) {
+            0 => {}
+            1 => groups.push(group("PAIR", 0b11, Some(rng.below(2) as usize))),
+            _ => {
+                groups.push(group("A", 0b01, None));
+                groups.push(group("B", 0b10, Some(1)));
+            }
+        }
+        encode_snapshot(&params, rounding, falling, &groups)
+    }
+
+    #[test]
+    fn snapshots_reencode_to_the_same_bytes() {
+        let mut rng = Rng::new(163);
+        for _ in 0..2_000 {
+            let blob = random_snapshot(&mut rng);
+            let snapshot = decode_snapshot(&blob).unwrap();
+            let again = encode_snapshot(
+                &snapshot.params,
+                snapshot.rounding,
+                snapshot.falling,
+                &snapshot.groups,
+            );
+            assert_eq!(again, blob);
+        }
+    }
+
+    #[test]
+    fn largest_snapshot_fits() {
+        let mut params = [0, 1].map(|ch| {
+            let mut p = channel(2 + ch, &[(u64::MAX, u32::MAX); NUM_PULSES_MAX]);
+            p.wide = true;
+            p.levels.extend([0b11; NUM_PULSES_MAX]);
+            p
+        });
+        params[0].marker = Some(Marker { pre: 1, post: 2 });
+        let name = "N".repeat(GROUP_NAME_MAX);
+        let groups = [group(&name, 0b01, Some(0)), group(&name, 0b10, None)];
+        let blob = encode_snapshot(&params, Rounding::Up, 1 << 29, &groups);
+        assert_eq!(blob.len(), 2069);
+        assert!(decode_snapshot(&blob).is_ok());
+    }
+
+    #[test]
+    fn snapshot_with_a_bad_crc_is_corrupt() {
+        let mut rng = Rng::new(1163);
+        for _ in 0..200 {
+            let mut blob = random_snapshot(&mut rng);
+            let at = rng.below(blob.len() as u64) as usize;
+            blob[at] ^= 1 << rng.below(8);
+            assert_eq!(error(&blob), Some(SnapError::Corrupt));
+        }
+    }
+
+    #[test]
+    fn snapshot_of_another_version_is_refused() {
+        let good = blob(&empty(), &[]);
+        let version = good[4];
+        for other in [0, version - 1, version + 1, 0xff] {
+            assert_eq!(
+                error(&patched(good.clone(), 4, other)),
+                Some(SnapError::Version)
+            );
+        }
+    }
+
+    #[test]
+    fn valid_blob_loads() {
+        let params = [channel(1, &[(100, 10), (50, 5)]), channel(2, &[(0, 3)])];
+        let snapshot = decode_snapshot(&blob(&params, &[group("A", 0b11, Some(0))])).unwrap();
+        assert_eq!(snapshot.params[0].delay.as_slice(), [100, 50]);
+        assert_eq!(snapshot.params[1].width.as_slice(), [3]);
+        assert_eq!(snapshot.groups[0].members, 0b11);
+        assert_eq!(snapshot.check(&pio_pins(), 0), Ok(()));
+    }
+
+    #[test]
+    fn enum_and_bool_bytes_out_of_range_are_corrupt() {
+        let good = blob(&empty(), &[]);
+        assert_eq!(error(&good), None);
+        // wide, output mode, retrigger policy and the gap level
+        for (at, byte) in [(1, 2), (2, 2), (8, 3), (27, 4)] {
+            assert_eq!(
+                error(&patched(good.clone(), CH0 + at, byte)),
+                Some(SnapError::Corrupt),
+                "byte {at} = {byte}"
+            );
+        }
+        // The same fields on the second channel
+        assert_eq!(
+            error(&patched(good.clone(), CH0 + EMPTY_CHANNEL_LEN + 2, 7)),
+            Some(SnapError::Corrupt)
+        );
+    }
+
+    #[test]
+    fn fields_the_setters_refuse_are_corrupt() {
+        let broken: [fn(&mut PulseParameter); 9] = [
+            |p| p.trigger_divider = 0,
+            |p| p.trigger_pin = probe::GPIO_COUNT,
+            |p| p.trigger_from = Some(NUM_CHANNELS),
+            |p| {
+                p.mirror = Some(Mirror {
+                    source: NUM_CHANNELS,
+                    invert: false,
+                })
+            },
+            |p| {
+                p.delay_requested.pop();
+            },
+            |p| {
+                p.width_requested.pop();
+            },
+            |p| p.levels.push(0b100),
+            |p| {
+                p.marker = Some(Marker {
+                    pre: Marker::PRE_MIN - 1,
+                    post: Marker::POST_MIN,
+                })
+            },
+            |p| {
+                p.marker = Some(Marker {
+                    pre: Marker::PRE_MIN,
+                    post: Marker::POST_MIN - 1,
+                })
+            },
+        ];
+        for (i, edit) in broken.iter().enumerate() {
+            for ch in 0..NUM_CHANNELS {
+                let mut params = [channel(1, &[(100, 10)]), channel(2, &[(100, 10)])];
+                edit(&mut params[ch]);
+                assert_eq!(
+                    error(&blob(&params, &[])),
+                    Some(SnapError::Corrupt),
+                    "edit {i} on ch{ch}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn chained_mirrors_are_corrupt() {
+        let mirror = |source| {
+            Some(Mirror {
+                source,
+                invert: false,
+            })
+        };
+        let mut params = empty();
+        params[0].mirror = mirror(0);
+        assert_eq!(error(&blob(&params, &[])), Some(SnapError::Corrupt));
+        let mut params = empty();
+        params[0].mirror = mirror(1);
+        params[1].mirror = mirror(0);
+        assert_eq!(error(&blob(&params, &[])), Some(SnapError::Corrupt));
+        // A plain mirror takes its source's table and keeps its own pin
+        let mut params = [channel(1, &[(100, 10)]), channel(2, &[])];
+        params[1].mirror = mirror(0);
+        let snapshot = decode_snapshot(&blob(&params, &[])).unwrap();
+        assert_eq!(snapshot.params[1].delay.as_slice(), [100]);
+        assert_eq!(snapshot.params[1].pin, 2);
+    }
+
+    #[test]
+    fn bad_rounding_edges_and_groups_are_corrupt() {
+        let good = blob(&empty(), &[]);
+        // The rounding byte, the falling edge pins, then an empty group
+        // list, then the CRC
+        let rounding = good.len() - 10;
+        assert_eq!(good[rounding], Rounding::Nearest as u8);
+        assert_eq!(
+            error(&patched(good.clone(), rounding, 3)),
+            Some(SnapError::Corrupt)
+        );
+        let params = empty();
+        let pins = encode_snapshot(
+            &params,
+            Rounding::Nearest,
+            1 << (probe::GPIO_COUNT - 1),
+            &[],
+        );
+        assert_eq!(error(&pins), None);
+        let pins = encode_snapshot(&params, Rounding::Nearest, 1 << probe::GPIO_COUNT, &[]);
+        assert_eq!(error(&pins), Some(SnapError::Corrupt));
+        for groups in [
+            [group("A", 0, None)].as_slice(),
+            &[group("A", 1 << NUM_CHANNELS, None)],
+            &[group("A", 0b01, None), group("B", 0b11, None)],
+            &[group("A", 0b01, Some(1))],
+            &[group("A", 0b01, Some(NUM_CHANNELS))],
+        ] {
+            assert_eq!(error(&blob(&empty(), groups)), Some(SnapError::Corrupt));
+        }
+        // A name that isn't UTF-8: rounding, edges, list length, name
+        // length, name
+        let named = blob(&empty(), &[group("A", 0b01, None)]);
+        let name = CH0 + 2 * EMPTY_CHANNEL_LEN + 7;
+        assert_eq!(named[name], b'A');
+        assert_eq!(error(&patched(named, name, 0xff)), Some(SnapError::Corrupt));
+    }
+
+    #[test]
+    fn list_longer_than_its_table_is_corrupt() {
+        let good = blob(&empty(), &[]);
+        // The first channel's delay count
+        let at = CH0 + EMPTY_CHANNEL_LEN - 5;
+        assert_eq!(good[at], 0);
+        assert_eq!(
+            error(&patched(good, at, NUM_PULSES_MAX as u8 + 1)),
+            Some(SnapError::Corrupt)
+        );
+    }
+
+    #[test]
+    fn truncated_or_padded_blobs_are_corrupt() {
+        let good = blob(&[channel(1, &[(100, 10)]), channel(2, &[])], &[]);
+        let body = good.len() - 4;
+        let mut short = good.clone();
+        short.remove(body - 1);
+        assert_eq!(error(&resealed(short)), Some(SnapError::Corrupt));
+        let mut long = good.clone();
+        long.insert(body, 0);
+        assert_eq!(error(&resealed(long)), Some(SnapError::Corrupt));
+        for len in 0..good.len() {
+            assert!(error(&good[..len]).is_some(), "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn output_on_the_trigger_input_is_refused() {
+        // GPIO0 as ch1's output while it is the default trigger input
+        let params = [channel(1, &[(100, 10)]), channel(TRIGGER_PIN, &[(100, 10)])];
+        let snapshot = decode_snapshot(&blob(&params, &[])).unwrap();
+        assert_eq!(
+            snapshot.check(&pio_pins(), 0),
+            Err(Invalid {
+                first: Violation::PinUnavailable {
+                    ch: 1,
+                    pin: TRIGGER_PIN
+                },
+                count: 1
+            })
+        );
+        // Or as another channel's trigger
+        let mut params = [channel(1, &[(100, 10)]), channel(2, &[(100, 10)])];
+        params[0].trigger_pin = 2;
+        let snapshot = decode_snapshot(&blob(&params, &[])).unwrap();
+        assert_eq!(
+            snapshot.check(&pio_pins(), 0),
+            Err(Invalid {
+                first: Violation::PinUnavailable { ch: 1, pin: 2 },
+                count: 1
+            })
+        );
+    }
+
+    #[test]
+    fn unpaired_tables_are_not_refused() {
+        let mut params = [channel(1, &[(100, 10)]), channel(2, &[])];
+        params[0].delay.push(5);
+        params[0].delay_requested.push(40_000);
+        let snapshot = decode_snapshot(&blob(&params, &[])).unwrap();
+        assert_eq!(snapshot.check(&pio_pins(), 0), Ok(()));
+        // But everything else is still refused with them
+        params[1].pin = 1;
+        let snapshot = decode_snapshot(&blob(&params, &[])).unwrap();
+        assert_eq!(
+            snapshot.check(&pio_pins(), 0),
+            Err(Invalid {
+                first: Violation::PinConflict {
+                    ch: 1,
+                    other: 0,
+                    pin: 1
+                },
+                count: 1
+            })
+        );
+    }
+
+    #[test]
+    fn the_first_violation_is_kept_and_the_rest_counted() {
+        let params = [
+            channel(TRIGGER_PIN, &[(100, 10)]),
+            channel(TRIGGER_PIN, &[(100, 10)]),
+        ];
+        let mut found = Vec::new();
+        violations(&params, &pio_pins(), 0, |violation| found.push(violation));
+        assert_eq!(
+            found,
+            [
+                Violation::PinUnavailable {
+                    ch: 0,
+                    pin: TRIGGER_PIN
+                },
+                Violation::PinConflict {
+                    ch: 1,
+                    other: 0,
+                    pin: TRIGGER_PIN
+                },
+                Violation::PinUnavailable {
+                    ch: 1,
+                    pin: TRIGGER_PIN
+                },
+            ]
+        );
+        assert_eq!(
+            validate(&params, &pio_pins(), 0),
+            Err(Invalid {
+                first: found[0],
+                count: 3
+            })
+        );
+    }
+
+    fn achieved(cycles: u64) -> Achieved {
+        Achieved {
+            requested_ps: cycles * 8_000,
+            cycles,
+        }
+    }
+
+    #[test]
+    fn delays_and_widths_fill_to_num_pulses_max() {
+        let mut p = PulseParameter::new(1);
+        for i in 0..NUM_PULSES_MAX as u64 {
+            assert_eq!(p.push_delay(achieved(100 + i)), Ok(()));
+            assert_eq!(p.push_width(achieved(10 + i)), Ok(()));
+        }
+        assert_eq!(p.push_delay(achieved(1)), Err(SequenceFull));
+        assert_eq!(p.push_width(achieved(1)), Err(SequenceFull));
+        // Nothing of the refused ones was kept
+        assert_eq!(p.delay.len(), NUM_PULSES_MAX);
+        assert_eq!(p.delay_requested.len(), NUM_PULSES_MAX);
+        assert_eq!(p.width.len(), NUM_PULSES_MAX);
+        assert_eq!(p.width_requested.len(), NUM_PULSES_MAX);
+        assert_eq!(p.delay.last(), Some(&(100 + NUM_PULSES_MAX as u64 - 1)));
+        assert_eq!(p.width.last(), Some(&(10 + NUM_PULSES_MAX as u32 - 1)));
+    }
+
+    #[test]
+    fn pulses_fill_to_num_pulses_max() {
+        let mut p = PulseParameter::new(1);
+        for _ in 0..NUM_PULSES_MAX {
+            assert_eq!(p.push_pulse(achieved(100), achieved(10), 0b10), Ok(()));
+        }
+        assert_eq!(
+            p.push_pulse(achieved(100), achieved(10), 0b10),
+            Err(SequenceFull)
+        );
+        assert_eq!(p.pulses(), NUM_PULSES_MAX);
+        assert_eq!(p.levels.as_slice(), [0b10; NUM_PULSES_MAX]);
+    }
+
+    #[test]
+    fn pulse_is_refused_whole_when_either_list_is_full() {
+        let mut p = PulseParameter::new(1);
+        for _ in 0..NUM_PULSES_MAX {
+            p.push_delay(achieved(100)).unwrap();
+        }
+        p.push_width(achieved(10)).unwrap();
+        assert_eq!(
+            p.push_pulse(achieved(100), achieved(10), 0b11),
+            Err(SequenceFull)
+        );
+        assert_eq!(p.width.len(), 1);
+        assert!(p.levels.is_empty());
+        // The earlier pulses get the default levels
+        let mut p = PulseParameter::new(1);
+        p.push_delay(achieved(100)).unwrap();
+        p.push_width(achieved(10)).unwrap();
+        p.push_pulse(achieved(100), achieved(10), 0b11).unwrap();
+        assert_eq!(p.levels.as_slice(), [LEVELS_DEFAULT, 0b11]);
+    }
+
+    // A table from a sweep or an export: some gaps below what the program
+    // can count, in any of the variants coalescing applies to
+    fn random_table(rng: &mut Rng) -> PulseParameter {
+        let mut p = PulseParameter::new(1);
+        p.wide = rng.one_in(4);
+        p.marker = rng.one_in(4).then_some(Marker { pre: 1, post: 2 });
+        p.gap = *rng.pick(&[None, Some(Level::High)]);
+        p.compensate_latency = rng.one_in(2);
+        let min = p.program_config().min_next_delay() as u64;
+        for i in 0..2 + rng.below(NUM_PULSES_MAX as u64 - 1) {
+            let delay = match rng.below(4) {
+                0 if i > 0 => rng.below(min.max(1)),
+                1 => rng.below(4 * min + 4),
+                2 if rng.one_in(8) => SHORT_DELAY_MAX + rng.below(1 << 33),
+                _ => rng.below(10_000),
+            };
+            let width = match rng.below(8) {
+                0 => u32::MAX - rng.below(1_000) as u32,
+                1 => rng.below(3) as u32,
+                _ => 1 + rng.below(10_000) as u32,
+            };
+            p.push_pulse(achieved(delay), achieved(width as u64), rng.below(4) as u8)
+                .unwrap();
+        }
+        if !p.wide {
+            p.levels.clear();
+        }
+        p
+    }
+
+    #[test]
+    fn coalescing_adds_no_more_high_time_than_the_gaps_it_swallows() {
+        const SYS_HZ: u32 = 125_000_000;
+        let mut rng = Rng::new(192);
+        let mut merges = 0;
+        for _ in 0..5_000 {
+            let mut p = random_table(&mut rng);
+            let requested: u64 = p.width_requested.iter().sum();
+            let before: Vec<(u64, u64)> = p.timeline(SYS_HZ).collect();
+            let delays_requested = p.delay_requested.clone();
+            let merged = p.coalesce(SYS_HZ);
+            let after: Vec<(u64, u64)> = p.timeline(SYS_HZ).collect();
+            merges += merged.len();
+
+            assert_eq!(after.len() + merged.len(), before.len());
+            assert!(merged.windows(2).all(|w| w[0] < w[1]) && merged.first() != Some(&0));
+            assert_eq!(after[0].0, before[0].0);
+            assert!(after.last().unwrap().1 <= before.last().unwrap().1);
+
+            let high = |edges: &[(u64, u64)]| edges.iter().map(|(rise, fall)| fall - rise).sum();
+            let (high_before, high_after): (u64, u64) = (high(&before), high(&after));
+            let swallowed: u64 = merged.iter().map(|&i| before[i].0 - before[i - 1].1).sum();
+            assert!(
+                high_after.abs_diff(high_before) <= swallowed,
+                "high {high_before} -> {high_after}, swallowed {swallowed}, merged {merged:?}"
+            );
+            // As asked for, the high time grows by exactly the low time
+            let asked: u64 = merged
+                .iter()
+                .map(|&i| cycles_to_ps(PULSE_GAP_CYCLES as u64, SYS_HZ) + delays_requested[i])
+                .sum();
+            assert_eq!(p.width_requested.iter().sum::<u64>(), requested + asked);
+        }
+        assert!(merges > 1_000, "{merges} merges");
+    }
+
+    #[test]
+    fn per_edge_and_trigger_out_tables_are_never_coalesced() {
+        let mut rng = Rng::new(1192);
+        for _ in 0..100 {
+            let mut p = random_table(&mut rng);
+            p.wide = false;
+            p.marker = None;
+            if rng.one_in(2) {
+                p.per_edge = true;
+            } else {
+                p.trigger_out = Some(100);
+            }
+            let delays = p.delay.clone();
+            assert!(p.coalesce(125_000_000).is_empty());
+            assert_eq!(p.delay, delays);
+        }
+    }
+
+    // Any table validate() lets through, on pin 2 away from the trigger: a
+    // few pulses, now and then a double pulse or delays past 2^32 cycles,
+    // with widths and delays below the program's minimum mixed in
+    fn random_program(rng: &mut Rng) -> Option<PulseParameter> {
+        let levels = [None, Some(Level::Low), Some(Level::High)];
+        let mut p = PulseParameter::new(2);
+        p.wide = rng.one_in(5);
+        p.output = *rng.pick(&[OutputMode::PushPull, OutputMode::OpenDrain]);
+        p.marker = rng.one_in(5).then(|| Marker {
+            pre: Marker::PRE_MIN + rng.below(20) as u32,
+            post: Marker::POST_MIN + rng.below(20) as u32,
+        });
+        p.per_edge = rng.one_in(6);
+        p.trigger_out = rng.one_in(8).then(|| rng.below(100) as u32);
+        if rng.one_in(3) {
+            p.gap = *rng.pick(&levels);
+            p.idle = *rng.pick(&levels);
+        }
+        p.compensate_latency = rng.one_in(2);
+        p.pre_delay = rng.below(100).saturating_sub(75);
+        p.trigger_divider = 1 + rng.below(4) as u32;
+        let double = rng.one_in(6);
+        let pulses = if double { 2 } else { 1 + rng.below(6) };
+        for i in 0..pulses {
+            let delay = match rng.below(6) {
+                _ if double && i == 1 => 0,
+                0 => rng.below(4),
+                1 if rng.one_in(4) => SHORT_DELAY_MAX + rng.below(1 << 34),
+                _ => rng.below(200),
+            };
+            let width = match rng.below(6) {
+                0 => rng.below(4) as u32,
+                1 if rng.one_in(4) => u32::MAX - rng.below(1_000) as u32,
+                _ => 1 + rng.below(200) as u32,
+            };
+            let levels = 1 + rng.below(3) as u8;
+            p.push_pulse(achieved(delay), achieved(width as u64), levels)
+                .unwrap();
+        }
+        let params = [p, PulseParameter::new(20)];
+        validate(&params, &pio_pins(), 0).ok()?;
+        let [p, _] = params;
+        Some(p)
+    }
+
+    // Runs the channel's program on its words in the PIO model, triggered
+    // as the program expects or started past the edge wait as
+    // skip_trigger() does
+    fn run_model(p: &PulseParameter, immediate: bool) -> pio_model::Run {
+        let config = p.program_config();
+        let mut words = Vec::new();
+        p.write_words(immediate, |word| words.push(word));
+        let (start, groups) = if immediate {
+            (config.edge_loop_end() + 1, vec![])
+        } else if config.trigger_out {
+            (0, vec![1; 3])
+        } else if config.per_edge {
+            (0, vec![p.trigger_divider; p.pulses()])
+        } else {
+            (0, vec![p.trigger_divider])
+        };
+        pio_model::run(&compile(config), start, config.idle_side(), &words, &groups)
+    }
+
+    // (rise, fall) of each stretch of the pins matching `active`, in cycles.
+    // One the pins start in is left out, one they end in has no fall.
+    fn stretches(run: &pio_model::Run, active: impl Fn(u8) -> bool) -> Vec<(u64, Option<u64>)> {
+        let mut stretches: Vec<(u64, Option<u64>)> = Vec::new();
+        let mut was = active(run.pins[0].1);
+        for &(at, pins) in &run.pins[1..] {
+            match (was, active(pins)) {
+                (false, true) => stretches.push((at, None)),
+                (true, false) => {
+                    if let Some(last) = stretches.last_mut() {
+                        last.1 = Some(at);
+                    }
+                }
+                _ => {}
+            }
+            was = active(pins);
+        }
+        stretches
+    }
+
+    #[test]
+    fn programs_produce_the_predicted_timeline() {
+        const SYS_HZ: u32 = 125_000_000;
+        let ps = |cycles: u64| cycles_to_ps(cycles, SYS_HZ);
+        let mut rng = Rng::new(187);
+        let mut variants = Vec::new();
+        let mut runs = 0;
+        while runs < 3_000 {
+            let Some(p) = random_program(&mut rng) else {
+                continue;
+            };
+            runs += 1;
+            let config = p.program_config();
+            if !variants.contains(&config.as_str()) {
+                variants.push(config.as_str());
+            }
+            let run = run_model(&p, false);
+            let pulse = |pins: u8| if config.wide { pins } else { pins & 1 } != config.gap;
+            let pulses = stretches(&run, pulse);
+
+            if let Some(width) = p.trigger_out {
+                assert_eq!(pulses.len(), run.releases.len());
+                for (&(rise, fall), release) in pulses.iter().zip(&run.releases) {
+                    let rise = rise - release;
+                    assert_eq!(rise, TRIGGER_OUT_LATENCY_CYCLES as u64);
+                    assert_eq!(fall, Some(release + rise + width.max(2) as u64));
+                }
+                continue;
+            }
+            let predicted: Vec<(u64, u64)> = p.timeline(SYS_HZ).collect();
+            assert_eq!(pulses.len(), predicted.len(), "{}", config.as_str());
+            for (i, (&(rise, fall), &expected)) in pulses.iter().zip(&predicted).enumerate() {
+                let release = run.releases[if config.per_edge { i } else { 0 }];
+                let fall = fall.map(|fall| ps(fall - release));
+                let last = i == predicted.len() - 1;
+                // The split program rests at the pulse level after its table
+                let expected_fall = (!(config.split && last)).then_some(expected.1);
+                assert_eq!(
+                    (ps(rise - release), fall),
+                    (expected.0, expected_fall),
+                    "{} pulse {i}",
+                    config.as_str()
+                );
+                if config.wide {
+                    let levels = p.levels.get(i).copied().unwrap_or(LEVELS_DEFAULT);
+                    assert!(run.pins.contains(&(rise, levels)));
+                }
+            }
+            if let Some(marker) = p.marker {
+                let marked = stretches(&run, |pins| pins & 0b10 != 0);
+                let around: Vec<(u64, Option<u64>)> = pulses
+                    .iter()
+                    .map(|&(rise, fall)| {
+                        let pre = rise - marker.pre_cycles() as u64;
+                        (pre, fall.map(|fall| fall + marker.post_cycles() as u64))
+                    })
+                    .collect();
+                assert_eq!(marked, around);
+            }
+        }
+        for variant in [
+            "STANDARD",
+            "STANDARD_OD",
+            "WIDE",
+            "LONG",
+            "LONG_OD",
+            "MARKER",
+            "MARKER_OD",
+            "DOUBLE",
+            "DOUBLE_OD",
+            "SPLIT",
+            "SPLIT_OD",
+            "PER_EDGE",
+            "PER_EDGE_OD",
+            "TRIGOUT",
+            "TRIGOUT_OD",
+        ] {
+            assert!(variants.contains(&variant), "no {variant} program ran");
+        }
+    }
+
+    // ping() and the test patterns start right at the first delay, some
+    // fixed cycles earlier than a trigger edge would
+    #[test]
+    fn immediate_tables_run_as_triggered_ones() {
+        let mut rng = Rng::new(1187);
+        let mut runs = 0;
+        while runs < 1_000 {
+            let Some(p) = random_program(&mut rng) else {
+                continue;
+            };
+            let config = p.program_config();
+            if config.long_delay || config.double || config.per_edge || config.trigger_out {
+                continue;
+            }
+            runs += 1;
+            let triggered = run_model(&p, false);
+            let release = triggered.releases[0];
+            let early = (TRIGGER_SYNC_CYCLES + TRIGGER_PATH_CYCLES - PULSE_GAP_CYCLES) as u64;
+            let immediate = run_model(&p, true);
+            assert!(immediate.releases.is_empty());
+            let shifted: Vec<(u64, u8)> = immediate
+                .pins
+                .iter()
+                .map(|&(at, pins)| (at + release + early, pins))
+                .collect();
+            // Both rest at the idle level until the first delay
+            let pulses = triggered.pins.iter().skip_while(|&&(at, _)| at < release);
+            assert_eq!(shifted[1..], pulses.copied().collect::<Vec<_>>()[..]);
+        }
     }
 }
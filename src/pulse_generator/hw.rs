@@ -0,0 +1,3291 @@
+use cortex_m::peripheral::{scb::VectActive, SCB};
+use cortex_m::singleton;
+use embedded_dma::ReadBuffer;
+#[cfg(feature = "selftest")]
+use pio::InSource;
+use pio::{Instruction, InstructionOperands, SetDestination};
+#[cfg(any(feature = "capture", feature = "selftest"))]
+use rp2040_hal::pio::Buffers::OnlyRx;
+use rp2040_hal::{
+    dma::{single_buffer, Channel, ChannelIndex, DMAExt, CH0, CH1, CH2, CH3},
+    pac::{self, DMA, PIO0, RESETS},
+    pio::{
+        Buffers::OnlyTx, InstalledProgram, PIOBuilder, PIOExt, PinDir, Running, Rx, ShiftDirection,
+        StateMachine, StateMachineIndex, Stopped, Tx, UninitStateMachine, PIO, SM0, SM1, SM2, SM3,
+    },
+};
+
+use super::*;
+use crate::arm_queue::{ArmQueue, ArmQueueFull, ArmRequest};
+use crate::debugpin;
+#[cfg(feature = "capture")]
+use crate::glitch::{self, Glitches};
+use crate::perf::{ArmPerf, ArmPhase, Stopwatch};
+use crate::safestate;
+use crate::tick;
+use crate::time::PS_PER_US;
+#[cfg(feature = "capture")]
+use crate::tlog::{self, TLOG_LEN};
+use crate::usblog::info;
+
+// Armed and still waiting for trigger edges, from the SM's program counter
+pub fn can_accept_trigger(ch: usize) -> bool {
+    if !is_armed(ch) {
+        return false;
+    }
+    let pcs = WAIT_PCS[ch].load(Ordering::Relaxed);
+    // Safety: read-only access to the SM's address register
+    let pio = unsafe { &*pac::PIO0::ptr() };
+    let pc = pio.sm(ch).sm_addr().read().bits() as u16;
+    (pcs & 0xff..=pcs >> 8).contains(&pc)
+}
+
+// Static word buffer handed to the DMA, only the first `len` words are sent
+pub struct WordBuffer {
+    words: &'static mut [u32],
+    len: usize,
+}
+
+unsafe impl ReadBuffer for WordBuffer {
+    type Word = u32;
+
+    unsafe fn read_buffer(&self) -> (*const u32, usize) {
+        (self.words.as_ptr(), self.len)
+    }
+}
+
+type Transfer<SM, CH> = single_buffer::Transfer<Channel<CH>, WordBuffer, Tx<(PIO0, SM)>>;
+
+enum SmState<SM: StateMachineIndex> {
+    Running(StateMachine<(PIO0, SM), Running>),
+    Stopped(StateMachine<(PIO0, SM), Stopped>),
+}
+
+// Timer ticks (us), the count main's Timer reads
+fn timer_now() -> u64 {
+    // Safety: read-only access to the free-running counter
+    let timer = unsafe { &*pac::TIMER::ptr() };
+    loop {
+        let high = timer.timerawh().read().bits();
+        let low = timer.timerawl().read().bits();
+        if timer.timerawh().read().bits() == high {
+            return (high as u64) << 32 | low as u64;
+        }
+    }
+}
+
+struct CachedProgram {
+    config: ProgramConfig,
+    program: InstalledProgram<PIO0>,
+    words: usize,
+    users: u8,
+}
+
+// Each distinct program variant is installed once and shared by every
+// channel running it, the last channel to release it uninstalls it
+struct ProgramCache {
+    entries: ArrayVec<CachedProgram, CACHED_PROGRAMS>,
+    // Held by expert programs, which are installed around the cache
+    expert_words: usize,
+}
+
+impl ProgramCache {
+    fn acquire(
+        &mut self,
+        pio: &mut PIO<PIO0>,
+        config: ProgramConfig,
+    ) -> Result<InstalledProgram<PIO0>, InstructionMemoryFull> {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.config == config) {
+            entry.users += 1;
+            // Safety: only uninstalled once every user has released it
+            return Ok(unsafe { entry.program.share() });
+        }
+        let program = compile(config);
+        let installed = pio.install(&program).map_err(|_| InstructionMemoryFull {
+            words: program.code.len(),
+            free: self.free(),
+            installed: self.installed(),
+        })?;
+        let shared = unsafe { installed.share() };
+        self.entries.push(CachedProgram {
+            config,
+            program: installed,
+            words: program.code.len(),
+            users: 1,
+        });
+        Ok(shared)
+    }
+
+    // Takes back the handle a state machine returned on uninit
+    fn release(&mut self, pio: &mut PIO<PIO0>, program: InstalledProgram<PIO0>) {
+        let entry = self
+            .entries
+            .iter()
+            .position(|e| e.program.offset() == program.offset());
+        if let Some(i) = entry {
+            self.entries[i].users -= 1;
+            if self.entries[i].users == 0 {
+                pio.uninstall(self.entries.swap_remove(i).program);
+            }
+        }
+    }
+
+    fn installed(&self) -> ArrayVec<ProgramConfig, CACHED_PROGRAMS> {
+        self.entries.iter().map(|e| e.config).collect()
+    }
+
+    fn free(&self) -> usize {
+        let used: usize = self.entries.iter().map(|e| e.words).sum();
+        INSTRUCTION_MEMORY.saturating_sub(used + self.expert_words)
+    }
+}
+
+// PIO state machine and DMA channel driving one output
+struct ChannelHw<SM: StateMachineIndex, CH: ChannelIndex> {
+    sm: Option<SmState<SM>>,
+    tx: Option<Tx<(PIO0, SM)>>,
+    rx: Option<Rx<(PIO0, SM)>>,
+    dma: Option<Channel<CH>>,
+    transfer: Option<Transfer<SM, CH>>,
+    table_buf: Option<WordBuffer>,
+    stream_free: ArrayVec<WordBuffer, STREAM_BLOCKS>,
+    stream_queue: ArrayVec<WordBuffer, STREAM_BLOCKS>,
+    stream: Option<Stream>,
+    // Instruction memory address the program was installed at
+    offset: u8,
+    config: ProgramConfig,
+    // Output pins of the loaded program
+    pins: Range<u8>,
+    // Pin the program waits on, and the one to use from the next arm
+    wait_pin: u8,
+    trigger: u8,
+    idle_tristate: bool,
+    // Side-set value of the idle level, see PulseParameter::idle_side()
+    idle: u8,
+    // Words in the table transfer of the last arm, the prologue isn't part
+    // of it
+    table_len: u32,
+    underrun: Option<Underrun>,
+}
+
+impl<SM: StateMachineIndex, CH: ChannelIndex> ChannelHw<SM, CH> {
+    // Starts out stopped on the standard program, shared by all channels,
+    // driving `pin` low. The SM isn't started until the channel is armed.
+    fn new(
+        pio: &mut PIO<PIO0>,
+        programs: &mut ProgramCache,
+        pin: u8,
+        sm: UninitStateMachine<(PIO0, SM)>,
+        dma: Channel<CH>,
+        table: &'static mut [u32],
+        blocks: &'static mut [StreamBlock],
+    ) -> Result<Self, InstructionMemoryFull> {
+        let config = ProgramConfig {
+            wide: false,
+            output: OutputMode::PushPull,
+            long_delay: false,
+            marker: false,
+            trigger_out: false,
+            double: false,
+            per_edge: false,
+            gap: 0,
+            split: false,
+        };
+        let program = programs.acquire(pio, config)?;
+
+        let mut stream_free = ArrayVec::new();
+        for block in blocks.iter_mut() {
+            stream_free.push(WordBuffer {
+                words: block,
+                len: 0,
+            });
+        }
+
+        let mut hw = Self {
+            sm: None,
+            tx: None,
+            rx: None,
+            dma: Some(dma),
+            transfer: None,
+            table_buf: Some(WordBuffer {
+                words: table,
+                len: 0,
+            }),
+            stream_free,
+            stream_queue: ArrayVec::new(),
+            stream: None,
+            offset: 0,
+            config,
+            pins: 0..0,
+            wait_pin: TRIGGER_PIN,
+            trigger: TRIGGER_PIN,
+            idle_tristate: false,
+            idle: 0,
+            table_len: 0,
+            underrun: None,
+        };
+        let mut sm = hw.configure(sm, program, pin, config);
+        hw.idle = config.idle_side();
+        force_idle(&mut sm, hw.idle);
+        sm.set_pindirs(hw.pins.clone().map(|pin| (pin, PinDir::Output)));
+        hw.sm = Some(SmState::Stopped(sm));
+        hw.publish();
+        Ok(hw)
+    }
+
+    // Reloads the program and starts feeding the table, leaving the SM
+    // stopped so several channels can be started together. An immediate
+    // table skips the trigger wait and runs as soon as the SM starts. Laps
+    // the stopwatch at the end of each phase.
+    fn load_table(
+        &mut self,
+        pio: &mut PIO<PIO0>,
+        programs: &mut ProgramCache,
+        params: &PulseParameter,
+        immediate: bool,
+        stopwatch: &mut Stopwatch,
+    ) -> Result<(), InstructionMemoryFull> {
+        let config = params.program_config();
+        self.reload(pio, programs, params, config)?;
+        stopwatch.lap(ArmPhase::Stop);
+        let mut buf = self.table_buf.take().unwrap();
+        buf.len = 0;
+        params.write_table(|word| {
+            buf.words[buf.len] = word;
+            buf.len += 1;
+        });
+        stopwatch.lap(ArmPhase::Build);
+        // The SM is stopped with its FIFO empty, the prologue goes in ahead
+        // of the table. An immediate table has no trigger edge count.
+        let tx = self.tx.as_mut().unwrap();
+        let mut prologue = 0;
+        params.write_prologue(immediate, |word| {
+            tx.write(word);
+            prologue += 1;
+        });
+        // Hold the SM until everything up to the first pulse is queued (the
+        // joined FIFO takes 8 words), so a trigger arriving right after
+        // arming never waits on DMA arbitration
+        let primed = (prologue + buf.len).min(8) as u8;
+        self.table_len = buf.len as u32;
+        self.start_transfer(buf);
+        while self.tx_level() < primed {}
+        if immediate {
+            self.skip_trigger();
+        }
+        stopwatch.lap(ArmPhase::Dma);
+        Ok(())
+    }
+
+    // Streams always use the 1-bit program
+    fn stream_start(
+        &mut self,
+        pio: &mut PIO<PIO0>,
+        programs: &mut ProgramCache,
+        params: &PulseParameter,
+    ) -> Result<(), InstructionMemoryFull> {
+        let config = ProgramConfig {
+            wide: false,
+            output: params.output,
+            long_delay: false,
+            marker: false,
+            trigger_out: false,
+            double: false,
+            per_edge: false,
+            gap: params.gap_side(),
+            split: false,
+        };
+        self.reload(pio, programs, params, config)?;
+        // The edge count goes straight into the FIFO, blocks follow by DMA
+        self.tx.as_mut().unwrap().write(params.trigger_divider - 1);
+        self.stream = Some(Stream {
+            words_fed: 0,
+            ended: false,
+        });
+        self.start_sm();
+        Ok(())
+    }
+
+    #[cfg(feature = "binary-proto")]
+    fn stream_block(&mut self, pairs: &[(Cycles, Cycles)]) -> Result<usize, StreamError> {
+        match &self.stream {
+            None => return Err(StreamError::NotStreaming),
+            Some(stream) if stream.ended => return Err(StreamError::Ended),
+            _ => {}
+        }
+        if pairs.len() > STREAM_BLOCK_PAIRS {
+            return Err(StreamError::BlockTooLarge);
+        }
+        let mut buf = self.stream_free.pop().ok_or(StreamError::Full)?;
+        buf.len = 0;
+        for &(Cycles(delay), Cycles(width)) in pairs {
+            buf.words[buf.len] = delay.saturating_sub(1);
+            buf.words[buf.len + 1] = width.saturating_sub(1);
+            buf.len += 2;
+        }
+        self.stream_queue.push(buf);
+        self.service_stream_dma();
+        Ok(self.stream_free.len())
+    }
+
+    fn stream_end(&mut self) -> Result<(), StreamError> {
+        let stream = self.stream.as_mut().ok_or(StreamError::NotStreaming)?;
+        stream.ended = true;
+        Ok(())
+    }
+
+    fn service(&mut self, now: u64) -> Option<ChannelEvent> {
+        if self.stream.is_none() {
+            return self.service_table(now);
+        }
+        self.service_stream_dma();
+
+        // Once running, the SM only stalls on autopull when the FIFO is empty
+        if !self.take_tx_stall() {
+            return None;
+        }
+        let stream = self.stream.as_ref().unwrap();
+        let pulses = stream.words_fed / 2;
+        let event = if stream.ended && self.transfer.is_none() && self.stream_queue.is_empty() {
+            debugpin::pulse(debugpin::END);
+            ChannelEvent::StreamDone(pulses)
+        } else {
+            let underrun = Underrun { pulses, at: now };
+            self.underrun = Some(underrun);
+            ChannelEvent::Underrun(underrun)
+        };
+        self.disarm();
+        Some(event)
+    }
+
+    // A table only leaves the SM stalled early when its DMA fell behind. The
+    // stall flag is read before the DMA count, so a stall after the last
+    // word at the end of the table is never mistaken for one.
+    fn service_table(&mut self, now: u64) -> Option<ChannelEvent> {
+        if !matches!(self.sm, Some(SmState::Running(_))) || !self.tx_stalled() {
+            return None;
+        }
+        let remaining = self.debug().dma_remaining?;
+        if remaining == 0 {
+            return None;
+        }
+        self.take_tx_stall();
+        let consumed = self
+            .table_len
+            .saturating_sub(remaining + self.tx_level() as u32);
+        let underrun = Underrun {
+            pulses: consumed / self.config.words_per_pulse(),
+            at: now,
+        };
+        self.disarm();
+        self.underrun = Some(underrun);
+        Some(ChannelEvent::Underrun(underrun))
+    }
+
+    fn disarm(&mut self) {
+        self.reclaim_transfer();
+        let mut sm = match self.sm.take().unwrap() {
+            SmState::Running(sm) => sm.stop(),
+            SmState::Stopped(sm) => sm,
+        };
+        force_idle(&mut sm, self.idle);
+        if self.idle_tristate && self.config.output == OutputMode::PushPull {
+            sm.set_pindirs(self.pins.clone().map(|pin| (pin, PinDir::Input)));
+        }
+        self.sm = Some(SmState::Stopped(sm));
+        self.publish();
+    }
+
+    // Loads a new edge count into an SM waiting on its trigger edges, the
+    // edges seen so far are forgotten. The table already in the FIFO is left
+    // as it is. `set` only carries 5 bits, larger counts and the per-edge
+    // program, which keeps its count in the ISR, wait for the next arm.
+    // False if the SM wasn't waiting or had moved on by the time it stopped.
+    fn set_edge_count(&mut self, edges: u32) -> bool {
+        let config = self.config;
+        if edges > 32 || config.per_edge || config.trigger_out || !can_accept_trigger(SM::id()) {
+            return false;
+        }
+        let mut sm = match self.sm.take().unwrap() {
+            SmState::Running(sm) => sm.stop(),
+            SmState::Stopped(sm) => {
+                self.sm = Some(SmState::Stopped(sm));
+                return false;
+            }
+        };
+        // The two waits and the jump back, the pulls ahead of them are done
+        let end = self.offset + config.edge_loop_end();
+        let start = end - 2;
+        let waiting = (start..=end).contains(&(sm.instruction_address() as u8));
+        if waiting {
+            for operands in [
+                InstructionOperands::SET {
+                    destination: SetDestination::Y,
+                    data: (edges - 1) as u8,
+                },
+                InstructionOperands::JMP {
+                    condition: JmpCondition::Always,
+                    address: start,
+                },
+            ] {
+                sm.exec_instruction(Instruction {
+                    operands,
+                    delay: 0,
+                    side_set: Some(self.idle),
+                });
+            }
+        }
+        self.sm = Some(SmState::Running(sm.start()));
+        waiting
+    }
+
+    // Points the stopped SM past the edge wait at the first delay. Not for
+    // long delays, their chunk reload is pulled ahead of the wait.
+    fn skip_trigger(&mut self) {
+        let address = self.offset + self.config.edge_loop_end() + 1;
+        if let Some(SmState::Stopped(sm)) = &mut self.sm {
+            sm.exec_instruction(Instruction {
+                operands: InstructionOperands::JMP {
+                    condition: JmpCondition::Always,
+                    address,
+                },
+                delay: 0,
+                side_set: Some(self.idle),
+            });
+        }
+    }
+
+    // Updates is_armed(), can_accept_trigger() and output_pins() after the
+    // SM was started, stopped or given another program
+    fn publish(&self) {
+        let ch = SM::id();
+        let start = self.offset + self.config.edge_loop_start();
+        let end = self.offset + self.config.edge_loop_end();
+        WAIT_PCS[ch].store(start as u16 | (end as u16) << 8, Ordering::Relaxed);
+        let outputs = self.pins.clone().fold(0, |pins, pin| pins | 1 << pin);
+        OUTPUTS[ch].store(outputs, Ordering::Relaxed);
+        let running = matches!(self.sm, Some(SmState::Running(_)));
+        ARMED[ch].store(running, Ordering::Release);
+    }
+
+    // Takes effect right away while disarmed, otherwise on the next disarm
+    fn set_idle_tristate(&mut self, tristate: bool) {
+        self.idle_tristate = tristate;
+        if self.config.output == OutputMode::OpenDrain {
+            return;
+        }
+        if let Some(SmState::Stopped(sm)) = &mut self.sm {
+            let dir = if tristate {
+                PinDir::Input
+            } else {
+                PinDir::Output
+            };
+            sm.set_pindirs(self.pins.clone().map(|pin| (pin, dir)));
+        }
+    }
+
+    // The stopped SM drives its pins at the idle level, read back from PIO's
+    // pad outputs, or their directions in open drain. A running one counts
+    // as driving it.
+    fn drives_idle(&self) -> bool {
+        if !matches!(self.sm, Some(SmState::Stopped(_))) {
+            return true;
+        }
+        // Safety: read-only accesses to PIO0's debug registers
+        let pio = unsafe { &*pac::PIO0::ptr() };
+        let driven = match self.config.output {
+            OutputMode::PushPull => pio.dbg_padout().read().bits(),
+            OutputMode::OpenDrain => pio.dbg_padoe().read().bits(),
+        };
+        self.pins
+            .clone()
+            .enumerate()
+            .all(|(i, pin)| driven >> pin & 1 == (self.idle >> i & 1) as u32)
+    }
+
+    // Swaps in the program for `config`. If it doesn't fit the channel keeps
+    // its previous program and is left disarmed. Only this SM is stopped and
+    // rebuilt, the other channel runs on even when it shares either program.
+    fn reload(
+        &mut self,
+        pio: &mut PIO<PIO0>,
+        programs: &mut ProgramCache,
+        params: &PulseParameter,
+        config: ProgramConfig,
+    ) -> Result<(), InstructionMemoryFull> {
+        self.reclaim_transfer();
+        self.underrun = None;
+        let mut sm = if config == self.config
+            && self.pins == config.pins(params.pin)
+            && self.wait_pin == self.trigger
+        {
+            self.rewind()
+        } else {
+            // The new program goes in next to the old one, which is only
+            // let go once the SM no longer runs it
+            let acquired = programs.acquire(pio, config);
+            let (rx, tx) = (self.rx.take().unwrap(), self.tx.take().unwrap());
+            let (sm, old) = match self.sm.take().unwrap() {
+                SmState::Running(sm) => sm.uninit(rx, tx),
+                SmState::Stopped(sm) => sm.uninit(rx, tx),
+            };
+            let (program, old) = match acquired {
+                Ok(program) => (program, Some(old)),
+                Err(_) => {
+                    // Both don't fit at once, the new one may still fit in
+                    // the old one's words. A shared old program stays.
+                    programs.release(pio, old);
+                    match programs.acquire(pio, config) {
+                        Ok(program) => (program, None),
+                        Err(err) => {
+                            // The previous program's words were just freed
+                            // or it is still shared, so it always fits again
+                            let program = programs.acquire(pio, self.config).unwrap();
+                            let pin = self.pins.start;
+                            let mut sm = self.configure(sm, program, pin, self.config);
+                            force_idle(&mut sm, self.idle);
+                            self.sm = Some(SmState::Stopped(sm));
+                            self.publish();
+                            return Err(err);
+                        }
+                    }
+                }
+            };
+            let sm = self.configure(sm, program, params.pin, config);
+            if let Some(old) = old {
+                programs.release(pio, old);
+            }
+            sm
+        };
+        self.idle_tristate = params.idle_tristate;
+        self.idle = params.idle_side();
+        // The side-set latch is set before the pin directions, so the pins
+        // only ever come out of high impedance driving the idle level
+        force_idle(&mut sm, self.idle);
+        if !self.idle_tristate && config.output == OutputMode::PushPull {
+            sm.set_pindirs(self.pins.clone().map(|pin| (pin, PinDir::Output)));
+        }
+        self.take_tx_stall();
+        self.sm = Some(SmState::Stopped(sm));
+        self.publish();
+        Ok(())
+    }
+
+    // Same program on the same pins: the SM is stopped and sent back to the
+    // start of its program instead of being rebuilt, which is most of the
+    // time an arm takes
+    fn rewind(&mut self) -> StateMachine<(PIO0, SM), Stopped> {
+        let mut sm = match self.sm.take().unwrap() {
+            SmState::Running(sm) => sm.stop(),
+            SmState::Stopped(sm) => sm,
+        };
+        sm.clear_fifos();
+        // Clears the shift counters, so autopull refills the OSR first thing
+        sm.restart();
+        sm.exec_instruction(Instruction {
+            operands: InstructionOperands::JMP {
+                condition: JmpCondition::Always,
+                address: self.offset,
+            },
+            delay: 0,
+            side_set: Some(self.idle),
+        });
+        sm
+    }
+
+    fn configure(
+        &mut self,
+        sm: UninitStateMachine<(PIO0, SM)>,
+        program: InstalledProgram<PIO0>,
+        pin: u8,
+        config: ProgramConfig,
+    ) -> StateMachine<(PIO0, SM), Stopped> {
+        self.config = config;
+        self.offset = program.offset();
+        let (sm, rx, tx) = PIOBuilder::from_installed_program(program)
+            // OnlyTx joins the FIFOs into 8 TX entries. Autopull refills the
+            // OSR as each full word is shifted out, the levels word LSB first
+            .buffers(OnlyTx)
+            .autopull(true)
+            .pull_threshold(32)
+            .out_shift_direction(ShiftDirection::Right)
+            .side_set_pin_base(pin)
+            .out_pins(pin, 1 + config.wide as u8)
+            .in_pin_base(self.trigger)
+            .build(sm);
+        self.pins = config.pins(pin);
+        self.wait_pin = self.trigger;
+        self.tx = Some(tx);
+        self.rx = Some(rx);
+        sm
+    }
+
+    fn start_sm(&mut self) {
+        if let Some(SmState::Stopped(_)) = self.sm {
+            let mut sm = self.take_stopped();
+            self.enable_outputs(&mut sm);
+            self.sm = Some(SmState::Running(sm.start()));
+            self.publish();
+        }
+    }
+
+    fn take_stopped(&mut self) -> StateMachine<(PIO0, SM), Stopped> {
+        match self.sm.take() {
+            Some(SmState::Stopped(sm)) => sm,
+            _ => unreachable!(),
+        }
+    }
+
+    // With an idle tristate the pins are driven from just before the SM
+    // starts: low while waiting for the trigger and the first delay. A rest
+    // level held on them comes off here too.
+    fn enable_outputs(&self, sm: &mut StateMachine<(PIO0, SM), Stopped>) {
+        safestate::release(self.pins.clone().fold(0, |pins, pin| pins | 1 << pin));
+        // Open-drain pin directions belong to the program
+        if self.idle_tristate && self.config.output == OutputMode::PushPull {
+            sm.set_pindirs(self.pins.clone().map(|pin| (pin, PinDir::Output)));
+        }
+    }
+
+    fn start_transfer(&mut self, buf: WordBuffer) {
+        let config =
+            single_buffer::Config::new(self.dma.take().unwrap(), buf, self.tx.take().unwrap());
+        self.transfer = Some(config.start());
+        // The HAL starts channels at normal priority, only the first words
+        // can go out before this
+        set_high_priority(CH::id());
+    }
+
+    // Aborts any transfer in flight and takes back its resources
+    fn reclaim_transfer(&mut self) {
+        if let Some(transfer) = self.transfer.take() {
+            if !transfer.is_done() {
+                abort_dma(CH::id());
+            }
+            let (dma, buf, tx) = transfer.wait();
+            self.dma = Some(dma);
+            self.tx = Some(tx);
+            self.return_buf(buf);
+        }
+        if self.stream.take().is_some() {
+            while let Some(buf) = self.stream_queue.pop() {
+                self.stream_free.push(buf);
+            }
+        }
+    }
+
+    fn return_buf(&mut self, buf: WordBuffer) {
+        if self.stream.is_some() {
+            self.stream_free.push(buf);
+        } else {
+            self.table_buf = Some(buf);
+        }
+    }
+
+    // Moves finished stream blocks back to the free list and starts the next
+    fn service_stream_dma(&mut self) {
+        if self.transfer.as_ref().map_or(false, |t| t.is_done()) {
+            let (dma, buf, tx) = self.transfer.take().unwrap().wait();
+            self.dma = Some(dma);
+            self.tx = Some(tx);
+            if let Some(stream) = self.stream.as_mut() {
+                stream.words_fed += buf.len as u32;
+            }
+            self.return_buf(buf);
+        }
+        if self.transfer.is_none() && !self.stream_queue.is_empty() {
+            let buf = self.stream_queue.remove(0);
+            self.start_transfer(buf);
+        }
+    }
+
+    // Snapshot of the SM and DMA registers, leaves the stall flag set
+    fn debug(&self) -> DebugInfo {
+        // Safety: read-only accesses to this SM's and DMA channel's registers
+        let pio = unsafe { &*pac::PIO0::ptr() };
+        let dma = unsafe { &*pac::DMA::ptr() };
+        let sm_id = SM::id();
+
+        let tx_level = self.tx_level();
+        let tx_stalled = self.tx_stalled();
+        let addr = pio.sm(sm_id).sm_addr().read().bits() as u8;
+        let phase = Phase::from_pc(addr.wrapping_sub(self.offset), self.config);
+        let dma_remaining = self
+            .transfer
+            .as_ref()
+            .map(|_| dma.ch(CH::id() as usize).ch_trans_count().read().bits());
+
+        DebugInfo {
+            running: matches!(self.sm, Some(SmState::Running(_))),
+            ready: can_accept_trigger(SM::id()),
+            tx_level,
+            tx_stalled,
+            pc: addr.wrapping_sub(self.offset),
+            phase,
+            dma_remaining,
+            progress: dma_remaining.and_then(|remaining| self.progress(remaining, tx_level, phase)),
+            underrun: self.underrun,
+        }
+    }
+
+    // Pulse of the running table the SM has pulled the first word of. The
+    // words still in the FIFO are counted out, leaving only the one in the
+    // OSR, so the index is at most a pulse early and is the last pulse once
+    // the table went out.
+    fn progress(&self, remaining: u32, tx_level: u8, phase: Phase) -> Option<Progress> {
+        if self.stream.is_some() || self.config.trigger_out {
+            return None;
+        }
+        let per_pulse = self.config.words_per_pulse();
+        // Rounded up for the one word second pulse of a double pulse
+        let pulses = self
+            .table_len
+            .saturating_sub(self.config.epilogue_words())
+            .div_ceil(per_pulse);
+        let pulled = self.table_len.saturating_sub(remaining + tx_level as u32);
+        // The end word of the split program counts as the last pulse's
+        let pulse = pulled
+            .div_ceil(per_pulse)
+            .saturating_sub(1)
+            .min(pulses.saturating_sub(1));
+        Some(Progress {
+            pulse,
+            pulses,
+            remaining: pulses
+                .saturating_sub(pulse)
+                .saturating_sub((phase != Phase::WaitTrigger) as u32),
+        })
+    }
+
+    fn tx_level(&self) -> u8 {
+        // Safety: read-only access to FLEVEL
+        let pio = unsafe { &*pac::PIO0::ptr() };
+        (pio.flevel().read().bits() >> (8 * SM::id())) as u8 & 0xf
+    }
+
+    fn tx_stalled(&self) -> bool {
+        // Safety: read-only access to FDEBUG
+        let pio = unsafe { &*pac::PIO0::ptr() };
+        pio.fdebug().read().txstall().bits() & (1 << SM::id()) != 0
+    }
+
+    // Reads and clears the sticky TX stall flag of this SM
+    fn take_tx_stall(&self) -> bool {
+        // Safety: FDEBUG flags are write-1-to-clear, only this SM's bit is
+        // touched
+        let pio = unsafe { &*pac::PIO0::ptr() };
+        let mask = 1 << SM::id();
+        let stalled = self.tx_stalled();
+        if stalled {
+            pio.fdebug().write(|w| unsafe { w.txstall().bits(mask) });
+        }
+        stalled
+    }
+}
+
+// Where the program is, from its address relative to the install offset
+// Puts the pins in their idle state from a stopped SM: output latches low
+// and side-set `side`, 0 driving low or, for open drain, releasing the pin
+fn force_idle<SM: StateMachineIndex>(sm: &mut StateMachine<(PIO0, SM), Stopped>, side: u8) {
+    sm.exec_instruction(Instruction {
+        operands: InstructionOperands::MOV {
+            destination: MovDestination::PINS,
+            op: MovOperation::None,
+            source: MovSource::NULL,
+        },
+        delay: 0,
+        side_set: Some(side),
+    });
+}
+
+// X = !0 for the timestamp log program to count down from
+#[cfg(feature = "capture")]
+fn load_counter<SM: StateMachineIndex>(sm: &mut StateMachine<(PIO0, SM), Stopped>) {
+    sm.exec_instruction(Instruction {
+        operands: InstructionOperands::MOV {
+            destination: MovDestination::X,
+            op: MovOperation::Invert,
+            source: MovSource::NULL,
+        },
+        delay: 0,
+        side_set: None,
+    });
+}
+
+// Pulses the channel's trigger input `pin` as seen by PIO low then high with
+// the GPIO input override, leaving the pad itself alone. Refused unless the
+// channel waits for its trigger, every other armed channel on the pin sees
+// it as well.
+fn force_trigger(ch: usize, pin: u8) -> bool {
+    if !can_accept_trigger(ch) {
+        return false;
+    }
+    // Safety: only the trigger pin's input override is changed and restored
+    let io = unsafe { &*pac::IO_BANK0::ptr() };
+    let ctrl = io.gpio(pin as usize).gpio_ctrl();
+    // Normal, or inverted for a falling edge trigger
+    let edge = ctrl.read().inover().variant();
+    // A few cycles per level for the input synchronizer and the `wait`
+    ctrl.modify(|_, w| w.inover().low());
+    cortex_m::asm::delay(8);
+    ctrl.modify(|_, w| w.inover().high());
+    cortex_m::asm::delay(8);
+    ctrl.modify(|_, w| w.inover().variant(edge));
+    true
+}
+
+// Inverts the input of the pins in `falling` with the GPIO input override
+// and returns the rest of `changed` to normal, so the programs' `wait 1 pin`
+// and the rising edge latches see a falling edge trigger's active edge as a
+// rise. Edges the flips latched are cleared.
+fn invert_triggers(changed: u32, falling: u32) {
+    // Safety: only the input overrides of the pins in `changed` are written
+    let io = unsafe { &*pac::IO_BANK0::ptr() };
+    for pin in (0..probe::GPIO_COUNT as usize).filter(|pin| changed & 1 << pin != 0) {
+        io.gpio(pin).gpio_ctrl().modify(|_, w| {
+            if falling & 1 << pin != 0 {
+                w.inover().invert()
+            } else {
+                w.inover().normal()
+            }
+        });
+    }
+    take_trigger_edges(changed);
+}
+
+// Reads and clears the latched rising edges of a bit mask of trigger
+// inputs, forced triggers included. A bit per GPIO that had one.
+fn take_trigger_edges(pins: u32) -> u32 {
+    // Safety: INTR edge bits are write-1-to-clear, only the trigger pins'
+    // rising edge bits are touched
+    let io = unsafe { &*pac::IO_BANK0::ptr() };
+    let mut edges = 0;
+    for pin in (0..probe::GPIO_COUNT as usize).filter(|pin| pins & 1 << pin != 0) {
+        let mask = 1 << (4 * (pin % 8) + 3);
+        let intr = io.intr(pin / 8);
+        if intr.read().bits() & mask != 0 {
+            intr.write(|w| unsafe { w.bits(mask) });
+            edges |= 1 << pin;
+        }
+    }
+    edges
+}
+
+// Stops a DMA channel mid-transfer so its transfer can be waited for
+fn abort_dma(id: u8) {
+    // Safety: CHAN_ABORT only affects the channels whose bits are written
+    unsafe { (*pac::DMA::ptr()).chan_abort().write(|w| w.bits(1 << id)) };
+}
+
+// DMA priority policy: the channels' table and stream transfers are the
+// only high priority ones, so their FIFO refills win arbitration against
+// anything else in flight. Every other DMA user (expert feeds, the timestamp
+// log, STRESS DMA's bulk copy and whatever comes next) stays at normal
+// priority, and never sets this.
+fn set_high_priority(id: u8) {
+    // Safety: through the non-triggering CTRL alias, only the priority bit
+    // of this channel changes
+    let dma = unsafe { &*pac::DMA::ptr() };
+    dma.ch(id as usize)
+        .ch_al1_ctrl()
+        .modify(|_, w| w.high_priority().set_bit());
+}
+
+// Unclaimed by the HAL split, STRESS DMA's bus load
+const BULK_DMA_CH: u8 = 4;
+#[cfg(feature = "selftest")]
+const BULK_DMA_WORDS: u32 = 1 << 20;
+
+#[cfg(feature = "selftest")]
+static mut BULK_WORD: u32 = 0;
+
+// Word to word copies back to back at normal priority, with no DREQ
+#[cfg(feature = "selftest")]
+fn start_bulk_dma() {
+    // Safety: only the unclaimed channel's registers are touched, and it
+    // only reads and writes BULK_WORD
+    let dma = unsafe { &*pac::DMA::ptr() };
+    let word = unsafe { core::ptr::addr_of_mut!(BULK_WORD) } as u32;
+    let ch = dma.ch(BULK_DMA_CH as usize);
+    ch.ch_read_addr().write(|w| unsafe { w.bits(word) });
+    ch.ch_write_addr().write(|w| unsafe { w.bits(word) });
+    ch.ch_trans_count()
+        .write(|w| unsafe { w.bits(BULK_DMA_WORDS) });
+    ch.ch_ctrl_trig().write(|w| unsafe {
+        w.data_size()
+            .size_word()
+            .chain_to()
+            .bits(BULK_DMA_CH)
+            .treq_sel()
+            .permanent()
+            .irq_quiet()
+            .set_bit()
+            .en()
+            .set_bit()
+    });
+}
+
+#[cfg(feature = "selftest")]
+fn bulk_dma_busy() -> bool {
+    // Safety: read-only access to the channel's CTRL
+    let dma = unsafe { &*pac::DMA::ptr() };
+    dma.ch(BULK_DMA_CH as usize)
+        .ch_al1_ctrl()
+        .read()
+        .busy()
+        .bit_is_set()
+}
+
+// Spare state machine running a user supplied program, fed by its own DMA
+// channel. It never touches the channels' state machines or pins.
+struct ExpertHw<SM: StateMachineIndex, CH: ChannelIndex> {
+    uninit: Option<UninitStateMachine<(PIO0, SM)>>,
+    sm: Option<StateMachine<(PIO0, SM), Running>>,
+    rx: Option<Rx<(PIO0, SM)>>,
+    tx: Option<Tx<(PIO0, SM)>>,
+    dma: Option<Channel<CH>>,
+    transfer: Option<Transfer<SM, CH>>,
+    buf: Option<WordBuffer>,
+    pins: Range<u8>,
+    // Length of the loaded program
+    words: usize,
+    // Offset of the timestamp log program while the SM runs it. The DMA
+    // channel is then programmed by tlog and `dma` left unused.
+    capture: Option<u8>,
+    // The SM runs the glitch detector, its DMA channel as with `capture`
+    glitch: bool,
+}
+
+impl<SM: StateMachineIndex, CH: ChannelIndex> ExpertHw<SM, CH> {
+    fn new(sm: UninitStateMachine<(PIO0, SM)>, dma: Channel<CH>, buf: &'static mut [u32]) -> Self {
+        Self {
+            uninit: Some(sm),
+            sm: None,
+            rx: None,
+            tx: None,
+            dma: Some(dma),
+            transfer: None,
+            buf: Some(WordBuffer { words: buf, len: 0 }),
+            pins: 0..0,
+            words: 0,
+            capture: None,
+            glitch: false,
+        }
+    }
+
+    // Replaces any loaded program and starts the SM. `pins` is used as
+    // side-set, set, out and in base.
+    #[cfg(feature = "binary-proto")]
+    fn load(
+        &mut self,
+        pio: &mut PIO<PIO0>,
+        program: &pio::Program<32>,
+        pins: Range<u8>,
+    ) -> Result<(), ExpertError> {
+        if self.dedicated() {
+            return Err(ExpertError::InUse);
+        }
+        self.unload(pio);
+        let installed = pio.install(program).map_err(|_| ExpertError::NoSpace)?;
+        let count = pins.len() as u8;
+        let (sm, rx, tx) = PIOBuilder::from_installed_program(installed)
+            .side_set_pin_base(pins.start)
+            .set_pins(pins.start, count.min(5))
+            .out_pins(pins.start, count)
+            .in_pin_base(pins.start)
+            .build(self.uninit.take().unwrap());
+        safestate::release(pins.clone().fold(0, |pins, pin| pins | 1 << pin));
+        self.sm = Some(sm.start());
+        self.rx = Some(rx);
+        self.tx = Some(tx);
+        self.pins = pins;
+        self.words = program.code.len();
+        Ok(())
+    }
+
+    // Runs the timestamp log on `pin` in place of an expert program,
+    // refused while one is loaded
+    #[cfg(feature = "capture")]
+    fn start_capture(&mut self, pio: &mut PIO<PIO0>, pin: u8) -> Result<(), ExpertError> {
+        if self.capture.is_some() {
+            self.restart_capture();
+            return Ok(());
+        }
+        if self.sm.is_some() || self.transfer.is_some() {
+            return Err(ExpertError::InUse);
+        }
+        let program = tlog::program();
+        let installed = pio.install(&program).map_err(|_| ExpertError::NoSpace)?;
+        let offset = installed.offset();
+        let (mut sm, rx, tx) = PIOBuilder::from_installed_program(installed)
+            .jmp_pin(pin)
+            .in_pin_base(pin)
+            .buffers(OnlyRx)
+            .build(self.uninit.take().unwrap());
+        load_counter(&mut sm);
+        tlog::start_dma(CH::id(), SM::id() as u8);
+        self.sm = Some(sm.start());
+        self.rx = Some(rx);
+        self.tx = Some(tx);
+        self.pins = 0..0;
+        self.words = program.code.len();
+        self.capture = Some(offset);
+        Ok(())
+    }
+
+    // Back to a count of 0 with the ring cleared
+    #[cfg(feature = "capture")]
+    fn restart_capture(&mut self) {
+        let Some(offset) = self.capture else {
+            return;
+        };
+        abort_dma(CH::id());
+        let mut sm = self.sm.take().unwrap().stop();
+        sm.clear_fifos();
+        sm.restart();
+        load_counter(&mut sm);
+        sm.exec_instruction(Instruction {
+            operands: InstructionOperands::JMP {
+                condition: JmpCondition::Always,
+                address: offset,
+            },
+            delay: 0,
+            side_set: None,
+        });
+        tlog::start_dma(CH::id(), SM::id() as u8);
+        self.sm = Some(sm.start());
+    }
+
+    // Waits for `pin` to go high, then samples every GPIO on each of the
+    // following cycles until the RX FIFO is full. Refused while a program
+    // is loaded.
+    #[cfg(feature = "selftest")]
+    fn start_sampler(&mut self, pio: &mut PIO<PIO0>, pin: u8) -> Result<(), ExpertError> {
+        if self.sm.is_some() || self.transfer.is_some() {
+            return Err(ExpertError::InUse);
+        }
+        let mut asm: Assembler<32> = Assembler::new();
+        let mut wrap_target = asm.label();
+        let mut wrap_source = asm.label();
+        asm.wait(1, WaitSource::GPIO, pin, false);
+        asm.bind(&mut wrap_target);
+        asm.r#in(InSource::PINS, 32);
+        asm.bind(&mut wrap_source);
+        let program = asm.assemble_with_wrap(wrap_source, wrap_target);
+        let installed = pio.install(&program).map_err(|_| ExpertError::NoSpace)?;
+        let (sm, rx, tx) = PIOBuilder::from_installed_program(installed)
+            .in_pin_base(0)
+            .autopush(true)
+            .push_threshold(32)
+            .buffers(OnlyRx)
+            .build(self.uninit.take().unwrap());
+        self.sm = Some(sm.start());
+        self.rx = Some(rx);
+        self.tx = Some(tx);
+        self.pins = 0..0;
+        self.words = program.code.len();
+        Ok(())
+    }
+
+    // The samples taken so far, a bit per GPIO, then unloads the sampler
+    #[cfg(feature = "selftest")]
+    fn stop_sampler(&mut self, pio: &mut PIO<PIO0>) -> ArrayVec<u32, SKEW_SAMPLES> {
+        let mut samples = ArrayVec::new();
+        if let Some(rx) = self.rx.as_mut() {
+            while let Some(sample) = rx.read() {
+                if samples.try_push(sample).is_err() {
+                    break;
+                }
+            }
+        }
+        self.unload(pio);
+        samples
+    }
+
+    #[cfg(feature = "capture")]
+    fn stop_capture(&mut self, pio: &mut PIO<PIO0>) {
+        if self.capture.take().is_some() {
+            abort_dma(CH::id());
+            self.unload(pio);
+        }
+    }
+
+    // Glitch widths counted in place of an expert program, refused while
+    // one is loaded. The detector running already is restarted with the
+    // new `loops`, see glitch.
+    #[cfg(feature = "capture")]
+    fn start_glitch(
+        &mut self,
+        pio: &mut PIO<PIO0>,
+        pin: u8,
+        loops: u32,
+    ) -> Result<(), ExpertError> {
+        self.stop_glitch(pio);
+        if self.sm.is_some() || self.transfer.is_some() {
+            return Err(ExpertError::InUse);
+        }
+        let program = glitch::program();
+        let installed = pio.install(&program).map_err(|_| ExpertError::NoSpace)?;
+        let (sm, rx, mut tx) = PIOBuilder::from_installed_program(installed)
+            .jmp_pin(pin)
+            .in_pin_base(pin)
+            .build(self.uninit.take().unwrap());
+        // Pulled by the program's first instruction
+        tx.write(loops);
+        glitch::start_dma(CH::id(), SM::id() as u8);
+        self.sm = Some(sm.start());
+        self.rx = Some(rx);
+        self.tx = Some(tx);
+        self.pins = 0..0;
+        self.words = program.code.len();
+        self.glitch = true;
+        Ok(())
+    }
+
+    // Back to no widths, the SM carries on
+    #[cfg(feature = "capture")]
+    fn restart_glitch(&mut self) {
+        if self.glitch {
+            abort_dma(CH::id());
+            glitch::start_dma(CH::id(), SM::id() as u8);
+        }
+    }
+
+    #[cfg(feature = "capture")]
+    fn stop_glitch(&mut self, pio: &mut PIO<PIO0>) {
+        if self.glitch {
+            self.glitch = false;
+            abort_dma(CH::id());
+            self.unload(pio);
+        }
+    }
+
+    // The timestamp log or the glitch detector, which unload() leaves alone
+    fn dedicated(&self) -> bool {
+        self.capture.is_some() || self.glitch
+    }
+
+    // The SM and its DMA channel go together
+    fn role(&self) -> Role {
+        if self.capture.is_some() {
+            Role::Tlog
+        } else if self.glitch {
+            Role::Glitch
+        } else if self.sm.is_some() || self.transfer.is_some() {
+            Role::Expert
+        } else {
+            Role::Free
+        }
+    }
+
+    // Stops the SM, releases its pins and frees its instruction memory.
+    // Leaves a running timestamp log or glitch detector alone.
+    fn unload(&mut self, pio: &mut PIO<PIO0>) {
+        if self.dedicated() {
+            return;
+        }
+        self.reclaim_transfer(true);
+        if let Some(sm) = self.sm.take() {
+            let mut sm = sm.stop();
+            sm.set_pindirs(self.pins.clone().map(|pin| (pin, PinDir::Input)));
+            let (sm, program) = sm.uninit(self.rx.take().unwrap(), self.tx.take().unwrap());
+            pio.uninstall(program);
+            self.uninit = Some(sm);
+            self.words = 0;
+        }
+    }
+
+    #[cfg(feature = "binary-proto")]
+    fn feed(&mut self, words: &[u32]) -> Result<(), ExpertError> {
+        if self.dedicated() {
+            return Err(ExpertError::InUse);
+        }
+        if self.sm.is_none() {
+            return Err(ExpertError::NotLoaded);
+        }
+        if words.len() > EXPERT_FEED_LEN {
+            return Err(ExpertError::FeedTooLarge);
+        }
+        if self.transfer.as_ref().map_or(false, |t| !t.is_done()) {
+            return Err(ExpertError::Busy);
+        }
+        self.reclaim_transfer(false);
+        let mut buf = self.buf.take().unwrap();
+        buf.words[..words.len()].copy_from_slice(words);
+        buf.len = words.len();
+        let config =
+            single_buffer::Config::new(self.dma.take().unwrap(), buf, self.tx.take().unwrap());
+        self.transfer = Some(config.start());
+        Ok(())
+    }
+
+    fn reclaim_transfer(&mut self, abort: bool) {
+        if let Some(transfer) = self.transfer.take() {
+            if abort && !transfer.is_done() {
+                abort_dma(CH::id());
+            }
+            let (dma, buf, tx) = transfer.wait();
+            self.dma = Some(dma);
+            self.buf = Some(buf);
+            self.tx = Some(tx);
+        }
+    }
+}
+
+// Runs $body with $hw bound to the hardware of channel $ch
+macro_rules! with_hw {
+    ($self:ident, $ch:expr, $hw:ident => $body:expr) => {
+        match $ch {
+            0 => {
+                let $hw = &mut $self.hw0;
+                $body
+            }
+            _ => {
+                let $hw = &mut $self.hw1;
+                $body
+            }
+        }
+    };
+}
+
+pub struct PulseGenerator {
+    pio: PIO<PIO0>,
+    programs: ProgramCache,
+    hw0: ChannelHw<SM0, CH0>,
+    hw1: ChannelHw<SM1, CH1>,
+    params: [PulseParameter; NUM_CHANNELS],
+    // Pending configuration edited between STAGE and APPLY
+    staged: Option<[PulseParameter; NUM_CHANNELS]>,
+    // Raw program uploads are refused until EXPERT ON
+    expert_enabled: bool,
+    expert2: ExpertHw<SM2, CH2>,
+    expert3: ExpertHw<SM3, CH3>,
+    sys_hz: u32,
+    pins: BoardPins,
+    rounding: Rounding,
+    // A bit per GPIO triggering on its falling edge, see set_trigger_edge()
+    falling: u32,
+    // Short gaps are merged away on arming rather than stretched, see
+    // PulseParameter::coalesce()
+    coalesce: bool,
+    // What the channel's last arm merged
+    coalesced: [Merged; NUM_CHANNELS],
+    groups: ArrayVec<Group, NUM_CHANNELS>,
+    retrigger: [Retrigger; NUM_CHANNELS],
+    internal: Option<Internal>,
+    // Bit per channel left out of arming and validation, see set_enabled()
+    disabled: u32,
+    // Arming refuses a channel whose trigger input is already high
+    refuse_stuck_trigger: bool,
+    arm_guard: &'static dyn ArmGuard,
+    // See request_arm()
+    arm_queue: ArmQueue,
+    // Refusals of re-arms from service() not reported yet
+    guard_refused: [Option<GuardError>; NUM_CHANNELS],
+    // Cycles added to each channel's first delay, see set_calibration()
+    calibration: [i32; NUM_CHANNELS],
+    run_stats: [RunStats; NUM_CHANNELS],
+    run_totals: [RunTotals; NUM_CHANNELS],
+    run_track: [RunTrack; NUM_CHANNELS],
+    // Phase cycles of single channel arms, empty without the perf feature
+    arm_perf: ArmPerf,
+    // Tables queued with NEXT for when the running one went out
+    next: [Option<Pairs>; NUM_CHANNELS],
+    // A queued table failed to take over, until the next arm or NEXT
+    next_failed: [bool; NUM_CHANNELS],
+    // Running in place of the table until TESTPAT OFF or the next arm
+    test_pattern: [Option<TestPattern>; NUM_CHANNELS],
+    // Timer ticks (us) when the timestamp log last restarted
+    log_restarted_at: u64,
+    // While the glitch detector runs on SM2
+    #[cfg(feature = "capture")]
+    glitches: Option<Glitches>,
+}
+
+impl PulseGenerator {
+    // Fails if the channels' initial program doesn't fit, which a build
+    // with a larger program variant could run into
+    pub fn new(
+        pio: PIO0,
+        dma: DMA,
+        resets: &mut RESETS,
+        sys_hz: u32,
+        pins: BoardPins,
+    ) -> Result<Self, InstructionMemoryFull> {
+        let (mut pio, sm0, sm1, sm2, sm3) = pio.split(resets);
+        let dma = dma.split(resets);
+
+        let tables: &'static mut Tables =
+            singleton!(: Tables = [[0; DMA_BUF_LEN]; NUM_CHANNELS]).unwrap();
+        let blocks: &'static mut StreamBuffers = singleton!(
+            : StreamBuffers = [[[0; 2 * STREAM_BLOCK_PAIRS]; STREAM_BLOCKS]; NUM_CHANNELS]
+        )
+        .unwrap();
+        let [table0, table1] = tables;
+        let [blocks0, blocks1] = blocks;
+        let [feed2, feed3] = singleton!(: ExpertFeeds = [[0; EXPERT_FEED_LEN]; 2]).unwrap();
+
+        let mut programs = ProgramCache {
+            entries: ArrayVec::new(),
+            expert_words: 0,
+        };
+        let [pin0, pin1] = pins.default_outputs;
+        Ok(Self {
+            hw0: ChannelHw::new(&mut pio, &mut programs, pin0, sm0, dma.ch0, table0, blocks0)?,
+            hw1: ChannelHw::new(&mut pio, &mut programs, pin1, sm1, dma.ch1, table1, blocks1)?,
+            pio,
+            programs,
+            params: pins.default_outputs.map(PulseParameter::new),
+            pins,
+            rounding: Rounding::Nearest,
+            falling: 0,
+            coalesce: false,
+            coalesced: Default::default(),
+            groups: ArrayVec::new(),
+            retrigger: [Retrigger::default(); NUM_CHANNELS],
+            internal: None,
+            disabled: 0,
+            refuse_stuck_trigger: false,
+            arm_guard: &AllowAll,
+            arm_queue: ArmQueue::default(),
+            guard_refused: [None; NUM_CHANNELS],
+            calibration: [0; NUM_CHANNELS],
+            run_stats: [RunStats::default(); NUM_CHANNELS],
+            run_totals: [RunTotals::default(); NUM_CHANNELS],
+            run_track: [RunTrack::default(); NUM_CHANNELS],
+            arm_perf: ArmPerf::new(),
+            next: Default::default(),
+            next_failed: [false; NUM_CHANNELS],
+            test_pattern: [None; NUM_CHANNELS],
+            log_restarted_at: 0,
+            #[cfg(feature = "capture")]
+            glitches: None,
+            staged: None,
+            expert_enabled: false,
+            expert2: ExpertHw::new(sm2, dma.ch2, feed2),
+            expert3: ExpertHw::new(sm3, dma.ch3, feed3),
+            sys_hz,
+        })
+    }
+
+    // A mirrored channel arms with its source or mirrors, see arm_group()
+    pub fn arm(&mut self, ch: usize) -> Result<(), PulseError> {
+        let linked = linked(&self.params, ch);
+        if linked != 1 << ch {
+            return self.arm_group(linked);
+        }
+        self.arm_one(ch)
+    }
+
+    // Queues an arm for service_arm(), the pipelined counterpart of arm(),
+    // arm_all() and arm_group(). Nothing is checked before it is carried
+    // out.
+    pub fn request_arm(&mut self, request: ArmRequest) -> Result<(), ArmQueueFull> {
+        self.arm_queue.push(request)
+    }
+
+    // Carries out the oldest queued arm, called from the main loop between
+    // its USB polls. Arms as arm(), arm_all() or arm_group() would at that
+    // point, with the configuration as it is then.
+    pub fn service_arm(&mut self) -> Option<(ArmRequest, Result<(), PulseError>)> {
+        let (request, cancelled) = self.arm_queue.pop()?;
+        let result = match (cancelled, request) {
+            (Some(ch), _) => Err(PulseError::Cancelled { ch }),
+            (None, ArmRequest::Channel(ch)) => self.arm(ch),
+            (None, ArmRequest::All) => self.arm_all(),
+            (None, ArmRequest::Group(members)) => self.arm_group(members),
+        };
+        Some((request, result))
+    }
+
+    // Drops every queued arm, service_arm() reports them as cancelled
+    pub fn cancel_arms(&mut self) {
+        self.cancel_queued((1 << NUM_CHANNELS) - 1);
+    }
+
+    fn cancel_queued(&mut self, channels: u32) {
+        let params = &self.params;
+        // Channels a queued arm would arm, with their sources and mirrors
+        self.arm_queue.cancel(channels, |request| match request {
+            ArmRequest::Channel(ch) => linked(params, ch),
+            ArmRequest::All => (1 << NUM_CHANNELS) - 1,
+            ArmRequest::Group(members) => (0..NUM_CHANNELS)
+                .filter(|ch| members & 1 << ch != 0)
+                .fold(0, |mask, ch| mask | linked(params, ch)),
+        });
+    }
+
+    fn arm_one(&mut self, ch: usize) -> Result<(), PulseError> {
+        self.coalesce_table(ch);
+        self.check_trigger(ch)?;
+        self.rearm(ch)?;
+        self.run_stats[ch] = RunStats::default();
+        self.next_failed[ch] = false;
+        Ok(())
+    }
+
+    // With COALESCE ON the live table loses its short gaps as a host arm
+    // takes it, re-arms from service() find it merged already. A mirror
+    // follows its source's merge.
+    fn coalesce_table(&mut self, ch: usize) {
+        if !self.coalesce || self.params[ch].mirror.is_some() {
+            self.coalesced[ch].clear();
+            return;
+        }
+        self.coalesced[ch] = self.params[ch].coalesce(self.sys_hz);
+        sync_mirrors(&mut self.params);
+    }
+
+    pub fn coalesce(&self) -> bool {
+        self.coalesce
+    }
+
+    // Off, the default, a delay below the program's minimum is stretched to
+    // it as ACHIEVED? shows
+    pub fn set_coalesce(&mut self, enabled: bool) {
+        self.coalesce = enabled;
+    }
+
+    // Pulses merged by the channel's last arm, handed out once
+    pub fn take_coalesced(&mut self, ch: usize) -> Merged {
+        core::mem::take(&mut self.coalesced[ch])
+    }
+
+    // Arms the channel for another run, keeping its run statistics
+    fn rearm(&mut self, ch: usize) -> Result<(), PulseError> {
+        info!("arm {}", ch);
+        self.test_pattern[ch] = None;
+        if self.disabled & 1 << ch != 0 {
+            return Err(PulseError::Disabled { ch });
+        }
+        if self.params[ch].is_empty() {
+            return Err(PulseError::EmptySequence { ch });
+        }
+        self.guard(ch)?;
+        self.place_first_delay(ch)?;
+        self.select_trigger(ch);
+        let params = &self.params[ch];
+        let mut stopwatch = Stopwatch::start();
+        with_hw!(self, ch, hw => {
+            // Returns with the first pulse in the FIFO
+            hw.load_table(&mut self.pio, &mut self.programs, params, false, &mut stopwatch)?;
+            hw.start_sm();
+        });
+        stopwatch.lap(ArmPhase::Start);
+        self.arm_perf.record(ch, &stopwatch);
+        let now = timer_now();
+        self.start_run(ch, now);
+        #[cfg(feature = "capture")]
+        {
+            self.expert3.restart_capture();
+            self.log_restarted_at = now;
+        }
+        Ok(())
+    }
+
+    // Emits a single pulse of `width` on the channel's pin right away, with
+    // the channel's output settings but none of its table, marker or
+    // trigger settings, which are left untouched. Refused while the channel
+    // is armed and its table hasn't gone out yet.
+    pub fn ping(&mut self, ch: usize, width: Achieved) -> Result<(), PulseError> {
+        if self.disabled & 1 << ch != 0 {
+            return Err(PulseError::Disabled { ch });
+        }
+        let info = self.debug(ch);
+        if info.running && !info.emitted() {
+            return Err(PulseError::Armed { ch });
+        }
+        let params = &self.params[ch];
+        let mut scratch = PulseParameter::new(params.pin);
+        scratch.output = params.output;
+        scratch.idle_tristate = params.idle_tristate;
+        scratch.gap = params.gap;
+        scratch.idle = params.idle;
+        scratch.delay.push(1);
+        scratch.width.push(width.cycles as u32);
+        with_hw!(self, ch, hw => {
+            hw.load_table(&mut self.pio, &mut self.programs, &scratch, true, &mut Stopwatch::start())?;
+            hw.start_sm();
+        });
+        // Not a run of the table
+        self.run_track[ch].done = true;
+        Ok(())
+    }
+
+    // Runs the pattern on the channel right away and again each time it went
+    // out, with the channel's output settings as ping() has them and nothing
+    // else of its configuration, which is left untouched. None stops it and
+    // leaves the channel disarmed. Refused while the channel is armed or an
+    // internal tick member, arming it stops the pattern.
+    pub fn set_test_pattern(
+        &mut self,
+        ch: usize,
+        pattern: Option<TestPattern>,
+    ) -> Result<(), PulseError> {
+        let Some(pattern) = pattern else {
+            if self.test_pattern[ch].take().is_some() {
+                with_hw!(self, ch, hw => hw.disarm());
+            }
+            return Ok(());
+        };
+        if self.disabled & 1 << ch != 0 {
+            return Err(PulseError::Disabled { ch });
+        }
+        let info = self.debug(ch);
+        let member = self
+            .internal
+            .is_some_and(|internal| internal.members & 1 << ch != 0);
+        if self.test_pattern[ch].is_none() && (info.running && !info.emitted() || member) {
+            return Err(PulseError::Armed { ch });
+        }
+        info!("test pattern {} on {}", pattern.as_str(), ch);
+        self.test_pattern[ch] = Some(pattern);
+        self.run_test_pattern(ch, pattern)
+    }
+
+    // Shortest width and gap in cycles of the channel's output settings
+    // without a table's, TESTPAT FAST runs these and CAP? reports them
+    pub fn fastest(&self, ch: usize) -> (Cycles, Cycles) {
+        let config = self.test_scratch(ch).program_config();
+        (Cycles(config.min_width()), Cycles(config.min_gap()))
+    }
+
+    // The parameters ping() runs with, no table yet
+    fn test_scratch(&self, ch: usize) -> PulseParameter {
+        let params = &self.params[ch];
+        let mut scratch = PulseParameter::new(params.pin);
+        scratch.output = params.output;
+        scratch.idle_tristate = params.idle_tristate;
+        scratch.gap = params.gap;
+        scratch.idle = params.idle;
+        scratch
+    }
+
+    fn run_test_pattern(&mut self, ch: usize, pattern: TestPattern) -> Result<(), PulseError> {
+        let mut scratch = self.test_scratch(ch);
+        let config = scratch.program_config();
+        let (Cycles(min_width), Cycles(min_gap)) = self.fastest(ch);
+        // As delays, the gap after each falling edge comes on top
+        let pairs = match pattern {
+            TestPattern::Fast => [(config.min_next_delay() as u64, min_width)]
+                .into_iter()
+                .cycle()
+                .take(NUM_PULSES_MAX)
+                .collect(),
+            TestPattern::Staircase => (0..STAIRCASE_STEPS)
+                .map(|step| {
+                    let width = (1u32 << step).max(min_width);
+                    (width.max(min_gap) as u64 - PULSE_GAP_CYCLES as u64, width)
+                })
+                .collect(),
+            TestPattern::Reference => {
+                let width = (self.sys_hz / 1_000_000 * REFERENCE_WIDTH_US).max(min_width);
+                let period = self.sys_hz / REFERENCE_HZ;
+                let delay = period.saturating_sub(width).max(min_gap) - PULSE_GAP_CYCLES;
+                [(delay as u64, width)]
+                    .into_iter()
+                    .cycle()
+                    .take(NUM_PULSES_MAX)
+                    .collect::<ArrayVec<_, NUM_PULSES_MAX>>()
+            }
+        };
+        for (i, (delay, width)) in pairs.into_iter().enumerate() {
+            // The table starts right away, the first delay is only there
+            // for the program
+            let delay = if i == 0 {
+                config.min_delay() as u64
+            } else {
+                delay
+            };
+            scratch.delay.push(delay);
+            scratch.width.push(width);
+        }
+        with_hw!(self, ch, hw => {
+            hw.load_table(&mut self.pio, &mut self.programs, &scratch, true, &mut Stopwatch::start())?;
+            hw.start_sm();
+        });
+        // Not a run of the table
+        self.run_track[ch].done = true;
+        Ok(())
+    }
+
+    // Starts a test pattern again once it went out. A failure leaves the
+    // channel disarmed.
+    fn service_test_patterns(&mut self) {
+        for ch in 0..NUM_CHANNELS {
+            let Some(pattern) = self.test_pattern[ch] else {
+                continue;
+            };
+            if self.debug(ch).emitted() && self.run_test_pattern(ch, pattern).is_err() {
+                self.test_pattern[ch] = None;
+                with_hw!(self, ch, hw => hw.disarm());
+            }
+        }
+    }
+
+    // Arms every enabled channel and starts their state machines on the same
+    // cycle
+    pub fn arm_all(&mut self) -> Result<(), PulseError> {
+        info!("arm all");
+        if self.disabled != 0 {
+            return self.arm_group((1 << NUM_CHANNELS) - 1);
+        }
+        for ch in 0..NUM_CHANNELS {
+            self.coalesce_table(ch);
+            self.check_trigger(ch)?;
+        }
+        self.rearm_all()?;
+        for ch in 0..NUM_CHANNELS {
+            self.run_stats[ch] = RunStats::default();
+            self.next_failed[ch] = false;
+        }
+        Ok(())
+    }
+
+    // Arms every channel for another run, keeping their run statistics, and
+    // starts their state machines on the same cycle
+    fn rearm_all(&mut self) -> Result<(), PulseError> {
+        self.test_pattern = [None; NUM_CHANNELS];
+        if let Some(ch) = self.params.iter().position(|p| p.is_empty()) {
+            return Err(PulseError::EmptySequence { ch });
+        }
+        for ch in 0..NUM_CHANNELS {
+            self.guard(ch)?;
+        }
+        for ch in 0..NUM_CHANNELS {
+            self.place_first_delay(ch)?;
+            self.select_trigger(ch);
+        }
+        // Only single channel arms are timed for PERF?
+        let mut stopwatch = Stopwatch::start();
+        self.hw0.load_table(
+            &mut self.pio,
+            &mut self.programs,
+            &self.params[0],
+            false,
+            &mut stopwatch,
+        )?;
+        self.hw1.load_table(
+            &mut self.pio,
+            &mut self.programs,
+            &self.params[1],
+            false,
+            &mut stopwatch,
+        )?;
+        let mut sm0 = self.hw0.take_stopped();
+        let mut sm1 = self.hw1.take_stopped();
+        self.hw0.enable_outputs(&mut sm0);
+        self.hw1.enable_outputs(&mut sm1);
+        let (sm0, sm1) = sm0.with(sm1).start().free();
+        self.hw0.sm = Some(SmState::Running(sm0));
+        self.hw1.sm = Some(SmState::Running(sm1));
+        self.hw0.publish();
+        self.hw1.publish();
+        let now = timer_now();
+        for ch in 0..NUM_CHANNELS {
+            self.start_run(ch, now);
+        }
+        #[cfg(feature = "capture")]
+        {
+            self.expert3.restart_capture();
+            self.log_restarted_at = now;
+        }
+        Ok(())
+    }
+
+    // Follows the table just armed on the channel
+    fn start_run(&mut self, ch: usize, now: u64) {
+        let high_cycles = self.achieved(ch).map(|(_, width)| width.cycles).sum();
+        self.run_track[ch] = RunTrack {
+            armed_at: now,
+            started_at: None,
+            done: false,
+            pulses: self.params[ch].delay.len() as u32,
+            high_cycles,
+            ended_at: None,
+        };
+    }
+
+    // The channel's re-arm guard is still running. It counts from when the
+    // main loop saw the table go out, so up to a loop pass late, and is
+    // waited out by service() rather than spun on.
+    fn guarding(&self, ch: usize, now: u64) -> bool {
+        let guard_us = cycles_to_ps(self.params[ch].rearm_guard, self.sys_hz).div_ceil(PS_PER_US);
+        self.run_track[ch]
+            .ended_at
+            .is_some_and(|at| now.saturating_sub(at) < guard_us)
+    }
+
+    // Counts a table run once the main loop sees it went out whole
+    fn service_runs(&mut self, now: u64) {
+        for ch in 0..NUM_CHANNELS {
+            let info = self.debug(ch);
+            if self.run_track[ch].done || !info.running || info.ready {
+                continue;
+            }
+            if !info.emitted() {
+                self.run_track[ch].started_at.get_or_insert(now);
+                continue;
+            }
+            let logged = self.logged_trigger(ch);
+            debugpin::pulse(debugpin::END);
+            let track = &mut self.run_track[ch];
+            track.done = true;
+            track.ended_at = Some(now);
+            let stats = &mut self.run_stats[ch];
+            stats.runs += 1;
+            stats.pulses += track.pulses as u64;
+            stats.high_cycles += track.high_cycles;
+            stats.triggered_at = logged.or(track.started_at);
+            stats.last_us = stats.triggered_at.map(|at| now.saturating_sub(at));
+            stats.logged = logged.is_some();
+            let totals = &mut self.run_totals[ch];
+            totals.runs += 1;
+            totals.high_cycles += track.high_cycles;
+        }
+    }
+
+    // Timer ticks (us) of the edge that triggered the channel's run, from
+    // the timestamp log. Only when the log restarted with this arm, the
+    // channel is on the trigger input and the edge is still in the ring.
+    #[cfg(feature = "capture")]
+    fn logged_trigger(&self, ch: usize) -> Option<u64> {
+        let track = &self.run_track[ch];
+        if self.wait_pin(ch) != TRIGGER_PIN || track.armed_at != self.log_restarted_at {
+            return None;
+        }
+        let (total, cycles) = self.tlog()?;
+        if total as usize > TLOG_LEN {
+            return None;
+        }
+        let edge = cycles.get(self.params[ch].trigger_divider as usize - 1)?;
+        Some(track.armed_at + edge * 1_000_000 / self.sys_hz as u64)
+    }
+
+    #[cfg(not(feature = "capture"))]
+    fn logged_trigger(&self, _ch: usize) -> Option<u64> {
+        None
+    }
+
+    pub fn run_stats(&self, ch: usize) -> RunStats {
+        self.run_stats[ch]
+    }
+
+    pub fn run_totals(&self, ch: usize) -> RunTotals {
+        self.run_totals[ch]
+    }
+
+    // The run statistics once the table armed last went out whole, None
+    // while it still waits for its trigger or runs. A channel that was
+    // disarmed, or never armed, has nothing left to wait for and is done
+    // with the statistics it has.
+    pub fn run_done(&mut self, ch: usize) -> Option<RunStats> {
+        self.service_runs(timer_now());
+        if self.run_track[ch].done || !is_armed(ch) {
+            return Some(self.run_stats[ch]);
+        }
+        None
+    }
+
+    // Waits up to `timeout_us` for run_done(). Nothing else in the main loop
+    // runs meanwhile, USB included, so it is for waits bounded by a short
+    // table. Never from an interrupt handler, which would hold off the
+    // timer and the very interrupts a run may need.
+    pub fn wait_done(&mut self, ch: usize, timeout_us: u32) -> Result<RunStats, Timeout> {
+        debug_assert!(
+            SCB::vect_active() == VectActive::ThreadMode,
+            "wait_done() in an interrupt handler"
+        );
+        let start = timer_now();
+        loop {
+            if let Some(stats) = self.run_done(ch) {
+                return Ok(stats);
+            }
+            if timer_now() - start >= timeout_us as u64 {
+                return Err(Timeout);
+            }
+            cortex_m::asm::delay(WAIT_DONE_POLL_CYCLES);
+        }
+    }
+
+    pub fn poll_status(&self, ch: usize) -> PollStatus {
+        let stats = &self.run_stats[ch];
+        let underrun = match ch {
+            0 => self.hw0.underrun,
+            _ => self.hw1.underrun,
+        };
+        PollStatus {
+            disabled: self.disabled & 1 << ch != 0,
+            running: is_armed(ch),
+            pulses: stats.pulses,
+            triggered_at: stats.triggered_at,
+            error: underrun.is_some() || self.next_failed[ch],
+        }
+    }
+
+    // Arms the enabled channels of a member mask and the channels mirroring
+    // or mirrored by them, several of them start their state machines on
+    // the same cycle
+    pub fn arm_group(&mut self, members: u32) -> Result<(), PulseError> {
+        let members = self.linked_mask(members);
+        match members & !self.disabled {
+            0b01 => self.arm_one(0),
+            0b10 => self.arm_one(1),
+            0b11 => self.arm_all(),
+            _ => Ok(()),
+        }
+    }
+
+    // Arms the channel again with its source or mirrors, as arm_group()
+    // would but keeping the run statistics. Used for re-arms from
+    // service(), the mirrors follow their source's there.
+    fn rearm_linked(&mut self, ch: usize) -> Result<(), PulseError> {
+        match linked(&self.params, ch) & !self.disabled {
+            0b11 => self.rearm_all(),
+            _ => self.rearm(ch),
+        }
+    }
+
+    // The members with their sources and mirrors
+    fn linked_mask(&self, members: u32) -> u32 {
+        (0..NUM_CHANNELS)
+            .filter(|ch| members & 1 << ch != 0)
+            .fold(0, |mask, ch| mask | linked(&self.params, ch))
+    }
+
+    // A mirror whose source is enabled, armed by the source's re-arms
+    fn follows(&self, ch: usize) -> bool {
+        self.params[ch]
+            .mirror
+            .is_some_and(|mirror| self.disabled & 1 << mirror.source == 0)
+    }
+
+    pub fn arm_perf(&self) -> &ArmPerf {
+        &self.arm_perf
+    }
+
+    // Member mask of the channels with at least one pulse
+    pub fn configured(&self) -> u32 {
+        (0..NUM_CHANNELS)
+            .filter(|&ch| !self.params[ch].is_empty())
+            .fold(0, |mask, ch| mask | 1 << ch)
+    }
+
+    // Everything arming the enabled channels would refuse, found without
+    // changing anything or touching the hardware beyond reading the trigger
+    // inputs: the violations, then what arm() itself checks. Leaves them in
+    // `problems`, which it clears first, and returns the longest enabled
+    // table in cycles. Program memory is only checked as a whole, expert
+    // programs can still leave it in gaps too small.
+    pub fn check(&self, problems: &mut Problems) -> u64 {
+        problems.clear();
+        violations(&self.params, self.pins.pio, self.disabled, |violation| {
+            problems.push(Problem::Violation(violation))
+        });
+        let enabled = (0..NUM_CHANNELS).filter(|ch| self.disabled & 1 << ch == 0);
+        let mut longest = 0;
+        for ch in enabled {
+            if self.params[ch].is_empty() {
+                problems.push(Problem::Pulse(PulseError::EmptySequence { ch }));
+                continue;
+            }
+            if let Err(err) = self.check_trigger(ch) {
+                problems.push(Problem::Pulse(err));
+            }
+            if let Err(err) = self.placed_first_delay(ch) {
+                problems.push(Problem::Pulse(err));
+            }
+            let cycles = self
+                .achieved(ch)
+                .map(|(delay, width)| delay.cycles + width.cycles)
+                .sum::<u64>()
+                + self.params[ch].pre_delay;
+            longest = longest.max(cycles);
+        }
+        let words = program_words(&self.params, self.disabled);
+        let free = INSTRUCTION_MEMORY - self.programs.expert_words;
+        if words <= INSTRUCTION_MEMORY && words > free {
+            let full = InstructionMemoryFull {
+                words,
+                free,
+                installed: self.programs.installed(),
+            };
+            problems.push(Problem::Pulse(full.into()));
+        }
+        longest
+    }
+
+    // A disabled channel is disarmed and left out of group arms, ARM ALL,
+    // AUTOARM and validation, and stays disabled through edits, APPLY and
+    // *RST. Enabling checks its configuration as an edit would.
+    pub fn set_enabled(&mut self, ch: usize, enabled: bool) -> Result<(), Violation> {
+        if !enabled {
+            with_hw!(self, ch, hw => hw.disarm());
+            self.disabled |= 1 << ch;
+            return Ok(());
+        }
+        let disabled = self.disabled & !(1 << ch);
+        validate_allowing_unpaired(&self.params, self.pins.pio, disabled)
+            .map_err(|invalid| invalid.first)?;
+        self.disabled = disabled;
+        Ok(())
+    }
+
+    pub fn disabled(&self) -> u32 {
+        self.disabled
+    }
+
+    // Level of the input the channel waits on. The programs wait for it to
+    // be low before looking for the rising edge, so a trigger already high
+    // at arming isn't an edge: the first one comes only after it has been
+    // low again, which a line stuck high never is.
+    pub fn trigger_high(&self, ch: usize) -> bool {
+        probe::read(self.trigger_input(ch))
+    }
+
+    // Selects the GPIO the channel waits on for its trigger, used from the
+    // next arm. The SM is rebuilt for it then, as for a new output pin.
+    // Channels can share one, an internal member waits on the internal pin
+    // meanwhile.
+    pub fn set_trigger_pin(&mut self, ch: usize, pin: u8) -> Result<(), Violation> {
+        self.set_trigger(ch, Trigger::Pin(pin))
+    }
+
+    // A loopback is checked against the source's configuration as it is
+    // now and again on every edit, see Violation::LoopbackSource
+    pub fn set_trigger(&mut self, ch: usize, trigger: Trigger) -> Result<(), Violation> {
+        self.edit_checked(ch, |p| match trigger {
+            Trigger::Pin(pin) => {
+                p.trigger_pin = pin;
+                p.trigger_from = None;
+            }
+            Trigger::InternalFromChannel(source) => p.trigger_from = Some(source),
+        })
+    }
+
+    pub fn trigger(&self, ch: usize) -> Trigger {
+        let p = &self.params[ch];
+        match p.trigger_from {
+            Some(source) => Trigger::InternalFromChannel(source),
+            None => Trigger::Pin(p.trigger_pin),
+        }
+    }
+
+    // The GPIO the channel's trigger comes in on, a loopback source's
+    // output pin
+    pub fn trigger_pin(&self, ch: usize) -> u8 {
+        let p = &self.params[ch];
+        p.trigger_from
+            .map_or(p.trigger_pin, |source| self.params[source].pin)
+    }
+
+    // A bit per GPIO some channel takes its trigger from, the default
+    // trigger input always among them, loopback sources' outputs not
+    pub fn trigger_pins(&self) -> u32 {
+        trigger_pins(&self.params)
+    }
+
+    // The pin the channel's next arm waits on, a mirror's source's
+    fn trigger_input(&self, ch: usize) -> u8 {
+        let ch = self.params[ch].mirror.map_or(ch, |mirror| mirror.source);
+        match self.internal {
+            Some(internal) if internal.members & 1 << ch != 0 => internal.pin,
+            _ => self.trigger_pin(ch),
+        }
+    }
+
+    // Hands the channel's trigger input to its hardware ahead of a load
+    fn select_trigger(&mut self, ch: usize) {
+        let pin = self.trigger_input(ch);
+        with_hw!(self, ch, hw => hw.trigger = pin);
+    }
+
+    // The pin the channel's loaded program waits on
+    fn wait_pin(&self, ch: usize) -> u8 {
+        match ch {
+            0 => self.hw0.wait_pin,
+            _ => self.hw1.wait_pin,
+        }
+    }
+
+    // Fires the trigger the channel is waiting on, see force_trigger(),
+    // unless the guard refuses the channel now
+    fn force_trigger(&self, ch: usize) -> bool {
+        self.arm_guard.check(ch).is_ok() && force_trigger(ch, self.wait_pin(ch))
+    }
+
+    // Replaces the guard asked before arming, see ArmGuard. Channels armed
+    // already are left running.
+    pub fn set_arm_guard(&mut self, guard: &'static dyn ArmGuard) {
+        self.arm_guard = guard;
+    }
+
+    fn guard(&self, ch: usize) -> Result<(), PulseError> {
+        self.arm_guard
+            .check(ch)
+            .map_err(|err| PulseError::Guard { ch, err })
+    }
+
+    // Keeps a refusal of a re-arm from service() for the next event
+    fn note_refused(&mut self, result: Result<(), PulseError>) -> Result<(), PulseError> {
+        if let Err(PulseError::Guard { ch, err }) = result {
+            self.guard_refused[ch] = Some(err);
+        }
+        result
+    }
+
+    // Refuses arming rather than reporting the trigger level with it. Only
+    // arms from the host, AUTOARM and groups are checked: re-arms for
+    // retriggers and internal ticks happen with the trigger high by design.
+    pub fn set_refuse_stuck_trigger(&mut self, refuse: bool) {
+        self.refuse_stuck_trigger = refuse;
+    }
+
+    pub fn refuse_stuck_trigger(&self) -> bool {
+        self.refuse_stuck_trigger
+    }
+
+    fn check_trigger(&self, ch: usize) -> Result<(), PulseError> {
+        if self.refuse_stuck_trigger && self.disabled & 1 << ch == 0 && self.trigger_high(ch) {
+            return Err(PulseError::TriggerHigh { ch });
+        }
+        Ok(())
+    }
+
+    pub fn disarm_group(&mut self, members: u32) {
+        for ch in (0..NUM_CHANNELS).filter(|ch| members & 1 << ch != 0) {
+            self.disarm(ch);
+        }
+    }
+
+    // Defines or redefines a group, no members deletes it
+    pub fn set_group(&mut self, name: &str, members: u32) -> Result<(), GroupError> {
+        let mut name = GroupName::from(name).map_err(|_| GroupError::NameTooLong)?;
+        name.make_ascii_uppercase();
+        if let Some(ch) = (0..32).find(|&ch| members & 1 << ch != 0 && ch >= NUM_CHANNELS) {
+            return Err(GroupError::BadChannel { ch });
+        }
+        let existing = self.groups.iter().position(|g| g.name == name);
+        for ch in (0..NUM_CHANNELS).filter(|ch| members & 1 << ch != 0) {
+            if let Some(other) = self
+                .groups
+                .iter()
+                .find(|g| g.name != name && g.members & 1 << ch != 0)
+            {
+                return Err(GroupError::ChannelInGroup {
+                    ch,
+                    group: other.name,
+                });
+            }
+            let current = existing.map_or(0, |i| self.groups[i].members);
+            if current & 1 << ch == 0 && self.debug(ch).running {
+                return Err(GroupError::ChannelArmed { ch });
+            }
+        }
+        match (existing, members) {
+            (None, 0) => return Err(GroupError::UnknownGroup),
+            (Some(i), 0) => {
+                self.groups.remove(i);
+            }
+            (Some(i), _) => {
+                let group = &mut self.groups[i];
+                group.members = members;
+                // A reference left out of the group is dropped with it
+                if group.reference.is_some_and(|ch| members & 1 << ch == 0) {
+                    group.reference = None;
+                }
+            }
+            (None, _) => self.groups.push(Group {
+                name,
+                members,
+                reference: None,
+                lead_max: 0,
+            }),
+        }
+        Ok(())
+    }
+
+    // Makes a member's first delay the group's T0, which the other members'
+    // offsets count from, or with None goes back to every member's own
+    // first delay. An offset can reach before the trigger path allows by
+    // up to `lead_max` cycles, by delaying the whole group as much. Takes
+    // effect from the next arm of any member.
+    pub fn set_t0(
+        &mut self,
+        name: &str,
+        reference: Option<usize>,
+        lead_max: u64,
+    ) -> Result<(), GroupError> {
+        let group = self
+            .groups
+            .iter_mut()
+            .find(|g| g.name.eq_ignore_ascii_case(name))
+            .ok_or(GroupError::UnknownGroup)?;
+        if let Some(ch) = reference.filter(|&ch| group.members & 1 << ch == 0) {
+            return Err(GroupError::NotMember { ch });
+        }
+        group.reference = reference;
+        group.lead_max = lead_max;
+        Ok(())
+    }
+
+    // Sets the channel's first delay relative to its group's T0, negative
+    // before it. Only used while the group has a reference, the channel's
+    // own first delay is used otherwise. Used from the next arm.
+    pub fn set_t0_offset(&mut self, ch: usize, offset: Option<i64>) {
+        self.edit(ch, |p| p.t0_offset = offset);
+    }
+
+    pub fn t0_offset(&self, ch: usize) -> Option<i64> {
+        self.params[ch].t0_offset
+    }
+
+    // Holds the channel's table off by this much after the trigger, on top
+    // of the first delay and wherever a group's T0 placed it. In per edge
+    // mode only the first pulse waits for it. Used from the next arm.
+    pub fn set_pre_delay(&mut self, ch: usize, pre_delay: Achieved) {
+        self.edit(ch, |p| {
+            p.pre_delay = pre_delay.cycles;
+            p.pre_delay_requested = pre_delay.requested_ps;
+        });
+    }
+
+    pub fn pre_delay(&self, ch: usize) -> Achieved {
+        let p = &self.params[ch];
+        Achieved {
+            requested_ps: p.pre_delay_requested,
+            cycles: p.pre_delay,
+        }
+    }
+
+    // Keeps the channel from being armed again by the firmware for this
+    // long after its table went out: the next internal tick, a latched
+    // retrigger and a queued NEXT table all wait for it. Retriggers meanwhile
+    // follow the retrigger policy. Arms from the host aren't held off.
+    pub fn set_rearm_guard(&mut self, ch: usize, guard: Achieved) {
+        self.edit(ch, |p| {
+            p.rearm_guard = guard.cycles;
+            p.rearm_guard_requested = guard.requested_ps;
+        });
+    }
+
+    pub fn rearm_guard(&self, ch: usize) -> Achieved {
+        let p = &self.params[ch];
+        Achieved {
+            requested_ps: p.rearm_guard_requested,
+            cycles: p.rearm_guard,
+        }
+    }
+
+    // The first delays the group's next arm would use, None without such a
+    // group or a reference
+    pub fn t0(&self, name: &str) -> Option<Result<T0Solution, PulseError>> {
+        let group = self
+            .groups
+            .iter()
+            .find(|g| g.name.eq_ignore_ascii_case(name))?;
+        Some(self.solve_t0(group, group.reference?))
+    }
+
+    // Lays out a group's first delays around its reference's, T0. Members
+    // with an offset start T0 plus it after the trigger, the others keep
+    // their own first delay, each plus its output's calibration. Where that
+    // falls short of a member program's
+    // shortest delay the whole group is delayed by the same lead, so the
+    // members keep their places relative to each other.
+    fn solve_t0(&self, group: &Group, reference: usize) -> Result<T0Solution, PulseError> {
+        let t0 = *self.params[reference]
+            .delay
+            .first()
+            .ok_or(PulseError::EmptySequence { ch: reference })?;
+        let wanted = |ch: usize, delay: u64| {
+            let delay = match self.params[ch].t0_offset {
+                Some(offset) if ch != reference => t0 as i128 + offset as i128,
+                _ => delay as i128,
+            };
+            delay + self.calibration[ch] as i128
+        };
+        let mut lead = 0;
+        let mut delays = [None; NUM_CHANNELS];
+        let mut short_ch = reference;
+        for ch in (0..NUM_CHANNELS).filter(|ch| group.members & 1 << ch != 0) {
+            let Some(&delay) = self.params[ch].delay.first() else {
+                continue;
+            };
+            let min = self.params[ch].program_config().min_delay() as i128;
+            let needed = min - wanted(ch, delay);
+            if needed > lead as i128 {
+                lead = needed as u64;
+                short_ch = ch;
+            }
+            delays[ch] = Some(delay);
+        }
+        if lead > group.lead_max {
+            return Err(PulseError::T0Infeasible {
+                ch: short_ch,
+                short_ps: cycles_to_ps(lead - group.lead_max, self.sys_hz),
+            });
+        }
+        for (ch, delay) in delays.iter_mut().enumerate() {
+            *delay = delay.map(|delay| (wanted(ch, delay) + lead as i128) as u64);
+        }
+        Ok(T0Solution {
+            reference,
+            t0,
+            lead,
+            delays,
+        })
+    }
+
+    // The channel's first delay as its group's T0 and its output's
+    // calibration place it, None where that is its own. Outside a group
+    // with a reference nothing can make up for a calibration taking the
+    // delay below the program's shortest, which fails as an offset would.
+    fn placed_first_delay(&self, ch: usize) -> Result<Option<u64>, PulseError> {
+        if let Some(mirror) = self.params[ch].mirror {
+            return self.placed_mirror_delay(ch, mirror.source);
+        }
+        let group = self.groups.iter().find(|g| g.members & 1 << ch != 0);
+        if let Some((group, reference)) = group.and_then(|g| Some((g, g.reference?))) {
+            return Ok(self.solve_t0(group, reference)?.delays[ch]);
+        }
+        let calibration = self.calibration[ch] as i128;
+        let Some(&delay) = self.params[ch].delay.first().filter(|_| calibration != 0) else {
+            return Ok(None);
+        };
+        let min = self.params[ch].program_config().min_delay() as i128;
+        let placed = delay as i128 + calibration;
+        if placed < min {
+            return Err(PulseError::T0Infeasible {
+                ch,
+                short_ps: cycles_to_ps((min - placed) as u64, self.sys_hz),
+            });
+        }
+        Ok(Some(placed as u64))
+    }
+
+    // A mirror's first delay where its source's is placed, with the
+    // mirror's calibration in place of the source's. Mirrors follow their
+    // source's group, not their own.
+    fn placed_mirror_delay(&self, ch: usize, source: usize) -> Result<Option<u64>, PulseError> {
+        let Some(&delay) = self.params[ch].delay.first() else {
+            return Ok(None);
+        };
+        let placed = match self.placed_first_delay(source)? {
+            Some(placed) => placed as i128 - self.calibration[source] as i128,
+            None => delay as i128,
+        } + self.calibration[ch] as i128;
+        let min = self.params[ch].program_config().min_delay() as i128;
+        if placed < min {
+            return Err(PulseError::T0Infeasible {
+                ch,
+                short_ps: cycles_to_ps((min - placed) as u64, self.sys_hz),
+            });
+        }
+        Ok((placed != delay as i128).then_some(placed as u64))
+    }
+
+    // Cycles added to the channel's first delay from the next arm, negative
+    // to make up for a longer cable or slower driver than the other
+    // outputs'. Only the first delay counts from the trigger, the later
+    // ones follow from it, so the whole table moves. Kept through *RST, see
+    // flash's Calibration.
+    pub fn set_calibration(&mut self, ch: usize, cycles: i32) {
+        self.calibration[ch] = cycles;
+    }
+
+    pub fn calibration(&self, ch: usize) -> i32 {
+        self.calibration[ch]
+    }
+
+    // Places the channel's first delay ahead of arming
+    fn place_first_delay(&mut self, ch: usize) -> Result<(), PulseError> {
+        self.params[ch].placed_delay = self.placed_first_delay(ch)?;
+        Ok(())
+    }
+
+    pub fn group(&self, name: &str) -> Option<u32> {
+        self.groups
+            .iter()
+            .find(|g| g.name.eq_ignore_ascii_case(name))
+            .map(|g| g.members)
+    }
+
+    pub fn groups(&self) -> &[Group] {
+        &self.groups
+    }
+
+    // Stops the output and drives it low, or releases it with an idle
+    // tristate. Its source or mirrors stop with it, and queued arms of any
+    // of them are dropped.
+    pub fn disarm(&mut self, ch: usize) {
+        info!("disarm {}", ch);
+        let linked = linked(&self.params, ch);
+        self.cancel_queued(linked);
+        for ch in (0..NUM_CHANNELS).filter(|ch| linked & 1 << ch != 0) {
+            self.test_pattern[ch] = None;
+            with_hw!(self, ch, hw => hw.disarm())
+        }
+    }
+
+    // Arms the channel for a host-fed sequence of any length
+    pub fn stream_start(&mut self, ch: usize) -> Result<(), InstructionMemoryFull> {
+        info!("stream start {}", ch);
+        self.test_pattern[ch] = None;
+        self.select_trigger(ch);
+        let params = &self.params[ch];
+        with_hw!(self, ch, hw => hw.stream_start(&mut self.pio, &mut self.programs, params))?;
+        // Streams aren't counted
+        self.run_track[ch].done = true;
+        Ok(())
+    }
+
+    // Queues (delay, width) cycle pairs and returns the number of free blocks
+    #[cfg(feature = "binary-proto")]
+    pub fn stream_block(
+        &mut self,
+        ch: usize,
+        pairs: &[(Cycles, Cycles)],
+    ) -> Result<usize, StreamError> {
+        with_hw!(self, ch, hw => hw.stream_block(pairs))
+    }
+
+    pub fn stream_end(&mut self, ch: usize) -> Result<(), StreamError> {
+        with_hw!(self, ch, hw => hw.stream_end())
+    }
+
+    // Some channel is streaming and needs service() to keep it fed
+    pub fn streaming(&self) -> bool {
+        self.hw0.stream.is_some() || self.hw1.stream.is_some()
+    }
+
+    // Called from the main loop with the timer ticks to keep streams fed,
+    // detect their end and catch underruns
+    pub fn service(&mut self, now: u64) -> Option<(usize, ChannelEvent)> {
+        // Before a rerun restarts a table that went out
+        self.service_runs(now);
+        // Often enough that the ring rarely laps between two looks
+        #[cfg(feature = "capture")]
+        if let Some(glitches) = self.glitches.as_mut() {
+            glitches.update(CH2::id());
+        }
+        self.service_test_patterns();
+        if let Some(event) = self.service_next(now) {
+            return Some(event);
+        }
+        // The edges are the trigger inputs', internal members go by ticks.
+        // Taken for all of them at once since channels can share a pin.
+        let internal = self.internal.map_or(0, |internal| internal.members);
+        let external = (0..NUM_CHANNELS).filter(|ch| internal & 1 << ch == 0);
+        let pins = external
+            .clone()
+            .fold(0, |pins, ch| pins | 1 << self.wait_pin(ch));
+        let edges = take_trigger_edges(pins);
+        for ch in external {
+            let edge = edges & 1 << self.wait_pin(ch) != 0;
+            self.service_retrigger(ch, edge, now);
+        }
+        self.service_internal(now);
+        if let Some(ch) = self.guard_refused.iter().position(Option::is_some) {
+            let err = self.guard_refused[ch].take().unwrap();
+            return Some((ch, ChannelEvent::Guarded(err)));
+        }
+        if let Some(event) = self.hw0.service(now) {
+            return Some((0, event));
+        }
+        self.hw1.service(now).map(|event| (1, event))
+    }
+
+    // Applies the channel's retrigger policy to a trigger edge seen since the
+    // last call. Polled, so edges before the main loop notices the table
+    // started count as the trigger itself and the rerun starts one loop
+    // iteration late.
+    fn service_retrigger(&mut self, ch: usize, edge: bool, now: u64) {
+        // In per edge mode the edges after the first release the pulses
+        let policy =
+            if self.params[ch].per_edge || self.follows(ch) || self.test_pattern[ch].is_some() {
+                RetriggerPolicy::Ignore
+            } else {
+                self.params[ch].retrigger
+            };
+        let guarding = self.guarding(ch, now);
+        let info = self.debug(ch);
+        let state = &mut self.retrigger[ch];
+        if policy == RetriggerPolicy::Ignore || !info.running || info.ready {
+            *state = Retrigger::default();
+            return;
+        }
+        if !state.started {
+            state.started = true;
+            return;
+        }
+        let rerun = match policy {
+            // Edges during the re-arm guard are latched or dropped as they
+            // would be while the table runs
+            _ if info.emitted() => {
+                if guarding && policy == RetriggerPolicy::Latch {
+                    state.pending |= edge;
+                }
+                state.pending && !guarding
+            }
+            RetriggerPolicy::Latch => {
+                state.pending |= edge;
+                false
+            }
+            _ => edge,
+        };
+        if rerun {
+            *state = Retrigger::default();
+            // Arming drives the output low before the SM restarts
+            let result = self.rearm_linked(ch);
+            if self.note_refused(result).is_ok() {
+                self.force_trigger(ch);
+            }
+        }
+    }
+
+    // Switches the member channels over to ticks on the internal pin and
+    // arms them together, or stops the ticks and disarms them with
+    // None. Each tick starts a run of every member's table, a member is
+    // re-armed for the next tick once its table went out, which has to
+    // leave time for the main loop to get to it. Returns the period the
+    // ticks run at.
+    pub fn set_internal(&mut self, internal: Option<Internal>) -> Result<u64, InternalError> {
+        if let Some(current) = self.internal.take() {
+            self.disarm_group(current.members);
+            tick::stop(current.pin, self.pins.pio.contains(&current.pin));
+        }
+        let Some(Internal {
+            pin,
+            period,
+            members,
+        }) = internal
+        else {
+            return Ok(0);
+        };
+        let output = self.params.iter().any(|p| p.pins().contains(&pin));
+        if pin >= 30 || self.trigger_pins() & 1 << pin != 0 || output {
+            return Err(InternalError::BadPin);
+        }
+        if !(tick::PERIOD_MIN..=tick::PERIOD_MAX).contains(&period) {
+            return Err(InternalError::BadPeriod);
+        }
+        let members = members & ((1 << NUM_CHANNELS) - 1);
+        if members == 0 {
+            return Err(InternalError::NoChannels);
+        }
+        // Set first so the members are armed on the internal pin, and armed
+        // before the first tick so none of them misses it
+        self.internal = Some(Internal {
+            pin,
+            members,
+            period,
+        });
+        if let Err(err) = self.arm_group(members) {
+            self.internal = None;
+            self.disarm_group(members);
+            return Err(InternalError::Arm(err));
+        }
+        let period = tick::start(pin, period);
+        self.internal = Some(Internal {
+            pin,
+            members,
+            period,
+        });
+        Ok(period)
+    }
+
+    pub fn internal(&self) -> Option<Internal> {
+        self.internal
+    }
+
+    fn service_internal(&mut self, now: u64) {
+        let Some(internal) = self.internal else {
+            return;
+        };
+        for ch in (0..NUM_CHANNELS).filter(|ch| internal.members & 1 << ch != 0) {
+            if self.debug(ch).emitted() && !self.guarding(ch, now) && !self.follows(ch) {
+                let result = self.rearm_linked(ch);
+                let _ = self.note_refused(result);
+            }
+        }
+    }
+
+    // Returns to the power-on state: every channel disarmed with its DMA
+    // aborted, tables cleared, default pins and no staged configuration.
+    // Safe while a table or stream is running.
+    pub fn reset_all(&mut self) {
+        info!("reset all");
+        let _ = self.set_internal(None);
+        #[cfg(feature = "capture")]
+        {
+            let _ = self.set_tlog(false);
+            let _ = self.set_glitch(None);
+        }
+        self.next = Default::default();
+        self.next_failed = [false; NUM_CHANNELS];
+        for ch in 0..NUM_CHANNELS {
+            self.disarm(ch);
+        }
+        self.params = self.pins.default_outputs.map(PulseParameter::new);
+        self.staged = None;
+        self.rounding = Rounding::Nearest;
+        self.set_falling(0);
+        self.coalesce = false;
+        self.groups.clear();
+        self.set_expert(false);
+    }
+
+    // Timestamps trigger edges on SM3, see tlog. Refused while an expert
+    // program is loaded there.
+    #[cfg(feature = "capture")]
+    pub fn set_tlog(&mut self, enabled: bool) -> Result<(), ExpertError> {
+        if enabled {
+            self.expert3.start_capture(&mut self.pio, TRIGGER_PIN)?;
+        } else {
+            self.expert3.stop_capture(&mut self.pio);
+        }
+        self.programs.expert_words = self.expert2.words + self.expert3.words;
+        Ok(())
+    }
+
+    // Edges since the last arm and the last TLOG_LEN of them in cycles from
+    // the arm, None while the log is off
+    #[cfg(feature = "capture")]
+    pub fn tlog(&self) -> Option<(u32, ArrayVec<u64, TLOG_LEN>)> {
+        self.expert3.capture?;
+        Some(tlog::read(CH3::id()))
+    }
+
+    // Counts trigger input pulses shorter than `threshold` cycles, at least
+    // glitch::MIN_THRESHOLD, on SM2 until None. Runs whether or not a
+    // channel is armed, refused while an expert program or SKEW? has SM2.
+    #[cfg(feature = "capture")]
+    pub fn set_glitch(&mut self, threshold: Option<u32>) -> Result<(), ExpertError> {
+        self.glitches = None;
+        let result = match threshold {
+            Some(threshold) => {
+                let glitches = Glitches::new(threshold);
+                let loops = glitches.loops();
+                let started = self.expert2.start_glitch(&mut self.pio, TRIGGER_PIN, loops);
+                started.map(|()| self.glitches = Some(glitches))
+            }
+            None => {
+                self.expert2.stop_glitch(&mut self.pio);
+                Ok(())
+            }
+        };
+        self.programs.expert_words = self.expert2.words + self.expert3.words;
+        result
+    }
+
+    // The count and shortest width so far, None while the detector is off
+    #[cfg(feature = "capture")]
+    pub fn glitches(&mut self) -> Option<Glitches> {
+        let glitches = self.glitches.as_mut()?;
+        glitches.update(CH2::id());
+        Some(*glitches)
+    }
+
+    // Starts the count over, keeping the threshold
+    #[cfg(feature = "capture")]
+    pub fn reset_glitches(&mut self) {
+        if let Some(glitches) = self.glitches.as_mut() {
+            self.expert2.restart_glitch();
+            glitches.reset();
+        }
+    }
+
+    // Cycles from ch0's output rising to ch1's, negative if ch1 rises
+    // first, for the same pulse armed on both and started together. Each pass arms a
+    // single test pulse on both channels, ch1's delay offset from ch0's, and
+    // has SM2 sample every GPIO on the SKEW_SAMPLES cycles after ch0's pin
+    // rises; the offset moves the window until ch1's rise lands in it. The
+    // channels' configurations are restored and both left disarmed.
+    // Outputs are read back at their pads, open drain ones need a pull-up.
+    #[cfg(feature = "selftest")]
+    pub fn measure_skew(&mut self) -> Result<i32, SkewError> {
+        if (0..NUM_CHANNELS).any(|ch| self.debug(ch).running) || self.internal.is_some() {
+            return Err(SkewError::Busy);
+        }
+        if let Some(ch) = (0..NUM_CHANNELS).find(|ch| self.disabled & 1 << ch != 0) {
+            return Err(SkewError::Disabled { ch });
+        }
+        let saved = self.params.clone();
+        let result = self.skew_windows();
+        self.params = saved;
+        for ch in 0..NUM_CHANNELS {
+            self.disarm(ch);
+        }
+        result
+    }
+
+    #[cfg(feature = "selftest")]
+    fn skew_windows(&mut self) -> Result<i32, SkewError> {
+        let [pin0, pin1] = [self.params[0].pin, self.params[1].pin];
+        // A sample k cycles after the one at ch0's rise sees ch1 high if ch1
+        // rose on or before it. Only k >= 1 pins the rise down, so a window
+        // covers ch1 rising 2..=SKEW_SAMPLES cycles after ch0.
+        let mut offset = SKEW_MAX + 2;
+        while 2 - offset <= SKEW_MAX {
+            for (ch, extra) in [(0, -offset), (1, offset)] {
+                let params = &self.params[ch];
+                let mut scratch = PulseParameter::new(params.pin);
+                scratch.output = params.output;
+                scratch.idle_tristate = params.idle_tristate;
+                scratch.delay.push(SKEW_DELAY + extra.max(0) as u64);
+                scratch.width.push(SKEW_WIDTH);
+                self.params[ch] = scratch;
+            }
+            self.arm_all().map_err(SkewError::Arm)?;
+            let samples = self.sample_run(0, pin0)?;
+            let high = samples.iter().position(|sample| sample & 1 << pin1 != 0);
+            if let Some(k @ 1..) = high {
+                return Ok(1 + k as i32 - offset);
+            }
+            // Spread 7 apart, so the windows' 7 usable samples join up
+            offset -= SKEW_SAMPLES as i32 - 1;
+        }
+        Err(SkewError::NoEdge)
+    }
+
+    // Fires the trigger of the armed channel `ch` and has SM2 sample every
+    // GPIO on the SKEW_SAMPLES cycles after `pin` rises, a bit per GPIO in
+    // each sample. Every armed channel is waited for and left disarmed.
+    #[cfg(feature = "selftest")]
+    pub fn sample_run(
+        &mut self,
+        ch: usize,
+        pin: u8,
+    ) -> Result<ArrayVec<u32, SKEW_SAMPLES>, SkewError> {
+        let armed = (0..NUM_CHANNELS)
+            .filter(|&ch| is_armed(ch))
+            .fold(0, |armed, ch| armed | 1 << ch);
+        let started = self.expert2.start_sampler(&mut self.pio, pin);
+        self.programs.expert_words = self.expert2.words + self.expert3.words;
+        let result = started
+            .map_err(|err| match err {
+                ExpertError::NoSpace => SkewError::NoSpace,
+                _ => SkewError::InUse,
+            })
+            .and_then(|()| {
+                self.force_trigger(ch);
+                let done = (0..NUM_CHANNELS)
+                    .filter(|ch| armed & 1 << ch != 0)
+                    .all(|ch| self.wait_done(ch, SKEW_TIMEOUT_US).is_ok());
+                let samples = self.expert2.stop_sampler(&mut self.pio);
+                self.programs.expert_words = self.expert2.words + self.expert3.words;
+                if done {
+                    Ok(samples)
+                } else {
+                    Err(SkewError::NoEdge)
+                }
+            });
+        self.disarm_group(armed);
+        result
+    }
+
+    // The board's GPIOs the channels' outputs can go on
+    pub fn pio_pins(&self) -> &'static [u8] {
+        self.pins.pio
+    }
+
+    // Turning expert mode off unloads every expert program
+    pub fn set_expert(&mut self, enabled: bool) {
+        if !enabled {
+            self.expert2.unload(&mut self.pio);
+            self.expert3.unload(&mut self.pio);
+            self.programs.expert_words = self.expert2.words + self.expert3.words;
+        }
+        self.expert_enabled = enabled;
+    }
+
+    // Installs a raw program on spare state machine `sm` and starts it,
+    // bypassing compile()
+    #[cfg(feature = "binary-proto")]
+    pub fn expert_load(
+        &mut self,
+        sm: usize,
+        program: &pio::Program<32>,
+        pins: Range<u8>,
+    ) -> Result<(), ExpertError> {
+        if !self.expert_enabled {
+            return Err(ExpertError::Locked);
+        }
+        if !EXPERT_SM.contains(&sm) {
+            return Err(ExpertError::BadStateMachine);
+        }
+        let len = program.code.len();
+        if program
+            .origin
+            .map_or(false, |origin| origin as usize + len > 32)
+        {
+            return Err(ExpertError::BadOrigin);
+        }
+        if program.wrap.source as usize >= len || program.wrap.target as usize >= len {
+            return Err(ExpertError::BadWrap);
+        }
+        if program.side_set.bits() > 5 {
+            return Err(ExpertError::BadSideSet);
+        }
+        let pins_ok = pins.clone().all(|pin| self.pins.pio.contains(&pin))
+            && !self
+                .params
+                .iter()
+                .any(|p| p.pins().any(|pin| pins.contains(&pin)));
+        if !pins_ok {
+            return Err(ExpertError::BadPins);
+        }
+        let result = match sm {
+            2 => self.expert2.load(&mut self.pio, program, pins),
+            _ => self.expert3.load(&mut self.pio, program, pins),
+        };
+        self.programs.expert_words = self.expert2.words + self.expert3.words;
+        result
+    }
+
+    // Pushes words into the expert SM's TX FIFO by DMA
+    #[cfg(feature = "binary-proto")]
+    pub fn expert_feed(&mut self, sm: usize, words: &[u32]) -> Result<(), ExpertError> {
+        if !self.expert_enabled {
+            return Err(ExpertError::Locked);
+        }
+        match sm {
+            2 => self.expert2.feed(words),
+            3 => self.expert3.feed(words),
+            _ => Err(ExpertError::BadStateMachine),
+        }
+    }
+
+    // Who holds what, from the state the hardware is driven by
+    pub fn resources(&self) -> Resources {
+        let mut dma = [Role::Free; DMA_CHANNELS];
+        dma[CH0::id() as usize] = Role::Channel(0);
+        dma[CH1::id() as usize] = Role::Channel(1);
+        dma[CH2::id() as usize] = self.expert2.role();
+        dma[CH3::id() as usize] = self.expert3.role();
+        if cfg!(feature = "selftest") {
+            dma[BULK_DMA_CH as usize] = Role::Bulk;
+        }
+        Resources {
+            pio0_free: self.programs.free(),
+            sm: [
+                Role::Channel(0),
+                Role::Channel(1),
+                self.expert2.role(),
+                self.expert3.role(),
+            ],
+            dma,
+        }
+    }
+
+    pub fn debug(&self, ch: usize) -> DebugInfo {
+        match ch {
+            0 => self.hw0.debug(),
+            _ => self.hw1.debug(),
+        }
+    }
+
+    // The words the channel's next arm feeds its SM, the prologue the CPU
+    // writes then the DMA table, built without touching the hardware
+    pub fn build_words(&self, ch: usize) -> Result<ArrayVec<u32, DMA_BUF_LEN>, PulseError> {
+        if self.params[ch].is_empty() {
+            return Err(PulseError::EmptySequence { ch });
+        }
+        let mut params = self.params[ch].clone();
+        params.placed_delay = self.placed_first_delay(ch)?;
+        let mut words = ArrayVec::new();
+        params.write_words(false, |word| words.push(word));
+        Ok(words)
+    }
+
+    // Program loaded by the channel on its next arm
+    pub fn program(&self, ch: usize) -> pio::Program<32> {
+        compile(self.program_config(ch))
+    }
+
+    pub fn program_config(&self, ch: usize) -> ProgramConfig {
+        self.params[ch].program_config()
+    }
+
+    // Requested and achieved (delay, width) of each pulse, with the program's
+    // minimums applied to the achieved cycles
+    pub fn achieved(&self, ch: usize) -> impl Iterator<Item = (Achieved, Achieved)> + '_ {
+        let p = &self.params[ch];
+        let timing = p.program_config().timing();
+        let cycles = p.delay.iter().zip(&p.width);
+        let requested = p.delay_requested.iter().zip(&p.width_requested);
+        cycles.zip(requested).enumerate().map(
+            move |(i, ((&delay, &width), (&delay_ps, &width_ps)))| {
+                let min_delay = if i == 0 {
+                    timing.min_delay
+                } else {
+                    timing.min_next_delay
+                };
+                (
+                    Achieved {
+                        requested_ps: delay_ps,
+                        cycles: delay.max(min_delay as u64),
+                    },
+                    Achieved {
+                        requested_ps: width_ps,
+                        cycles: width.max(timing.min_width) as u64,
+                    },
+                )
+            },
+        )
+    }
+
+    pub fn rounding(&self) -> Rounding {
+        self.rounding
+    }
+
+    // Selects how durations given in time units become cycles, applies to
+    // values set from now on
+    pub fn set_rounding(&mut self, rounding: Rounding) {
+        self.rounding = rounding;
+    }
+
+    pub fn trigger_edge(&self, pin: u8) -> Edge {
+        if self.falling & 1 << pin != 0 {
+            Edge::Falling
+        } else {
+            Edge::Rising
+        }
+    }
+
+    // Selects the edge of the GPIO that triggers every channel waiting on
+    // it, internal triggers and TRIGOUT included. A falling edge inverts the
+    // pin's input for the whole chip: PIN?, WATCH and the timestamp log see
+    // the active edge as a rise as well. Refused while an armed channel
+    // waits on the pin, the flip would look like an edge to it.
+    pub fn set_trigger_edge(&mut self, pin: u8, edge: Edge) -> Result<(), TriggerArmed> {
+        if let Some(ch) =
+            (0..NUM_CHANNELS).find(|&ch| self.debug(ch).running && self.wait_pin(ch) == pin)
+        {
+            return Err(TriggerArmed { ch });
+        }
+        let falling = match edge {
+            Edge::Rising => self.falling & !(1 << pin),
+            Edge::Falling => self.falling | 1 << pin,
+        };
+        self.set_falling(falling);
+        Ok(())
+    }
+
+    fn set_falling(&mut self, falling: u32) {
+        invert_triggers(self.falling ^ falling, falling);
+        self.falling = falling;
+    }
+
+    // Output edges the channel's table produces on its next arm
+    pub fn timeline(&self, ch: usize) -> Timeline<'_> {
+        self.params[ch].timeline(self.sys_hz)
+    }
+
+    // Arms the channel and fires the trigger right away `runs` times. Before
+    // each trigger the SM has to be waiting for it without ever having
+    // stalled on an empty FIFO. Returns the number of runs where it had, or
+    // where it couldn't be armed.
+    // The forced trigger is seen by every armed channel.
+    #[cfg(feature = "selftest")]
+    pub fn stress(&mut self, ch: usize, runs: u32) -> u32 {
+        let mut failures = 0;
+        for _ in 0..runs {
+            if self.arm(ch).is_err() {
+                failures += 1;
+                continue;
+            }
+            let stalled = self.debug(ch).tx_stalled;
+            if !self.force_trigger(ch) || stalled {
+                failures += 1;
+            }
+        }
+        failures
+    }
+
+    // Like stress() with a normal priority DMA copy loading the bus through
+    // each run. Returns the number of runs where the table's DMA fell behind,
+    // seen as the SM stalling with words still to feed, or that couldn't be
+    // armed or triggered. A run ends with its table or with the copy.
+    #[cfg(feature = "selftest")]
+    pub fn stress_dma(&mut self, ch: usize, runs: u32) -> u32 {
+        let mut failures = 0;
+        for _ in 0..runs {
+            if self.arm(ch).is_err() {
+                failures += 1;
+                continue;
+            }
+            with_hw!(self, ch, hw => hw.take_tx_stall());
+            start_bulk_dma();
+            let early = !self.force_trigger(ch)
+                || loop {
+                    // Stall flag first, as in service_table()
+                    let info = self.debug(ch);
+                    match info.dma_remaining {
+                        Some(remaining) if remaining > 0 && info.tx_stalled => break true,
+                        Some(remaining) if remaining > 0 && bulk_dma_busy() => {}
+                        _ => break false,
+                    }
+                };
+            abort_dma(BULK_DMA_CH);
+            failures += early as u32;
+        }
+        failures
+    }
+
+    // Makes the first delay, every delay in per edge mode, count from the
+    // trigger edge at the pin rather than from the end of the trigger path
+    pub fn set_latency_compensation(&mut self, ch: usize, compensate: bool) {
+        self.edit(ch, |p| p.compensate_latency = compensate);
+    }
+
+    // Makes each qualifying trigger edge release only the next pulse, with
+    // the divider applying before every pulse. Disarming stops the table
+    // wherever it is. Used from the next arm.
+    pub fn set_per_edge(&mut self, ch: usize, per_edge: bool) -> Result<(), Violation> {
+        self.edit_checked(ch, |p| p.per_edge = per_edge)
+    }
+
+    pub fn per_edge(&self, ch: usize) -> bool {
+        self.params[ch].per_edge
+    }
+
+    // Sets the level between pulses, None for the output mode's own, with
+    // pulses at the other level. The idle level stays the same unless it
+    // was left at its default too. Used from the next arm.
+    pub fn set_gap_level(&mut self, ch: usize, level: Option<Level>) -> Result<(), Violation> {
+        self.edit_checked(ch, |p| p.gap = level)
+    }
+
+    // Sets the level while disarmed, waiting for the trigger and after the
+    // last pulse. Taken from the next arm or disarm.
+    pub fn set_idle_level(&mut self, ch: usize, level: Option<Level>) -> Result<(), Violation> {
+        self.edit_checked(ch, |p| p.idle = level)
+    }
+
+    pub fn gap_level(&self, ch: usize) -> Option<Level> {
+        self.params[ch].gap
+    }
+
+    pub fn idle_level(&self, ch: usize) -> Option<Level> {
+        self.params[ch].idle
+    }
+
+    // The level the channel's pin rests at while disarmed and waiting for
+    // the trigger, None released. Its program asserts it from the first
+    // instruction on, see prologue().
+    pub fn rest_level(&self, ch: usize) -> Option<Level> {
+        let params = &self.params[ch];
+        let side = params.program_config().idle_side() & 1;
+        match params.output {
+            OutputMode::PushPull if params.idle_tristate => None,
+            OutputMode::PushPull if side == 1 => Some(Level::High),
+            OutputMode::PushPull => Some(Level::Low),
+            OutputMode::OpenDrain if side == 1 => Some(Level::Low),
+            OutputMode::OpenDrain => None,
+        }
+    }
+
+    // Channels whose stopped SM doesn't drive its pins at the idle level,
+    // a bug in a program or its setup. Checked once after new(), before
+    // anything arms.
+    pub fn idle_mismatch(&self) -> u32 {
+        let driving = [self.hw0.drives_idle(), self.hw1.drives_idle()];
+        (0..NUM_CHANNELS)
+            .filter(|&ch| !driving[ch])
+            .fold(0, |mask, ch| mask | 1 << ch)
+    }
+
+    // Skips n - 1 trigger edges before the table runs, 0 is taken as 1.
+    // Used from the next arm, and right away by a channel waiting on its
+    // edges if ChannelHw::set_edge_count() can, with its mirrors. True if
+    // it did.
+    pub fn set_trigger_divider(&mut self, ch: usize, n: u32) -> bool {
+        let n = n.max(1);
+        self.edit(ch, |p| p.trigger_divider = n);
+        if self.staged.is_some() {
+            return false;
+        }
+        let linked = linked(&self.params, ch);
+        let mut applied = false;
+        for ch in (0..NUM_CHANNELS).filter(|ch| linked & 1 << ch != 0) {
+            applied |= with_hw!(self, ch, hw => hw.set_edge_count(n));
+        }
+        applied
+    }
+
+    pub fn sys_hz(&self) -> u32 {
+        self.sys_hz
+    }
+
+    // Starts editing a copy of the configuration, setters apply to it until
+    // apply() or discard()
+    pub fn stage(&mut self) {
+        self.staged = Some(self.params.clone());
+    }
+
+    pub fn is_staging(&self) -> bool {
+        self.staged.is_some()
+    }
+
+    pub fn discard(&mut self) {
+        self.staged = None;
+    }
+
+    // Swaps in the staged configuration if it is valid as a whole, otherwise
+    // nothing changes. Takes effect on the next arm.
+    pub fn apply(&mut self) -> Result<(), Invalid> {
+        if let Some(staged) = &self.staged {
+            validate(staged, self.pins.pio, self.disabled)?;
+        }
+        if let Some(staged) = self.staged.take() {
+            self.params = staged;
+            self.hw0.set_idle_tristate(self.params[0].idle_tristate);
+            self.hw1.set_idle_tristate(self.params[1].idle_tristate);
+        }
+        Ok(())
+    }
+
+    // The live configuration, see Snapshot
+    pub fn snapshot(&self) -> Blob {
+        encode_snapshot(&self.params, self.rounding, self.falling, &self.groups)
+    }
+
+    // Replaces the live configuration with a snapshot()'s, all of it or
+    // nothing. Refused while a channel runs, as group changes are, or if the
+    // result doesn't validate. Drops a staged configuration.
+    pub fn restore(&mut self, blob: &[u8]) -> Result<(), RestoreError> {
+        let snapshot = decode_snapshot(blob)?;
+        if let Some(ch) = (0..NUM_CHANNELS).find(|&ch| self.debug(ch).running) {
+            return Err(RestoreError::Armed { ch });
+        }
+        snapshot
+            .check(self.pins.pio, self.disabled)
+            .map_err(RestoreError::Invalid)?;
+        self.params = snapshot.params;
+        self.rounding = snapshot.rounding;
+        self.set_falling(snapshot.falling);
+        self.groups = snapshot.groups;
+        self.staged = None;
+        self.hw0.set_idle_tristate(self.params[0].idle_tristate);
+        self.hw1.set_idle_tristate(self.params[1].idle_tristate);
+        Ok(())
+    }
+
+    // Makes the channel a mirror of `source` or, with None, independent
+    // again with an empty table. A mirror takes its source's table,
+    // trigger and every other setting but the output pin, which it keeps,
+    // and follows its edits from then on. It is armed, re-armed and
+    // disarmed together with its source, their state machines started on
+    // the same cycle. Streams aren't mirrored. Editing a mirror itself is
+    // left to the caller to refuse, an edit would only last until the
+    // source's next one.
+    pub fn set_mirror(&mut self, ch: usize, mirror: Option<Mirror>) -> Result<(), MirrorError> {
+        let params = self.staged.as_ref().unwrap_or(&self.params);
+        if let Some(Mirror { source, .. }) = mirror {
+            let mirrored = params
+                .iter()
+                .any(|p| p.mirror.is_some_and(|m| m.source == ch));
+            if source == ch || params[source].mirror.is_some() || mirrored {
+                return Err(MirrorError::Chain);
+            }
+        }
+        let current = params[ch].mirror.map(|mirror| mirror.source);
+        let channels = [Some(ch), current, mirror.map(|mirror| mirror.source)];
+        if let Some(ch) = channels
+            .into_iter()
+            .flatten()
+            .find(|&ch| self.debug(ch).running)
+        {
+            return Err(MirrorError::Armed { ch });
+        }
+        self.edit_checked(ch, |p| {
+            if mirror.is_none() && p.mirror.is_some() {
+                p.set_pairs(&[]);
+            }
+            p.mirror = mirror;
+        })
+        .map_err(MirrorError::Invalid)?;
+        if !self.is_staging() {
+            self.hw0.set_idle_tristate(self.params[0].idle_tristate);
+            self.hw1.set_idle_tristate(self.params[1].idle_tristate);
+        }
+        Ok(())
+    }
+
+    pub fn mirror(&self, ch: usize) -> Option<Mirror> {
+        self.params[ch].mirror
+    }
+
+    // Applies a change to the channel in the staged configuration if there
+    // is one, the live one otherwise, and passes it on to its mirrors
+    fn edit<R>(&mut self, ch: usize, update: impl FnOnce(&mut PulseParameter) -> R) -> R {
+        let params = self.staged.as_mut().unwrap_or(&mut self.params);
+        let result = update(&mut params[ch]);
+        sync_mirrors(params);
+        result
+    }
+
+    // Delays above 2^32 cycles switch the channel to the long delay program,
+    // which is rejected if it doesn't fit the channel's configuration
+    pub fn set_delay(&mut self, ch: usize, delay: Achieved) -> Result<(), DelayError> {
+        if self.edit(ch, |p| p.delay.is_full()) {
+            return Err(DelayError::Full(SequenceFull));
+        }
+        self.edit_checked(ch, |p| {
+            // Not full, checked above
+            let _ = p.push_delay(delay);
+        })
+        .map_err(DelayError::Invalid)
+    }
+
+    // Widths are limited to u32 cycles by the caller
+    pub fn set_width(&mut self, ch: usize, width: Achieved) -> Result<(), SequenceFull> {
+        self.edit(ch, |p| p.push_width(width))
+    }
+
+    // Replaces the channel's whole table with (delay, width) pairs
+    pub fn set_table(
+        &mut self,
+        ch: usize,
+        pairs: &[(Achieved, Achieved)],
+    ) -> Result<(), Violation> {
+        self.edit_checked(ch, |params| params.set_pairs(pairs))
+    }
+
+    // Queues a table to replace the channel's own once its running table
+    // went out, then arms the channel again. Returns true if it replaced a
+    // table already queued.
+    pub fn set_next(&mut self, ch: usize, pairs: Pairs) -> bool {
+        self.next_failed[ch] = false;
+        self.next[ch].replace(pairs).is_some()
+    }
+
+    // A table is queued, and whether the last queued one failed to take over
+    pub fn next_state(&self, ch: usize) -> (bool, bool) {
+        (self.next[ch].is_some(), self.next_failed[ch])
+    }
+
+    // Swaps a queued table in on a channel whose table went out and arms it
+    // again. The queued table is checked as TABLE would, outside of any
+    // staged configuration.
+    fn service_next(&mut self, now: u64) -> Option<(usize, ChannelEvent)> {
+        for ch in 0..NUM_CHANNELS {
+            if self.next[ch].is_none() || !self.debug(ch).emitted() || self.guarding(ch, now) {
+                continue;
+            }
+            let pairs = self.next[ch].take().unwrap();
+            let mut params = self.params.clone();
+            params[ch].set_pairs(&pairs);
+            sync_mirrors(&mut params);
+            let result = match validate_allowing_unpaired(&params, self.pins.pio, self.disabled) {
+                Err(invalid) => Err(NextError::Invalid(invalid.first)),
+                Ok(()) => {
+                    self.params = params;
+                    self.rearm_linked(ch).map_err(NextError::Arm)
+                }
+            };
+            if let Err(err) = result {
+                self.disarm(ch);
+                self.next_failed[ch] = true;
+                return Some((ch, ChannelEvent::NextFailed(err)));
+            }
+        }
+        None
+    }
+
+    // Selects the channel's output GPIO, used from the next arm. Outside of
+    // staging the pin is checked right away.
+    pub fn set_pin(&mut self, ch: usize, pin: u8) -> Result<(), Violation> {
+        self.edit_checked(ch, |p| p.pin = pin)
+    }
+
+    pub fn pin(&self, ch: usize) -> u8 {
+        self.params[ch].pin
+    }
+
+    // Bit per GPIO the channel drives from the next arm, pin + 1 too when
+    // wide
+    pub fn pin_mask(&self, ch: usize) -> u32 {
+        self.params[ch].pins().fold(0, |pins, pin| pins | 1 << pin)
+    }
+
+    // Pulses drive the pins high: push-pull with the gap low
+    pub fn active_high(&self, ch: usize) -> bool {
+        let p = &self.params[ch];
+        p.output == OutputMode::PushPull && p.gap_side() == 0
+    }
+
+    // Switches the channel between the 1-bit output and the wide output
+    // driving pin and pin + 1, used from the next arm
+    pub fn set_wide(&mut self, ch: usize, wide: bool) -> Result<(), Violation> {
+        self.edit_checked(ch, |p| p.wide = wide)
+    }
+
+    // Releases the channel's pins to high impedance while it is disarmed
+    pub fn set_idle_tristate(&mut self, ch: usize, tristate: bool) {
+        self.edit(ch, |p| p.idle_tristate = tristate);
+        if !self.is_staging() {
+            self.hw0.set_idle_tristate(self.params[0].idle_tristate);
+            self.hw1.set_idle_tristate(self.params[1].idle_tristate);
+        }
+    }
+
+    // Applies to the channel's runs from then on
+    pub fn set_retrigger_policy(&mut self, ch: usize, policy: RetriggerPolicy) {
+        self.edit(ch, |p| p.retrigger = policy);
+    }
+
+    pub fn retrigger_policy(&self, ch: usize) -> RetriggerPolicy {
+        self.params[ch].retrigger
+    }
+
+    // Adds a marker output on pin + 1 around each pulse, None removes it.
+    // Used from the next arm.
+    pub fn set_marker(&mut self, ch: usize, marker: Option<Marker>) -> Result<(), Violation> {
+        self.edit_checked(ch, |p| p.marker = marker)
+    }
+
+    // Switches the channel to copying every trigger edge as a pulse of
+    // `width`, or back to its table with None. Used from the next arm.
+    pub fn set_trigger_out(&mut self, ch: usize, width: Option<Achieved>) -> Result<(), Violation> {
+        self.edit_checked(ch, |p| {
+            p.trigger_out = width.map(|width| width.cycles as u32)
+        })
+    }
+
+    // Selects push-pull or open-drain output, used from the next arm
+    pub fn set_output_mode(&mut self, ch: usize, output: OutputMode) -> Result<(), Violation> {
+        self.edit_checked(ch, |p| p.output = output)
+    }
+
+    // Appends a pulse with the pin pair levels it drives in wide mode
+    pub fn add_pulse_levels(
+        &mut self,
+        ch: usize,
+        delay: Achieved,
+        width: Achieved,
+        levels: u8,
+    ) -> Result<(), SequenceFull> {
+        self.edit(ch, |p| p.push_pulse(delay, width, levels))
+    }
+
+    // Applies a change to the channel's pins or program. Outside of staging
+    // the result is checked right away and rejected if invalid.
+    fn edit_checked(
+        &mut self,
+        ch: usize,
+        update: impl Fn(&mut PulseParameter),
+    ) -> Result<(), Violation> {
+        if self.staged.is_none() {
+            let mut params = self.params.clone();
+            update(&mut params[ch]);
+            sync_mirrors(&mut params);
+            validate_allowing_unpaired(&params, self.pins.pio, self.disabled)
+                .map_err(|invalid| invalid.first)?;
+        }
+        self.edit(ch, update);
+        Ok(())
+    }
+}
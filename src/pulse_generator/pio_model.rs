@@ -0,0 +1,279 @@
+// A reference model of one PIO state machine, cycle by cycle as the RP2040
+// datasheet describes it, for the host tests to run what compile() builds on
+// the words write_words() feeds it. It decodes the program's instruction
+// words itself rather than trusting the assembler, and only knows what the
+// pulse programs use: jmp, wait on the trigger pin, out with autopull at 32
+// bits, mov between the scratch registers, side-set with its enable bit and
+// instruction delays. Anything else panics.
+//
+// Loops of a single jmp counting down to themselves are run in one step, so
+// delays of 2^32 cycles and more take no longer than short ones.
+
+use std::collections::VecDeque;
+
+use pio::Program;
+
+// Inputs pass two flip-flops before the SM sees them
+pub const SYNC_CYCLES: u64 = 2;
+
+pub struct Sm<'a> {
+    code: &'a [u16],
+    wrap_source: u8,
+    wrap_target: u8,
+    // Including the enable bit
+    side_bits: u8,
+    side_optional: bool,
+    pc: u8,
+    x: u32,
+    y: u32,
+    isr: u32,
+    osr: u32,
+    // Bits shifted out of the OSR, 32 is empty
+    osr_count: u8,
+    fifo: VecDeque<u32>,
+    // Output levels of the pins from the pin base, as the side-set and
+    // `out pins` drive them. In open drain the side-set drives the pin
+    // directions instead, 1 still being the active level.
+    pins: u8,
+    delay: u8,
+    stalled: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stall {
+    // On an `out` with the FIFO empty, the end of a table
+    Out,
+    // On a `wait` for the trigger pin
+    Wait,
+}
+
+impl<'a> Sm<'a> {
+    // As ChannelHw::rewind() leaves it: at `start` with the pins at `side`,
+    // the OSR empty so the first `out` pulls
+    pub fn new(program: &'a Program<32>, start: u8, side: u8, words: &[u32]) -> Self {
+        Self {
+            code: &program.code,
+            wrap_source: program.wrap.source,
+            wrap_target: program.wrap.target,
+            side_bits: program.side_set.bits(),
+            side_optional: program.side_set.optional(),
+            pc: start,
+            x: 0,
+            y: 0,
+            isr: 0,
+            osr: 0,
+            osr_count: 32,
+            fifo: words.iter().copied().collect(),
+            pins: side,
+            delay: 0,
+            stalled: false,
+        }
+    }
+
+    pub fn pins(&self) -> u8 {
+        self.pins
+    }
+
+    // What the last step stalled on, if it did
+    pub fn stall(&self) -> Option<Stall> {
+        if !self.stalled {
+            return None;
+        }
+        match self.code[self.pc as usize] >> 13 {
+            0b001 => Some(Stall::Wait),
+            _ => Some(Stall::Out),
+        }
+    }
+
+    // Runs an instruction, or a delay cycle after one, with `input` as the
+    // synchronized trigger pin. Pins set by side-set or `out pins` change
+    // from the cycle the instruction runs. Returns the cycles taken.
+    pub fn step(&mut self, input: bool) -> u64 {
+        if self.delay > 0 {
+            self.delay -= 1;
+            return 1;
+        }
+        let instruction = self.code[self.pc as usize];
+        let field = (instruction >> 8) as u8 & 0x1f;
+        let delay_bits = 5 - self.side_bits;
+        let delay = field & ((1 << delay_bits) - 1);
+        // Side-set happens even when the instruction stalls
+        if self.side_bits > 0 {
+            let value_bits = self.side_bits - self.side_optional as u8;
+            let enabled = !self.side_optional || field & 0x10 != 0;
+            if enabled {
+                let mask = (1 << value_bits) - 1;
+                let value = field >> delay_bits & mask;
+                self.pins = self.pins & !mask | value;
+            }
+        }
+        self.stalled = false;
+        let operands = instruction & 0xff;
+        let mut cycles = 1;
+        let mut jump = None;
+        match instruction >> 13 {
+            0b000 => {
+                let condition = operands >> 5;
+                let address = (operands & 0x1f) as u8;
+                // A countdown to itself runs out in one go, falling through
+                // with the register decremented past zero
+                if address == self.pc && delay == 0 && matches!(condition, 2 | 4) {
+                    let register = if condition == 2 {
+                        &mut self.x
+                    } else {
+                        &mut self.y
+                    };
+                    cycles += *register as u64;
+                    *register = u32::MAX;
+                } else {
+                    let taken = match condition {
+                        0 => true,
+                        1 => self.x == 0,
+                        2 => {
+                            self.x = self.x.wrapping_sub(1);
+                            self.x != u32::MAX
+                        }
+                        3 => self.y == 0,
+                        4 => {
+                            self.y = self.y.wrapping_sub(1);
+                            self.y != u32::MAX
+                        }
+                        5 => self.x != self.y,
+                        _ => panic!("jmp condition {condition}"),
+                    };
+                    if taken {
+                        jump = Some(address);
+                    }
+                }
+            }
+            0b001 => {
+                let polarity = operands >> 7 & 1 != 0;
+                let source = operands >> 5 & 0b11;
+                assert_eq!((source, operands & 0x1f), (1, 0), "wait on pin 0 only");
+                if input != polarity {
+                    self.stalled = true;
+                    return 1;
+                }
+            }
+            0b011 => {
+                let destination = operands >> 5;
+                let count = match operands & 0x1f {
+                    0 => 32,
+                    count => count as u8,
+                };
+                // Autopull: an empty OSR is refilled before the shift, or
+                // the `out` stalls until there is a word
+                if self.osr_count >= 32 {
+                    let Some(word) = self.fifo.pop_front() else {
+                        self.stalled = true;
+                        return 1;
+                    };
+                    self.osr = word;
+                    self.osr_count = 0;
+                }
+                let data = if count == 32 {
+                    self.osr
+                } else {
+                    self.osr & ((1 << count) - 1)
+                };
+                // Shifting right, LSB first
+                self.osr = self.osr.checked_shr(count as u32).unwrap_or(0);
+                self.osr_count += count;
+                match destination {
+                    0 => self.pins = data as u8 & 0b11,
+                    1 => self.x = data,
+                    2 => self.y = data,
+                    3 => {}
+                    6 => self.isr = data,
+                    _ => panic!("out destination {destination}"),
+                }
+            }
+            0b101 => {
+                let destination = operands >> 5;
+                let op = operands >> 3 & 0b11;
+                assert_eq!(op, 0, "mov without an operation only");
+                let value = match operands & 0b111 {
+                    1 => self.x,
+                    2 => self.y,
+                    3 => 0,
+                    6 => self.isr,
+                    7 => self.osr,
+                    source => panic!("mov source {source}"),
+                };
+                match destination {
+                    1 => self.x = value,
+                    2 => self.y = value,
+                    6 => self.isr = value,
+                    _ => panic!("mov destination {destination}"),
+                }
+            }
+            opcode => panic!("opcode {opcode:03b}"),
+        }
+        self.pc = match jump {
+            Some(address) => address,
+            None if self.pc == self.wrap_source => self.wrap_target,
+            None => self.pc + 1,
+        };
+        self.delay = delay;
+        cycles
+    }
+}
+
+// What the pins did while a table ran
+pub struct Run {
+    // Cycle and pins of every change, starting from the pins at rest
+    pub pins: Vec<(u64, u8)>,
+    // Last rising edge at the trigger pin of each group, the one releasing
+    // the SM
+    pub releases: Vec<u64>,
+}
+
+// Runs the SM from `start` with `words` queued, driving the trigger pin
+// with a group of `groups[i]` rising edges each time the SM waits for one
+// and the edges before are over. Edges are 8 cycles apart and high for 4.
+// Ends with the SM stalled pulling from the empty FIFO, or waiting for an
+// edge once all groups went out.
+pub fn run(program: &Program<32>, start: u8, side: u8, words: &[u32], groups: &[u32]) -> Run {
+    let mut sm = Sm::new(program, start, side, words);
+    let mut trigger: Vec<(u64, bool)> = vec![(0, false)];
+    let level = |trigger: &[(u64, bool)], at: u64| {
+        trigger
+            .iter()
+            .rev()
+            .find(|&&(change, _)| change <= at)
+            .is_some_and(|&(_, high)| high)
+    };
+    let mut run = Run {
+        pins: vec![(0, side)],
+        releases: Vec::new(),
+    };
+    let mut edges_over = 0;
+    let mut t = 0;
+    for _ in 0..1_000_000 {
+        let seen = t >= SYNC_CYCLES && level(&trigger, t - SYNC_CYCLES);
+        let before = sm.pins();
+        let cycles = sm.step(seen);
+        if sm.pins() != before {
+            run.pins.push((t, sm.pins()));
+        }
+        t += cycles;
+        match sm.stall() {
+            Some(Stall::Out) => return run,
+            Some(Stall::Wait) if t > edges_over => {
+                let Some(&edges) = groups.get(run.releases.len()) else {
+                    return run;
+                };
+                let mut rise = t;
+                for i in 0..edges as u64 {
+                    rise = t + 4 + 8 * i;
+                    trigger.push((rise, true));
+                    trigger.push((rise + 4, false));
+                }
+                run.releases.push(rise);
+                edges_over = rise + 4 + SYNC_CYCLES;
+            }
+            _ => {}
+        }
+    }
+    panic!("the SM neither stalled nor waited");
+}
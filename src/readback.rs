@@ -0,0 +1,224 @@
+// Chunked readback of replies longer than a host can trust to one read of
+// the 64-byte CDC pipe, in binary mode. A FRAME_READ renders the whole reply
+// once and keeps it, the host then walks it chunk by chunk:
+//
+//   READ <source> <bytes> <chunks> <crc32>   the header, then chunk 0
+//   CHUNK <n> <hex> <crc32>                 CHUNK_LEN bytes of the reply
+//   READ END                                after the last chunk
+//
+// FRAME_CHUNK acks chunk n, which sends chunk n + 1, or asks for chunk n
+// again. A dropped or corrupt chunk costs that chunk only, and the header's
+// length and CRC-32 of the whole reply tell the host it has all of it. The
+// reply is kept until the next FRAME_READ, so any of its chunks can be asked
+// for again after a timeout.
+
+use arrayvec::ArrayVec;
+
+use crate::crc::crc32;
+use crate::snapshot::SNAP_MAX;
+use crate::text::Text;
+use crate::wire::ReadSource;
+
+// The largest reply, a snapshot
+pub const READBACK_MAX: usize = SNAP_MAX;
+// Reply bytes per CHUNK line, 64 hex digits
+pub const CHUNK_LEN: usize = 32;
+
+pub struct Readback {
+    source: ReadSource,
+    data: ArrayVec<u8, READBACK_MAX>,
+}
+
+impl Readback {
+    // Every source fits READBACK_MAX, a longer reply is cut there and the
+    // header's length and CRC say so
+    pub fn new(source: ReadSource, reply: &[u8]) -> Self {
+        let mut data = ArrayVec::new();
+        let _ = data.try_extend_from_slice(&reply[..reply.len().min(READBACK_MAX)]);
+        Self { source, data }
+    }
+
+    // 0 for an empty reply, whose header READ END follows
+    pub fn chunks(&self) -> usize {
+        self.data.len().div_ceil(CHUNK_LEN)
+    }
+
+    // The chunk a FRAME_CHUNK gets: the one after an acked chunk, past the
+    // last one for READ END, or the one asked for again. None for a chunk
+    // the reply doesn't have.
+    pub fn answer(&self, n: usize, ack: bool) -> Option<usize> {
+        if n >= self.chunks() {
+            return None;
+        }
+        Some(n + ack as usize)
+    }
+
+    // "READ <source> <bytes> <chunks> <crc32>"
+    pub fn write_header(&self, out: &mut impl Text) {
+        out.put("READ ")
+            .put(self.source.as_str())
+            .put(" ")
+            .dec(self.data.len())
+            .put(" ")
+            .dec(self.chunks())
+            .put(" ")
+            .hex0(crc32(&self.data), 8);
+    }
+
+    // "CHUNK <n> <hex> <crc32>", or "READ END" past the last chunk
+    pub fn write_chunk(&self, n: usize, out: &mut impl Text) {
+        let Some(chunk) = self.data.chunks(CHUNK_LEN).nth(n) else {
+            out.put("READ END");
+            return;
+        };
+        out.put("CHUNK ").dec(n).put(" ");
+        for &byte in chunk {
+            out.hex0(byte, 2);
+        }
+        out.put(" ").hex0(crc32(chunk), 8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+    use arrayvec::ArrayString;
+
+    type Line = ArrayString<128>;
+
+    fn header(readback: &Readback) -> String {
+        let mut line = Line::new();
+        readback.write_header(&mut line);
+        line.to_string()
+    }
+
+    fn chunk(readback: &Readback, n: usize) -> String {
+        let mut line = Line::new();
+        readback.write_chunk(n, &mut line);
+        line.to_string()
+    }
+
+    // What a host gets out of a CHUNK line, checked against its CRC
+    fn bytes_of(line: &str, n: usize) -> Vec<u8> {
+        let fields: Vec<&str> = line.split(' ').collect();
+        assert_eq!(fields.len(), 4, "{line}");
+        assert_eq!(fields[..2], ["CHUNK", n.to_string().as_str()]);
+        let bytes: Vec<u8> = (0..fields[2].len())
+            .step_by(2)
+            .map(|at| u8::from_str_radix(&fields[2][at..at + 2], 16).unwrap())
+            .collect();
+        assert_eq!(u32::from_str_radix(fields[3], 16), Ok(crc32(&bytes)));
+        bytes
+    }
+
+    #[test]
+    fn chunk_counts() {
+        for (len, chunks) in [
+            (0, 0),
+            (1, 1),
+            (CHUNK_LEN - 1, 1),
+            (CHUNK_LEN, 1),
+            (CHUNK_LEN + 1, 2),
+            (READBACK_MAX, READBACK_MAX / CHUNK_LEN),
+        ] {
+            let readback = Readback::new(ReadSource::Log, &vec![7; len]);
+            assert_eq!(readback.chunks(), chunks);
+            let crc = crc32(&vec![7; len]);
+            assert_eq!(
+                header(&readback),
+                format!("READ LOG {len} {chunks} {crc:08x}")
+            );
+        }
+    }
+
+    #[test]
+    fn empty_reply_is_only_the_header() {
+        let readback = Readback::new(ReadSource::Tlog, b"");
+        assert_eq!(header(&readback), "READ TLOG 0 0 00000000");
+        assert_eq!(chunk(&readback, 0), "READ END");
+        assert_eq!(readback.answer(0, true), None);
+        assert_eq!(readback.answer(0, false), None);
+    }
+
+    #[test]
+    fn acks_walk_to_read_end() {
+        let reply: Vec<u8> = (0..100).collect();
+        let readback = Readback::new(ReadSource::Capabilities, &reply);
+        assert_eq!(readback.chunks(), 4);
+        let mut got = bytes_of(&chunk(&readback, 0), 0);
+        let mut n = 0;
+        while let Some(next) = readback.answer(n, true) {
+            n = next;
+            if n == readback.chunks() {
+                assert_eq!(chunk(&readback, n), "READ END");
+                break;
+            }
+            got.extend(bytes_of(&chunk(&readback, n), n));
+        }
+        assert_eq!(n, 4);
+        assert_eq!(got, reply);
+        // Acking READ END's number is out of range
+        assert_eq!(readback.answer(4, true), None);
+    }
+
+    #[test]
+    fn resend_repeats_the_chunk() {
+        let reply: Vec<u8> = (0..=255).collect();
+        let readback = Readback::new(ReadSource::Snap, &reply);
+        for n in 0..readback.chunks() {
+            assert_eq!(readback.answer(n, false), Some(n));
+            let line = chunk(&readback, n);
+            assert_eq!(chunk(&readback, n), line);
+            assert_eq!(bytes_of(&line, n), reply[n * CHUNK_LEN..][..CHUNK_LEN]);
+        }
+        assert_eq!(readback.answer(readback.chunks(), false), None);
+        assert_eq!(readback.answer(usize::MAX, false), None);
+    }
+
+    #[test]
+    fn longer_replies_are_cut_at_readback_max() {
+        let reply = vec![1; READBACK_MAX + 10];
+        let readback = Readback::new(ReadSource::Snap, &reply);
+        assert_eq!(readback.chunks(), READBACK_MAX / CHUNK_LEN);
+        let crc = crc32(&reply[..READBACK_MAX]);
+        assert!(header(&readback).ends_with(&format!(
+            " {READBACK_MAX} {} {crc:08x}",
+            READBACK_MAX / CHUNK_LEN
+        )));
+    }
+
+    // A host that loses or garbles lines at random, resends what it didn't
+    // get and acks what it did, still ends up with the whole reply
+    #[test]
+    fn lossy_host_gets_everything() {
+        let mut rng = Rng::new(198);
+        for _ in 0..200 {
+            let reply: Vec<u8> = (0..rng.below(READBACK_MAX as u64 + 1))
+                .map(|_| rng.u8())
+                .collect();
+            let readback = Readback::new(ReadSource::Log, &reply);
+            let mut got = Vec::new();
+            let mut asked = 0;
+            let mut requests = 0;
+            loop {
+                let line = chunk(&readback, asked);
+                if line == "READ END" {
+                    break;
+                }
+                let next = if rng.one_in(4) {
+                    // Dropped or corrupt, its CRC wouldn't check out
+                    readback.answer(asked, false)
+                } else {
+                    got.extend(bytes_of(&line, asked));
+                    readback.answer(asked, true)
+                };
+                asked = next.unwrap();
+                requests += 1;
+                assert!(requests < 10 * (readback.chunks() + 1));
+            }
+            assert_eq!(got, reply);
+            assert_eq!(asked, readback.chunks());
+        }
+    }
+}
@@ -0,0 +1,178 @@
+// Rest level of the output connectors before any sequence runs: a pin can
+// be held low, held high or left undriven on its pull-down from just after
+// the config is read, before the channels' SMs exist. It is held with the
+// GPIO output override like the interlock's, so whatever a stopped SM
+// drives doesn't show. Starting a channel's SM takes the override off its
+// pins, *RST and a protection trip put it back on the disarmed ones. An
+// interlock trip overrides the pins low and wins, its release puts the
+// rest levels back.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::board::{self, hal::pac};
+use crate::interlock;
+use crate::probe::GPIO_COUNT;
+use crate::pulse_generator::{self, NUM_CHANNELS, TRIGGER_PIN};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rest {
+    Low,
+    High,
+    Tristate,
+}
+
+pub const RESTS: [Rest; 3] = [Rest::Low, Rest::High, Rest::Tristate];
+
+impl Rest {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rest::Low => "LOW",
+            Rest::High => "HIGH",
+            Rest::Tristate => "TRISTATE",
+        }
+    }
+}
+
+// Stored in the flash config, bit per GPIO and at most one mask per pin
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Settings {
+    pub low: u32,
+    pub high: u32,
+    pub tristate: u32,
+}
+
+impl Settings {
+    pub fn get(&self, pin: u8) -> Option<Rest> {
+        RESTS
+            .into_iter()
+            .find(|&rest| *self.mask(rest) & 1 << pin != 0)
+    }
+
+    // None leaves the pin to the channels
+    pub fn set(&mut self, pin: u8, rest: Option<Rest>) {
+        for other in RESTS {
+            *self.mask_mut(other) &= !(1 << pin);
+        }
+        if let Some(rest) = rest {
+            *self.mask_mut(rest) |= 1 << pin;
+        }
+    }
+
+    // Bit per GPIO with a rest level
+    pub fn pins(&self) -> u32 {
+        self.low | self.high | self.tristate
+    }
+
+    // No pin in two masks and every pin a valid_pin()
+    pub fn valid(&self) -> bool {
+        self.low & self.high == 0
+            && (self.low | self.high) & self.tristate == 0
+            && (0..GPIO_COUNT).all(|pin| self.pins() & 1 << pin == 0 || valid_pin(pin))
+            && self.pins() >> GPIO_COUNT == 0
+    }
+
+    fn mask(&self, rest: Rest) -> &u32 {
+        match rest {
+            Rest::Low => &self.low,
+            Rest::High => &self.high,
+            Rest::Tristate => &self.tristate,
+        }
+    }
+
+    fn mask_mut(&mut self, rest: Rest) -> &mut u32 {
+        match rest {
+            Rest::Low => &mut self.low,
+            Rest::High => &mut self.high,
+            Rest::Tristate => &mut self.tristate,
+        }
+    }
+}
+
+// Whether the pin can have a rest level, one of the board's PIO pins, pulled
+// down by board::init(), and not the trigger input. The interlock's and the
+// debug pin are checked by the caller.
+pub fn valid_pin(pin: u8) -> bool {
+    board::PINS.pio.contains(&pin) && pin != TRIGGER_PIN
+}
+
+static LOW: AtomicU32 = AtomicU32::new(0);
+static HIGH: AtomicU32 = AtomicU32::new(0);
+static TRISTATE: AtomicU32 = AtomicU32::new(0);
+// Pins the override currently holds, only stored with interrupts off as
+// thumbv6m has no atomic read-modify-write
+static HELD: AtomicU32 = AtomicU32::new(0);
+
+// Holds the stored rest levels, nothing is armed yet
+pub fn init(settings: Settings) {
+    configure(settings);
+}
+
+// Pins dropped from the settings go back to the channels right away
+pub fn configure(settings: Settings) {
+    LOW.store(settings.low, Ordering::Relaxed);
+    HIGH.store(settings.high, Ordering::Relaxed);
+    TRISTATE.store(settings.tristate, Ordering::Relaxed);
+    apply();
+}
+
+pub fn settings() -> Settings {
+    Settings {
+        low: LOW.load(Ordering::Relaxed),
+        high: HIGH.load(Ordering::Relaxed),
+        tristate: TRISTATE.load(Ordering::Relaxed),
+    }
+}
+
+// Puts the override on every pin with a rest level that no armed channel
+// drives, and takes it off pins that no longer have one. Left alone while
+// the interlock blocks, its own override holds the pins then.
+pub fn apply() {
+    let settings = settings();
+    let armed = (0..NUM_CHANNELS)
+        .filter(|&ch| pulse_generator::is_armed(ch))
+        .fold(0, |pins, ch| pins | pulse_generator::channel_outputs(ch));
+    let hold = settings.pins() & !armed;
+    // Safety: only the overrides of pins this module holds or is about to,
+    // with interrupts off so the interlock's handler can't interleave
+    let io = unsafe { &*pac::IO_BANK0::ptr() };
+    cortex_m::interrupt::free(|_| {
+        if interlock::blocks() {
+            return;
+        }
+        let held = HELD.load(Ordering::Relaxed);
+        for pin in 0..GPIO_COUNT {
+            let ctrl = io.gpio(pin as usize).gpio_ctrl();
+            if hold & 1 << pin != 0 {
+                ctrl.modify(|_, w| match settings.get(pin) {
+                    Some(Rest::Low) => w.outover().low().oeover().enable(),
+                    Some(Rest::High) => w.outover().high().oeover().enable(),
+                    _ => w.outover().normal().oeover().disable(),
+                });
+            } else if held & !armed & 1 << pin != 0 {
+                ctrl.modify(|_, w| w.outover().normal().oeover().normal());
+            }
+        }
+        HELD.store(held & armed | hold, Ordering::Relaxed);
+    });
+}
+
+// Hands the pins back to PIO, called as a channel's SM starts
+pub fn release(pins: u32) {
+    if HELD.load(Ordering::Relaxed) & pins == 0 {
+        return;
+    }
+    // Safety: as in apply()
+    let io = unsafe { &*pac::IO_BANK0::ptr() };
+    cortex_m::interrupt::free(|_| {
+        if interlock::blocks() {
+            return;
+        }
+        let held = HELD.load(Ordering::Relaxed);
+        for pin in (0..GPIO_COUNT).filter(|pin| held & pins & 1 << pin != 0) {
+            io.gpio(pin as usize)
+                .gpio_ctrl()
+                .modify(|_, w| w.outover().normal().oeover().normal());
+        }
+        HELD.store(held & !pins, Ordering::Relaxed);
+    });
+}
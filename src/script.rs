@@ -0,0 +1,97 @@
+// Command scripts for running without a host: serial command lines stored
+// in flash with SCRIPT BEGIN .. SCRIPT END, run at power-on or with RUN.
+// The main loop runs one line per pass, so USB keeps being served through
+// a script and its SLEEPs. WAITDONE is the exception, it holds the loop
+// for up to WAITDONE_MAX_US.
+
+use arrayvec::ArrayVec;
+
+pub const SCRIPT_MAX: usize = 1024;
+// Longest a WAITDONE waits, longer timeouts are cut to it
+pub const WAITDONE_MAX_US: u32 = 1_000_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Flags {
+    // Run at power-on, unless ARM_BUTTON is held
+    pub boot: bool,
+    // Lines may change the stored settings
+    pub unrestricted: bool,
+}
+
+#[derive(Clone, Default)]
+pub struct Script {
+    pub flags: Flags,
+    // Non-empty lines, each ended by '\n'
+    pub text: ArrayVec<u8, SCRIPT_MAX>,
+}
+
+impl Script {
+    pub fn new(flags: Flags) -> Self {
+        Self {
+            flags,
+            text: ArrayVec::new(),
+        }
+    }
+
+    // Returns false, leaving the script as it was, if the line doesn't fit
+    pub fn push_line(&mut self, line: &[u8]) -> bool {
+        if self.text.remaining_capacity() < line.len() + 1 {
+            return false;
+        }
+        self.text.try_extend_from_slice(line).unwrap();
+        self.text.push(b'\n');
+        true
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &[u8]> {
+        self.text
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines().count()
+    }
+}
+
+pub enum Step<'a> {
+    // Sleeping
+    Wait,
+    // 1-based line number and the line to run
+    Line(usize, &'a [u8]),
+    // Every line ran, with the number of lines
+    Done(usize),
+}
+
+// Position in a running script
+pub struct Runner {
+    next: usize,
+    resume_at: u64,
+}
+
+impl Runner {
+    pub fn new() -> Self {
+        Self {
+            next: 0,
+            resume_at: 0,
+        }
+    }
+
+    pub fn poll<'a>(&mut self, script: &'a Script, now: u64) -> Step<'a> {
+        if now < self.resume_at {
+            return Step::Wait;
+        }
+        match script.lines().nth(self.next) {
+            Some(line) => {
+                self.next += 1;
+                Step::Line(self.next, line)
+            }
+            None => Step::Done(self.next),
+        }
+    }
+
+    // Holds off the next line until `until`
+    pub fn sleep(&mut self, until: u64) {
+        self.resume_at = until;
+    }
+}
@@ -0,0 +1,283 @@
+// Byte layout of SNAP? blobs: the magic, the version, the fields written by
+// PulseGenerator::snapshot() in order, then a CRC-32 of everything before it.
+// Little-endian like the flash records and the binary frames. A blob is only
+// read back by the same layout version, there is no migration.
+
+use arrayvec::ArrayVec;
+
+use crate::crc::crc32;
+
+// Two channels of full tables with their requested durations and levels,
+// plus settings and groups, 2069 bytes at most. A multiple of SNAP_CHUNK.
+pub const SNAP_MAX: usize = 2304;
+// Blob bytes per SNAP line, 128 hex digits
+pub const SNAP_CHUNK: usize = 64;
+// "PPSN"
+const MAGIC: u32 = 0x4e53_5050;
+const VERSION: u8 = 10;
+const HEADER_LEN: usize = 5;
+const CRC_LEN: usize = 4;
+
+pub type Blob = ArrayVec<u8, SNAP_MAX>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SnapError {
+    // Truncated, too long, bad CRC or a field out of range
+    Corrupt,
+    // Written by another layout version
+    Version,
+}
+
+impl SnapError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SnapError::Corrupt => "CORRUPT",
+            SnapError::Version => "VERSION",
+        }
+    }
+}
+
+pub struct Writer {
+    blob: Blob,
+}
+
+impl Writer {
+    pub fn start() -> Self {
+        let mut writer = Self { blob: Blob::new() };
+        writer.u32(MAGIC);
+        writer.u8(VERSION);
+        writer
+    }
+
+    // SNAP_MAX holds the largest configuration, a full blob is a bug
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.blob
+            .try_extend_from_slice(bytes)
+            .expect("snapshot exceeds SNAP_MAX");
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes(&[value]);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    // A length byte, then the items
+    pub fn list<T>(
+        &mut self,
+        items: impl ExactSizeIterator<Item = T>,
+        mut item: impl FnMut(&mut Self, T),
+    ) {
+        self.u8(items.len() as u8);
+        for value in items {
+            item(self, value);
+        }
+    }
+
+    pub fn finish(mut self) -> Blob {
+        let crc = crc32(&self.blob);
+        self.u32(crc);
+        self.blob
+    }
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    // Checks the CRC, magic and version, the reader starts at the first field
+    pub fn open(blob: &'a [u8]) -> Result<Self, SnapError> {
+        if blob.len() < HEADER_LEN + CRC_LEN {
+            return Err(SnapError::Corrupt);
+        }
+        let (body, crc) = blob.split_at(blob.len() - CRC_LEN);
+        if crc32(body) != u32::from_le_bytes(crc.try_into().unwrap()) {
+            return Err(SnapError::Corrupt);
+        }
+        let mut reader = Self { bytes: body };
+        if reader.u32()? != MAGIC {
+            return Err(SnapError::Corrupt);
+        }
+        if reader.u8()? != VERSION {
+            return Err(SnapError::Version);
+        }
+        Ok(reader)
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], SnapError> {
+        if self.bytes.len() < N {
+            return Err(SnapError::Corrupt);
+        }
+        let (taken, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        Ok(taken.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SnapError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SnapError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SnapError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SnapError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapError::Corrupt),
+        }
+    }
+
+    // A list written by Writer::list(), more than N items is corrupt
+    pub fn list<T, const N: usize>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, SnapError>,
+    ) -> Result<ArrayVec<T, N>, SnapError> {
+        let len = self.u8()? as usize;
+        if len > N {
+            return Err(SnapError::Corrupt);
+        }
+        let mut items = ArrayVec::new();
+        for _ in 0..len {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    // Bytes left over mean the layout doesn't match
+    pub fn finish(self) -> Result<(), SnapError> {
+        if !self.bytes.is_empty() {
+            return Err(SnapError::Corrupt);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Blob {
+        let mut w = Writer::start();
+        w.u8(7);
+        w.u32(0xdead_beef);
+        w.u64(u64::MAX - 1);
+        w.list([1u8, 2, 3].into_iter(), Writer::u8);
+        w.u8(1);
+        w.finish()
+    }
+
+    fn resealed(mut blob: Blob) -> Blob {
+        let body = blob.len() - CRC_LEN;
+        let crc = crc32(&blob[..body]);
+        blob[body..].copy_from_slice(&crc.to_le_bytes());
+        blob
+    }
+
+    #[test]
+    fn fields_read_back_in_order() {
+        let blob = sample();
+        let mut r = Reader::open(&blob).unwrap();
+        assert_eq!(r.u8(), Ok(7));
+        assert_eq!(r.u32(), Ok(0xdead_beef));
+        assert_eq!(r.u64(), Ok(u64::MAX - 1));
+        let list: ArrayVec<u8, 3> = r.list(Reader::u8).unwrap();
+        assert_eq!(list.as_slice(), [1, 2, 3]);
+        assert_eq!(r.bool(), Ok(true));
+        assert_eq!(r.finish(), Ok(()));
+    }
+
+    #[test]
+    fn any_flipped_bit_fails_the_crc() {
+        let blob = sample();
+        for at in 0..blob.len() {
+            for bit in 0..8 {
+                let mut bad = blob.clone();
+                bad[at] ^= 1 << bit;
+                assert_eq!(
+                    Reader::open(&bad).err(),
+                    Some(SnapError::Corrupt),
+                    "byte {at} bit {bit}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn other_versions_are_refused_even_with_a_good_crc() {
+        for version in (0..=u8::MAX).filter(|&v| v != VERSION) {
+            let mut blob = sample();
+            blob[4] = version;
+            assert_eq!(
+                Reader::open(&resealed(blob)).err(),
+                Some(SnapError::Version),
+                "version {version}"
+            );
+        }
+    }
+
+    #[test]
+    fn bad_magic_and_short_blobs_are_corrupt() {
+        let mut blob = sample();
+        blob[0] ^= 0x20;
+        assert_eq!(
+            Reader::open(&resealed(blob)).err(),
+            Some(SnapError::Corrupt)
+        );
+        let blob = sample();
+        for len in 0..HEADER_LEN + CRC_LEN {
+            assert_eq!(Reader::open(&blob[..len]).err(), Some(SnapError::Corrupt));
+        }
+    }
+
+    #[test]
+    fn reading_past_the_end_or_stopping_short_is_corrupt() {
+        let blob = sample();
+        let mut r = Reader::open(&blob).unwrap();
+        for _ in 0..blob.len() - HEADER_LEN - CRC_LEN {
+            r.u8().unwrap();
+        }
+        assert_eq!(r.u8(), Err(SnapError::Corrupt));
+        let mut r = Reader::open(&blob).unwrap();
+        r.u8().unwrap();
+        assert_eq!(r.finish(), Err(SnapError::Corrupt));
+    }
+
+    #[test]
+    fn lists_longer_than_their_capacity_are_corrupt() {
+        let blob = sample();
+        let mut r = Reader::open(&blob).unwrap();
+        r.u8().unwrap();
+        r.u32().unwrap();
+        r.u64().unwrap();
+        assert_eq!(r.list::<u8, 2>(Reader::u8).err(), Some(SnapError::Corrupt));
+    }
+
+    #[test]
+    fn bools_are_zero_or_one() {
+        for byte in 0..=u8::MAX {
+            let mut w = Writer::start();
+            w.u8(byte);
+            let blob = w.finish();
+            let mut r = Reader::open(&blob).unwrap();
+            let expected = match byte {
+                0 => Ok(false),
+                1 => Ok(true),
+                _ => Err(SnapError::Corrupt),
+            };
+            assert_eq!(r.bool(), expected);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+// Text and number output for the serial responses without core::fmt and
+// its formatting machinery. Each method produces what the format string it
+// replaced did, so responses are unchanged byte for byte.
+
+use arrayvec::ArrayString;
+
+const DIGITS: &[u8; 16] = b"0123456789abcdef";
+// u64::MAX in binary
+const DIGITS_MAX: usize = 64;
+
+// Integers written by value
+pub trait Unsigned: Copy {
+    fn widen(self) -> u64;
+}
+
+macro_rules! unsigned {
+    ($($t:ty),*) => {
+        $(impl Unsigned for $t {
+            fn widen(self) -> u64 {
+                self as u64
+            }
+        })*
+    };
+}
+unsigned!(u8, u16, u32, u64, usize);
+
+pub trait Text {
+    // Appends s, or nothing if it doesn't all fit, as write! into an
+    // ArrayString did
+    fn put(&mut self, s: &str) -> &mut Self;
+
+    // "{}"
+    fn dec(&mut self, n: impl Unsigned) -> &mut Self {
+        self.radix(n.widen(), 10, 0)
+    }
+
+    // "{:0width$}"
+    fn dec0(&mut self, n: impl Unsigned, width: usize) -> &mut Self {
+        self.radix(n.widen(), 10, width)
+    }
+
+    // "{:0width$x}"
+    fn hex0(&mut self, n: impl Unsigned, width: usize) -> &mut Self {
+        self.radix(n.widen(), 16, width)
+    }
+
+    // "{:0width$b}"
+    fn bin0(&mut self, n: impl Unsigned, width: usize) -> &mut Self {
+        self.radix(n.widen(), 2, width)
+    }
+
+    fn radix(&mut self, mut n: u64, radix: u64, width: usize) -> &mut Self {
+        let mut buf = [b'0'; DIGITS_MAX];
+        let mut start = DIGITS_MAX;
+        loop {
+            start -= 1;
+            buf[start] = DIGITS[(n % radix) as usize];
+            n /= radix;
+            if n == 0 {
+                break;
+            }
+        }
+        // The buffer is pre-filled with the padding
+        let start = start.min(DIGITS_MAX - width.min(DIGITS_MAX));
+        // Safety: only ASCII digits were written
+        self.put(unsafe { core::str::from_utf8_unchecked(&buf[start..]) })
+    }
+}
+
+impl<const N: usize> Text for ArrayString<N> {
+    fn put(&mut self, s: &str) -> &mut Self {
+        let _ = self.try_push_str(s);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    type Line = ArrayString<128>;
+
+    fn text(f: impl FnOnce(&mut Line) -> &mut Line) -> String {
+        let mut s = Line::new();
+        f(&mut s);
+        s.to_string()
+    }
+
+    const EDGES: &[u64] = &[
+        0,
+        1,
+        9,
+        10,
+        15,
+        16,
+        99,
+        100,
+        255,
+        256,
+        u16::MAX as u64,
+        u32::MAX as u64,
+        u32::MAX as u64 + 1,
+        999_999_999_999,
+        u64::MAX - 1,
+        u64::MAX,
+    ];
+
+    #[test]
+    fn edge_values_match_core_fmt() {
+        for &n in EDGES {
+            assert_eq!(text(|s| s.dec(n)), format!("{n}"));
+            for width in [0, 1, 2, 3, 8, 20, 21, 63, 64] {
+                assert_eq!(text(|s| s.dec0(n, width)), format!("{n:0width$}"));
+                assert_eq!(text(|s| s.hex0(n, width)), format!("{n:0width$x}"));
+                assert_eq!(text(|s| s.bin0(n, width)), format!("{n:0width$b}"));
+            }
+        }
+    }
+
+    #[test]
+    fn every_integer_type() {
+        assert_eq!(text(|s| s.dec(u8::MAX)), "255");
+        assert_eq!(text(|s| s.dec(u16::MAX)), "65535");
+        assert_eq!(text(|s| s.dec(u32::MAX)), "4294967295");
+        assert_eq!(text(|s| s.dec(usize::MAX)), usize::MAX.to_string());
+        assert_eq!(text(|s| s.bin0(u64::MAX, 0)), "1".repeat(64));
+    }
+
+    #[test]
+    fn random_values_match_core_fmt() {
+        let mut rng = Rng::new(137);
+        for _ in 0..10_000 {
+            // Every magnitude, not just the huge values a plain u64 gives
+            let n = rng.u64() >> rng.below(64);
+            let width = rng.below(30) as usize;
+            assert_eq!(text(|s| s.dec0(n, width)), format!("{n:0width$}"));
+            assert_eq!(text(|s| s.hex0(n, width)), format!("{n:0width$x}"));
+        }
+    }
+
+    // Padding past DIGITS_MAX is cut there, no response asks for more
+    #[test]
+    fn padding_stops_at_digits_max() {
+        assert_eq!(text(|s| s.dec0(7u8, 100)), format!("{:064}", 7u8));
+    }
+
+    #[test]
+    fn what_doesnt_fit_is_left_out_whole() {
+        let mut s = ArrayString::<6>::new();
+        s.put("ab").dec(1234u32).dec(5u8).put("xyz");
+        assert_eq!(s.as_str(), "ab1234");
+        let mut s = ArrayString::<4>::new();
+        s.dec(12345u32).put("ok");
+        assert_eq!(s.as_str(), "ok");
+    }
+}
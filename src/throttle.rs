@@ -0,0 +1,116 @@
+// Rate limit for the error lines the main loop sends unasked. A miswired
+// channel re-armed over and over can underrun many times a second, so past
+// BURST lines of one kind and channel within WINDOW_US the rest are only
+// counted, and once the window closes a single line says how many were held
+// back. Arming starts every kind afresh. ERR? has the counts of everything,
+// sent or not. Fixed size and a few compares per call, so it would do in an
+// interrupt handler too, behind a critical section.
+
+use crate::pulse_generator::NUM_CHANNELS;
+
+// Lines of one kind and channel sent per window
+pub const BURST: u32 = 5;
+pub const WINDOW_US: u64 = 1_000_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    Underrun,
+    Next,
+    Guard,
+}
+
+pub const KINDS: [Kind; 3] = [Kind::Underrun, Kind::Next, Kind::Guard];
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Underrun => "UNDERRUN",
+            Kind::Next => "NEXT",
+            Kind::Guard => "GUARD",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Slot {
+    // Timer ticks (us) the window opened, None with none open
+    window_at: Option<u64>,
+    sent: u32,
+    held: u32,
+    // Since power-on
+    total: u32,
+}
+
+fn open(slot: &Slot, now: u64) -> bool {
+    slot.window_at
+        .is_some_and(|at| now.saturating_sub(at) < WINDOW_US)
+}
+
+pub struct Throttle {
+    slots: [Slot; KINDS.len() * NUM_CHANNELS],
+}
+
+impl Throttle {
+    pub const fn new() -> Self {
+        Self {
+            slots: [Slot {
+                window_at: None,
+                sent: 0,
+                held: 0,
+                total: 0,
+            }; KINDS.len() * NUM_CHANNELS],
+        }
+    }
+
+    fn slot(&mut self, kind: Kind, ch: usize) -> &mut Slot {
+        &mut self.slots[kind as usize * NUM_CHANNELS + ch]
+    }
+
+    // Counts an occurrence, true if its line is to be sent
+    pub fn allow(&mut self, kind: Kind, ch: usize, now: u64) -> bool {
+        let slot = self.slot(kind, ch);
+        slot.total = slot.total.saturating_add(1);
+        if !open(slot, now) && slot.held == 0 {
+            *slot = Slot {
+                window_at: Some(now),
+                total: slot.total,
+                ..Slot::default()
+            };
+        }
+        if slot.sent < BURST {
+            slot.sent += 1;
+            return true;
+        }
+        slot.held += 1;
+        false
+    }
+
+    // A kind and channel whose window closed with lines held back, and how
+    // many, once each. Polled from the main loop.
+    pub fn poll(&mut self, now: u64) -> Option<(Kind, usize, u32)> {
+        let index = self
+            .slots
+            .iter()
+            .position(|slot| slot.held != 0 && !open(slot, now))?;
+        let slot = &mut self.slots[index];
+        let held = slot.held;
+        *slot = Slot {
+            total: slot.total,
+            ..Slot::default()
+        };
+        Some((KINDS[index / NUM_CHANNELS], index % NUM_CHANNELS, held))
+    }
+
+    // Closes every window, the lines held back are reported by the next
+    // poll()s and the next occurrences are sent again
+    pub fn reset(&mut self) {
+        for slot in &mut self.slots {
+            slot.window_at = None;
+        }
+    }
+
+    // Occurrences of the kind on the channel since power-on
+    pub fn total(&self, kind: Kind, ch: usize) -> u32 {
+        self.slots[kind as usize * NUM_CHANNELS + ch].total
+    }
+}
@@ -0,0 +1,79 @@
+// Internal trigger source: a PWM slice puts a rising edge on a spare GPIO
+// every period, and the channels wait on that pin instead of the trigger
+// input. The edges come from the PWM counter alone, so each train starts
+// the same number of cycles after its tick.
+
+use crate::board::hal::pac;
+
+// The PWM counter wraps at 2^16 counts, the integer divider goes to 255
+const COUNTS_MAX: u64 = 1 << 16;
+const DIV_MAX: u64 = 255;
+// Long enough a high phase for the input synchronizer and the `wait`
+pub const PERIOD_MIN: u64 = 16;
+pub const PERIOD_MAX: u64 = COUNTS_MAX * DIV_MAX;
+
+// Starts the slice behind `pin` on a rising edge every `period` cycles,
+// rounded down to a multiple of the divider, and returns the period it
+// runs at. The pin is taken over by the PWM until stop().
+pub fn start(pin: u8, period: u64) -> u64 {
+    let period = period.clamp(PERIOD_MIN, PERIOD_MAX);
+    let div = period.div_ceil(COUNTS_MAX);
+    let counts = period / div;
+
+    // Safety: only the PWM block, which nothing else uses, and this pin's
+    // pad and function select are touched
+    let resets = unsafe { &*pac::RESETS::ptr() };
+    resets.reset().modify(|_, w| w.pwm().clear_bit());
+    while resets.reset_done().read().pwm().bit_is_clear() {}
+    let pwm = unsafe { &*pac::PWM::ptr() };
+    let io = unsafe { &*pac::IO_BANK0::ptr() };
+    let pads = unsafe { &*pac::PADS_BANK0::ptr() };
+
+    let slice = pwm.ch(slice(pin));
+    slice.csr().write(|w| w.en().clear_bit());
+    slice
+        .div()
+        .write(|w| unsafe { w.int().bits(div as u8).frac().bits(0) });
+    slice
+        .top()
+        .write(|w| unsafe { w.top().bits((counts - 1) as u16) });
+    // High for the first half, the rising edge is the counter wrapping
+    let high = (counts / 2) as u16;
+    slice.cc().write(|w| unsafe {
+        if pin % 2 == 0 {
+            w.a().bits(high)
+        } else {
+            w.b().bits(high)
+        }
+    });
+    slice.ctr().write(|w| unsafe { w.ctr().bits(0) });
+    pads.gpio(pin as usize)
+        .modify(|_, w| w.ie().set_bit().od().clear_bit());
+    io.gpio(pin as usize)
+        .gpio_ctrl()
+        .write(|w| w.funcsel().pwm());
+    slice.csr().write(|w| w.en().set_bit());
+    div * counts
+}
+
+// Stops the ticks and hands the pin back to PIO0, which the board gave it at
+// init if it is a PIO pin, or to nothing. The pin is left floating either
+// way, channels aren't driving it.
+pub fn stop(pin: u8, pio: bool) {
+    // Safety: as in start()
+    let pwm = unsafe { &*pac::PWM::ptr() };
+    let io = unsafe { &*pac::IO_BANK0::ptr() };
+    pwm.ch(slice(pin)).csr().write(|w| w.en().clear_bit());
+    io.gpio(pin as usize).gpio_ctrl().write(|w| {
+        if pio {
+            w.funcsel().pio0()
+        } else {
+            w.funcsel().null()
+        }
+    });
+}
+
+// Two GPIOs per slice, from GPIO16 on the slices repeat
+fn slice(pin: u8) -> usize {
+    (pin as usize / 2) % 8
+}
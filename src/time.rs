@@ -0,0 +1,280 @@
+// Conversions between system clock cycles, nanoseconds and picoseconds.
+// Cycles and Nanos wrap counts that cross the PulseGenerator API, so one
+// can't be passed for the other.
+
+use crate::text::Text;
+
+pub const PS_PER_NS: u64 = 1_000;
+pub const PS_PER_US: u64 = 1_000_000;
+pub const PS_PER_MS: u64 = 1_000_000_000;
+pub const PS_PER_S: u64 = 1_000_000_000_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TimeError {
+    BelowResolution,
+    OutOfRange,
+}
+
+// How durations between two cycle counts are converted
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rounding {
+    Nearest,
+    Down,
+    // Never shorter than requested
+    Up,
+}
+
+impl Rounding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rounding::Nearest => "NEAREST",
+            Rounding::Down => "DOWN",
+            Rounding::Up => "UP",
+        }
+    }
+}
+
+// A duration as requested and the whole cycles it was converted to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Achieved {
+    pub requested_ps: u64,
+    pub cycles: u64,
+}
+
+impl Achieved {
+    // Bare cycle counts are achieved exactly
+    pub fn from_cycles(cycles: u64, sys_hz: u32) -> Self {
+        Self {
+            requested_ps: cycles_to_ps(cycles, sys_hz),
+            cycles,
+        }
+    }
+
+    pub fn from_ps(ps: u64, sys_hz: u32, rounding: Rounding) -> Result<Self, TimeError> {
+        Ok(Self {
+            requested_ps: ps,
+            cycles: ps_to_cycles(ps, sys_hz, rounding)?,
+        })
+    }
+}
+
+// System clock cycles as a table word counts them
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub struct Cycles(pub u32);
+
+// Counts above u32::MAX don't fit a word
+impl TryFrom<u64> for Cycles {
+    type Error = TimeError;
+
+    fn try_from(cycles: u64) -> Result<Self, TimeError> {
+        u32::try_from(cycles)
+            .map(Cycles)
+            .map_err(|_| TimeError::OutOfRange)
+    }
+}
+
+// Nanoseconds, as TLOG? reports a trigger's edges
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub struct Nanos(pub u64);
+
+impl Nanos {
+    // Cycles counted from an arm or a trigger, which can run past u32.
+    // Below 1GHz a count near u64::MAX is more nanoseconds than fit, those
+    // saturate, though no count gets there in centuries.
+    pub fn from_cycles(cycles: u64, sys_hz: u32, rounding: Rounding) -> Self {
+        let ns = divide(cycles as u128 * 1_000_000_000, sys_hz as u128, rounding);
+        Nanos(u64::try_from(ns).unwrap_or(u64::MAX))
+    }
+}
+
+fn divide(n: u128, d: u128, rounding: Rounding) -> u128 {
+    match rounding {
+        Rounding::Nearest => (n + d / 2) / d,
+        Rounding::Down => n / d,
+        Rounding::Up => n.div_ceil(d),
+    }
+}
+
+pub fn cycles_to_ps(cycles: u64, sys_hz: u32) -> u64 {
+    (cycles as u128 * PS_PER_S as u128 / sys_hz as u128) as u64
+}
+
+// A non-zero duration that rounds to zero cycles is rejected rather than
+// silently dropped. The result always fits, the clock runs below 1THz.
+pub fn ps_to_cycles(ps: u64, sys_hz: u32, rounding: Rounding) -> Result<u64, TimeError> {
+    let cycles = divide(ps as u128 * sys_hz as u128, PS_PER_S as u128, rounding);
+    if cycles == 0 && ps > 0 {
+        return Err(TimeError::BelowResolution);
+    }
+    Ok(cycles as u64)
+}
+
+// Writes a duration with three decimals in the largest unit that keeps the
+// integer part non-zero, e.g. "12.500us"
+pub fn write_ps(w: &mut impl Text, ps: u64) {
+    let (unit, name) = if ps >= PS_PER_S {
+        (PS_PER_S, "s")
+    } else if ps >= PS_PER_MS {
+        (PS_PER_MS, "ms")
+    } else if ps >= PS_PER_US {
+        (PS_PER_US, "us")
+    } else {
+        (PS_PER_NS, "ns")
+    };
+    w.dec(ps / unit)
+        .put(".")
+        .dec0(ps % unit / (unit / 1000), 3)
+        .put(name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrayvec::ArrayString;
+
+    fn ps(ps: u64) -> String {
+        let mut s = ArrayString::<32>::new();
+        write_ps(&mut s, ps);
+        s.to_string()
+    }
+
+    const MHZ_125: u32 = 125_000_000;
+    const MHZ_250: u32 = 250_000_000;
+    const MHZ_133: u32 = 133_000_000;
+
+    #[test]
+    fn cycles_take_counts_up_to_u32_max() {
+        assert_eq!(Cycles::try_from(0), Ok(Cycles(0)));
+        assert_eq!(Cycles::try_from(u32::MAX as u64), Ok(Cycles(u32::MAX)));
+        assert_eq!(
+            Cycles::try_from(u32::MAX as u64 + 1),
+            Err(TimeError::OutOfRange)
+        );
+        assert_eq!(Cycles::try_from(u64::MAX), Err(TimeError::OutOfRange));
+    }
+
+    #[test]
+    fn nanos_from_cycles() {
+        for rounding in [Rounding::Nearest, Rounding::Down, Rounding::Up] {
+            assert_eq!(Nanos::from_cycles(0, MHZ_125, rounding), Nanos(0));
+            assert_eq!(Nanos::from_cycles(1, MHZ_125, rounding), Nanos(8));
+            assert_eq!(Nanos::from_cycles(1, MHZ_250, rounding), Nanos(4));
+            assert_eq!(
+                Nanos::from_cycles(MHZ_250 as u64, MHZ_250, rounding),
+                Nanos(1_000_000_000)
+            );
+        }
+        // 7.52ns a cycle
+        assert_eq!(Nanos::from_cycles(1, MHZ_133, Rounding::Down), Nanos(7));
+        assert_eq!(Nanos::from_cycles(1, MHZ_133, Rounding::Nearest), Nanos(8));
+        assert_eq!(Nanos::from_cycles(1, MHZ_133, Rounding::Up), Nanos(8));
+        // 3.76ns
+        assert_eq!(
+            Nanos::from_cycles(1, 2 * MHZ_133, Rounding::Nearest),
+            Nanos(4)
+        );
+        assert_eq!(Nanos::from_cycles(1, 2 * MHZ_133, Rounding::Down), Nanos(3));
+    }
+
+    #[test]
+    fn nanos_saturate() {
+        let near_max = u64::MAX / 4;
+        assert_eq!(
+            Nanos::from_cycles(near_max, MHZ_250, Rounding::Down),
+            Nanos(near_max * 4)
+        );
+        assert_eq!(
+            Nanos::from_cycles(near_max + 1, MHZ_250, Rounding::Down),
+            Nanos(u64::MAX)
+        );
+        assert_eq!(
+            Nanos::from_cycles(u64::MAX, MHZ_125, Rounding::Up),
+            Nanos(u64::MAX)
+        );
+    }
+
+    #[test]
+    fn ps_to_cycles_rounding() {
+        // 4ns a cycle at 250MHz
+        for (ps, nearest, down, up) in [
+            (0, Ok(0), Ok(0), Ok(0)),
+            (
+                1,
+                Err(TimeError::BelowResolution),
+                Err(TimeError::BelowResolution),
+                Ok(1),
+            ),
+            (
+                1_999,
+                Err(TimeError::BelowResolution),
+                Err(TimeError::BelowResolution),
+                Ok(1),
+            ),
+            (2_000, Ok(1), Err(TimeError::BelowResolution), Ok(1)),
+            (4_000, Ok(1), Ok(1), Ok(1)),
+            (5_999, Ok(1), Ok(1), Ok(2)),
+            (6_000, Ok(2), Ok(1), Ok(2)),
+        ] {
+            assert_eq!(
+                ps_to_cycles(ps, MHZ_250, Rounding::Nearest),
+                nearest,
+                "{ps}"
+            );
+            assert_eq!(ps_to_cycles(ps, MHZ_250, Rounding::Down), down, "{ps}");
+            assert_eq!(ps_to_cycles(ps, MHZ_250, Rounding::Up), up, "{ps}");
+        }
+        assert_eq!(
+            ps_to_cycles(u64::MAX, MHZ_250, Rounding::Up),
+            Ok((u64::MAX as u128 * MHZ_250 as u128).div_ceil(PS_PER_S as u128) as u64)
+        );
+    }
+
+    #[test]
+    fn cycles_to_ps_and_back() {
+        assert_eq!(cycles_to_ps(1, MHZ_125), 8_000);
+        assert_eq!(cycles_to_ps(1, MHZ_250), 4_000);
+        assert_eq!(cycles_to_ps(3, MHZ_133), 22_556);
+        let mut rng = crate::rng::Rng::new(199);
+        for _ in 0..10_000 {
+            // Below 2^48, whose picoseconds still fit at 48MHz
+            let cycles = rng.u64() >> (16 + rng.below(48));
+            let sys_hz = *rng.pick(&[MHZ_125, MHZ_133, MHZ_250, 48_000_000]);
+            let ps = cycles_to_ps(cycles, sys_hz);
+            // cycles_to_ps cuts, so rounding up gets the count back
+            assert_eq!(ps_to_cycles(ps, sys_hz, Rounding::Up), Ok(cycles));
+            let achieved = Achieved::from_cycles(cycles, sys_hz);
+            assert_eq!(achieved.cycles, cycles);
+            assert_eq!(achieved.requested_ps, ps);
+        }
+    }
+
+    #[test]
+    fn write_ps_unit_boundaries() {
+        assert_eq!(ps(0), "0.000ns");
+        assert_eq!(ps(1), "0.001ns");
+        assert_eq!(ps(999), "0.999ns");
+        assert_eq!(ps(PS_PER_NS), "1.000ns");
+        assert_eq!(ps(PS_PER_US - 1), "999.999ns");
+        assert_eq!(ps(PS_PER_US), "1.000us");
+        assert_eq!(ps(PS_PER_MS - 1), "999.999us");
+        assert_eq!(ps(PS_PER_MS), "1.000ms");
+        assert_eq!(ps(PS_PER_S - 1), "999.999ms");
+        assert_eq!(ps(PS_PER_S), "1.000s");
+        assert_eq!(ps(u64::MAX), "18446744.073s");
+    }
+
+    // Decimals are cut, not rounded, so a duration never reads longer
+    #[test]
+    fn write_ps_truncates() {
+        assert_eq!(ps(1_999), "1.999ns");
+        assert_eq!(ps(12_500_999), "12.500us");
+        assert_eq!(ps(1_000_999_999), "1.000ms");
+    }
+
+    #[test]
+    fn write_ps_leading_zeros_in_decimals() {
+        assert_eq!(ps(1_005), "1.005ns");
+        assert_eq!(ps(1_050_000), "1.050us");
+        assert_eq!(ps(3 * PS_PER_S + 7 * PS_PER_MS), "3.007s");
+    }
+}
@@ -0,0 +1,81 @@
+// Predicted output edges of a pulse table. Only needs the fixed cycle counts
+// of the program variant, so it doesn't touch the PIO.
+
+use crate::time::cycles_to_ps;
+use core::{iter::Zip, slice::Iter};
+
+// Fixed cycle counts of a program variant around its delay and width loops
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Timing {
+    // Trigger edge at the pin to the start of the first delay, taken off the
+    // first delay with latency compensation
+    pub latency: u32,
+    // Shorter delays and widths are rounded up
+    pub min_delay: u32,
+    // For the delays after the first pulse
+    pub min_next_delay: u32,
+    pub min_width: u32,
+    // Low cycles from a falling edge to the start of the next delay
+    pub gap: u32,
+    // Every pulse is timed from its own trigger edge like the first
+    pub per_edge: bool,
+}
+
+// Yields (rise, fall) in ps from the trigger edge at the pin, one pair per
+// pulse. In per edge mode each pair is from the edge releasing its pulse.
+pub struct Timeline<'a> {
+    pulses: Zip<Iter<'a, u64>, Iter<'a, u32>>,
+    timing: Timing,
+    compensate: bool,
+    sys_hz: u32,
+    // Added to the first delay, taken by the first pulse
+    pre_delay: u64,
+    // Cycle the next delay starts on, None before the first pulse
+    next: Option<u64>,
+}
+
+pub fn predict<'a>(
+    delays: &'a [u64],
+    widths: &'a [u32],
+    pre_delay: u64,
+    timing: Timing,
+    compensate: bool,
+    sys_hz: u32,
+) -> Timeline<'a> {
+    Timeline {
+        pulses: delays.iter().zip(widths),
+        timing,
+        compensate,
+        sys_hz,
+        pre_delay,
+        next: None,
+    }
+}
+
+impl Iterator for Timeline<'_> {
+    type Item = (u64, u64);
+
+    fn next(&mut self) -> Option<(u64, u64)> {
+        let (&delay, &width) = self.pulses.next()?;
+        let delay = delay + core::mem::take(&mut self.pre_delay);
+        let latency = self.timing.latency as u64;
+        let (start, delay, min_delay) = match self.next {
+            None if self.compensate => (
+                latency,
+                delay.saturating_sub(latency),
+                self.timing.min_delay,
+            ),
+            None => (latency, delay, self.timing.min_delay),
+            Some(start) => (start, delay, self.timing.min_next_delay),
+        };
+        let rise = start + delay.max(min_delay as u64);
+        let fall = rise + width.max(self.timing.min_width) as u64;
+        if !self.timing.per_edge {
+            self.next = Some(fall + self.timing.gap as u64);
+        }
+        Some((
+            cycles_to_ps(rise, self.sys_hz),
+            cycles_to_ps(fall, self.sys_hz),
+        ))
+    }
+}
@@ -0,0 +1,137 @@
+// Trigger timestamp log: a spare SM counts system clocks in a 2 cycle loop
+// and pushes the count at each rising edge of the trigger input, and its DMA
+// channel copies the counts into a ring as they come, without the CPU. The
+// counting restarts at every arm, so a count is the time since the arm.
+// Runs on expert SM3 and DMA channel 3 while no expert program uses them.
+
+use pio::{ArrayVec, Assembler, JmpCondition, MovDestination, MovOperation, MovSource};
+
+use crate::board::hal::pac;
+
+// Counts kept, the oldest is overwritten first
+pub const TLOG_LEN: usize = 16;
+// The pin is sampled every other cycle
+pub const CYCLES_PER_COUNT: u64 = 2;
+// From the edge at the pad to the sample that saw it: the input
+// synchronizer, then the count the capture path takes before the mov
+const CAPTURE_LATENCY: u64 = 4;
+// DREQ of the first PIO0 RX FIFO
+const DREQ_PIO0_RX0: u8 = 4;
+
+// Aligned to its size for the DMA write ring
+#[repr(C, align(64))]
+struct Ring([u32; TLOG_LEN]);
+
+static mut RING: Ring = Ring([0; TLOG_LEN]);
+pub const RING_RAM: usize = core::mem::size_of::<Ring>();
+
+// Every path through the program takes 2 cycles per X decrement, so the
+// count pushed is half the cycles since the start. X starts at !0, put there
+// with an exec before the SM is started, and counts down.
+pub fn program() -> pio::Program<32> {
+    let mut asm: Assembler<32> = Assembler::new();
+    let mut low = asm.label();
+    let mut low_dec = asm.label();
+    let mut high = asm.label();
+    let mut edge = asm.label();
+    let mut wrap_source = asm.label();
+
+    // Wait for the input to go low
+    asm.bind(&mut low);
+    asm.jmp(JmpCondition::PinHigh, &mut low_dec);
+    asm.jmp(JmpCondition::XDecNonZero, &mut high);
+    asm.bind(&mut low_dec);
+    asm.jmp(JmpCondition::XDecNonZero, &mut low);
+
+    // Then high, which is the edge
+    asm.bind(&mut high);
+    asm.jmp(JmpCondition::PinHigh, &mut edge);
+    asm.jmp(JmpCondition::XDecNonZero, &mut high);
+
+    // Push the count, 6 cycles for 3 decrements. A full FIFO drops it,
+    // which only happens if the DMA is stopped.
+    asm.bind(&mut edge);
+    let mut capture = asm.label();
+    asm.jmp(JmpCondition::XDecNonZero, &mut capture);
+    asm.bind(&mut capture);
+    asm.mov(MovDestination::ISR, MovOperation::Invert, MovSource::X);
+    let mut push = asm.label();
+    asm.jmp(JmpCondition::XDecNonZero, &mut push);
+    asm.bind(&mut push);
+    asm.push(false, false);
+    asm.jmp(JmpCondition::XDecNonZero, &mut low);
+    asm.bind(&mut wrap_source);
+
+    // A decrement through zero falls through to the next instruction
+    // instead of jumping, the wrap takes the last one back to the start
+    asm.assemble_with_wrap(wrap_source, low)
+}
+
+// Starts the DMA channel copying the SM's RX FIFO into the ring, with the
+// ring cleared. Runs for 2^32 counts.
+pub fn start_dma(dma_ch: u8, sm: u8) {
+    // Safety: the ring is only written by this DMA channel, which is
+    // stopped, and read through read()
+    let ring = unsafe { core::ptr::addr_of_mut!(RING) };
+    unsafe { (*ring).0 = [0; TLOG_LEN] };
+    start_ring_dma(dma_ch, sm, ring as *mut u32, TLOG_LEN);
+}
+
+// The copy itself, shared with glitch's ring. `ring` holds `len` words, a
+// power of 2, and is aligned to its size in bytes.
+pub fn start_ring_dma(dma_ch: u8, sm: u8, ring: *mut u32, len: usize) {
+    // Safety: only this channel's registers are touched, its hal Channel
+    // is held unused meanwhile
+    let dma = unsafe { &*pac::DMA::ptr() };
+    let pio = unsafe { &*pac::PIO0::ptr() };
+    let ch = dma.ch(dma_ch as usize);
+    ch.ch_read_addr()
+        .write(|w| unsafe { w.bits(pio.rxf(sm as usize).as_ptr() as u32) });
+    ch.ch_write_addr().write(|w| unsafe { w.bits(ring as u32) });
+    ch.ch_trans_count().write(|w| unsafe { w.bits(u32::MAX) });
+    ch.ch_ctrl_trig().write(|w| unsafe {
+        w.data_size()
+            .size_word()
+            .incr_read()
+            .clear_bit()
+            .incr_write()
+            .set_bit()
+            // log2 of the ring size in bytes, wrapping the write address
+            .ring_size()
+            .bits(len.trailing_zeros() as u8 + 2)
+            .ring_sel()
+            .set_bit()
+            // Normal priority, see pulse_generator's set_high_priority().
+            // Chaining to itself is no chaining.
+            .chain_to()
+            .bits(dma_ch)
+            .treq_sel()
+            .bits(DREQ_PIO0_RX0 + sm)
+            .irq_quiet()
+            .set_bit()
+            .en()
+            .set_bit()
+    });
+}
+
+// Words pushed since start_dma() or start_ring_dma()
+pub fn captured(dma_ch: u8) -> u32 {
+    // Safety: read-only access to the channel's count
+    let dma = unsafe { &*pac::DMA::ptr() };
+    u32::MAX - dma.ch(dma_ch as usize).ch_trans_count().read().bits()
+}
+
+// The last TLOG_LEN edges as cycles from the arm, oldest first, and the
+// number of edges since the arm. An edge arriving meanwhile can replace the
+// oldest count.
+pub fn read(dma_ch: u8) -> (u32, ArrayVec<u64, TLOG_LEN>) {
+    let total = captured(dma_ch);
+    let first = total.saturating_sub(TLOG_LEN as u32);
+    // Safety: volatile reads of words the DMA writes whole
+    let ring = unsafe { core::ptr::addr_of!(RING.0) } as *const u32;
+    let cycles = (first..total)
+        .map(|i| unsafe { ring.add(i as usize % TLOG_LEN).read_volatile() })
+        .map(|count| (count as u64 * CYCLES_PER_COUNT).saturating_sub(CAPTURE_LATENCY))
+        .collect();
+    (total, cycles)
+}
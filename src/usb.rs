@@ -0,0 +1,50 @@
+// USB REENUM: the device drops off the bus by turning its D+ pull-up off and
+// comes back DETACH_US later as if it had just been plugged in. The host
+// resets the bus and enumerates it afresh, which resets the UsbDevice and
+// the serial class in place. The allocator and the endpoints are kept,
+// usb-device only hands them out once, so nothing is rebuilt. The channels
+// carry on as they were, armed or not.
+
+use crate::board::hal::pac;
+
+// Off the bus long enough for any host to see a disconnect
+pub const DETACH_US: u64 = 100_000;
+// Longest wait for the OK to go out before detaching
+pub const FLUSH_US: u64 = 20_000;
+
+pub struct Reenum {
+    // Timer ticks (us) the pull-up went off, None while attached
+    detached_at: Option<u64>,
+}
+
+impl Reenum {
+    pub const fn new() -> Self {
+        Self { detached_at: None }
+    }
+
+    pub fn detach(&mut self, now: u64) {
+        set_pullup(false);
+        self.detached_at = Some(now);
+    }
+
+    // Attaches again once DETACH_US passed, true when it did. Polled from
+    // the main loop.
+    pub fn poll(&mut self, now: u64) -> bool {
+        match self.detached_at {
+            Some(at) if now.saturating_sub(at) >= DETACH_US => {
+                set_pullup(true);
+                self.detached_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn set_pullup(enabled: bool) {
+    // Safety: only SIE_CTRL's pull-up enable, which the HAL's UsbBus sets
+    // once when the device is built, with interrupts off as the HAL takes
+    // the registers in a critical section too
+    let usb = unsafe { &*pac::USBCTRL_REGS::ptr() };
+    cortex_m::interrupt::free(|_| usb.sie_ctrl().modify(|_, w| w.pullup_en().bit(enabled)));
+}
@@ -0,0 +1,225 @@
+// Log lines mirrored to the serial port for hosts without a debug probe.
+// LOG USB ON queues the defmt call sites of the chosen level and up as a
+// plaintext record, the format string and its arguments copied, and the
+// main loop renders them as "# <LEVEL> <line>" between its other lines. A
+// full queue drops the record and counts it, pushing never blocks and does
+// no formatting, so it is safe from an interrupt handler. Without the
+// usb-log feature the macros are plain defmt calls and nothing else is in.
+
+#[cfg(feature = "usb-log")]
+use core::cell::RefCell;
+#[cfg(feature = "usb-log")]
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+#[cfg(feature = "usb-log")]
+use arrayvec::{ArrayString, ArrayVec};
+#[cfg(feature = "usb-log")]
+use cortex_m::interrupt::Mutex;
+
+#[cfg(feature = "usb-log")]
+use crate::text::Text;
+
+// Records waiting for the main loop
+#[cfg(feature = "usb-log")]
+pub const QUEUE_LEN: usize = 16;
+// Arguments per record, and bytes kept of a text argument
+#[cfg(feature = "usb-log")]
+const ARGS_MAX: usize = 3;
+#[cfg(feature = "usb-log")]
+const TEXT_MAX: usize = 24;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+}
+
+pub const LEVELS: [Level; 3] = [Level::Debug, Level::Info, Level::Warn];
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+        }
+    }
+}
+
+#[cfg(feature = "usb-log")]
+pub enum Arg {
+    Num(u64),
+    // Truncated to TEXT_MAX bytes
+    Text(ArrayString<TEXT_MAX>),
+}
+
+#[cfg(feature = "usb-log")]
+macro_rules! from_num {
+    ($($t:ty),*) => {$(
+        impl From<$t> for Arg {
+            fn from(value: $t) -> Self {
+                Arg::Num(value as u64)
+            }
+        }
+    )*};
+}
+
+#[cfg(feature = "usb-log")]
+from_num!(u8, u16, u32, u64, usize);
+
+#[cfg(feature = "usb-log")]
+impl From<&str> for Arg {
+    fn from(value: &str) -> Self {
+        let mut text = ArrayString::new();
+        for c in value.chars() {
+            if text.try_push(c).is_err() {
+                break;
+            }
+        }
+        Arg::Text(text)
+    }
+}
+
+#[cfg(feature = "usb-log")]
+pub struct Record {
+    level: Level,
+    format: &'static str,
+    args: ArrayVec<Arg, ARGS_MAX>,
+}
+
+#[cfg(feature = "usb-log")]
+type Queue = Mutex<RefCell<ArrayVec<Record, QUEUE_LEN>>>;
+#[cfg(feature = "usb-log")]
+static QUEUE: Queue = Mutex::new(RefCell::new(ArrayVec::new_const()));
+#[cfg(feature = "usb-log")]
+pub const QUEUE_RAM: usize = core::mem::size_of::<Queue>();
+// Lowest level queued, OFF when mirroring is off
+#[cfg(feature = "usb-log")]
+const OFF: u8 = 0xff;
+#[cfg(feature = "usb-log")]
+static MIN_LEVEL: AtomicU8 = AtomicU8::new(OFF);
+// Records dropped on a full queue since LOG USB ON, only stored inside
+// the queue's critical section as thumbv6m has no atomic add
+#[cfg(feature = "usb-log")]
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+// None turns mirroring off and forgets what was queued
+#[cfg(feature = "usb-log")]
+pub fn set_level(level: Option<Level>) {
+    cortex_m::interrupt::free(|cs| {
+        QUEUE.borrow(cs).borrow_mut().clear();
+        DROPPED.store(0, Ordering::Relaxed);
+        MIN_LEVEL.store(level.map_or(OFF, |level| level as u8), Ordering::Release);
+    });
+}
+
+pub fn level() -> Option<Level> {
+    #[cfg(feature = "usb-log")]
+    {
+        let min = MIN_LEVEL.load(Ordering::Acquire);
+        LEVELS.into_iter().find(|&level| level as u8 == min)
+    }
+    #[cfg(not(feature = "usb-log"))]
+    None
+}
+
+pub fn dropped() -> u32 {
+    #[cfg(feature = "usb-log")]
+    {
+        DROPPED.load(Ordering::Relaxed)
+    }
+    #[cfg(not(feature = "usb-log"))]
+    0
+}
+
+// Called by the macros below, arguments past ARGS_MAX are left out
+#[cfg(feature = "usb-log")]
+pub fn push(level: Level, format: &'static str, args: impl IntoIterator<Item = Arg>) {
+    if (level as u8) < MIN_LEVEL.load(Ordering::Acquire) {
+        return;
+    }
+    let record = Record {
+        level,
+        format,
+        args: args.into_iter().take(ARGS_MAX).collect(),
+    };
+    cortex_m::interrupt::free(|cs| {
+        if QUEUE.borrow(cs).borrow_mut().try_push(record).is_err() {
+            DROPPED.store(
+                DROPPED.load(Ordering::Relaxed).saturating_add(1),
+                Ordering::Relaxed,
+            );
+        }
+    });
+}
+
+// The oldest record, polled from the main loop
+#[cfg(feature = "usb-log")]
+pub fn pop() -> Option<Record> {
+    cortex_m::interrupt::free(|cs| {
+        let mut queue = QUEUE.borrow(cs).borrow_mut();
+        (!queue.is_empty()).then(|| queue.remove(0))
+    })
+}
+
+// "# <LEVEL> <line>", the format's placeholders replaced by the arguments
+// in order, "{:08x}" in hex and the rest in decimal, "?" once they run out
+#[cfg(feature = "usb-log")]
+pub fn render<T: Text>(out: &mut T, record: &Record) {
+    out.put("# ").put(record.level.as_str()).put(" ");
+    let mut args = record.args.iter();
+    let mut rest = record.format;
+    while let Some(start) = rest.find('{') {
+        out.put(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            rest = &rest[start..];
+            break;
+        };
+        let hex = &rest[start..start + end + 1] == "{:08x}";
+        match args.next() {
+            Some(Arg::Num(value)) if hex => {
+                out.hex0(*value, 8);
+            }
+            Some(Arg::Num(value)) => {
+                out.dec(*value);
+            }
+            Some(Arg::Text(text)) => {
+                out.put(text);
+            }
+            None => {
+                out.put("?");
+            }
+        }
+        rest = &rest[start + end + 1..];
+    }
+    out.put(rest);
+}
+
+// defmt's macros, also queued when built with usb-log. The arguments are
+// evaluated for each, so they should be plain values.
+macro_rules! mirror {
+    ($level:ident, $defmt:ident, $format:tt $(, $arg:expr)* $(,)?) => {{
+        defmt::$defmt!($format $(, $arg)*);
+        #[cfg(feature = "usb-log")]
+        $crate::usblog::push(
+            $crate::usblog::Level::$level,
+            $format,
+            [$($crate::usblog::Arg::from($arg)),*],
+        );
+    }};
+}
+
+macro_rules! debug {
+    ($($t:tt)*) => { $crate::usblog::mirror!(Debug, debug, $($t)*) };
+}
+
+macro_rules! info {
+    ($($t:tt)*) => { $crate::usblog::mirror!(Info, info, $($t)*) };
+}
+
+macro_rules! warn {
+    ($($t:tt)*) => { $crate::usblog::mirror!(Warn, warn, $($t)*) };
+}
+
+pub(crate) use {debug, info, mirror, warn};
@@ -0,0 +1,559 @@
+// Payload layouts of the binary frames. Every payload starts with
+// PROTOCOL_VERSION and a fixed header from the #[repr(C)] structs below,
+// table, stream and feed frames then carry little-endian words up to the
+// end of the payload. The structs are ordered so there is no padding and
+// their size is their size on the wire, but payloads are always decoded
+// field by field with from_le_bytes, never cast in place.
+//
+// Replies to frames, and framing errors in binary mode, are text lines
+// headed by the session nonce as 8 hex digits and a space. The nonce is
+// picked at power-on, so a different one tells the host the device
+// restarted and lost its configuration.
+//
+// The host tests write host/pico_pulse.h and host/pico_pulse.json from
+// this file, parser.rs and the pulse generator's capabilities, see
+// host-tests/src/header.rs. They fail while the checked-in copies differ,
+// PICO_PULSE_UPDATE_PROTOCOL=1 rewrites them.
+
+use core::mem::{offset_of, size_of};
+
+// The unversioned frames started with a channel or SM number below 4, which
+// no version uses, so a host tool written against them gets a
+// PROTOCOL_VERSION NAK rather than a misread frame. High nibble major, low
+// nibble minor.
+pub const PROTOCOL_VERSION: u8 = 0x11;
+
+// The command byte after FRAME_START, and FRAMES listing them by name for
+// the host header
+macro_rules! frames {
+    ($($name:ident = $id:literal,)+) => {
+        $(pub const $name: u8 = $id;)+
+        #[cfg(not(target_os = "none"))]
+        pub const FRAMES: &[(&str, u8)] = &[$((stringify!($name), $name)),+];
+    };
+}
+
+frames! {
+    FRAME_TABLE = 0x01,
+    FRAME_STREAM = 0x02,
+    FRAME_EXPERT_LOAD = 0x03,
+    FRAME_EXPERT_FEED = 0x04,
+    FRAME_POLL = 0x05,
+    FRAME_READ = 0x06,
+    FRAME_CHUNK = 0x07,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WireError {
+    Version,
+    Length,
+    // No frame has the command byte
+    UnknownFrame,
+}
+
+impl WireError {
+    pub const ALL: [WireError; 3] = [
+        WireError::Version,
+        WireError::Length,
+        WireError::UnknownFrame,
+    ];
+
+    // As the ERR line names it
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WireError::Version => "PROTOCOL_VERSION",
+            WireError::Length => "BAD_LENGTH",
+            WireError::UnknownFrame => "UNKNOWN_FRAME",
+        }
+    }
+}
+
+// What a FRAME_READ reads back, by its source byte, see readback
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReadSource {
+    // CAPS? as its text
+    Capabilities,
+    // LOG? as its text
+    Log,
+    // TLOG? as its text
+    Tlog,
+    // SNAP? as the blob, not its SNAP lines
+    Snap,
+}
+
+impl ReadSource {
+    // In source byte order
+    pub const ALL: [ReadSource; 4] = [
+        ReadSource::Capabilities,
+        ReadSource::Log,
+        ReadSource::Tlog,
+        ReadSource::Snap,
+    ];
+
+    pub fn from_u8(source: u8) -> Option<Self> {
+        Self::ALL.get(source as usize).copied()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReadSource::Capabilities => "CAPS",
+            ReadSource::Log => "LOG",
+            ReadSource::Tlog => "TLOG",
+            ReadSource::Snap => "SNAP",
+        }
+    }
+}
+
+// FRAME_TABLE, FRAME_STREAM and FRAME_EXPERT_FEED
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ChannelHeader {
+    pub version: u8,
+    // Channel, or SM for a feed
+    pub ch: u8,
+}
+
+// One table or stream entry, in cycles
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Pair {
+    pub delay: u32,
+    pub width: u32,
+}
+
+// FRAME_EXPERT_LOAD, followed by the program as u16 words
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ExpertLoadHeader {
+    pub version: u8,
+    pub sm: u8,
+    // 0xff for anywhere
+    pub origin: u8,
+    pub wrap_target: u8,
+    pub wrap_source: u8,
+    pub side_set_bits: u8,
+    // Bit 0 optional, bit 1 pindirs
+    pub side_set_flags: u8,
+    pub pin_base: u8,
+    pub pin_count: u8,
+}
+
+// FRAME_READ, the whole payload
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ReadHeader {
+    pub version: u8,
+    // See ReadSource
+    pub source: u8,
+}
+
+// FRAME_CHUNK, the whole payload
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ChunkHeader {
+    pub version: u8,
+    // 0 acks the chunk, 1 asks for it again
+    pub resend: u8,
+    pub chunk: u16,
+}
+
+pub const CHANNEL_HEADER_LEN: usize = 2;
+pub const PAIR_LEN: usize = 8;
+pub const EXPERT_LOAD_HEADER_LEN: usize = 9;
+pub const READ_HEADER_LEN: usize = 2;
+pub const CHUNK_HEADER_LEN: usize = 4;
+
+const _: () = assert!(size_of::<ChannelHeader>() == CHANNEL_HEADER_LEN);
+const _: () = assert!(size_of::<Pair>() == PAIR_LEN);
+const _: () = assert!(size_of::<ExpertLoadHeader>() == EXPERT_LOAD_HEADER_LEN);
+const _: () = assert!(size_of::<ReadHeader>() == READ_HEADER_LEN);
+const _: () = assert!(size_of::<ChunkHeader>() == CHUNK_HEADER_LEN);
+
+// A field of a struct above as the generated C header lays it out
+pub struct Field {
+    pub name: &'static str,
+    pub offset: usize,
+    // 1, 2 or 4 bytes, an unsigned little-endian integer
+    pub size: usize,
+}
+
+pub struct Layout {
+    pub name: &'static str,
+    pub size: usize,
+    pub fields: &'static [Field],
+}
+
+const fn field_size<T, F>(_: fn(&T) -> &F) -> usize {
+    size_of::<F>()
+}
+
+// $name's fields in order, checked below to cover the whole struct
+macro_rules! layout {
+    ($name:ident { $($field:ident),+ }) => {
+        Layout {
+            name: stringify!($name),
+            size: size_of::<$name>(),
+            fields: &[$(Field {
+                name: stringify!($field),
+                offset: offset_of!($name, $field),
+                size: field_size(|s: &$name| &s.$field),
+            }),+],
+        }
+    };
+}
+
+pub const LAYOUTS: [Layout; 5] = [
+    layout!(ChannelHeader { version, ch }),
+    layout!(Pair { delay, width }),
+    layout!(ExpertLoadHeader {
+        version,
+        sm,
+        origin,
+        wrap_target,
+        wrap_source,
+        side_set_bits,
+        side_set_flags,
+        pin_base,
+        pin_count
+    }),
+    layout!(ReadHeader { version, source }),
+    layout!(ChunkHeader {
+        version,
+        resend,
+        chunk
+    }),
+];
+
+// A field added to a struct but not to its layout leaves a hole or a short
+// total
+const _: () = {
+    let mut i = 0;
+    while i < LAYOUTS.len() {
+        let layout = &LAYOUTS[i];
+        let (mut size, mut j) = (0, 0);
+        while j < layout.fields.len() {
+            assert!(layout.fields[j].offset == size);
+            size += layout.fields[j].size;
+            j += 1;
+        }
+        assert!(size == layout.size);
+        i += 1;
+    }
+};
+
+// Frames of the unversioned format were never shorter than these headers,
+// so they always get as far as this check
+fn check_version(version: u8) -> Result<(), WireError> {
+    if version != PROTOCOL_VERSION {
+        return Err(WireError::Version);
+    }
+    Ok(())
+}
+
+// FRAME_POLL and other frames without arguments, only the version
+pub fn decode_bare(payload: &[u8]) -> Result<(), WireError> {
+    let [version] = payload else {
+        return Err(WireError::Length);
+    };
+    check_version(*version)
+}
+
+impl ChannelHeader {
+    // The header and the rest of the payload
+    pub fn decode(payload: &[u8]) -> Result<(Self, &[u8]), WireError> {
+        if payload.len() < CHANNEL_HEADER_LEN {
+            return Err(WireError::Length);
+        }
+        let (header, rest) = payload.split_at(CHANNEL_HEADER_LEN);
+        let header = Self {
+            version: header[0],
+            ch: header[1],
+        };
+        check_version(header.version)?;
+        Ok((header, rest))
+    }
+}
+
+impl Pair {
+    // `bytes` is PAIR_LEN long
+    pub fn decode(bytes: &[u8]) -> Self {
+        let word = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        Self {
+            delay: word(0),
+            width: word(4),
+        }
+    }
+}
+
+impl ExpertLoadHeader {
+    // The header and the program words after it
+    pub fn decode(payload: &[u8]) -> Result<(Self, &[u8]), WireError> {
+        if payload.len() < EXPERT_LOAD_HEADER_LEN {
+            return Err(WireError::Length);
+        }
+        let (h, rest) = payload.split_at(EXPERT_LOAD_HEADER_LEN);
+        let header = Self {
+            version: h[0],
+            sm: h[1],
+            origin: h[2],
+            wrap_target: h[3],
+            wrap_source: h[4],
+            side_set_bits: h[5],
+            side_set_flags: h[6],
+            pin_base: h[7],
+            pin_count: h[8],
+        };
+        check_version(header.version)?;
+        Ok((header, rest))
+    }
+}
+
+impl ReadHeader {
+    pub fn decode(payload: &[u8]) -> Result<Self, WireError> {
+        let &[version, source] = payload else {
+            return Err(WireError::Length);
+        };
+        check_version(version)?;
+        Ok(Self { version, source })
+    }
+}
+
+impl ChunkHeader {
+    pub fn decode(payload: &[u8]) -> Result<Self, WireError> {
+        let &[version, resend, lo, hi] = payload else {
+            return Err(WireError::Length);
+        };
+        check_version(version)?;
+        Ok(Self {
+            version,
+            resend,
+            chunk: u16::from_le_bytes([lo, hi]),
+        })
+    }
+}
+
+// Encoding is for the host side only, the firmware answers in text. Each is
+// the exact inverse of the decode above.
+
+#[cfg(not(target_os = "none"))]
+impl ChannelHeader {
+    pub fn encode(&self) -> [u8; CHANNEL_HEADER_LEN] {
+        [self.version, self.ch]
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl Pair {
+    pub fn encode(&self) -> [u8; PAIR_LEN] {
+        let mut bytes = [0; PAIR_LEN];
+        bytes[..4].copy_from_slice(&self.delay.to_le_bytes());
+        bytes[4..].copy_from_slice(&self.width.to_le_bytes());
+        bytes
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl ExpertLoadHeader {
+    pub fn encode(&self) -> [u8; EXPERT_LOAD_HEADER_LEN] {
+        [
+            self.version,
+            self.sm,
+            self.origin,
+            self.wrap_target,
+            self.wrap_source,
+            self.side_set_bits,
+            self.side_set_flags,
+            self.pin_base,
+            self.pin_count,
+        ]
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl ReadHeader {
+    pub fn encode(&self) -> [u8; READ_HEADER_LEN] {
+        [self.version, self.source]
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl ChunkHeader {
+    pub fn encode(&self) -> [u8; CHUNK_HEADER_LEN] {
+        let [lo, hi] = self.chunk.to_le_bytes();
+        [self.version, self.resend, lo, hi]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    // Every byte of a header is a field, so random bytes cover the values
+    fn expert_header(rng: &mut Rng) -> ExpertLoadHeader {
+        let mut b = [0; EXPERT_LOAD_HEADER_LEN].map(|_: u8| rng.u8());
+        b[0] = PROTOCOL_VERSION;
+        ExpertLoadHeader::decode(&b).unwrap().0
+    }
+
+    #[test]
+    fn channel_header_round_trips() {
+        for ch in [0, 1, 3, 0xff] {
+            let header = ChannelHeader {
+                version: PROTOCOL_VERSION,
+                ch,
+            };
+            let mut payload = header.encode().to_vec();
+            payload.extend(Pair { delay: 5, width: 6 }.encode());
+            let (decoded, rest) = ChannelHeader::decode(&payload).unwrap();
+            assert_eq!(decoded, header);
+            assert_eq!(rest, Pair { delay: 5, width: 6 }.encode());
+        }
+    }
+
+    #[test]
+    fn pair_round_trips() {
+        let mut rng = Rng::new(151);
+        let edges = [0, 1, 0xff, 0x100, 0x0102_0304, u32::MAX];
+        for delay in edges {
+            for width in edges {
+                let pair = Pair { delay, width };
+                assert_eq!(Pair::decode(&pair.encode()), pair);
+            }
+        }
+        for _ in 0..1000 {
+            let pair = Pair {
+                delay: rng.u64() as u32,
+                width: rng.u64() as u32,
+            };
+            assert_eq!(Pair::decode(&pair.encode()), pair);
+        }
+        // Little-endian, delay first
+        assert_eq!(
+            Pair {
+                delay: 0x0403_0201,
+                width: 0x0807_0605
+            }
+            .encode(),
+            [1, 2, 3, 4, 5, 6, 7, 8]
+        );
+    }
+
+    #[test]
+    fn expert_load_header_round_trips() {
+        let mut rng = Rng::new(151);
+        for _ in 0..1000 {
+            let header = expert_header(&mut rng);
+            let program = [0x12, 0x34, 0x56, 0x78];
+            let mut payload = header.encode().to_vec();
+            payload.extend(program);
+            let (decoded, rest) = ExpertLoadHeader::decode(&payload).unwrap();
+            assert_eq!(decoded, header);
+            assert_eq!(rest, program);
+        }
+    }
+
+    #[test]
+    fn read_and_chunk_headers_round_trip() {
+        for source in [0, 3, 0xff] {
+            let header = ReadHeader {
+                version: PROTOCOL_VERSION,
+                source,
+            };
+            assert_eq!(ReadHeader::decode(&header.encode()), Ok(header));
+        }
+        for chunk in [0, 1, 0x1234, u16::MAX] {
+            let header = ChunkHeader {
+                version: PROTOCOL_VERSION,
+                resend: 1,
+                chunk,
+            };
+            assert_eq!(ChunkHeader::decode(&header.encode()), Ok(header));
+        }
+    }
+
+    // Encoded fields sit where LAYOUTS, and so the C header, puts them
+    #[test]
+    fn encodings_follow_layouts() {
+        let mut rng = Rng::new(151);
+        let header = expert_header(&mut rng);
+        let bytes = header.encode();
+        let layout = &LAYOUTS[2];
+        assert_eq!(layout.name, "ExpertLoadHeader");
+        assert_eq!(bytes.len(), layout.size);
+        assert_eq!(
+            bytes[offset_of!(ExpertLoadHeader, wrap_source)],
+            header.wrap_source
+        );
+        assert_eq!(
+            bytes[offset_of!(ExpertLoadHeader, pin_count)],
+            header.pin_count
+        );
+        let chunk = ChunkHeader {
+            version: PROTOCOL_VERSION,
+            resend: 0,
+            chunk: 0xbeef,
+        }
+        .encode();
+        let at = offset_of!(ChunkHeader, chunk);
+        assert_eq!(u16::from_le_bytes([chunk[at], chunk[at + 1]]), 0xbeef);
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let mut rng = Rng::new(151);
+        for version in (0..=u8::MAX).filter(|&v| v != PROTOCOL_VERSION) {
+            let ch = ChannelHeader { version, ch: 0 }.encode();
+            assert_eq!(ChannelHeader::decode(&ch), Err(WireError::Version));
+            let mut expert = expert_header(&mut rng);
+            expert.version = version;
+            assert_eq!(
+                ExpertLoadHeader::decode(&expert.encode()),
+                Err(WireError::Version)
+            );
+            let read = ReadHeader { version, source: 0 }.encode();
+            assert_eq!(ReadHeader::decode(&read), Err(WireError::Version));
+            let chunk = ChunkHeader {
+                version,
+                resend: 0,
+                chunk: 0,
+            }
+            .encode();
+            assert_eq!(ChunkHeader::decode(&chunk), Err(WireError::Version));
+            assert_eq!(decode_bare(&[version]), Err(WireError::Version));
+        }
+    }
+
+    // An unversioned frame from an old host tool: channel byte first, then
+    // pairs straight away
+    #[test]
+    fn unversioned_frames_get_the_version_nak() {
+        let mut old = vec![1];
+        old.extend(
+            Pair {
+                delay: 10,
+                width: 20,
+            }
+            .encode(),
+        );
+        assert_eq!(ChannelHeader::decode(&old), Err(WireError::Version));
+        assert_eq!(WireError::Version.as_str(), "PROTOCOL_VERSION");
+    }
+
+    #[test]
+    fn short_payloads_are_rejected() {
+        assert_eq!(ChannelHeader::decode(&[]), Err(WireError::Length));
+        assert_eq!(
+            ChannelHeader::decode(&[PROTOCOL_VERSION]),
+            Err(WireError::Length)
+        );
+        let expert = [PROTOCOL_VERSION; EXPERT_LOAD_HEADER_LEN - 1];
+        assert_eq!(ExpertLoadHeader::decode(&expert), Err(WireError::Length));
+        assert_eq!(decode_bare(&[]), Err(WireError::Length));
+        assert_eq!(decode_bare(&[PROTOCOL_VERSION, 0]), Err(WireError::Length));
+        assert_eq!(
+            ChunkHeader::decode(&[PROTOCOL_VERSION, 0, 0]),
+            Err(WireError::Length)
+        );
+    }
+}
@@ -0,0 +1,461 @@
+// On-target tests of the pulse driver, what the host tests can't cover: the
+// programs running on PIO with their tables fed by DMA. Runs on a Pico with
+// a probe attached:
+//
+//   cargo test --test loopback
+//
+// Wiring: a jumper from GPIO15, ch0's default output, to GPIO0, the default
+// trigger input, and nothing else on GPIO0, GPIO2 and GPIO16. ch0 is
+// triggered from GPIO2, left unconnected, which only the forced triggers
+// drive. Its pulses come back on GPIO0, where the timestamp log on SM3
+// records each rise and ch1 can take them as its trigger edges. Without the
+// jumper GPIO0 stays on its pull-down, the log sees nothing and every test
+// logs a warning and passes without checking anything.
+//
+// Times come from the timestamp log, which samples every other cycle, and
+// the SKEW? sampler on SM2, which samples every cycle. They are checked
+// against what PulseGenerator::timeline() predicts.
+
+#![no_std]
+#![no_main]
+
+// The firmware's modules the pulse driver needs, see host-tests/src/lib.rs
+// for the layout
+#[path = "../src"]
+#[allow(dead_code, unused_imports)]
+mod firmware {
+    pub mod arm_queue;
+    pub mod board;
+    pub mod command;
+    pub mod crc;
+    pub mod debugpin;
+    pub mod features;
+    pub mod flash;
+    pub mod glitch;
+    pub mod interlock;
+    pub mod perf;
+    pub mod probe;
+    pub mod protect;
+    pub mod pulse_generator;
+    pub mod safestate;
+    pub mod script;
+    pub mod snapshot;
+    pub mod text;
+    pub mod tick;
+    pub mod time;
+    pub mod timeline;
+    pub mod tlog;
+    pub mod usblog;
+    pub mod wire;
+}
+
+pub use firmware::*;
+
+use defmt_rtt as _;
+use panic_probe as _;
+
+use arrayvec::{ArrayString, ArrayVec};
+use board::hal::{self, pac, Clock, Timer, Watchdog};
+use command::{Command, Target, Value};
+use flash::FlashError;
+use pulse_generator::{Level, Pairs, PulseError, PulseGenerator, NUM_PULSES_MAX};
+use text::Text;
+use time::{ps_to_cycles, Achieved, Rounding};
+use tlog::TLOG_LEN;
+
+// ch0's trigger input, unconnected
+const SPARE_PIN: u8 = 2;
+// ch0's output as the sampler sees it, and where the jumper takes it
+const OUTPUT_PIN: u8 = 15;
+const LOOPBACK_PIN: u8 = pulse_generator::TRIGGER_PIN;
+// The log's two cycle sampling, and a cycle for the synchronizer
+const TOLERANCE: u64 = tlog::CYCLES_PER_COUNT + 1;
+// Every table here is over in well under a millisecond
+const RUN_TIMEOUT_US: u32 = 10_000;
+
+pub struct State {
+    pulse_gen: PulseGenerator,
+    sys_hz: u32,
+    loopback: bool,
+}
+
+impl State {
+    // Both channels disarmed with `pairs` of (delay, width) cycles as ch0's
+    // table and ch0 triggered from SPARE_PIN
+    fn load(&mut self, pairs: &[(u64, u32)]) {
+        let sys_hz = self.sys_hz;
+        let pg = &mut self.pulse_gen;
+        pg.reset_all();
+        let pairs: Pairs = pairs
+            .iter()
+            .map(|&(delay, width)| {
+                (
+                    Achieved::from_cycles(delay, sys_hz),
+                    Achieved::from_cycles(width as u64, sys_hz),
+                )
+            })
+            .collect();
+        defmt::unwrap!(pg.set_table(0, &pairs).ok());
+        defmt::unwrap!(pg.set_trigger_pin(0, SPARE_PIN).ok());
+        defmt::unwrap!(pg.set_tlog(true).ok());
+    }
+
+    // Arms ch0, forces its trigger and waits for the table to go out.
+    // Returns the rises the log saw at GPIO0, in cycles from the arm.
+    fn fire(&mut self) -> ArrayVec<u64, TLOG_LEN> {
+        let pg = &mut self.pulse_gen;
+        defmt::assert_eq!(pg.stress(0, 1), 0, "ch0 not armed or triggered");
+        defmt::unwrap!(pg.wait_done(0, RUN_TIMEOUT_US).ok());
+        let (count, rises) = defmt::unwrap!(pg.tlog());
+        defmt::assert_eq!(count as usize, rises.len());
+        rises
+    }
+
+    // Rises ch0's table is predicted to produce, in cycles from the trigger
+    fn predicted(&self) -> ArrayVec<u64, NUM_PULSES_MAX> {
+        self.pulse_gen
+            .timeline(0)
+            .map(
+                |(rise, _)| defmt::unwrap!(ps_to_cycles(rise, self.sys_hz, Rounding::Nearest).ok()),
+            )
+            .collect()
+    }
+
+    fn skip(&self) -> bool {
+        if !self.loopback {
+            defmt::warn!("no jumper from GPIO15 to GPIO0, skipped");
+        }
+        !self.loopback
+    }
+}
+
+// Carries out a command line as main's execute() would, for the commands
+// the experiment in built_from_reset_over_serial() sends. RESET leaves out
+// the power-on defaults and the safe state, which aren't needed here.
+fn send(pg: &mut PulseGenerator, line: &str) {
+    let command = defmt::unwrap!(command::parse(line.as_bytes()).ok(), "{}", line);
+    let sys_hz = pg.sys_hz();
+    let rounding = pg.rounding();
+    let achieved = |value| match value {
+        Value::Cycles(cycles) => Achieved::from_cycles(cycles, sys_hz),
+        Value::Picos(ps) => defmt::unwrap!(Achieved::from_ps(ps, sys_hz, rounding).ok()),
+    };
+    let done = match command {
+        Command::Reset => {
+            pg.reset_all();
+            true
+        }
+        Command::Pin(ch, pin) => pg.set_pin(ch, pin).is_ok(),
+        Command::TriggerPin(ch, pin) => pg.set_trigger_pin(ch, pin).is_ok(),
+        Command::Divider(ch, n) => {
+            pg.set_trigger_divider(ch, n);
+            true
+        }
+        Command::Table(ch, table) => {
+            let pairs: Pairs = table
+                .pairs()
+                .map(|pair| {
+                    let (delay, width) = defmt::unwrap!(pair.ok());
+                    (achieved(delay), achieved(width))
+                })
+                .collect();
+            pg.set_table(ch, &pairs).is_ok()
+        }
+        Command::Group(name, members) => pg.set_group(name, members).is_ok(),
+        Command::PerEdge(ch, per_edge) => pg.set_per_edge(ch, per_edge).is_ok(),
+        Command::Arm(Target::Channel(ch)) => pg.arm(ch).is_ok(),
+        Command::Arm(Target::Group(name)) => {
+            let members = defmt::unwrap!(pg.group(name));
+            pg.arm_group(members).is_ok()
+        }
+        _ => false,
+    };
+    defmt::assert!(done, "{} refused", line);
+}
+
+// The rises are as far apart as predicted. Where the first lands depends on
+// how long the forced trigger took after the arm, so it is left out.
+fn assert_spacing(rises: &[u64], predicted: &[u64]) {
+    defmt::assert_eq!(rises.len(), predicted.len());
+    for (seen, expected) in rises.windows(2).zip(predicted.windows(2)) {
+        let seen = seen[1] - seen[0];
+        let expected = expected[1] - expected[0];
+        defmt::assert!(
+            seen.abs_diff(expected) <= TOLERANCE,
+            "rises {} cycles apart, {} predicted",
+            seen,
+            expected
+        );
+    }
+}
+
+#[defmt_test::tests]
+mod tests {
+    use super::*;
+
+    #[init]
+    fn init() -> State {
+        let mut pac = pac::Peripherals::take().unwrap();
+        let mut watchdog = Watchdog::new(pac.WATCHDOG);
+        let clocks = defmt::unwrap!(hal::clocks::init_clocks_and_plls(
+            board::XTAL_FREQ.to_Hz(),
+            pac.XOSC,
+            pac.CLOCKS,
+            pac.PLL_SYS,
+            pac.PLL_USB,
+            &mut pac.RESETS,
+            &mut watchdog,
+        )
+        .ok());
+        // The driver reads the timer for its run statistics
+        let _timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+        let sio = hal::Sio::new(pac.SIO);
+        let _led = board::init(
+            pac.IO_BANK0,
+            pac.PADS_BANK0,
+            sio.gpio_bank0,
+            &mut pac.RESETS,
+        );
+        let sys_hz = clocks.system_clock.freq().to_Hz();
+        let pulse_gen = defmt::unwrap!(PulseGenerator::new(
+            pac.PIO0,
+            pac.DMA,
+            &mut pac.RESETS,
+            sys_hz,
+            board::PINS
+        )
+        .ok());
+        defmt::assert_eq!(board::PINS.default_outputs[0], OUTPUT_PIN);
+        let mut state = State {
+            pulse_gen,
+            sys_hz,
+            loopback: true,
+        };
+        // A pulse the log sees only through the jumper
+        state.load(&[(1_000, 1_000)]);
+        state.loopback = !state.fire().is_empty();
+        state
+    }
+
+    #[test]
+    fn immediate_trigger_single_pulse(state: &mut State) {
+        if state.skip() {
+            return;
+        }
+        state.load(&[(1_000, 4)]);
+        let rises = state.fire();
+        defmt::assert_eq!(rises.len(), 1);
+        defmt::assert_eq!(state.pulse_gen.run_stats(0).pulses, 1);
+        // The width, at the output and through the jumper, from the sampler
+        // starting on the output's rise
+        let pg = &mut state.pulse_gen;
+        defmt::unwrap!(pg.arm(0).ok());
+        let samples = defmt::unwrap!(pg.sample_run(0, OUTPUT_PIN).ok());
+        for pin in [OUTPUT_PIN, LOOPBACK_PIN] {
+            let high = samples.iter().filter(|&&sample| sample & 1 << pin != 0);
+            let high = high.count() as u32;
+            defmt::assert!(
+                high.abs_diff(4) <= 1,
+                "GPIO{} high for {} samples, 4 cycles set",
+                pin,
+                high
+            );
+        }
+    }
+
+    #[test]
+    fn edge_trigger_count_3(state: &mut State) {
+        if state.skip() {
+            return;
+        }
+        state.load(&[(1_000, 100), (500, 100), (500, 100)]);
+        // ch1 on GPIO0 takes a pulse per rise of ch0's looped back output
+        let pg = &mut state.pulse_gen;
+        let sys_hz = state.sys_hz;
+        let width = Achieved::from_cycles(50, sys_hz);
+        let pairs = [(Achieved::from_cycles(100, sys_hz), width); 3];
+        defmt::unwrap!(pg.set_table(1, &pairs).ok());
+        defmt::unwrap!(pg.set_per_edge(1, true).ok());
+        defmt::unwrap!(pg.arm(1).ok());
+        let rises = state.fire();
+        assert_spacing(&rises, &state.predicted());
+        let ch1 = defmt::unwrap!(state.pulse_gen.wait_done(1, RUN_TIMEOUT_US).ok());
+        defmt::assert_eq!(ch1.pulses, 3);
+    }
+
+    #[test]
+    fn synchronized_start_skew(state: &mut State) {
+        if state.skip() {
+            return;
+        }
+        state.pulse_gen.reset_all();
+        let skew = defmt::unwrap!(state.pulse_gen.measure_skew().ok());
+        defmt::assert!(skew.abs() <= 1, "ch1 rises {} cycles after ch0", skew);
+    }
+
+    #[test]
+    fn rearm_after_disarm(state: &mut State) {
+        if state.skip() {
+            return;
+        }
+        state.load(&[(1_000, 100)]);
+        let runs = state.pulse_gen.run_totals(0).runs;
+        let pg = &mut state.pulse_gen;
+        defmt::unwrap!(pg.arm(0).ok());
+        pg.disarm(0);
+        defmt::assert!(!pulse_generator::is_armed(0));
+        let rises = state.fire();
+        defmt::assert_eq!(rises.len(), 1);
+        // And once more after a run that went out
+        let rises = state.fire();
+        defmt::assert_eq!(rises.len(), 1);
+        defmt::assert_eq!(state.pulse_gen.run_totals(0).runs, runs + 2);
+    }
+
+    // A zero width isn't skipped: the program stretches it to its shortest
+    // width, as timeline() predicts, so the pulses after it keep their place
+    #[test]
+    fn zero_width_pulse(state: &mut State) {
+        if state.skip() {
+            return;
+        }
+        state.load(&[(1_000, 100), (500, 0), (500, 100)]);
+        let rises = state.fire();
+        assert_spacing(&rises, &state.predicted());
+        defmt::assert_eq!(state.pulse_gen.run_stats(0).pulses, 3);
+    }
+
+    // A flash write with a channel armed is refused before it stops the
+    // main loop, leaving the stored snapshot and the channel as they were
+    #[test]
+    fn flash_write_refused_while_armed(state: &mut State) {
+        if state.skip() {
+            return;
+        }
+        state.load(&[(1_000, 100)]);
+        let pg = &mut state.pulse_gen;
+        defmt::unwrap!(pg.arm(0).ok());
+        let blob = pg.snapshot();
+        defmt::assert_eq!(
+            flash::save_snapshot(Some(&blob)),
+            Err(FlashError::ChannelsActive)
+        );
+        defmt::assert!(pulse_generator::is_armed(0));
+        let rises = state.fire();
+        defmt::assert_eq!(rises.len(), 1);
+        defmt::assert_eq!(state.pulse_gen.run_stats(0).pulses, 1);
+    }
+
+    // SELFTEST's experiment, see self_test(): two outputs and their shared
+    // trigger picked off the board's PIO pins, so every pin setting rebuilds
+    // an SM, set up from RESET with commands alone. The sampler has to see
+    // ch0 rise and ch1 OFFSET cycles later, both runs counted whole.
+    #[test]
+    fn built_from_reset_over_serial(state: &mut State) {
+        const OFFSET: usize = 4;
+        let pg = &mut state.pulse_gen;
+        send(pg, "RESET");
+        let taken = |pin: u8| {
+            pin == pulse_generator::TRIGGER_PIN
+                || (0..pulse_generator::NUM_CHANNELS).any(|ch| pg.pin(ch) == pin)
+                || interlock::settings().pin == Some(pin)
+                || debugpin::settings().pin == Some(pin)
+        };
+        let mut free = pg.pio_pins().iter().copied().filter(|&pin| !taken(pin));
+        let out0 = defmt::unwrap!(free.next());
+        let out1 = defmt::unwrap!(free.next());
+        let trigger = defmt::unwrap!(free.next());
+        let mut lines: [ArrayString<64>; 13] = Default::default();
+        lines[0].put("PIN 0 ").dec(out0);
+        lines[1].put("PIN 1 ").dec(out1);
+        lines[2].put("TRIGPIN 0 ").dec(trigger);
+        lines[3].put("TRIGPIN 1 ").dec(trigger);
+        lines[4].put("DIVIDER 0 1");
+        lines[5].put("TABLE 0 200,50;100,50");
+        lines[6].put("TABLE 1 ").dec(200 + OFFSET).put(",50;100,50");
+        lines[7].put("GROUP LOOPBACK 0 1");
+        lines[8].put("ARM LOOPBACK");
+        // ch0's program swapped twice next to ch1's armed SM
+        lines[9].put("PEREDGE 0 ON");
+        lines[10].put("ARM 0");
+        lines[11].put("PEREDGE 0 OFF");
+        lines[12].put("ARM 0");
+        for line in &lines {
+            send(pg, line.as_str());
+        }
+        let samples = defmt::unwrap!(pg.sample_run(0, out0).ok());
+        for ch in 0..pulse_generator::NUM_CHANNELS {
+            let stats = pg.run_stats(ch);
+            defmt::assert_eq!((stats.runs, stats.pulses), (1, 2), "ch{}", ch);
+        }
+        send(pg, "RESET");
+        defmt::assert!(samples
+            .first()
+            .is_some_and(|sample| sample & 1 << out0 != 0));
+        let rise1 = samples.iter().position(|sample| sample & 1 << out1 != 0);
+        defmt::assert_eq!(rise1, Some(OFFSET - 1));
+    }
+
+    // ch1 waits on GPIO0, held through the jumper by ch0 resting at its
+    // idle level while armed on the spare pin. The level ARM reports is
+    // trigger_high(), and with STUCKTRIG REFUSE a high one fails the arm.
+    #[test]
+    fn trigger_level_at_arming(state: &mut State) {
+        if state.skip() {
+            return;
+        }
+        state.load(&[(1_000, 100)]);
+        let pg = &mut state.pulse_gen;
+        let sys_hz = state.sys_hz;
+        let pairs = [(
+            Achieved::from_cycles(100, sys_hz),
+            Achieved::from_cycles(50, sys_hz),
+        )];
+        defmt::unwrap!(pg.set_table(1, &pairs).ok());
+        pg.set_refuse_stuck_trigger(true);
+
+        // Held low: reported and armed
+        defmt::assert!(!pg.trigger_high(1));
+        defmt::unwrap!(pg.arm(1).ok());
+        defmt::assert!(pulse_generator::is_armed(1));
+        pg.disarm(1);
+
+        // Held high: refused, then reported with STUCKTRIG WARN
+        defmt::unwrap!(pg.set_idle_level(0, Some(Level::High)).ok());
+        defmt::unwrap!(pg.arm(0).ok());
+        defmt::assert!(pg.trigger_high(1));
+        defmt::assert!(matches!(pg.arm(1), Err(PulseError::TriggerHigh { ch: 1 })));
+        defmt::assert!(!pulse_generator::is_armed(1));
+        pg.set_refuse_stuck_trigger(false);
+        defmt::unwrap!(pg.arm(1).ok());
+        defmt::assert!(pg.trigger_high(1));
+        // Not an edge, ch1 still waits for one
+        defmt::assert!(pulse_generator::can_accept_trigger(1));
+
+        // Back low for the tests after this one
+        pg.disarm(1);
+        defmt::unwrap!(pg.set_idle_level(0, None).ok());
+        pg.disarm(0);
+        defmt::assert!(!pg.trigger_high(1));
+    }
+
+    // PING runs a scratch table on ch0's SM, the configuration a snapshot
+    // stores is left byte for byte as it was
+    #[test]
+    fn ping_leaves_snapshot_unchanged(state: &mut State) {
+        if state.skip() {
+            return;
+        }
+        state.load(&[(1_000, 100), (500, 100)]);
+        let sys_hz = state.sys_hz;
+        let pg = &mut state.pulse_gen;
+        defmt::unwrap!(pg.set_group("PAIR", 0b11).ok());
+        let before = pg.snapshot();
+        defmt::unwrap!(pg.ping(0, Achieved::from_cycles(100, sys_hz)).ok());
+        // Well past the one pulse
+        cortex_m::asm::delay(sys_hz / 1_000);
+        let (count, _) = defmt::unwrap!(pg.tlog());
+        defmt::assert_eq!(count, 1, "ping didn't go out");
+        defmt::assert_eq!(pg.snapshot().as_slice(), before.as_slice());
+    }
+}